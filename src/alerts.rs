@@ -0,0 +1,328 @@
+//! Price alerts like "bitcoin above 70000", checked after every refresh
+
+use crate::config::xdg_dir;
+use crate::model::{Coin, NumberLocale, format_price, is_valid_coin_id};
+use crate::sinks::FiredAlert;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// ~/.local/share/tuicker/alerts.json, which alerts have already fired
+pub fn alert_state_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_DATA_HOME", ".local/share")?.join("alerts.json"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Above => "above",
+            Direction::Below => "below",
+        }
+    }
+}
+
+// Written in the config file as "<coin id> above|below <price>"
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AlertRule {
+    pub coin_id: String,
+    pub direction: Direction,
+    pub price: f64,
+}
+
+pub fn parse_alert_rule(text: &str) -> Result<AlertRule, String> {
+    let invalid = || {
+        format!(
+            "invalid alert `{}`, expected e.g. \"bitcoin above 70000\"",
+            text
+        )
+    };
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let [coin_id, direction, price] = words[..] else {
+        return Err(invalid());
+    };
+    let coin_id = coin_id.to_lowercase();
+    if !is_valid_coin_id(&coin_id) {
+        return Err(format!("invalid coin id `{}`", coin_id));
+    }
+    let direction = match direction.to_lowercase().as_str() {
+        "above" => Direction::Above,
+        "below" => Direction::Below,
+        _ => return Err(invalid()),
+    };
+    let price: f64 = price.parse().map_err(|_| invalid())?;
+    if !price.is_finite() || price < 0.0 {
+        return Err(invalid());
+    }
+    Ok(AlertRule {
+        coin_id,
+        direction,
+        price,
+    })
+}
+
+impl TryFrom<String> for AlertRule {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        parse_alert_rule(&text)
+    }
+}
+
+// The config file format again, so rules edited in the app can be saved
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.coin_id,
+            self.direction.as_str(),
+            self.price
+        )
+    }
+}
+
+impl Serialize for AlertRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl AlertRule {
+    // Identifies the rule in the saved state and for sink cooldowns
+    fn key(&self) -> String {
+        self.to_string()
+    }
+
+    fn is_met(&self, price: f64) -> bool {
+        match self.direction {
+            Direction::Above => price >= self.price,
+            Direction::Below => price <= self.price,
+        }
+    }
+
+    // What the sinks announce when the rule fires for `coin`
+    pub fn fired(&self, coin: &Coin, currency: &str, locale: NumberLocale) -> FiredAlert {
+        FiredAlert {
+            key: self.key(),
+            coin_id: coin.id.clone(),
+            symbol: coin.symbol.clone(),
+            direction: self.direction,
+            threshold: self.price,
+            price: coin.current_price,
+            fired_at: SystemTime::now(),
+            summary: format!(
+                "ALERT: {} crossed {} {}",
+                coin.symbol,
+                self.direction.as_str(),
+                format_price(self.price, currency, locale)
+            ),
+            body: format!(
+                "{} is at {}",
+                coin.symbol,
+                format_price(coin.current_price, currency, locale)
+            ),
+        }
+    }
+}
+
+// Alerts that fired and whose condition still holds. They fire again only
+// after the price has gone back across the threshold
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertState {
+    // The rules as last changed in the Alerts tab. Once set they are used
+    // instead of the ones in the config file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<AlertRule>>,
+    triggered: BTreeSet<String>,
+}
+
+impl AlertState {
+    pub fn is_triggered(&self, rule: &AlertRule) -> bool {
+        self.triggered.contains(&rule.key())
+    }
+
+    // Lets a triggered rule fire again on the next refresh its condition holds
+    pub fn rearm(&mut self, rule: &AlertRule) {
+        self.triggered.remove(&rule.key());
+    }
+
+    // Checks the rules against freshly fetched prices and returns the ones
+    // that just fired. Coins missing from `coins` leave their rules as they are
+    pub fn evaluate<'a>(&mut self, rules: &'a [AlertRule], coins: &[Coin]) -> Vec<&'a AlertRule> {
+        let mut fired = Vec::new();
+        for rule in rules {
+            let Some(coin) = coins.iter().find(|coin| coin.id == rule.coin_id) else {
+                continue;
+            };
+            let key = rule.key();
+            let met = rule.is_met(coin.current_price);
+            log::debug!(
+                "alert `{}` at {}: {}",
+                rule,
+                coin.current_price,
+                match met {
+                    true => "met",
+                    false => "not met",
+                }
+            );
+            if !met {
+                self.triggered.remove(&key);
+            } else if self.triggered.insert(key) {
+                log::info!("alert `{}` fired", rule);
+                fired.push(rule);
+            }
+        }
+        // Forget rules that were removed from the config
+        let keys: Vec<String> = rules.iter().map(AlertRule::key).collect();
+        self.triggered.retain(|key| keys.contains(key));
+        fired
+    }
+}
+
+// A missing or corrupt file means no alert has fired yet
+pub fn load_alert_state(path: &Path) -> AlertState {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_alert_state(path: &Path, state: &AlertState) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    }
+    let text = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    std::fs::write(path, text + "\n")
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(id: &str, price: f64) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: id.to_string(),
+            symbol: id[..3].to_uppercase(),
+            current_price: price,
+            ..Default::default()
+        }
+    }
+
+    fn rules() -> Vec<AlertRule> {
+        vec![
+            parse_alert_rule("bitcoin above 70000").unwrap(),
+            parse_alert_rule("cardano below 0.35").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn parse_alert_rule_reads_the_config_format() {
+        let rule = parse_alert_rule(" Bitcoin  ABOVE 70000 ").unwrap();
+        assert_eq!(rule.coin_id, "bitcoin");
+        assert_eq!(rule.direction, Direction::Above);
+        assert_eq!(rule.price, 70000.0);
+        assert!(parse_alert_rule("bitcoin over 70000").is_err());
+        assert!(parse_alert_rule("bitcoin above lots").is_err());
+        assert!(parse_alert_rule("bitcoin above").is_err());
+    }
+
+    #[test]
+    fn crossing_up_fires_once() {
+        let rules = rules();
+        let mut state = AlertState::default();
+        assert!(
+            state
+                .evaluate(&rules, &[coin("bitcoin", 69000.0)])
+                .is_empty()
+        );
+        let fired = state.evaluate(&rules, &[coin("bitcoin", 70500.0)]);
+        assert_eq!(fired, [&rules[0]]);
+        // Still above, so it doesn't fire again
+        assert!(
+            state
+                .evaluate(&rules, &[coin("bitcoin", 71000.0)])
+                .is_empty()
+        );
+        // Dropping back re-arms it
+        assert!(
+            state
+                .evaluate(&rules, &[coin("bitcoin", 69000.0)])
+                .is_empty()
+        );
+        assert_eq!(state.evaluate(&rules, &[coin("bitcoin", 70000.0)]).len(), 1);
+    }
+
+    #[test]
+    fn crossing_down_fires_once() {
+        let rules = rules();
+        let mut state = AlertState::default();
+        assert!(state.evaluate(&rules, &[coin("cardano", 0.40)]).is_empty());
+        assert_eq!(
+            state.evaluate(&rules, &[coin("cardano", 0.30)]),
+            [&rules[1]]
+        );
+        assert!(state.evaluate(&rules, &[coin("cardano", 0.29)]).is_empty());
+        // A refresh without the coin keeps the alert triggered
+        assert!(state.evaluate(&rules, &[]).is_empty());
+        assert!(state.evaluate(&rules, &[coin("cardano", 0.29)]).is_empty());
+    }
+
+    #[test]
+    fn triggered_state_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-alerts", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("alerts.json");
+        let rules = rules();
+        let mut state = load_alert_state(&path);
+        state.evaluate(&rules, &[coin("bitcoin", 75000.0)]);
+        save_alert_state(&path, &state).unwrap();
+        let mut state = load_alert_state(&path);
+        assert!(
+            state
+                .evaluate(&rules, &[coin("bitcoin", 75000.0)])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rules_are_saved_in_the_config_format() {
+        let state = AlertState {
+            rules: Some(rules()),
+            ..AlertState::default()
+        };
+        let text = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            text,
+            r#"{"rules":["bitcoin above 70000","cardano below 0.35"],"triggered":[]}"#
+        );
+        let loaded: AlertState = serde_json::from_str(&text).unwrap();
+        assert_eq!(loaded, state);
+        // Files from before rules could be edited in the app have none
+        let old: AlertState = serde_json::from_str(r#"{"triggered":[]}"#).unwrap();
+        assert!(old.rules.is_none());
+    }
+
+    #[test]
+    fn rearming_lets_a_rule_fire_again() {
+        let rules = rules();
+        let mut state = AlertState::default();
+        state.evaluate(&rules, &[coin("bitcoin", 71000.0)]);
+        assert!(state.is_triggered(&rules[0]));
+        state.rearm(&rules[0]);
+        assert!(!state.is_triggered(&rules[0]));
+        assert_eq!(state.evaluate(&rules, &[coin("bitcoin", 71000.0)]).len(), 1);
+    }
+}
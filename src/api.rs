@@ -0,0 +1,1289 @@
+//! Requests to the CoinGecko API and parsing what comes back
+
+use crate::config::{ApiTier, Config, proxy_host};
+use crate::error::TuickerError;
+use crate::limiter::Priority;
+use crate::logging::redact_url;
+use crate::model::{
+    Candle, ChartRange, Coin, MarketCoin, Quote, TrendingCoin, convert_to_coins, truncate,
+};
+use crate::source::CoinGeckoSource;
+use futures::{StreamExt, stream};
+use reqwest::{
+    Client, NoProxy, Proxy, Response, StatusCode,
+    header::{ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Where requests go unless a test points them at a mock server
+pub const COINGECKO_API_URL: &str = "https://api.coingecko.com/api/v3";
+// Paid plans are served from their own host
+pub const COINGECKO_PRO_API_URL: &str = "https://pro-api.coingecko.com/api/v3";
+
+// Used when a rate limited response doesn't say how long to wait
+pub(crate) const DEFAULT_RATE_LIMIT_DELAY: Duration = Duration::from_secs(60);
+
+// Longest error body shown in the footer
+pub(crate) const API_MESSAGE_LENGTH: usize = 80;
+
+// The error bodies CoinGecko answers with, either {"error": "coin not found"}
+// or {"status": {"error_code": 429, "error_message": "..."}}
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ErrorBody {
+    Status { status: ErrorStatus },
+    Message { error: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorStatus {
+    error_code: Option<u16>,
+    error_message: Option<String>,
+}
+
+impl ErrorBody {
+    // `status` is the HTTP status, used when the body has no error code
+    fn into_error(self, status: StatusCode) -> TuickerError {
+        let (code, message) = match self {
+            ErrorBody::Status { status } => (status.error_code, status.error_message),
+            ErrorBody::Message { error } => (None, Some(error)),
+        };
+        let status = code
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(status);
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return TuickerError::RateLimited(DEFAULT_RATE_LIMIT_DELAY);
+        }
+        TuickerError::Api {
+            status,
+            message: truncate(message.unwrap_or_default().trim(), API_MESSAGE_LENGTH),
+        }
+    }
+}
+
+// Some errors come back with a 200 status, so bodies are checked for the
+// error shapes before they are parsed as the data that was asked for
+fn parse_error_body(json_text: &str) -> Option<ErrorBody> {
+    match serde_json::from_str(json_text).ok()? {
+        ErrorBody::Status {
+            status:
+                ErrorStatus {
+                    error_code: None,
+                    error_message: None,
+                },
+        } => None,
+        body => Some(body),
+    }
+}
+
+// Turns error statuses into errors, keeping the rate limit delay and the
+// message from the body
+pub(crate) async fn check_status(response: Response) -> Result<Response, TuickerError> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(TuickerError::RateLimited(retry_after_delay(
+            response.headers(),
+        )));
+    }
+    if status.is_client_error() || status.is_server_error() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(match parse_error_body(&text) {
+            Some(body) => body.into_error(status),
+            None => TuickerError::Api {
+                status,
+                message: truncate(text.trim(), API_MESSAGE_LENGTH),
+            },
+        });
+    }
+    Ok(response)
+}
+
+// Every request to the API goes through here, so each one and its status
+// end up in the log
+pub(crate) async fn get(client: &Client, url: &str) -> Result<Response, TuickerError> {
+    get_with(client, url, HeaderMap::new()).await
+}
+
+async fn get_with(
+    client: &Client,
+    url: &str,
+    headers: HeaderMap,
+) -> Result<Response, TuickerError> {
+    let logged = redact_url(url);
+    log::debug!("GET {}", logged);
+    let response = client
+        .get(url)
+        .headers(headers)
+        .send()
+        .await
+        // The URL is logged above already
+        .map_err(|e| TuickerError::from(e.without_url()))
+        .inspect_err(|e| log::warn!("GET {} failed: {}", logged, e.detail()))?;
+    log::debug!("{} from {}", response.status(), logged);
+    check_status(response).await
+}
+
+// Retry-After is either a number of seconds or an HTTP date. The API only
+// sends seconds, anything else falls back to the default
+pub(crate) fn retry_after_delay(headers: &HeaderMap) -> Duration {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_RATE_LIMIT_DELAY)
+}
+
+pub fn api_base_url(config: &Config) -> &'static str {
+    match (&config.api_key, config.api_tier) {
+        (Some(_), ApiTier::Pro) => COINGECKO_PRO_API_URL,
+        _ => COINGECKO_API_URL,
+    }
+}
+
+// The proxy of the config or the environment, which main already put in
+// `config.proxy`. reqwest's own look at the environment is turned off, it
+// would skip a mistyped proxy instead of failing. $NO_PROXY still counts
+pub(crate) fn proxied(
+    builder: reqwest::ClientBuilder,
+    config: &Config,
+) -> Result<reqwest::ClientBuilder, TuickerError> {
+    let builder = builder.no_proxy();
+    let Some(url) = &config.proxy else {
+        return Ok(builder);
+    };
+    let proxy = Proxy::all(url)
+        .map_err(|e| TuickerError::Config(format!("invalid proxy {}: {}", proxy_host(url), e)))?;
+    Ok(builder.proxy(proxy.no_proxy(NoProxy::from_env())))
+}
+
+// Settings every client shares: the user agent, the timeouts and the proxy
+pub(crate) fn client_builder(config: &Config) -> Result<reqwest::ClientBuilder, TuickerError> {
+    let request_timeout = config.request_timeout;
+    let builder = Client::builder()
+        .user_agent(concat!("tuicker/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(config.connect_timeout.min(request_timeout))
+        .timeout(request_timeout);
+    proxied(builder, config)
+}
+
+// One client for the whole session so connections and TLS sessions are reused.
+// The API key goes into every request as a default header
+pub fn build_client(config: &Config) -> Result<Client, TuickerError> {
+    let mut headers = HeaderMap::new();
+    if let Some(api_key) = &config.api_key {
+        let header = match config.api_tier {
+            ApiTier::Demo => "x-cg-demo-api-key",
+            ApiTier::Pro => "x-cg-pro-api-key",
+        };
+        // The error would describe the value, so don't pass it on
+        let mut value = HeaderValue::from_str(api_key.expose()).map_err(|_| {
+            TuickerError::Config("the API key contains characters that aren't allowed".to_string())
+        })?;
+        value.set_sensitive(true);
+        headers.insert(header, value);
+    }
+    Ok(client_builder(config)?.default_headers(headers).build()?)
+}
+
+// Shared by every webhook call and the Fear & Greed index. Kept apart from
+// the API client so the API key never leaves for a host that isn't CoinGecko
+pub fn build_webhook_client(config: &Config) -> Result<Client, TuickerError> {
+    Ok(client_builder(config)?.build()?)
+}
+
+fn markets_url(base_url: &str, coin_ids: &[String], currency: &str) -> String {
+    let api_url: String = format!("{}/coins/markets?", base_url);
+    let vs_currency: String = format!("vs_currency={}&", currency);
+    let coin_ids: String = format!("ids={}&", coin_ids.join(","));
+    // The 24h change comes anyway, the others only when asked for
+    let changes = "price_change_percentage=1h,24h,7d,30d";
+    format!("{}{}{}{}", api_url, vs_currency, coin_ids, changes)
+}
+
+pub async fn fetch_coin_prices(
+    client: &Client,
+    base_url: &str,
+    coin_ids: &[String],
+    currency: &str,
+) -> Result<String, TuickerError> {
+    // 1. Define the API URL
+    let url = markets_url(base_url, coin_ids, currency);
+    // 2. Make HTTP GET request. An error body isn't the coin list, don't
+    // try to parse it
+    let response: Response = get(client, &url).await?;
+
+    // 3. Get response text
+    let response_text = response.text().await?;
+
+    Ok(response_text)
+}
+
+// ETag and Last-Modified of an answer, sent back as If-None-Match and
+// If-Modified-Since to get an empty 304 while nothing changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Validators {
+    pub etag: Option<HeaderValue>,
+    pub last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Validators {
+            etag: headers.get(ETAG).cloned(),
+            last_modified: headers.get(LAST_MODIFIED).cloned(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    fn request_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = &self.etag {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        headers
+    }
+}
+
+// Watchlist edits make new URLs, the answers of old ones are let go of
+// once there are this many
+const MAX_ETAG_ENTRIES: usize = 64;
+
+// The validators of the last answer of every markets URL, with the coins
+// parsed out of it that a 304 says still hold
+#[derive(Debug, Default)]
+pub struct EtagCache {
+    entries: Mutex<HashMap<String, (Validators, Vec<Coin>)>>,
+}
+
+impl EtagCache {
+    pub fn get(&self, url: &str) -> Option<(Validators, Vec<Coin>)> {
+        self.entries.lock().ok()?.get(url).cloned()
+    }
+
+    // An answer without validators can't be asked about, so it isn't kept
+    pub fn store(&self, url: String, validators: Validators, coins: Vec<Coin>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if validators.is_empty() {
+            entries.remove(&url);
+            return;
+        }
+        if entries.len() >= MAX_ETAG_ENTRIES && !entries.contains_key(&url) {
+            entries.clear();
+        }
+        entries.insert(url, (validators, coins));
+    }
+}
+
+// A markets answer and its validators
+struct Answer {
+    json_text: String,
+    validators: Validators,
+}
+
+// None when the server says the answer to `validators` still holds
+async fn fetch_markets(
+    client: &Client,
+    url: &str,
+    validators: Option<&Validators>,
+) -> Result<Option<Answer>, TuickerError> {
+    let headers = validators.map_or_else(HeaderMap::new, Validators::request_headers);
+    let response = get_with(client, url, headers).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let validators = Validators::from_headers(response.headers());
+    Ok(Some(Answer {
+        json_text: response.text().await?,
+        validators,
+    }))
+}
+
+// A refresh is tried this many times in total before giving up
+pub const MAX_FETCH_ATTEMPTS: u32 = 4;
+// Doubled after every failed attempt: 1s, 2s, 4s
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+// Random-ish duration up to `max`, so that many clients failing at the same
+// moment don't all retry in lockstep
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or(0);
+    // xorshift to spread the clock bits over the whole range
+    let mut x = nanos ^ 0x9e37_79b9;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    max.mul_f64(f64::from(x) / f64::from(u32::MAX))
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+    backoff + jitter(backoff / 2)
+}
+
+// Runs `request` until it succeeds, fails with an error that isn't worth
+// retrying, or MAX_FETCH_ATTEMPTS is reached. `on_retry` is told the number
+// of the attempt about to start
+pub async fn fetch_with_retry<T, F, Fut>(
+    mut request: F,
+    mut on_retry: impl FnMut(u32),
+) -> Result<T, TuickerError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, TuickerError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt < MAX_FETCH_ATTEMPTS && error.is_retryable() => {
+                let delay = retry_delay(attempt);
+                log::info!(
+                    "attempt {}/{} failed: {}, retrying in {:.1}s",
+                    attempt,
+                    MAX_FETCH_ATTEMPTS,
+                    error,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                on_retry(attempt);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+pub fn parse_coin_response(json_text: &str) -> Result<Vec<MarketCoin>, TuickerError> {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    let parsed: Vec<MarketCoin> = serde_json::from_str(json_text)?;
+    Ok(parsed)
+}
+
+// CoinGecko takes up to 250 ids in one coins/markets request
+pub const MAX_IDS_PER_REQUEST: usize = 250;
+// Batches fetched at the same time, more would use up the rate limit
+const CONCURRENT_BATCHES: usize = 2;
+
+// The coins of every batch that came through. `failure` is set when some,
+// but not all, of them failed
+#[derive(Debug, Default)]
+pub struct Refresh {
+    pub coins: Vec<Coin>,
+    pub failure: Option<PartialFailure>,
+}
+
+#[derive(Debug)]
+pub struct PartialFailure {
+    pub fetched: usize,
+    pub requested: usize,
+    pub error: TuickerError,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fetched {}/{} coins: {}",
+            self.fetched, self.requested, self.error
+        )
+    }
+}
+
+pub type RefreshResult = Result<Refresh, TuickerError>;
+
+// Splits the watchlist into requests of at most `size` ids, each id once
+pub fn chunk_ids(coin_ids: &[String], size: usize) -> Vec<Vec<String>> {
+    let mut seen = HashSet::new();
+    let unique: Vec<String> = coin_ids
+        .iter()
+        .filter(|id| seen.insert(id.as_str()))
+        .cloned()
+        .collect();
+    unique.chunks(size.max(1)).map(<[String]>::to_vec).collect()
+}
+
+// Joins the batches in order, a coin in two of them is kept once. Only
+// fails when every batch did
+pub fn merge_batches(
+    batches: Vec<Result<Vec<Coin>, TuickerError>>,
+    requested: usize,
+) -> RefreshResult {
+    let mut coins: Vec<Coin> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut error = None;
+    let mut any_fetched = false;
+    for batch in batches {
+        match batch {
+            Ok(batch) => {
+                any_fetched = true;
+                for coin in batch {
+                    if seen.insert(coin.id.clone()) {
+                        coins.push(coin);
+                    }
+                }
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    let failure = match error {
+        None => None,
+        Some(error) if !any_fetched => return Err(error),
+        Some(error) => Some(PartialFailure {
+            fetched: coins.len(),
+            requested,
+            error,
+        }),
+    };
+    Ok(Refresh { coins, failure })
+}
+
+// (timestamp in milliseconds, price) pairs, oldest first
+pub type ChartResult = Result<Vec<(f64, f64)>, TuickerError>;
+
+// The coins/{id}/market_chart response, only the prices are used
+#[derive(Debug, Deserialize)]
+struct MarketChart {
+    prices: Vec<(f64, f64)>,
+}
+
+pub async fn fetch_market_chart(
+    client: &Client,
+    base_url: &str,
+    coin_id: &str,
+    currency: &str,
+    range: ChartRange,
+) -> ChartResult {
+    let url = format!(
+        "{}/coins/{}/market_chart?vs_currency={}&days={}",
+        base_url,
+        coin_id,
+        currency,
+        range.days()
+    );
+    let response: Response = get(client, &url).await?;
+    let response_text = response.text().await?;
+    parse_market_chart(&response_text)
+}
+
+pub fn parse_market_chart(json_text: &str) -> Result<Vec<(f64, f64)>, TuickerError> {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    let parsed: MarketChart = serde_json::from_str(json_text)?;
+    Ok(parsed.prices)
+}
+
+// Oldest first. CoinGecko picks the candle length from the days asked for,
+// 30 minutes for a day, 4 hours up to 30 days and 4 days beyond
+pub type OhlcResult = Result<Vec<Candle>, TuickerError>;
+
+pub async fn fetch_ohlc(
+    client: &Client,
+    base_url: &str,
+    coin_id: &str,
+    currency: &str,
+    range: ChartRange,
+) -> OhlcResult {
+    let url = format!(
+        "{}/coins/{}/ohlc?vs_currency={}&days={}",
+        base_url,
+        coin_id,
+        currency,
+        range.days()
+    );
+    let response: Response = get(client, &url).await?;
+    let response_text = response.text().await?;
+    parse_ohlc(&response_text)
+}
+
+pub fn parse_ohlc(json_text: &str) -> OhlcResult {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    Ok(serde_json::from_str(json_text)?)
+}
+
+pub type GlobalResult = Result<GlobalStats, TuickerError>;
+
+// The figures of the /global response shown in the header
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GlobalStats {
+    // By currency code
+    pub total_market_cap: BTreeMap<String, f64>,
+    // Percent of the total market cap by coin symbol, "btc" is the dominance
+    pub market_cap_percentage: BTreeMap<String, f64>,
+    // Only given for usd, the change is about the same in every currency
+    #[serde(rename = "market_cap_change_percentage_24h_usd")]
+    pub market_cap_change_24h: f64,
+}
+
+impl GlobalStats {
+    pub fn btc_dominance(&self) -> Option<f64> {
+        self.market_cap_percentage.get("btc").copied()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Global {
+    data: GlobalStats,
+}
+
+pub async fn fetch_global(client: &Client, base_url: &str) -> GlobalResult {
+    let url = format!("{}/global", base_url);
+    let response: Response = get(client, &url).await?;
+    let response_text = response.text().await?;
+    parse_global(&response_text)
+}
+
+pub fn parse_global(json_text: &str) -> GlobalResult {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    let parsed: Global = serde_json::from_str(json_text)?;
+    Ok(parsed.data)
+}
+
+// How many trending coins are shown, the API returns more
+pub const TRENDING_COUNT: usize = 7;
+
+pub type TrendingResult = Result<Vec<TrendingCoin>, TuickerError>;
+
+// The search/trending response. NFTs and categories are left out
+#[derive(Debug, Deserialize)]
+struct Trending {
+    coins: Vec<TrendingEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrendingEntry {
+    item: TrendingCoin,
+}
+
+pub async fn fetch_trending(client: &Client, base_url: &str) -> TrendingResult {
+    let url = format!("{}/search/trending", base_url);
+    let response: Response = get(client, &url).await?;
+    let response_text = response.text().await?;
+    parse_trending(&response_text)
+}
+
+// The top TRENDING_COUNT coins, most trending first
+pub fn parse_trending(json_text: &str) -> TrendingResult {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    let parsed: Trending = serde_json::from_str(json_text)?;
+    let mut coins: Vec<TrendingCoin> = parsed.coins.into_iter().map(|entry| entry.item).collect();
+    coins.sort_by_key(|coin| coin.score);
+    coins.truncate(TRENDING_COUNT);
+    for coin in &mut coins {
+        coin.symbol = coin.symbol.to_uppercase();
+    }
+    Ok(coins)
+}
+
+// simple/price takes several currencies at once, unlike coins/markets
+pub async fn fetch_simple_prices(
+    client: &Client,
+    base_url: &str,
+    coin_ids: &[String],
+    currencies: &[String],
+) -> Result<String, TuickerError> {
+    let url = format!(
+        "{}/simple/price?ids={}&vs_currencies={}&include_24hr_change=true",
+        base_url,
+        coin_ids.join(","),
+        currencies.join(",")
+    );
+    let response: Response = get(client, &url).await?;
+    Ok(response.text().await?)
+}
+
+// The simple/price response, keyed by coin id and then by the currency
+// codes and `<currency>_24h_change`:
+// {"bitcoin": {"eur": 61000.0, "eur_24h_change": -0.5}}
+pub fn parse_simple_prices(
+    json_text: &str,
+    currencies: &[String],
+) -> Result<HashMap<String, BTreeMap<String, Quote>>, TuickerError> {
+    if let Some(body) = parse_error_body(json_text) {
+        return Err(body.into_error(StatusCode::OK));
+    }
+    let parsed: HashMap<String, HashMap<String, Option<f64>>> = serde_json::from_str(json_text)?;
+    Ok(parsed
+        .into_iter()
+        .map(|(coin_id, values)| {
+            let value = |key: &str| values.get(key).copied().flatten();
+            let quotes = currencies
+                .iter()
+                .filter_map(|currency| {
+                    // Like coins/markets, no price means nothing to show
+                    let price = value(currency)?;
+                    let change_24h = value(&format!("{}_24h_change", currency)).unwrap_or(0.0);
+                    Some((currency.clone(), Quote { price, change_24h }))
+                })
+                .collect();
+            (coin_id, quotes)
+        })
+        .collect())
+}
+
+// What the markets request of a refresh sent back, for the debug view
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchDebugInfo {
+    // With any key redacted
+    pub url: String,
+    // None when no response came at all
+    pub status: Option<StatusCode>,
+    // Including the retries
+    pub elapsed: Duration,
+    // Pretty-printed when it is JSON
+    pub body: String,
+    // "parsed 3 coins" or what went wrong
+    pub outcome: String,
+}
+
+// The JSON indented for reading, anything else as it came
+pub fn pretty_json(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| text.to_string())
+}
+
+type OnResponse<'a> = Option<&'a (dyn Fn(FetchDebugInfo) + Sync)>;
+
+// The coins in `currency`, with their prices in `extra_currencies` as
+// quotes. Long watchlists are fetched in batches of MAX_IDS_PER_REQUEST.
+// `on_response` gets the raw markets response of every batch
+pub async fn refresh_output(
+    api: &CoinGeckoSource,
+    coin_ids: &[String],
+    currency: &str,
+    extra_currencies: &[String],
+    on_retry: impl FnMut(u32),
+    on_response: OnResponse<'_>,
+) -> RefreshResult {
+    let batches = chunk_ids(coin_ids, MAX_IDS_PER_REQUEST);
+    let requested = batches.iter().map(Vec::len).sum();
+    // Shared by the batches in flight, a Mutex keeps the future Send
+    let on_retry = Mutex::new(on_retry);
+    // Futures do nothing until polled, so only CONCURRENT_BATCHES run at once
+    let requests: Vec<_> = batches
+        .iter()
+        .map(|batch| {
+            refresh_batch(
+                api,
+                batch,
+                currency,
+                extra_currencies,
+                |attempt| {
+                    if let Ok(mut on_retry) = on_retry.lock() {
+                        on_retry(attempt)
+                    }
+                },
+                on_response,
+            )
+        })
+        .collect();
+    let results = stream::iter(requests)
+        .buffered(CONCURRENT_BATCHES)
+        .collect()
+        .await;
+    merge_batches(results, requested)
+}
+
+fn debug_info(
+    url: String,
+    elapsed: Duration,
+    fetched: &Result<Option<Answer>, TuickerError>,
+    parsed: &Result<Vec<MarketCoin>, TuickerError>,
+) -> FetchDebugInfo {
+    // check_status only lets success through, which the API sends as 200
+    // or as 304 to a conditional request
+    let (status, body) = match fetched {
+        Ok(Some(answer)) => (Some(StatusCode::OK), pretty_json(&answer.json_text)),
+        Ok(None) => (Some(StatusCode::NOT_MODIFIED), String::new()),
+        Err(TuickerError::Api { status, message }) => (Some(*status), message.clone()),
+        Err(TuickerError::RateLimited(_)) => (Some(StatusCode::TOO_MANY_REQUESTS), String::new()),
+        Err(_) => (None, String::new()),
+    };
+    let outcome = match (fetched, parsed) {
+        (Err(e), _) | (Ok(_), Err(e)) => e.detail(),
+        (Ok(None), Ok(_)) => "not modified, the coins of the last answer are kept".to_string(),
+        (Ok(Some(_)), Ok(coins)) => format!("parsed {} coins", coins.len()),
+    };
+    FetchDebugInfo {
+        url,
+        status,
+        elapsed,
+        body,
+        outcome,
+    }
+}
+
+// The quotes come from a second request, only made when there are any.
+// The markets are asked for with the validators of their last answer, a
+// 304 keeps its coins without anything to parse
+async fn refresh_batch(
+    api: &CoinGeckoSource,
+    coin_ids: &[String],
+    currency: &str,
+    extra_currencies: &[String],
+    mut on_retry: impl FnMut(u32),
+    on_response: OnResponse<'_>,
+) -> Result<Vec<Coin>, TuickerError> {
+    let (client, base_url) = (&api.client, api.api_url.as_str());
+    let url = markets_url(base_url, coin_ids, currency);
+    let cached = api.etags.get(&url);
+    let validators = cached.as_ref().map(|(validators, _)| validators);
+    let (limiter, markets_url) = (&api.limiter, &url);
+    let started = Instant::now();
+    // Every attempt is a request of its own to the limit
+    let fetched = fetch_with_retry(
+        || async move {
+            limiter.acquire(Priority::Queued).await?;
+            fetch_markets(client, markets_url, validators).await
+        },
+        &mut on_retry,
+    )
+    .await;
+    let parsed = match &fetched {
+        Ok(Some(answer)) => parse_coin_response(&answer.json_text),
+        // Never looked at, it's the cached coins or the fetch error
+        Ok(None) | Err(_) => Ok(Vec::new()),
+    };
+    if let Some(on_response) = on_response {
+        on_response(debug_info(
+            redact_url(&url),
+            started.elapsed(),
+            &fetched,
+            &parsed,
+        ));
+    }
+    let mut coins = match (fetched?, cached) {
+        (Some(answer), _) => {
+            let coins = convert_to_coins(parsed?);
+            api.etags.store(url, answer.validators, coins.clone());
+            coins
+        }
+        (None, Some((_, coins))) => {
+            log::debug!("markets not modified, keeping {} coins", coins.len());
+            coins
+        }
+        // Validators are only sent along with coins to fall back on
+        (None, None) => {
+            return Err(TuickerError::Api {
+                status: StatusCode::NOT_MODIFIED,
+                message: "not modified, but nothing was asked about".to_string(),
+            });
+        }
+    };
+    if extra_currencies.is_empty() || coins.is_empty() {
+        return Ok(coins);
+    }
+    let json_text = fetch_with_retry(
+        || async move {
+            limiter.acquire(Priority::Queued).await?;
+            fetch_simple_prices(client, base_url, coin_ids, extra_currencies).await
+        },
+        &mut on_retry,
+    )
+    .await?;
+    let mut quotes = parse_simple_prices(&json_text, extra_currencies)?;
+    for coin in &mut coins {
+        coin.quotes = quotes.remove(&coin.id).unwrap_or_default();
+    }
+    Ok(coins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKey;
+    use crate::model::{NumberLocale, SortDir, SortKey, format_coins, sort_coins};
+
+    #[test]
+    fn pro_tier_uses_the_pro_host_only_with_a_key() {
+        let mut config = Config {
+            api_tier: ApiTier::Pro,
+            ..Config::default()
+        };
+        assert_eq!(api_base_url(&config), COINGECKO_API_URL);
+        config.api_key = ApiKey::new("CG-secret");
+        assert_eq!(api_base_url(&config), COINGECKO_PRO_API_URL);
+    }
+
+    #[test]
+    fn answers_without_validators_are_not_cached() {
+        let cache = EtagCache::default();
+        let validators = Validators {
+            etag: Some(HeaderValue::from_static("\"abc\"")),
+            last_modified: None,
+        };
+        cache.store("a".to_string(), validators.clone(), Vec::new());
+        assert_eq!(cache.get("a").unwrap().0, validators);
+        assert_eq!(
+            validators.request_headers().get(IF_NONE_MATCH).unwrap(),
+            "\"abc\""
+        );
+        // The URL stopped sending one, the old answer mustn't be asked about
+        cache.store("a".to_string(), Validators::default(), Vec::new());
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn api_key_is_not_in_client_errors() {
+        let config = Config {
+            api_key: ApiKey::new("CG-\nsecret"),
+            ..Config::default()
+        };
+        let error = build_client(&config).unwrap_err();
+        assert!(!error.to_string().contains("secret"));
+    }
+    use std::cell::Cell;
+
+    const MARKETS_JSON: &str = r#"[
+        {
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "current_price": 65123.45,
+            "market_cap": 1280000000000,
+            "market_cap_rank": 1,
+            "price_change_percentage_24h": -1.52,
+            "total_volume": 32000000000
+        },
+        {
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "current_price": 3150.2,
+            "market_cap": null,
+            "market_cap_rank": null,
+            "price_change_percentage_24h": null
+        }
+    ]"#;
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut builder = http::Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        Response::from(builder.body(body.to_string()).unwrap())
+    }
+
+    fn api_error(status: u16) -> TuickerError {
+        TuickerError::Api {
+            status: StatusCode::from_u16(status).unwrap(),
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn parse_coin_response_reads_markets() {
+        let coins = parse_coin_response(MARKETS_JSON).unwrap();
+        assert_eq!(coins.len(), 2);
+        assert_eq!(coins[0].id, "bitcoin");
+        assert_eq!(coins[0].current_price, Some(65123.45));
+        assert_eq!(coins[0].price_change_percentage_24h, Some(-1.52));
+        assert_eq!(coins[0].market_cap_rank, Some(1));
+        assert_eq!(coins[1].price_change_percentage_24h, None);
+    }
+
+    fn coin(id: &str) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: id.to_string(),
+            symbol: id.to_uppercase(),
+            current_price: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn coin_ids(coins: &[Coin]) -> Vec<&str> {
+        coins.iter().map(|coin| coin.id.as_str()).collect()
+    }
+
+    #[test]
+    fn chunk_ids_splits_under_the_limit_without_repeats() {
+        let ids: Vec<String> = (0..7).map(|i| format!("coin-{}", i % 6)).collect();
+        let chunks = chunk_ids(&ids, 4);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0], ["coin-0", "coin-1", "coin-2", "coin-3"]);
+        assert_eq!(chunks[1], ["coin-4", "coin-5"]);
+        assert!(chunk_ids(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn merge_batches_keeps_a_coin_in_two_batches_once() {
+        let batches = vec![
+            Ok(vec![coin("bitcoin"), coin("ethereum")]),
+            Ok(vec![coin("ethereum"), coin("solana")]),
+        ];
+        let refresh = merge_batches(batches, 4).unwrap();
+        assert_eq!(coin_ids(&refresh.coins), ["bitcoin", "ethereum", "solana"]);
+        assert!(refresh.failure.is_none());
+    }
+
+    #[test]
+    fn merge_batches_reports_partial_failures() {
+        let batches = vec![
+            Ok(vec![coin("bitcoin"), coin("ethereum")]),
+            Err(api_error(503)),
+        ];
+        let refresh = merge_batches(batches, 4).unwrap();
+        assert_eq!(coin_ids(&refresh.coins), ["bitcoin", "ethereum"]);
+        let failure = refresh.failure.unwrap();
+        assert_eq!((failure.fetched, failure.requested), (2, 4));
+        assert!(failure.to_string().starts_with("fetched 2/4 coins: "));
+
+        let batches = vec![Err(api_error(503)), Err(api_error(500))];
+        let error = merge_batches(batches, 4).unwrap_err();
+        assert!(matches!(error, TuickerError::Api { status, .. } if status == 503));
+    }
+
+    #[test]
+    fn parse_coin_response_rejects_other_shapes() {
+        assert!(parse_coin_response(r#"{"bitcoin": {"usd": 1.0}}"#).is_err());
+        assert!(parse_coin_response("not json").is_err());
+        assert!(parse_coin_response("[]").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_simple_prices_splits_the_currency_keys() {
+        let currencies: Vec<String> = ["usd", "eur", "gbp", "jpy"]
+            .iter()
+            .map(|currency| currency.to_string())
+            .collect();
+        let json = r#"{
+            "bitcoin": {
+                "usd": 67187.34, "usd_24h_change": 2.1,
+                "eur": 61000.5, "eur_24h_change": -0.5, "gbp": 52000.0,
+                "jpy": 10451234, "jpy_24h_change": 2.35
+            },
+            "cardano": {"eur": null, "gbp": 0.3, "gbp_24h_change": 1.25}
+        }"#;
+        let quotes = parse_simple_prices(json, &currencies).unwrap();
+        let bitcoin = &quotes["bitcoin"];
+        assert_eq!(
+            bitcoin["eur"],
+            Quote {
+                price: 61000.5,
+                change_24h: -0.5
+            }
+        );
+        assert_eq!(bitcoin["usd"].price, 67187.34);
+        // Whole yen come without a decimal point
+        assert_eq!(
+            bitcoin["jpy"],
+            Quote {
+                price: 10451234.0,
+                change_24h: 2.35
+            }
+        );
+        // No change in the response counts as unchanged
+        assert_eq!(bitcoin["gbp"].change_24h, 0.0);
+        let cardano = &quotes["cardano"];
+        assert!(!cardano.contains_key("eur"));
+        assert!(!cardano.contains_key("usd"));
+        assert_eq!(cardano["gbp"].change_24h, 1.25);
+        assert!(parse_simple_prices(r#"{"error": "invalid vs_currency"}"#, &currencies).is_err());
+    }
+
+    #[test]
+    fn parse_market_chart_reads_prices() {
+        let json =
+            r#"{"prices": [[1700000000000, 1.5], [1700003600000, 2.0]], "total_volumes": []}"#;
+        assert_eq!(
+            parse_market_chart(json).unwrap(),
+            [(1700000000000.0, 1.5), (1700003600000.0, 2.0)]
+        );
+    }
+
+    #[test]
+    fn parse_ohlc_reads_the_candles() {
+        let json = r#"[[1700000000000, 1.5, 2.5, 1.0, 2.0], [1700014400000, 2.0, 2.1, 1.2, 1.4]]"#;
+        let candles = parse_ohlc(json).unwrap();
+        assert_eq!(
+            candles[0],
+            Candle {
+                time: 1700000000000.0,
+                open: 1.5,
+                high: 2.5,
+                low: 1.0,
+                close: 2.0,
+            }
+        );
+        assert!(candles[0].is_up() && !candles[1].is_up());
+        assert!(parse_ohlc("[]").unwrap().is_empty());
+        // Short arrays and error bodies are not candles
+        assert!(matches!(
+            parse_ohlc("[[1700000000000, 1.5, 2.5]]"),
+            Err(TuickerError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_ohlc(r#"{"error": "coin not found"}"#),
+            Err(TuickerError::Api { .. })
+        ));
+    }
+
+    fn api_message(result: Result<Vec<MarketCoin>, TuickerError>) -> (StatusCode, String) {
+        match result {
+            Err(TuickerError::Api { status, message }) => (status, message),
+            other => panic!("expected an API error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn error_shapes_are_checked_before_the_data() {
+        assert_eq!(
+            api_message(parse_coin_response(r#"{"error": "coin not found"}"#)),
+            (StatusCode::OK, "coin not found".to_string())
+        );
+        assert_eq!(
+            api_message(parse_coin_response(
+                r#"{"status": {"error_code": 503, "error_message": "under maintenance"}}"#
+            )),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "under maintenance".to_string()
+            )
+        );
+        assert!(matches!(
+            parse_coin_response(r#"{"status": {"error_code": 429, "error_message": "slow down"}}"#),
+            Err(TuickerError::RateLimited(_))
+        ));
+        assert!(matches!(
+            parse_market_chart(r#"{"error": "coin not found"}"#),
+            Err(TuickerError::Api { .. })
+        ));
+    }
+
+    #[test]
+    fn other_objects_are_parse_errors() {
+        assert!(matches!(
+            parse_coin_response(r#"{"status": {}}"#),
+            Err(TuickerError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_coin_response("{}"),
+            Err(TuickerError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn retry_after_delay_falls_back_to_the_default() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after_delay(&headers), DEFAULT_RATE_LIMIT_DELAY);
+        headers.insert(reqwest::header::RETRY_AFTER, "42".parse().unwrap());
+        assert_eq!(retry_after_delay(&headers), Duration::from_secs(42));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after_delay(&headers), DEFAULT_RATE_LIMIT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn check_status_reads_retry_after_on_429() {
+        let result = check_status(response(429, &[("retry-after", "42")], "")).await;
+        assert!(matches!(
+            result,
+            Err(TuickerError::RateLimited(delay)) if delay == Duration::from_secs(42)
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_status_falls_back_to_the_raw_body() {
+        let body = format!("  Bad Gateway {}\n", "x".repeat(200));
+        match check_status(response(502, &[], &body)).await {
+            Err(TuickerError::Api { status, message }) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert!(message.starts_with("Bad Gateway x"));
+                assert_eq!(message.chars().count(), API_MESSAGE_LENGTH);
+            }
+            other => panic!("expected an API error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_status_keeps_the_error_message() {
+        let result = check_status(response(404, &[], r#"{"error": "coin not found"}"#)).await;
+        match result {
+            Err(TuickerError::Api { status, message }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(message, "coin not found");
+            }
+            other => panic!("expected an API error, got {:?}", other.map(|_| ())),
+        }
+        assert!(check_status(response(200, &[], "[]")).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetch_with_retry_retries_server_errors() {
+        let calls = Cell::new(0);
+        let mut retries = Vec::new();
+        let result = fetch_with_retry(
+            || {
+                calls.set(calls.get() + 1);
+                let attempt = calls.get();
+                async move {
+                    if attempt < 3 {
+                        Err(api_error(503))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            |attempt| retries.push(attempt),
+        )
+        .await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(retries, [2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetch_with_retry_gives_up_after_the_last_attempt() {
+        let calls = Cell::new(0);
+        let result: Result<(), _> = fetch_with_retry(
+            || {
+                calls.set(calls.get() + 1);
+                async { Err(api_error(500)) }
+            },
+            |_| {},
+        )
+        .await;
+        assert!(matches!(result, Err(TuickerError::Api { .. })));
+        assert_eq!(calls.get(), MAX_FETCH_ATTEMPTS);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fetch_with_retry_does_not_retry_client_errors() {
+        let calls = Cell::new(0);
+        let result: Result<(), _> = fetch_with_retry(
+            || {
+                calls.set(calls.get() + 1);
+                async { Err(api_error(404)) }
+            },
+            |_| {},
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn retry_delay_doubles_with_jitter() {
+        for attempt in 1..MAX_FETCH_ATTEMPTS {
+            let backoff = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+            let delay = retry_delay(attempt);
+            assert!(
+                delay >= backoff && delay <= backoff + backoff / 2,
+                "{:?}",
+                delay
+            );
+        }
+    }
+
+    fn ids(coins: &[Coin]) -> Vec<&str> {
+        coins.iter().map(|coin| coin.id.as_str()).collect()
+    }
+
+    fn parse(json: &str) -> Vec<Coin> {
+        let coin_ids: Vec<String> = ["bitcoin", "ethereum", "cardano"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let mut coins = convert_to_coins(parse_coin_response(json).unwrap());
+        sort_coins(
+            &mut coins,
+            SortKey::Watchlist,
+            SortDir::Ascending,
+            &coin_ids,
+        );
+        coins
+    }
+
+    #[test]
+    fn two_parses_of_the_same_response_give_the_same_order() {
+        let json = r#"[
+            {"id": "dogecoin", "symbol": "doge", "name": "Dogecoin", "current_price": 0.12},
+            {"id": "cardano", "symbol": "ada", "name": "Cardano", "current_price": 0.35},
+            {"id": "avalanche-2", "symbol": "avax", "name": "Avalanche", "current_price": 27.4},
+            {"id": "bitcoin", "symbol": "btc", "name": "Bitcoin", "current_price": 67187.34},
+            {"id": "ethereum", "symbol": "eth", "name": "Ethereum", "current_price": 3512.8}
+        ]"#;
+        let first = parse(json);
+        // Coins that weren't asked for go last, by id
+        assert_eq!(
+            ids(&first),
+            ["bitcoin", "ethereum", "cardano", "avalanche-2", "dogecoin"]
+        );
+        assert_eq!(ids(&parse(json)), ids(&first));
+    }
+
+    // A coins/markets response as CoinGecko sends it, with all its fields
+    const FULL_MARKETS_JSON: &str = r#"[
+        {
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://coin-images.coingecko.com/coins/images/1/large/bitcoin.png",
+            "current_price": 67187.34,
+            "market_cap": 1326542808329,
+            "market_cap_rank": 1,
+            "total_volume": 28751026092,
+            "high_24h": 69012.55,
+            "low_24h": 66412.9,
+            "price_change_24h": -1610.02,
+            "price_change_percentage_24h": -2.34,
+            "last_updated": "2024-10-14T09:58:12.481Z"
+        },
+        {
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "image": "https://coin-images.coingecko.com/coins/images/279/large/ethereum.png",
+            "current_price": 3512.8,
+            "market_cap": 422580187517,
+            "market_cap_rank": 2,
+            "total_volume": 15877213922,
+            "high_24h": 3530.11,
+            "low_24h": 3421.07,
+            "price_change_24h": 59.43,
+            "price_change_percentage_24h": 1.72159,
+            "last_updated": "2024-10-14T09:58:09.212Z"
+        }
+    ]"#;
+
+    #[test]
+    fn changes_are_already_percentages() {
+        let coins = convert_to_coins(parse_coin_response(FULL_MARKETS_JSON).unwrap());
+        assert_eq!(coins[0].change_24h_formatted(), "-2.34%");
+        assert!(!coins[0].is_up() && coins[0].is_down());
+        assert_eq!(coins[1].change_24h_formatted(), "+1.72%");
+        assert!(coins[1].is_up());
+        let text = format_coins(&coins, "usd", false, NumberLocale::En);
+        assert!(text.lines().nth(1).unwrap().ends_with("  -2.34%"));
+    }
+}
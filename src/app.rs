@@ -0,0 +1,2861 @@
+//! App state and how it reacts to input and fetch results
+
+use crate::alerts::{AlertRule, AlertState, Direction, load_alert_state, save_alert_state};
+use crate::api::{
+    ChartResult, FetchDebugInfo, GlobalResult, GlobalStats, OhlcResult, Refresh, RefreshResult,
+    TrendingResult,
+};
+use crate::cache::{CachedCoins, load_cache, save_cache};
+use crate::config::{Config, clamp_refresh_interval, proxy_host};
+use crate::error::TuickerError;
+use crate::export::export_csv;
+use crate::fear_greed::{FEAR_GREED_URL, FearGreedResult, fetch_fear_greed};
+use crate::heatmap;
+use crate::history::{HistoryStore, Sample, millis};
+use crate::keymap::{Action, Key, Scope};
+use crate::metrics::SharedMetrics;
+use crate::model::{
+    BTC, Candle, ChangeHorizon, ChartRange, Coin, Extreme, PriceHistory, Quote, SortDir, SortKey,
+    TrendingCoin, format_btc_amount, format_price, parse_coin_ids, sort_coins,
+};
+use crate::portfolio::Portfolio;
+use crate::sinks::AlertSinks;
+use crate::source::{DataSource, RefreshHooks};
+use crate::state::{RowLayout, Tab, UiState, save_ui_state};
+use crate::stream::StreamEvent;
+use crate::theme::{Theme, ThemePreset};
+use crate::title::render_title;
+use crate::watchlist::{Watchlist, save_watchlist, watchlist_path};
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::{Position, Rect};
+use reqwest::Client;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::{AbortHandle, JoinHandle};
+
+// How long a fetched chart is reused before asking the API again
+const CHART_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+// A coin climbing all afternoon flashes its new highs no more often than this
+const EXTREME_FLASH_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+// Failed refreshes in a row before the feed counts as offline
+pub const OFFLINE_AFTER_FAILURES: u32 = 3;
+// Trending coins change slowly, no need to spend requests on them as often
+// as on prices
+pub const TRENDING_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// The global market figures in the header move even slower than the prices
+pub const GLOBAL_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// The Fear & Greed index is only updated once a day, hourly is plenty
+pub const FEAR_GREED_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// What 'h' steps the single change column through
+const CYCLED_HORIZONS: [ChangeHorizon; 3] = [
+    ChangeHorizon::Day,
+    ChangeHorizon::Week,
+    ChangeHorizon::Month,
+];
+
+// Sent back to the event loop by the background fetch tasks
+pub enum FetchMessage {
+    // A refresh failed and is being tried again, with the attempt number
+    Retrying(u32),
+    Refreshed(RefreshResult),
+    // Looked up a single coin before adding it to the watchlist
+    CoinLookup(String, RefreshResult),
+    // Price history for the detail view of a coin
+    Chart(String, ChartRange, ChartResult),
+    // Candles for the same view
+    Ohlc(String, ChartRange, OhlcResult),
+    // An alert could not be delivered to the webhook, even after a retry
+    WebhookFailed(String),
+    // The coins of the Trending tab
+    Trending(TrendingResult),
+    // Total market cap and BTC dominance for the header
+    Global(GlobalResult),
+    FearGreed(FearGreedResult),
+    // Saved prices of earlier sessions, read at startup
+    HistoryLoaded(Result<HashMap<String, Vec<Sample>>, String>),
+    HistoryFailed(String),
+    // The raw markets response of a refresh, only sent in debug mode
+    Debug(FetchDebugInfo),
+    // Pushed by the price stream of `stream = true`
+    Stream(StreamEvent),
+}
+
+// The tasks spawned off the event loop that may still be running when the
+// app quits: fetches are given up on, saves of the history are finished
+#[derive(Debug, Default)]
+pub struct Background {
+    fetches: Mutex<Vec<AbortHandle>>,
+    saves: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Background {
+    pub fn fetch<T>(&self, task: JoinHandle<T>) {
+        if let Ok(mut fetches) = self.fetches.lock() {
+            fetches.retain(|fetch| !fetch.is_finished());
+            fetches.push(task.abort_handle());
+        }
+    }
+
+    fn save(&self, task: JoinHandle<()>) {
+        if let Ok(mut saves) = self.saves.lock() {
+            saves.retain(|save| !save.is_finished());
+            saves.push(task);
+        }
+    }
+
+    // Aborts the fetches and waits for the saves
+    pub async fn finish(&self) {
+        let (fetches, saves) = match (self.fetches.lock(), self.saves.lock()) {
+            (Ok(mut fetches), Ok(mut saves)) => {
+                (std::mem::take(&mut *fetches), std::mem::take(&mut *saves))
+            }
+            _ => return,
+        };
+        for fetch in fetches {
+            fetch.abort();
+        }
+        for save in saves {
+            let _ = save.await;
+        }
+    }
+}
+
+// Fetch in the background so a slow request never blocks drawing or input
+fn spawn_refresh(
+    tx: UnboundedSender<FetchMessage>,
+    source: Arc<dyn DataSource>,
+    config: Config,
+    extra_currencies: Vec<String>,
+    debug: bool,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // The receiver only goes away when the app is quitting
+        let on_retry = |attempt| {
+            let _ = tx.send(FetchMessage::Retrying(attempt));
+        };
+        let on_response = |info| {
+            let _ = tx.send(FetchMessage::Debug(info));
+        };
+        let hooks = RefreshHooks {
+            on_retry: &on_retry,
+            on_response: match debug {
+                true => Some(&on_response),
+                false => None,
+            },
+        };
+        let result = source
+            .refresh(&config.coins, &config.currency, &extra_currencies, hooks)
+            .await;
+        let _ = tx.send(FetchMessage::Refreshed(result));
+    })
+}
+
+// Starts a background refresh unless one is already running or there is nothing to fetch
+pub fn request_refresh(app: &mut App, tx: &UnboundedSender<FetchMessage>) -> bool {
+    let skipped = match () {
+        _ if app.fetching => Some("the last one is still running"),
+        _ if app.config.coins.is_empty() => Some("no coins to fetch"),
+        _ if app.rate_limited() => Some("rate limited"),
+        _ => None,
+    };
+    if let Some(reason) = skipped {
+        log::debug!("refresh skipped: {}", reason);
+        return false;
+    }
+    log::debug!("refreshing {} coins", app.config.coins.len());
+    app.fetching = true;
+    app.fetch_started = Some(Instant::now());
+    app.background.fetch(spawn_refresh(
+        tx.clone(),
+        app.source.clone(),
+        app.config.clone(),
+        app.fetch_currencies(),
+        app.debug,
+    ));
+    true
+}
+
+pub fn spawn_chart_fetch(app: &App, tx: &UnboundedSender<FetchMessage>, coin_id: String) {
+    let (tx, source) = (tx.clone(), app.source.clone());
+    let (currency, range) = (app.config.currency.clone(), app.chart_range);
+    app.background.fetch(tokio::spawn(async move {
+        let result = source.chart(&coin_id, &currency, range).await;
+        let _ = tx.send(FetchMessage::Chart(coin_id, range, result));
+    }));
+}
+
+pub fn spawn_ohlc_fetch(app: &App, tx: &UnboundedSender<FetchMessage>, coin_id: String) {
+    let (tx, source) = (tx.clone(), app.source.clone());
+    let (currency, range) = (app.config.currency.clone(), app.chart_range);
+    app.background.fetch(tokio::spawn(async move {
+        let result = source.ohlc(&coin_id, &currency, range).await;
+        let _ = tx.send(FetchMessage::Ohlc(coin_id, range, result));
+    }));
+}
+
+// Reads the saved prices off the event loop, the ones saved from here on
+// are this session's
+pub fn spawn_history_load(app: &App, tx: &UnboundedSender<FetchMessage>) {
+    let Some(store) = app.history_store.clone() else {
+        return;
+    };
+    let (tx, coin_ids, now) = (tx.clone(), app.config.coins.clone(), SystemTime::now());
+    tokio::task::spawn_blocking(move || {
+        let _ = tx.send(FetchMessage::HistoryLoaded(store.load(&coin_ids, now)));
+    });
+}
+
+// Appends the prices of a refresh to the history files, off the event loop
+pub fn spawn_history_save(app: &App, tx: &UnboundedSender<FetchMessage>, coins: &[Coin]) {
+    let Some(store) = app.history_store.clone() else {
+        return;
+    };
+    let tracked = |coin: &&Coin| app.config.coins.contains(&coin.id);
+    let coins: Vec<Coin> = coins.iter().filter(tracked).cloned().collect();
+    let tx = tx.clone();
+    app.background.save(tokio::task::spawn_blocking(move || {
+        if let Err(e) = store.append(&coins, SystemTime::now()) {
+            let _ = tx.send(FetchMessage::HistoryFailed(e));
+        }
+    }));
+}
+
+// Fetches the trending coins while their tab is open and the last fetch is
+// older than TRENDING_REFRESH_INTERVAL. Runs next to the price refresh,
+// whose state it leaves alone
+pub fn request_trending(app: &mut App, tx: &UnboundedSender<FetchMessage>) -> bool {
+    let is_stale = app
+        .trending_fetched_at
+        .is_none_or(|fetched_at| fetched_at.elapsed() >= TRENDING_REFRESH_INTERVAL);
+    if app.view != View::Trending || app.fetching_trending || !is_stale {
+        return false;
+    }
+    app.fetching_trending = true;
+    let (tx, source) = (tx.clone(), app.source.clone());
+    app.background.fetch(tokio::spawn(async move {
+        let result = source.trending().await;
+        let _ = tx.send(FetchMessage::Trending(result));
+    }));
+    true
+}
+
+// Fetches the global market figures once GLOBAL_REFRESH_INTERVAL has passed
+// since the last try, whatever tab is open
+pub fn request_global(app: &mut App, tx: &UnboundedSender<FetchMessage>) -> bool {
+    let is_stale = app
+        .global_fetched_at
+        .is_none_or(|fetched_at| fetched_at.elapsed() >= GLOBAL_REFRESH_INTERVAL);
+    if app.fetching_global || !is_stale {
+        return false;
+    }
+    app.fetching_global = true;
+    let (tx, source) = (tx.clone(), app.source.clone());
+    app.background.fetch(tokio::spawn(async move {
+        let result = source.global().await;
+        let _ = tx.send(FetchMessage::Global(result));
+    }));
+    true
+}
+
+// Fetches the Fear & Greed index when `show_fear_greed` is on and the last
+// try is older than FEAR_GREED_REFRESH_INTERVAL
+pub fn request_fear_greed(app: &mut App, tx: &UnboundedSender<FetchMessage>) -> bool {
+    let is_stale = app
+        .fear_greed_fetched_at
+        .is_none_or(|fetched_at| fetched_at.elapsed() >= FEAR_GREED_REFRESH_INTERVAL);
+    let Some(client) = app.fear_greed_client.clone() else {
+        return false;
+    };
+    if !app.config.show_fear_greed || app.fetching_fear_greed || !is_stale {
+        return false;
+    }
+    app.fetching_fear_greed = true;
+    let tx = tx.clone();
+    app.background.fetch(tokio::spawn(async move {
+        let result = fetch_fear_greed(&client, FEAR_GREED_URL).await;
+        let _ = tx.send(FetchMessage::FearGreed(result));
+    }));
+    true
+}
+
+pub fn spawn_coin_lookup(app: &App, tx: &UnboundedSender<FetchMessage>, coin_id: String) {
+    let (tx, source) = (tx.clone(), app.source.clone());
+    let currency = app.config.currency.clone();
+    let extra_currencies = app.fetch_currencies();
+    app.background.fetch(tokio::spawn(async move {
+        let coin_ids = std::slice::from_ref(&coin_id);
+        let result = source
+            .refresh(coin_ids, &currency, &extra_currencies, RefreshHooks::NONE)
+            .await;
+        let _ = tx.send(FetchMessage::CoinLookup(coin_id, result));
+    }));
+}
+
+// What a key needs from the event loop, the app itself is already updated
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    Nothing,
+    Quit,
+    Refresh,
+    // Check a coin exists before it joins the watchlist
+    LookupCoin(String),
+    FetchChart(String),
+    FetchOhlc(String),
+    // Put the text on the clipboard
+    Copy(String),
+    #[cfg(unix)]
+    Suspend,
+}
+
+// Where the table on screen ended up, recorded while drawing so a click can
+// be matched to its row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableLayout {
+    // The rows below the header, inside the border
+    pub rows: Rect,
+    // Index of the first row shown, more than 0 once the table scrolled
+    pub offset: usize,
+    // Lines of the terminal each row takes up
+    pub row_height: u16,
+}
+
+// Which screen is shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum View {
+    Market,
+    // Full screen chart of one coin
+    Detail(String),
+    // Holdings valued at the prices of the market view
+    Portfolio,
+    // The alert rules and whether they are triggered
+    Alerts,
+    // The most searched coins on CoinGecko right now
+    Trending,
+}
+
+// The (timestamp, price) pairs of the line chart by default, or the candles
+#[derive(Debug)]
+pub enum ChartData<T = Vec<(f64, f64)>> {
+    Loading,
+    Loaded(T),
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct ChartEntry<T = Vec<(f64, f64)>> {
+    pub data: ChartData<T>,
+    pub fetched_at: Instant,
+}
+
+impl<T> ChartEntry<T> {
+    fn loading() -> Self {
+        ChartEntry {
+            data: ChartData::Loading,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    fn finished(result: Result<T, TuickerError>) -> Self {
+        let data = match result {
+            Ok(data) => ChartData::Loaded(data),
+            Err(e) => ChartData::Failed(e.to_string()),
+        };
+        ChartEntry {
+            data,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    fn is_loading(&self) -> bool {
+        matches!(self.data, ChartData::Loading)
+    }
+
+    // Failures are not reused so reopening the view tries again
+    fn is_fresh(&self) -> bool {
+        !matches!(self.data, ChartData::Failed(_)) && self.fetched_at.elapsed() < CHART_CACHE_TTL
+    }
+}
+
+// What the detail view draws, switched with 'c'
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChartMode {
+    #[default]
+    Line,
+    Candles,
+}
+
+// Charts by coin id and range
+pub type Charts<T = Vec<(f64, f64)>> = HashMap<(String, ChartRange), ChartEntry<T>>;
+
+// Marks a chart as loading unless a fresh one is there. Returns true when
+// it has to be fetched
+fn start_chart<T>(charts: &mut Charts<T>, coin_id: &str, range: ChartRange) -> bool {
+    let key = (coin_id.to_string(), range);
+    if charts.get(&key).is_some_and(ChartEntry::is_fresh) {
+        return false;
+    }
+    charts.insert(key, ChartEntry::loading());
+    true
+}
+
+// The dot in the footer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connection {
+    // Nothing fetched or failed yet
+    Connecting,
+    Live,
+    // Retrying, rate limited, a refresh that failed or only got some coins
+    Degraded,
+    // The last OFFLINE_AFTER_FAILURES refreshes all failed
+    Offline,
+}
+
+// The last refresh that got through is more than `intervals` refresh
+// intervals old. Never with 0
+pub fn is_stale(last_ok: Instant, interval: Duration, intervals: u32, now: Instant) -> bool {
+    intervals > 0 && now.saturating_duration_since(last_ok) > interval * intervals
+}
+
+// Where key presses go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    Normal,
+    // Typing into the filter bar
+    Filter,
+    // Typing the id of a coin to add
+    AddCoin,
+    // Waiting for y/n before removing a coin
+    ConfirmDelete,
+    // Going through the steps of a new alert rule
+    AddAlert,
+}
+
+// The steps of the add alert prompt, each one carrying what was picked so far
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertPrompt {
+    // Typing or picking a coin from the watchlist
+    Coin,
+    Direction(String),
+    // Typing the threshold
+    Price(String, Direction),
+}
+
+pub struct App {
+    pub config: Config,
+    // Where every fetch goes, picked by `config.source`
+    pub source: Arc<dyn DataSource>,
+    // Saved to disk on every change. None when --coins picked the coins
+    // for this session only
+    pub watchlist: Option<Watchlist>,
+    pub portfolio: Portfolio,
+    pub coins: Vec<Coin>,
+    pub history: PriceHistory,
+    // Time between automatic refreshes, already clamped to the minimum
+    pub refresh_interval: Duration,
+    // Only one fetch is allowed to be in flight at a time
+    pub fetching: bool,
+    // Which try of the current refresh is running, starting at 1
+    pub fetch_attempt: u32,
+    // When that try started
+    pub fetch_started: Option<Instant>,
+    // When the last successful refresh finished
+    pub last_updated: Option<Instant>,
+    // The price stream is up, so the automatic refresh is left to it
+    pub streaming: bool,
+    pub background: Background,
+    // Set with --metrics-listen
+    pub metrics: Option<SharedMetrics>,
+    // Which alerts have fired, saved to `alert_state_path` so they don't
+    // fire again after a restart
+    pub alert_state: AlertState,
+    pub alert_state_path: Option<PathBuf>,
+    // Where fired alerts go: the banner, and the bell or desktop
+    // notifications when the config turns them on
+    pub alert_sinks: AlertSinks,
+    // Where every successful refresh is saved. None with --no-cache
+    pub cache_path: Option<PathBuf>,
+    // The coins on screen came from the cache and were fetched at this time
+    pub cached_at: Option<SystemTime>,
+    // The coins on screen are from before a refresh that failed
+    pub last_refresh_failed: bool,
+    // Why the last refresh failed
+    pub last_error: Option<String>,
+    // The same with every cause, for the connection panel
+    pub last_error_detail: Option<String>,
+    // Refreshes that failed in a row, until one gets through again
+    pub consecutive_failures: u32,
+    // No refreshes are sent before this, the API asked us to back off
+    pub rate_limited_until: Option<Instant>,
+    // When the refresh interval fires next
+    pub next_refresh: Instant,
+    // Advanced on every UI tick while a fetch is running
+    pub spinner_frame: usize,
+    // The selection follows the coin id, not the row, so it survives refreshes and re-sorting
+    pub selected_id: Option<String>,
+    pub sort_key: SortKey,
+    pub sort_dir: SortDir,
+    // Index into `currency` followed by `other_currencies`, cycled with 'c'
+    pub currency_index: usize,
+    // The single change column 'h' switched to, None for `change_columns`
+    pub change_horizon: Option<ChangeHorizon>,
+    // Saved to `ui_state_path` whenever it changes
+    pub ui_state: UiState,
+    pub ui_state_path: Option<PathBuf>,
+    pub input_mode: InputMode,
+    // Only coins matching this are shown, the rest are kept in `coins`
+    pub filter: String,
+    // Text typed into the add coin prompt, or the add alert prompt
+    pub add_input: String,
+    // Shown next to the add prompts, e.g. why an id was rejected
+    pub add_message: Option<String>,
+    pub alert_prompt: AlertPrompt,
+    // Row of the selected rule in the Alerts tab
+    pub selected_alert: usize,
+    // The Trending tab. A failed fetch keeps the coins from the one before
+    pub trending: Vec<TrendingCoin>,
+    pub trending_error: Option<String>,
+    pub trending_fetched_at: Option<Instant>,
+    pub fetching_trending: bool,
+    pub selected_trending: usize,
+    // Figures for the header, None until fetched and after a failed fetch
+    pub global: Option<GlobalStats>,
+    // How much the BTC dominance moved since the previous fetch
+    pub dominance_change: Option<f64>,
+    pub global_fetched_at: Option<Instant>,
+    pub fetching_global: bool,
+    // Without the CoinGecko key, only set when the index is shown
+    pub fear_greed_client: Option<Client>,
+    // None until fetched and after a failed fetch, which hides it
+    pub fear_greed: Option<u8>,
+    pub fear_greed_fetched_at: Option<Instant>,
+    pub fetching_fear_greed: bool,
+    // The list of keybindings on top of the view, opened with '?'
+    pub show_help: bool,
+    // Opened with 'i', closed like the help
+    pub show_connection: bool,
+    // F12 or --debug: keeps the last raw response and shows it
+    pub debug: bool,
+    pub debug_info: Option<FetchDebugInfo>,
+    pub debug_scroll: u16,
+    // The market as a heatmap instead of the table, toggled with 'm'
+    pub show_heatmap: bool,
+    // Set by `ui` on every frame with a table, None on the others
+    pub table_layout: Cell<Option<TableLayout>>,
+    // Where the heatmap put each coin on the last frame, in the order of the
+    // market view
+    pub heatmap_cells: RefCell<Vec<Rect>>,
+    // First row of the market table on screen, kept between frames so the
+    // table only scrolls once the selection leaves it
+    pub market_offset: Cell<usize>,
+    // Coin id being checked against the API before it is added
+    pub pending_lookup: Option<String>,
+    // Coin id waiting for the delete to be confirmed
+    pub pending_delete: Option<String>,
+    // Shown in the footer until the next key press
+    pub status_message: Option<String>,
+    pub view: View,
+    // Detail charts, reused for CHART_CACHE_TTL so flipping between ranges
+    // doesn't fetch them again
+    pub charts: Charts,
+    // Candles of the same coins, fetched once the detail view shows them
+    pub ohlc: Charts<Vec<Candle>>,
+    pub chart_mode: ChartMode,
+    pub chart_range: ChartRange,
+    // Prices of the line chart on a log10 axis, for the rest of the session
+    pub log_scale: bool,
+    // No automatic refresh until resumed, never saved so every launch is live
+    pub paused: bool,
+    // When each coin last flashed a new session high or low in the footer
+    extreme_flashed_at: HashMap<String, Instant>,
+    // Where every refresh is saved for the next sessions, unless turned off
+    pub history_store: Option<HistoryStore>,
+    // The saved prices and the ones of this session by coin id, charted
+    // while the detail chart loads or when it can't be
+    pub saved_charts: HashMap<String, Vec<(f64, f64)>>,
+}
+
+// Case insensitive substring match on the name or symbol
+fn matches_filter(coin: &Coin, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    coin.name.to_lowercase().contains(&filter) || coin.symbol.to_lowercase().contains(&filter)
+}
+
+impl App {
+    pub fn new(config: Config, watchlist: Option<Watchlist>, source: Arc<dyn DataSource>) -> Self {
+        let (refresh_interval, interval_warning) =
+            clamp_refresh_interval(config.refresh_interval, config.min_refresh_interval());
+        App {
+            source,
+            refresh_interval,
+            config,
+            watchlist,
+            portfolio: Portfolio::default(),
+            coins: Vec::new(),
+            history: PriceHistory::default(),
+            fetching: false,
+            fetch_attempt: 1,
+            fetch_started: None,
+            last_updated: None,
+            streaming: false,
+            background: Background::default(),
+            metrics: None,
+            alert_state: AlertState::default(),
+            alert_state_path: None,
+            alert_sinks: AlertSinks::default(),
+            cache_path: None,
+            cached_at: None,
+            last_refresh_failed: false,
+            last_error: None,
+            last_error_detail: None,
+            consecutive_failures: 0,
+            rate_limited_until: None,
+            next_refresh: Instant::now() + refresh_interval,
+            spinner_frame: 0,
+            selected_id: None,
+            sort_key: SortKey::Rank,
+            sort_dir: SortDir::Ascending,
+            currency_index: 0,
+            change_horizon: None,
+            ui_state: UiState::default(),
+            ui_state_path: None,
+            input_mode: InputMode::Normal,
+            filter: String::new(),
+            add_input: String::new(),
+            add_message: None,
+            alert_prompt: AlertPrompt::Coin,
+            selected_alert: 0,
+            trending: Vec::new(),
+            trending_error: None,
+            trending_fetched_at: None,
+            fetching_trending: false,
+            selected_trending: 0,
+            global: None,
+            dominance_change: None,
+            global_fetched_at: None,
+            fetching_global: false,
+            fear_greed_client: None,
+            fear_greed: None,
+            fear_greed_fetched_at: None,
+            fetching_fear_greed: false,
+            show_help: false,
+            show_connection: false,
+            debug: false,
+            debug_info: None,
+            debug_scroll: 0,
+            show_heatmap: false,
+            table_layout: Cell::new(None),
+            heatmap_cells: RefCell::new(Vec::new()),
+            market_offset: Cell::new(0),
+            pending_lookup: None,
+            pending_delete: None,
+            status_message: interval_warning,
+            view: View::Market,
+            charts: HashMap::new(),
+            ohlc: HashMap::new(),
+            chart_mode: ChartMode::Line,
+            chart_range: ChartRange::default(),
+            log_scale: false,
+            paused: false,
+            extreme_flashed_at: HashMap::new(),
+            history_store: None,
+            saved_charts: HashMap::new(),
+        }
+    }
+
+    // The coins shown in the table, in display order
+    pub fn visible_coins(&self) -> Vec<&Coin> {
+        self.coins
+            .iter()
+            .filter(|coin| matches_filter(coin, &self.filter))
+            .collect()
+    }
+
+    // Row index of the selected coin among the visible coins
+    pub fn selected(&self) -> Option<usize> {
+        let selected_id = self.selected_id.as_ref()?;
+        self.visible_coins()
+            .iter()
+            .position(|coin| &coin.id == selected_id)
+    }
+
+    fn selected_coin(&self) -> Option<&Coin> {
+        let index = self.selected()?;
+        self.visible_coins().get(index).copied()
+    }
+
+    fn select_index(&mut self, index: usize) {
+        let selected_id = self.visible_coins().get(index).map(|coin| coin.id.clone());
+        self.selected_id = selected_id;
+    }
+
+    pub fn select_next(&mut self) {
+        if self.in_heatmap() {
+            return self.select_cell(true);
+        }
+        match self.selected() {
+            Some(index) => {
+                let last = self.visible_coins().len() - 1;
+                self.select_index((index + 1).min(last));
+            }
+            None => self.select_first(),
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if self.in_heatmap() {
+            return self.select_cell(false);
+        }
+        match self.selected() {
+            Some(index) => self.select_index(index.saturating_sub(1)),
+            None => self.select_first(),
+        }
+    }
+
+    fn in_heatmap(&self) -> bool {
+        self.show_heatmap && self.view == View::Market
+    }
+
+    // Up or down a row of the heatmap, staying put at the top and bottom
+    fn select_cell(&mut self, down: bool) {
+        let Some(index) = self.selected() else {
+            return self.select_first();
+        };
+        let next = heatmap::vertical_neighbour(&self.heatmap_cells.borrow(), index, down);
+        if let Some(next) = next {
+            self.select_index(next);
+        }
+    }
+
+    // Along the heatmap or the table one coin at a time
+    fn select_beside(&mut self, right: bool) {
+        match (self.selected(), right) {
+            (Some(index), true) => {
+                let last = self.visible_coins().len() - 1;
+                self.select_index((index + 1).min(last));
+            }
+            (Some(index), false) => self.select_index(index.saturating_sub(1)),
+            (None, _) => self.select_first(),
+        }
+    }
+
+    // As many rows as the table showed on the last frame
+    fn page_size(&self) -> usize {
+        self.table_layout.get().map_or(1, |layout| {
+            (layout.rows.height / layout.row_height.max(1)).max(1) as usize
+        })
+    }
+
+    pub fn page_down(&mut self) {
+        let last = self.visible_coins().len().saturating_sub(1);
+        let index = self.selected().map_or(0, |index| index + self.page_size());
+        self.select_index(index.min(last));
+    }
+
+    pub fn page_up(&mut self) {
+        let index = self.selected().unwrap_or(0);
+        self.select_index(index.saturating_sub(self.page_size()));
+    }
+
+    pub fn select_first(&mut self) {
+        self.select_index(0);
+    }
+
+    pub fn select_last(&mut self) {
+        self.select_index(self.visible_coins().len().saturating_sub(1));
+    }
+
+    // If the selected coin is no longer visible, select whatever is now on its old row
+    fn keep_selection(&mut self, old_index: usize) {
+        if self.selected().is_none() {
+            let last = self.visible_coins().len().saturating_sub(1);
+            self.select_index(old_index.min(last));
+        }
+    }
+
+    // Swap in freshly fetched coins, keeping the selection on the same coin
+    // or on the same row if that coin is gone
+    fn set_coins(&mut self, mut coins: Vec<Coin>) {
+        let old_index = self.selected().unwrap_or(0);
+        // A refresh started before a coin was removed still contains it
+        coins.retain(|coin| self.config.coins.contains(&coin.id));
+        self.coins = coins;
+        self.history.retain(&self.config.coins);
+        self.sort();
+        self.keep_selection(old_index);
+    }
+
+    // Shows the coins saved by the last session until the first refresh
+    // finishes, and saves every refresh after that
+    pub fn use_cache(&mut self, path: PathBuf) {
+        if let Some(cached) = load_cache(&path, &self.config.currency) {
+            self.cached_at = Some(cached.fetched_at());
+            self.set_coins(cached.coins);
+        }
+        self.cache_path = Some(path);
+    }
+
+    // Rules changed in the Alerts tab replace the ones in the config file
+    pub fn use_alert_state(&mut self, path: PathBuf) {
+        self.alert_state = load_alert_state(&path);
+        if let Some(rules) = &self.alert_state.rules {
+            self.config.alerts = rules.clone();
+        }
+        self.alert_state_path = Some(path);
+    }
+
+    fn check_alerts(&mut self, coins: &[Coin]) {
+        let old_state = self.alert_state.clone();
+        let fired = self.alert_state.evaluate(&self.config.alerts, coins);
+        for rule in fired {
+            if let Some(coin) = coins.iter().find(|coin| coin.id == rule.coin_id) {
+                let alert = rule.fired(coin, &self.config.currency, self.config.number_locale());
+                // A sink that failed is no reason to stop, the banner is still there
+                if let Some(error) = self.alert_sinks.dispatch(&alert).pop() {
+                    self.status_message = Some(error);
+                }
+            }
+        }
+        if let Some(path) = &self.alert_state_path
+            && self.alert_state != old_state
+            && let Err(e) = save_alert_state(path, &self.alert_state)
+        {
+            self.status_message = Some(e);
+        }
+    }
+
+    // "BTC new session high" in the footer, once per coin every
+    // EXTREME_FLASH_COOLDOWN
+    fn flash_extremes(&mut self, coins: &[Coin], extremes: Vec<(String, Extreme)>) {
+        let now = Instant::now();
+        let mut flashed = Vec::new();
+        for (coin_id, extreme) in extremes {
+            let cooled_down = self
+                .extreme_flashed_at
+                .get(&coin_id)
+                .is_none_or(|at| now.duration_since(*at) >= EXTREME_FLASH_COOLDOWN);
+            let coin = coins.iter().find(|coin| coin.id == coin_id);
+            if let (true, Some(coin)) = (cooled_down, coin) {
+                flashed.push(format!("{} new session {}", coin.symbol, extreme.label()));
+                self.extreme_flashed_at.insert(coin_id, now);
+            }
+        }
+        if !flashed.is_empty() {
+            self.status_message = Some(flashed.join(" · "));
+        }
+    }
+
+    pub fn dismiss_alerts(&mut self) {
+        self.alert_sinks.banner.dismiss();
+    }
+
+    // A refresh finished successfully
+    pub fn apply_refresh(&mut self, coins: Vec<Coin>) {
+        self.finish_refresh(Refresh {
+            coins,
+            failure: None,
+        });
+    }
+
+    // `title_format` filled in with the prices on screen, once there are any
+    pub fn window_title(&self) -> Option<String> {
+        let template = self.config.title_template()?;
+        match self.coins.is_empty() {
+            true => None,
+            false => Some(render_title(
+                template,
+                &self.coins,
+                &self.config.currency,
+                self.config.number_locale(),
+            )),
+        }
+    }
+
+    // Coins of a batch that failed keep their previous prices
+    pub fn finish_refresh(&mut self, refresh: Refresh) {
+        let Refresh { mut coins, failure } = refresh;
+        self.last_updated = Some(Instant::now());
+        self.cached_at = None;
+        self.last_refresh_failed = failure.is_some();
+        match &failure {
+            Some(failure) => log::warn!("refresh got only some coins: {}", failure),
+            None => log::debug!("refreshed {} coins", coins.len()),
+        }
+        self.last_error = failure.as_ref().map(ToString::to_string);
+        self.last_error_detail = failure.as_ref().map(|failure| {
+            format!(
+                "fetched {}/{} coins: {}",
+                failure.fetched,
+                failure.requested,
+                failure.error.detail()
+            )
+        });
+        // Some coins came through, so the feed is only degraded
+        self.consecutive_failures = 0;
+        let extremes = self.history.record(&coins);
+        self.flash_extremes(&coins, extremes);
+        if self.history_store.is_some() {
+            let now = millis(SystemTime::now()) as f64;
+            for coin in &coins {
+                let points = self.saved_charts.entry(coin.id.clone()).or_default();
+                points.push((now, coin.current_price));
+            }
+        }
+        self.check_alerts(&coins);
+        if failure.is_some() {
+            let stale: Vec<Coin> = self
+                .coins
+                .iter()
+                .filter(|old| !coins.iter().any(|coin| coin.id == old.id))
+                .cloned()
+                .collect();
+            coins.extend(stale);
+        }
+        if let Some(path) = &self.cache_path {
+            let cached = CachedCoins::new(&self.config.currency, &coins);
+            if let Err(e) = save_cache(path, &cached) {
+                self.status_message = Some(e);
+            }
+        }
+        self.set_coins(coins);
+        self.publish_metrics(false);
+    }
+
+    // Hands the coins on screen to --metrics-listen
+    fn publish_metrics(&self, failed: bool) {
+        let Some(Ok(mut snapshot)) = self.metrics.as_ref().map(|metrics| metrics.lock()) else {
+            return;
+        };
+        match failed {
+            true => snapshot.failed_refreshes += 1,
+            false => {
+                snapshot.refreshes += 1;
+                snapshot.last_refresh = Some(SystemTime::now());
+                snapshot.currency = self.config.currency.clone();
+                snapshot.coins = self.coins.clone();
+            }
+        }
+    }
+
+    // Returns true when the stream dropped, so polling has to take over
+    // right away
+    pub fn finish_stream(&mut self, event: StreamEvent) -> bool {
+        match event {
+            StreamEvent::Connected => {
+                log::info!("streaming prices");
+                self.streaming = true;
+                false
+            }
+            StreamEvent::Dropped(reason) => {
+                let dropped = std::mem::replace(&mut self.streaming, false);
+                match dropped {
+                    true => log::warn!("{}, polling until it is back", reason),
+                    false => log::debug!("{}", reason),
+                }
+                dropped
+            }
+            // Nothing is updated while paused, like with polling
+            StreamEvent::Prices(_) if self.paused => false,
+            StreamEvent::Prices(ticks) => {
+                let mut moved = Vec::new();
+                for tick in ticks {
+                    let Some(coin) = self.coins.iter_mut().find(|coin| coin.id == tick.coin_id)
+                    else {
+                        continue;
+                    };
+                    coin.current_price = tick.price;
+                    coin.price_change_24h = tick.change_24h;
+                    coin.total_volume = Some(tick.volume);
+                    moved.push(coin.clone());
+                }
+                if !moved.is_empty() {
+                    self.last_updated = Some(Instant::now());
+                    self.consecutive_failures = 0;
+                    self.check_alerts(&moved);
+                    let coins = std::mem::take(&mut self.coins);
+                    self.set_coins(coins);
+                }
+                false
+            }
+        }
+    }
+
+    // The streamed prices as a refresh, so the history still gets a sample
+    // every refresh interval
+    pub fn streamed_refresh(&self) -> Refresh {
+        Refresh {
+            coins: self.coins.clone(),
+            failure: None,
+        }
+    }
+
+    pub fn retrying(&mut self, attempt: u32) {
+        self.fetch_attempt = attempt;
+        self.fetch_started = Some(Instant::now());
+    }
+
+    // The running try already took half of what it may take, the data is
+    // late and the footer says why
+    pub fn slow(&self) -> bool {
+        self.fetching
+            && self
+                .fetch_started
+                .is_some_and(|started| started.elapsed() >= self.config.request_timeout / 2)
+    }
+
+    pub fn tick(&mut self) {
+        if self.fetching || self.chart_loading() {
+            self.spinner_frame = self.spinner_frame.wrapping_add(1);
+        }
+    }
+
+    // Returns when refreshing may start again if the API rate limited us
+    pub fn refresh_failed(&mut self, error: &TuickerError) -> Option<Instant> {
+        self.last_refresh_failed = true;
+        self.last_error = Some(error.to_string());
+        self.last_error_detail = Some(match (&self.config.proxy, error.is_connect()) {
+            (Some(proxy), true) => format!("{} (through {})", error.detail(), proxy_host(proxy)),
+            _ => error.detail(),
+        });
+        self.consecutive_failures += 1;
+        self.publish_metrics(true);
+        log::warn!(
+            "refresh failed ({} in a row): {}",
+            self.consecutive_failures,
+            error.detail()
+        );
+        let TuickerError::RateLimited(delay) = error else {
+            return None;
+        };
+        let until = Instant::now() + *delay;
+        log::info!("rate limited, next refresh in {}s", delay.as_secs());
+        self.rate_limited_until = Some(until);
+        self.next_refresh = until;
+        Some(until)
+    }
+
+    pub fn connection(&self) -> Connection {
+        if self.consecutive_failures >= OFFLINE_AFTER_FAILURES {
+            return Connection::Offline;
+        }
+        let retrying = self.fetching && self.fetch_attempt > 1;
+        if retrying || self.rate_limited() || self.last_refresh_failed {
+            return Connection::Degraded;
+        }
+        match self.last_updated {
+            Some(_) => Connection::Live,
+            None => Connection::Connecting,
+        }
+    }
+
+    // The prices on screen are too old to pass for live ones
+    pub fn stale(&self) -> bool {
+        self.last_updated.is_some_and(|last_ok| {
+            is_stale(
+                last_ok,
+                self.refresh_interval,
+                self.config.stale_after_intervals,
+                Instant::now(),
+            )
+        })
+    }
+
+    pub fn rate_limited(&self) -> bool {
+        self.rate_limited_until
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    // The refresh interval was (re)started
+    pub fn schedule_next_refresh(&mut self) {
+        self.next_refresh = Instant::now() + self.refresh_interval;
+    }
+
+    pub fn start_filter(&mut self) {
+        self.input_mode = InputMode::Filter;
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.input_mode = InputMode::Normal;
+        self.filter.clear();
+        self.keep_selection(0);
+    }
+
+    // Keys typed while the filter bar is open
+    pub fn handle_filter_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Esc => self.clear_filter(),
+            // Keep the filter applied and go back to normal keys
+            KeyCode::Enter => self.input_mode = InputMode::Normal,
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.keep_selection(0);
+            }
+            KeyCode::Char(c) => {
+                self.filter.push(c);
+                self.keep_selection(0);
+            }
+            KeyCode::Down => self.select_next(),
+            KeyCode::Up => self.select_previous(),
+            _ => {}
+        }
+    }
+
+    pub fn start_add_coin(&mut self) {
+        self.input_mode = InputMode::AddCoin;
+        self.add_input.clear();
+        self.add_message = None;
+        self.pending_lookup = None;
+    }
+
+    // Keys typed into the add prompt. Returns the coin id to look up on Enter
+    pub fn handle_add_key(&mut self, code: KeyCode) -> Option<String> {
+        match code {
+            KeyCode::Esc => {
+                self.input_mode = InputMode::Normal;
+                self.pending_lookup = None;
+            }
+            KeyCode::Enter => match self.validate_new_coin() {
+                Ok(coin_id) => {
+                    self.add_message = Some(format!("checking {}…", coin_id));
+                    self.pending_lookup = Some(coin_id.clone());
+                    return Some(coin_id);
+                }
+                Err(message) => self.add_message = Some(message),
+            },
+            KeyCode::Backspace => {
+                self.add_input.pop();
+            }
+            KeyCode::Char(c) => {
+                self.add_input.push(c);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn validate_new_coin(&self) -> Result<String, String> {
+        if self.add_input.contains(',') {
+            return Err("enter a single coin id".to_string());
+        }
+        let coin_id = parse_coin_ids(&self.add_input)?
+            .into_iter()
+            .next()
+            .ok_or("enter a coin id")?;
+        if self.config.coins.contains(&coin_id) {
+            return Err(format!("{} is already tracked", coin_id));
+        }
+        Ok(coin_id)
+    }
+
+    // Show the chart of the selected coin, asking for it to be fetched when
+    // there is no fresh one
+    pub fn open_detail(&mut self) -> KeyAction {
+        let Some(coin) = self.selected_coin() else {
+            return KeyAction::Nothing;
+        };
+        let coin_id = coin.id.clone();
+        self.view = View::Detail(coin_id.clone());
+        self.fetch_chart(coin_id)
+    }
+
+    // The chart of the mode shown
+    fn fetch_chart(&mut self, coin_id: String) -> KeyAction {
+        let range = self.chart_range;
+        match self.chart_mode {
+            ChartMode::Line if start_chart(&mut self.charts, &coin_id, range) => {
+                KeyAction::FetchChart(coin_id)
+            }
+            ChartMode::Candles if start_chart(&mut self.ohlc, &coin_id, range) => {
+                KeyAction::FetchOhlc(coin_id)
+            }
+            _ => KeyAction::Nothing,
+        }
+    }
+
+    // Charts the coin in the detail view over `range`
+    pub fn set_chart_range(&mut self, range: ChartRange) -> KeyAction {
+        self.chart_range = range;
+        match &self.view {
+            View::Detail(coin_id) => self.fetch_chart(coin_id.clone()),
+            _ => KeyAction::Nothing,
+        }
+    }
+
+    // Whether the chart on screen is still being fetched
+    pub fn chart_loading(&self) -> bool {
+        let View::Detail(coin_id) = &self.view else {
+            return false;
+        };
+        let key = (coin_id.clone(), self.chart_range);
+        match self.chart_mode {
+            ChartMode::Line => self.charts.get(&key).is_some_and(ChartEntry::is_loading),
+            ChartMode::Candles => self.ohlc.get(&key).is_some_and(ChartEntry::is_loading),
+        }
+    }
+
+    // Between the line chart and the candles of the coin in the detail view
+    pub fn toggle_chart_mode(&mut self) -> KeyAction {
+        self.chart_mode = match self.chart_mode {
+            ChartMode::Line => ChartMode::Candles,
+            ChartMode::Candles => ChartMode::Line,
+        };
+        match &self.view {
+            View::Detail(coin_id) => self.fetch_chart(coin_id.clone()),
+            _ => KeyAction::Nothing,
+        }
+    }
+
+    pub fn show_market(&mut self) {
+        self.show_tab(Tab::Market);
+    }
+
+    pub fn tab(&self) -> Tab {
+        match self.view {
+            View::Market | View::Detail(_) => Tab::Market,
+            View::Portfolio => Tab::Portfolio,
+            View::Alerts => Tab::Alerts,
+            View::Trending => Tab::Trending,
+        }
+    }
+
+    // Every tab keeps its own selection, so switching back finds it as it
+    // was left. The tab is saved for the next session
+    pub fn show_tab(&mut self, tab: Tab) {
+        self.view = match tab {
+            Tab::Market => View::Market,
+            Tab::Portfolio => View::Portfolio,
+            Tab::Alerts => View::Alerts,
+            Tab::Trending => View::Trending,
+        };
+        if self.ui_state.tab != tab {
+            self.ui_state.tab = tab;
+            self.save_ui_state();
+        }
+    }
+
+    // Tab goes from the market to the portfolio, the alerts, the trending
+    // coins and back, Shift-Tab the other way
+    pub fn next_view(&mut self) {
+        let index = (self.tab().index() + 1) % Tab::ALL.len();
+        self.show_tab(Tab::ALL[index]);
+    }
+
+    pub fn previous_view(&mut self) {
+        let index = (self.tab().index() + Tab::ALL.len() - 1) % Tab::ALL.len();
+        self.show_tab(Tab::ALL[index]);
+    }
+
+    // Keys go to the prompt that is open, then the help overlay, and last
+    // to the binding of the view on screen in KEYMAP
+    pub fn handle_key(&mut self, key: KeyEvent) -> KeyAction {
+        let code = key.code;
+        match self.input_mode {
+            InputMode::Filter => {
+                self.handle_filter_key(code);
+                return KeyAction::Nothing;
+            }
+            InputMode::AddCoin => {
+                return self
+                    .handle_add_key(code)
+                    .map_or(KeyAction::Nothing, KeyAction::LookupCoin);
+            }
+            InputMode::ConfirmDelete => {
+                self.handle_delete_key(code);
+                return KeyAction::Nothing;
+            }
+            InputMode::AddAlert => {
+                self.handle_alert_key(code);
+                return KeyAction::Nothing;
+            }
+            InputMode::Normal => self.status_message = None,
+        }
+        let action = self
+            .config
+            .keys
+            .binding_for(Key::from(key), self.key_scope())
+            .map(|binding| binding.action);
+        if self.show_help {
+            if code == KeyCode::Esc || action == Some(Action::ToggleHelp) {
+                self.show_help = false;
+            }
+            return KeyAction::Nothing;
+        }
+        if self.debug {
+            self.debug_key(code, action);
+            return KeyAction::Nothing;
+        }
+        if self.show_connection {
+            if code == KeyCode::Esc || action == Some(Action::ToggleConnection) {
+                self.show_connection = false;
+            }
+            return KeyAction::Nothing;
+        }
+        match action {
+            Some(action) => self.run_action(action),
+            None => KeyAction::Nothing,
+        }
+    }
+
+    // Clicking a row selects it, clicking the selected coin again opens its
+    // chart. The wheel moves the selection like the arrow keys
+    pub fn handle_mouse(&mut self, mouse: MouseEvent) -> KeyAction {
+        let has_list = matches!(self.view, View::Market | View::Alerts | View::Trending);
+        let overlay = self.show_help || self.show_connection || self.debug;
+        if self.input_mode != InputMode::Normal || overlay || !has_list {
+            return KeyAction::Nothing;
+        }
+        match mouse.kind {
+            MouseEventKind::ScrollDown => self.run_action(Action::SelectNext),
+            MouseEventKind::ScrollUp => self.run_action(Action::SelectPrevious),
+            MouseEventKind::Down(MouseButton::Left) => self.click(mouse.column, mouse.row),
+            _ => KeyAction::Nothing,
+        }
+    }
+
+    fn click(&mut self, column: u16, row: u16) -> KeyAction {
+        let position = Position::new(column, row);
+        let cell = match self.in_heatmap() {
+            true => self
+                .heatmap_cells
+                .borrow()
+                .iter()
+                .position(|cell| cell.contains(position)),
+            false => None,
+        };
+        let index = match (cell, self.table_layout.get()) {
+            (Some(cell), _) => cell,
+            (None, Some(layout)) if layout.rows.contains(position) => {
+                layout.offset + ((row - layout.rows.y) / layout.row_height.max(1)) as usize
+            }
+            _ => return KeyAction::Nothing,
+        };
+        match self.view {
+            View::Market if index < self.visible_coins().len() => {
+                if self.selected() == Some(index) {
+                    return self.run_action(Action::OpenDetail);
+                }
+                self.select_index(index);
+            }
+            View::Alerts if index < self.config.alerts.len() => self.selected_alert = index,
+            View::Trending if index < self.trending.len() => self.selected_trending = index,
+            _ => {}
+        }
+        KeyAction::Nothing
+    }
+
+    pub fn key_scope(&self) -> Scope {
+        match self.view {
+            View::Market => Scope::Market,
+            View::Detail(_) => Scope::Detail,
+            View::Portfolio => Scope::Portfolio,
+            View::Alerts => Scope::Alerts,
+            View::Trending => Scope::Trending,
+        }
+    }
+
+    pub fn run_action(&mut self, action: Action) -> KeyAction {
+        match action {
+            Action::Quit => return KeyAction::Quit,
+            #[cfg(unix)]
+            Action::Suspend => return KeyAction::Suspend,
+            Action::Refresh => return KeyAction::Refresh,
+            Action::TogglePause => self.paused = !self.paused,
+            Action::ToggleHelp => self.show_help = !self.show_help,
+            Action::ToggleConnection => self.show_connection = true,
+            Action::ToggleDebug => self.debug = true,
+            Action::NextTab => self.next_view(),
+            Action::PreviousTab => self.previous_view(),
+            Action::ShowTab(tab) => self.show_tab(tab),
+            Action::Back => self.show_market(),
+            Action::SelectNext => match self.view {
+                View::Alerts => self.select_next_alert(),
+                View::Trending => self.select_next_trending(),
+                _ => self.select_next(),
+            },
+            Action::SelectPrevious => match self.view {
+                View::Alerts => self.select_previous_alert(),
+                View::Trending => self.select_previous_trending(),
+                _ => self.select_previous(),
+            },
+            Action::SelectLeft => self.select_beside(false),
+            Action::SelectRight => self.select_beside(true),
+            Action::SelectFirst => self.select_first(),
+            Action::SelectLast => self.select_last(),
+            Action::PageDown => self.page_down(),
+            Action::PageUp => self.page_up(),
+            Action::OpenDetail => return self.open_detail(),
+            Action::ToggleChartMode => return self.toggle_chart_mode(),
+            Action::ToggleLogScale => self.log_scale = !self.log_scale,
+            Action::SetChartRange(range) => return self.set_chart_range(range),
+            Action::CycleChartRange(forward) => {
+                return self.set_chart_range(self.chart_range.cycled(forward));
+            }
+            Action::CycleCurrency => self.cycle_currency(),
+            Action::ToggleBtc => {
+                if self.toggle_btc() {
+                    return KeyAction::Refresh;
+                }
+            }
+            Action::CycleChangeHorizon => self.cycle_change_horizon(),
+            Action::CycleSort => self.cycle_sort_key(),
+            Action::ReverseSort => self.reverse_sort(),
+            Action::StartFilter => self.start_filter(),
+            Action::ClearFilter => self.clear_filter(),
+            Action::AddCoin => self.start_add_coin(),
+            Action::DeleteCoin => self.start_delete(),
+            Action::Export => self.export_visible(),
+            Action::Copy(line) => return self.copy_selected(line),
+            Action::DismissAlerts => self.dismiss_alerts(),
+            Action::ToggleTheme => self.toggle_theme(),
+            Action::ToggleRowLayout => self.toggle_row_layout(),
+            Action::ToggleHeatmap => self.show_heatmap = !self.show_heatmap,
+            Action::AddAlert => self.start_add_alert(),
+            Action::DeleteAlert => self.delete_selected_alert(),
+            Action::RearmAlert => self.rearm_selected_alert(),
+            Action::AddTrending => {
+                if let Some(coin_id) = self.add_selected_trending() {
+                    return KeyAction::LookupCoin(coin_id);
+                }
+            }
+        }
+        KeyAction::Nothing
+    }
+
+    pub fn select_next_alert(&mut self) {
+        let last = self.config.alerts.len().saturating_sub(1);
+        self.selected_alert = (self.selected_alert + 1).min(last);
+    }
+
+    pub fn select_previous_alert(&mut self) {
+        self.selected_alert = self.selected_alert.saturating_sub(1);
+    }
+
+    fn selected_alert_rule(&self) -> Option<&AlertRule> {
+        self.config.alerts.get(self.selected_alert)
+    }
+
+    pub fn start_add_alert(&mut self) {
+        self.input_mode = InputMode::AddAlert;
+        self.alert_prompt = AlertPrompt::Coin;
+        self.add_input.clear();
+        self.add_message = None;
+    }
+
+    // Keys typed into the add alert prompt, which asks for the coin, the
+    // direction and the threshold in turn
+    pub fn handle_alert_key(&mut self, code: KeyCode) {
+        if code == KeyCode::Esc {
+            self.input_mode = InputMode::Normal;
+            return;
+        }
+        match (self.alert_prompt.clone(), code) {
+            (AlertPrompt::Coin, KeyCode::Enter) => match self.watchlist_coin(&self.add_input) {
+                Ok(coin_id) => {
+                    self.alert_prompt = AlertPrompt::Direction(coin_id);
+                    self.add_input.clear();
+                    self.add_message = None;
+                }
+                Err(message) => self.add_message = Some(message),
+            },
+            (AlertPrompt::Coin, KeyCode::Down) => self.pick_alert_coin(1),
+            (AlertPrompt::Coin, KeyCode::Up) => self.pick_alert_coin(-1),
+            (AlertPrompt::Direction(coin_id), KeyCode::Char(c)) => {
+                let direction = match c {
+                    'a' => Direction::Above,
+                    'b' => Direction::Below,
+                    _ => {
+                        self.add_message = Some("press a for above or b for below".to_string());
+                        return;
+                    }
+                };
+                self.alert_prompt = AlertPrompt::Price(coin_id, direction);
+                self.add_message = None;
+            }
+            (AlertPrompt::Price(coin_id, direction), KeyCode::Enter) => {
+                match self.new_alert_rule(coin_id, direction) {
+                    Ok(rule) => self.add_alert(rule),
+                    Err(message) => self.add_message = Some(message),
+                }
+            }
+            (AlertPrompt::Coin | AlertPrompt::Price(..), KeyCode::Backspace) => {
+                self.add_input.pop();
+            }
+            (AlertPrompt::Coin | AlertPrompt::Price(..), KeyCode::Char(c)) => {
+                self.add_input.push(c);
+            }
+            _ => {}
+        }
+    }
+
+    // Alerts are only checked against fetched prices, so the coin has to be
+    // in the watchlist. Its symbol works as well as its id
+    fn watchlist_coin(&self, text: &str) -> Result<String, String> {
+        let text = text.trim().to_lowercase();
+        if text.is_empty() {
+            return Err("enter a coin from the watchlist".to_string());
+        }
+        if self.config.coins.contains(&text) {
+            return Ok(text);
+        }
+        self.coins
+            .iter()
+            .find(|coin| coin.symbol.to_lowercase() == text)
+            .map(|coin| coin.id.clone())
+            .ok_or_else(|| format!("`{}` is not in the watchlist", text))
+    }
+
+    // Up and down in the coin step fill in the watchlist coins in order
+    fn pick_alert_coin(&mut self, step: isize) {
+        let count = self.config.coins.len() as isize;
+        if count == 0 {
+            return;
+        }
+        let index = match self
+            .config
+            .coins
+            .iter()
+            .position(|id| *id == self.add_input)
+        {
+            Some(index) => (index as isize + step).rem_euclid(count),
+            None if step > 0 => 0,
+            None => count - 1,
+        };
+        self.add_input = self.config.coins[index as usize].clone();
+        self.add_message = None;
+    }
+
+    fn new_alert_rule(&self, coin_id: String, direction: Direction) -> Result<AlertRule, String> {
+        let text = self.add_input.trim();
+        let price: f64 = text
+            .parse()
+            .map_err(|_| format!("`{}` is not a number", text))?;
+        if !price.is_finite() || price < 0.0 {
+            return Err("the threshold can't be negative".to_string());
+        }
+        let rule = AlertRule {
+            coin_id,
+            direction,
+            price,
+        };
+        if self.config.alerts.contains(&rule) {
+            return Err(format!("`{}` is already an alert", rule));
+        }
+        Ok(rule)
+    }
+
+    fn add_alert(&mut self, rule: AlertRule) {
+        self.input_mode = InputMode::Normal;
+        self.config.alerts.push(rule.clone());
+        self.selected_alert = self.config.alerts.len() - 1;
+        self.status_message = Some(match self.save_alerts() {
+            Ok(()) => format!("added alert {}", rule),
+            Err(e) => format!("added alert {}, but it was not saved: {}", rule, e),
+        });
+    }
+
+    pub fn delete_selected_alert(&mut self) {
+        if self.selected_alert >= self.config.alerts.len() {
+            return;
+        }
+        let rule = self.config.alerts.remove(self.selected_alert);
+        self.alert_state.rearm(&rule);
+        // The next rule moves up into the old row, unless it was the last one
+        let last = self.config.alerts.len().saturating_sub(1);
+        self.selected_alert = self.selected_alert.min(last);
+        self.status_message = Some(match self.save_alerts() {
+            Ok(()) => format!("deleted alert {}", rule),
+            Err(e) => format!("deleted alert {}, but it was not saved: {}", rule, e),
+        });
+    }
+
+    pub fn rearm_selected_alert(&mut self) {
+        let Some(rule) = self.selected_alert_rule().cloned() else {
+            return;
+        };
+        if !self.alert_state.is_triggered(&rule) {
+            self.status_message = Some(format!("{} is already armed", rule));
+            return;
+        }
+        self.alert_state.rearm(&rule);
+        self.status_message = Some(match self.save_alerts() {
+            Ok(()) => format!("re-armed {}", rule),
+            Err(e) => format!("re-armed {}, but it was not saved: {}", rule, e),
+        });
+    }
+
+    // Writes the rules next to the triggered state, from where they are
+    // loaded on the next start
+    fn save_alerts(&mut self) -> Result<(), String> {
+        self.alert_state.rules = Some(self.config.alerts.clone());
+        match &self.alert_state_path {
+            Some(path) => save_alert_state(path, &self.alert_state),
+            None => Ok(()),
+        }
+    }
+
+    pub fn finish_trending(&mut self, result: TrendingResult) {
+        self.fetching_trending = false;
+        // Dropped for the rate limit, the next tick tries again
+        if matches!(result, Err(TuickerError::Throttled)) {
+            return;
+        }
+        // Also after a failure, which is tried again on the slow cadence
+        self.trending_fetched_at = Some(Instant::now());
+        match result {
+            Ok(coins) => {
+                self.trending = coins;
+                self.trending_error = None;
+            }
+            Err(e) => self.trending_error = Some(e.to_string()),
+        }
+        self.selected_trending = self
+            .selected_trending
+            .min(self.trending.len().saturating_sub(1));
+    }
+
+    // A failure only drops the figures from the header, the next try is on
+    // the same slow cadence
+    pub fn finish_global(&mut self, result: GlobalResult) {
+        self.fetching_global = false;
+        if matches!(result, Err(TuickerError::Throttled)) {
+            return;
+        }
+        self.global_fetched_at = Some(Instant::now());
+        match result {
+            Ok(stats) => {
+                let previous = self.global.as_ref().and_then(GlobalStats::btc_dominance);
+                self.dominance_change = match (stats.btc_dominance(), previous) {
+                    (Some(dominance), Some(previous)) => Some(dominance - previous),
+                    _ => None,
+                };
+                self.global = Some(stats);
+            }
+            Err(_) => {
+                self.global = None;
+                self.dominance_change = None;
+            }
+        }
+    }
+
+    // Fills the trend column and the saved charts with the prices of
+    // earlier sessions
+    pub fn finish_history_load(&mut self, result: Result<HashMap<String, Vec<Sample>>, String>) {
+        let saved = match result {
+            Ok(saved) => saved,
+            Err(e) => {
+                self.status_message = Some(format!("could not read the saved prices: {}", e));
+                return;
+            }
+        };
+        for (coin_id, samples) in saved {
+            // Removed while the files were read
+            if !self.config.coins.contains(&coin_id) {
+                continue;
+            }
+            let prices: Vec<f64> = samples.iter().map(|sample| sample.price).collect();
+            self.history.seed(&coin_id, &prices);
+            let session = self.saved_charts.remove(&coin_id).unwrap_or_default();
+            let mut points: Vec<(f64, f64)> = samples
+                .iter()
+                .map(|sample| (sample.time as f64, sample.price))
+                .collect();
+            points.extend(session);
+            self.saved_charts.insert(coin_id, points);
+        }
+    }
+
+    // The saved prices of the coin inside the last `range`
+    pub fn saved_chart(&self, coin_id: &str, range: ChartRange) -> Vec<(f64, f64)> {
+        let range_millis = range.days() as f64 * 24.0 * 60.0 * 60.0 * 1000.0;
+        let start = millis(SystemTime::now()) as f64 - range_millis;
+        self.saved_charts
+            .get(coin_id)
+            .map(|points| points.iter().filter(|(time, _)| *time >= start).copied())
+            .map(Iterator::collect)
+            .unwrap_or_default()
+    }
+
+    pub fn finish_fear_greed(&mut self, result: FearGreedResult) {
+        self.fetching_fear_greed = false;
+        self.fear_greed_fetched_at = Some(Instant::now());
+        self.fear_greed = result.ok();
+    }
+
+    pub fn select_next_trending(&mut self) {
+        if self.selected_trending + 1 < self.trending.len() {
+            self.selected_trending += 1;
+        }
+    }
+
+    pub fn select_previous_trending(&mut self) {
+        self.selected_trending = self.selected_trending.saturating_sub(1);
+    }
+
+    // Looks up the selected trending coin so it can join the watchlist, the
+    // same way ones typed in after 'a' are. Returns the id to look up
+    pub fn add_selected_trending(&mut self) -> Option<String> {
+        let coin_id = self.trending.get(self.selected_trending)?.id.clone();
+        if self.config.coins.contains(&coin_id) {
+            self.status_message = Some(format!("{} is already tracked", coin_id));
+            return None;
+        }
+        self.status_message = Some(format!("adding {}…", coin_id));
+        self.pending_lookup = Some(coin_id.clone());
+        Some(coin_id)
+    }
+
+    // Turned off in the meantime, the body is dropped right away
+    pub fn finish_debug(&mut self, info: FetchDebugInfo) {
+        if self.debug {
+            self.debug_info = Some(info);
+        }
+    }
+
+    // Scrolls the body, Esc or F12 close the view and let go of it
+    fn debug_key(&mut self, code: KeyCode, action: Option<Action>) {
+        match (code, action) {
+            (KeyCode::Esc, _) | (_, Some(Action::ToggleDebug)) => {
+                self.debug = false;
+                self.debug_info = None;
+                self.debug_scroll = 0;
+            }
+            (KeyCode::Down | KeyCode::Char('j'), _) => self.scroll_debug(1),
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.scroll_debug(-1),
+            (KeyCode::PageDown, _) => self.scroll_debug(10),
+            (KeyCode::PageUp, _) => self.scroll_debug(-10),
+            _ => {}
+        }
+    }
+
+    fn scroll_debug(&mut self, lines: i16) {
+        self.debug_scroll = self.debug_scroll.saturating_add_signed(lines);
+    }
+
+    pub fn finish_chart(&mut self, coin_id: String, range: ChartRange, result: ChartResult) {
+        if let Err(e) = &result {
+            log::warn!("chart of {} failed: {}", coin_id, e.detail());
+        }
+        self.charts
+            .insert((coin_id, range), ChartEntry::finished(result));
+    }
+
+    pub fn finish_ohlc(&mut self, coin_id: String, range: ChartRange, result: OhlcResult) {
+        if let Err(e) = &result {
+            log::warn!("candles of {} failed: {}", coin_id, e.detail());
+        }
+        self.ohlc
+            .insert((coin_id, range), ChartEntry::finished(result));
+    }
+
+    // Saves the coins on screen, in their current order, as CSV
+    pub fn export_visible(&mut self) {
+        let dir = self
+            .config
+            .export_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."));
+        let coins: Vec<Coin> = self.visible_coins().into_iter().cloned().collect();
+        self.status_message = Some(match export_csv(&dir, &coins, &self.config.currency) {
+            Ok(path) => format!("exported to {}", path.display()),
+            Err(e) => e,
+        });
+    }
+
+    // Ask for confirmation before removing the selected coin
+    pub fn start_delete(&mut self) {
+        if let Some(coin) = self.selected_coin() {
+            self.pending_delete = Some(coin.id.clone());
+            self.input_mode = InputMode::ConfirmDelete;
+        }
+    }
+
+    pub fn handle_delete_key(&mut self, code: KeyCode) {
+        let coin_id = self.pending_delete.take();
+        self.input_mode = InputMode::Normal;
+        if let (KeyCode::Char('y'), Some(coin_id)) = (code, coin_id) {
+            self.remove_coin(&coin_id);
+        }
+    }
+
+    fn remove_coin(&mut self, coin_id: &str) {
+        let old_index = self.selected().unwrap_or(0);
+        self.config.coins.retain(|id| id != coin_id);
+        self.coins.retain(|coin| coin.id != coin_id);
+        self.history.retain(&self.config.coins);
+        self.extreme_flashed_at.remove(coin_id);
+        self.saved_charts.remove(coin_id);
+        // The next row moves up into the old position
+        self.keep_selection(old_index);
+
+        if let Some(watchlist) = &mut self.watchlist {
+            watchlist.remove(coin_id);
+        }
+        self.status_message = Some(match self.save_watchlist() {
+            Ok(()) => format!("removed {}", coin_id),
+            Err(e) => format!(
+                "removed {}, but the watchlist was not saved: {}",
+                coin_id, e
+            ),
+        });
+    }
+
+    // The price of the selected coin as a plain number, or with `line` its
+    // symbol, price and 24h change like "BTC $65,321.18 +2.30%"
+    fn copy_selected(&self, line: bool) -> KeyAction {
+        let Some(coin) = self.selected_coin() else {
+            return KeyAction::Nothing;
+        };
+        let Some(quote) = self.displayed_quote(coin) else {
+            return KeyAction::Nothing;
+        };
+        if !line {
+            return KeyAction::Copy(quote.price.to_string());
+        }
+        let locale = self.config.number_locale();
+        let price = match self.display_currency() == BTC {
+            true => format_btc_amount(quote.price, self.config.prefer_sats, locale),
+            false => format_price(quote.price, &self.display_currency(), locale),
+        };
+        let text = format!("{} {} {:+.2}%", coin.symbol, price, quote.change_24h);
+        KeyAction::Copy(text)
+    }
+
+    // Copying failing is only worth a message, like a failed export
+    pub fn finish_copy(&mut self, text: &str, result: Result<(), String>) {
+        self.status_message = Some(match result {
+            Ok(()) => format!("copied {}", text),
+            Err(e) => e,
+        });
+    }
+
+    pub fn save_watchlist(&self) -> Result<(), String> {
+        match (&self.watchlist, watchlist_path()) {
+            (Some(watchlist), Some(path)) => save_watchlist(&path, watchlist),
+            _ => Ok(()),
+        }
+    }
+
+    // In the prompt when the id was typed in, in the footer when it came
+    // from the Trending tab
+    fn lookup_failed(&mut self, message: String) {
+        match self.input_mode {
+            InputMode::AddCoin => self.add_message = Some(message),
+            _ => self.status_message = Some(message),
+        }
+    }
+
+    // The API answered for a coin typed into the add prompt
+    pub fn finish_coin_lookup(&mut self, coin_id: String, result: RefreshResult) {
+        // The prompt was cancelled or a different id was entered since
+        if self.pending_lookup.as_ref() != Some(&coin_id) {
+            return;
+        }
+        self.pending_lookup = None;
+        let refresh = match result {
+            Ok(refresh) => refresh,
+            Err(e) => {
+                self.lookup_failed(format!("could not check {}: {}", coin_id, e));
+                return;
+            }
+        };
+        let Some(coin) = refresh.coins.into_iter().find(|coin| coin.id == coin_id) else {
+            // An exchange source says why, e.g. the id isn't in its symbol table
+            let message = match refresh.failure {
+                Some(failure) => failure.error.to_string(),
+                None => format!(
+                    "{} has no coin with id `{}`",
+                    self.source.supplier().unwrap_or(self.config.source.label()),
+                    coin_id
+                ),
+            };
+            self.lookup_failed(message);
+            return;
+        };
+
+        self.config.coins.push(coin_id.clone());
+        self.history.record(std::slice::from_ref(&coin));
+        let mut coins = self.coins.clone();
+        coins.push(coin);
+        self.set_coins(coins);
+        self.selected_id = Some(coin_id.clone());
+        self.input_mode = InputMode::Normal;
+
+        if let Some(watchlist) = &mut self.watchlist {
+            watchlist.add(&coin_id);
+        }
+        self.status_message = Some(match self.save_watchlist() {
+            Ok(()) => format!("added {}", coin_id),
+            Err(e) => format!("added {}, but the watchlist was not saved: {}", coin_id, e),
+        });
+    }
+
+    pub fn sort(&mut self) {
+        sort_coins(
+            &mut self.coins,
+            self.sort_key,
+            self.sort_dir,
+            &self.config.coins,
+        );
+    }
+
+    // The currency the market view shows prices in, BTC while prices are
+    // denominated in bitcoin
+    pub fn display_currency(&self) -> String {
+        match self.currency_index {
+            _ if self.ui_state.btc_denominated => BTC.to_string(),
+            0 => self.config.currency.clone(),
+            index => self.config.other_currencies()[index - 1].clone(),
+        }
+    }
+
+    // Currencies a refresh fetches besides the main one. BTC prices are only
+    // asked for while they are shown, they cost no request of their own
+    pub fn fetch_currencies(&self) -> Vec<String> {
+        let mut currencies = self.config.other_currencies();
+        if self.ui_state.btc_denominated {
+            currencies.push(BTC.to_string());
+        }
+        currencies
+    }
+
+    // Returns true when prices in BTC have to be fetched for the table
+    pub fn toggle_btc(&mut self) -> bool {
+        self.ui_state.btc_denominated = !self.ui_state.btc_denominated;
+        self.save_ui_state();
+        self.ui_state.btc_denominated
+            && self.coins.iter().any(|coin| !coin.quotes.contains_key(BTC))
+    }
+
+    // The preset picked with 't', or the one of the config file
+    pub fn theme_preset(&self) -> ThemePreset {
+        self.ui_state.theme.unwrap_or(self.config.theme.preset)
+    }
+
+    pub fn theme(&self) -> Theme {
+        if self.config.no_color {
+            return Theme::monochrome();
+        }
+        self.config.theme.theme(self.theme_preset())
+    }
+
+    pub fn toggle_theme(&mut self) {
+        self.ui_state.theme = Some(self.theme_preset().toggled());
+        self.save_ui_state();
+    }
+
+    pub fn row_layout(&self) -> RowLayout {
+        self.ui_state.row_layout
+    }
+
+    pub fn toggle_row_layout(&mut self) {
+        self.ui_state.row_layout = self.ui_state.row_layout.toggled();
+        self.save_ui_state();
+    }
+
+    // Quitting, with `q` or a signal: what is still being fetched is given
+    // up on, what is being saved is written out
+    pub async fn shut_down(&mut self) {
+        self.background.finish().await;
+        self.save_ui_state();
+        if let Err(e) = self.save_watchlist() {
+            log::warn!("could not save the watchlist: {}", e);
+        }
+    }
+
+    fn save_ui_state(&mut self) {
+        if let Some(path) = &self.ui_state_path
+            && let Err(e) = save_ui_state(path, &self.ui_state)
+        {
+            self.status_message = Some(e);
+        }
+    }
+
+    // Switches to the next currency, using the prices already fetched
+    pub fn cycle_currency(&mut self) {
+        let count = self.config.other_currencies().len() + 1;
+        self.currency_index = (self.currency_index + 1) % count;
+    }
+
+    // Price of `coin` in the display currency. None until a refresh has
+    // fetched it, e.g. for coins loaded from the cache
+    pub fn displayed_quote(&self, coin: &Coin) -> Option<Quote> {
+        // Whatever the API rounds it to, a bitcoin is worth one bitcoin
+        if self.ui_state.btc_denominated && coin.id == "bitcoin" {
+            return Some(Quote {
+                price: 1.0,
+                change_24h: 0.0,
+            });
+        }
+        if self.currency_index == 0 && !self.ui_state.btc_denominated {
+            return Some(Quote {
+                price: coin.current_price,
+                change_24h: coin.price_change_24h,
+            });
+        }
+        coin.quotes.get(&self.display_currency()).copied()
+    }
+
+    // Whether the market view has a column for `key`
+    pub fn shows_sort_key(&self, key: SortKey) -> bool {
+        match key {
+            SortKey::MarketCap => self.config.show_market_cap,
+            SortKey::Volume => self.config.show_volume,
+            SortKey::Change(horizon) => self.change_columns().contains(&horizon),
+            _ => true,
+        }
+    }
+
+    // Hidden columns are skipped
+    pub fn change_columns(&self) -> Vec<ChangeHorizon> {
+        match self.change_horizon {
+            Some(horizon) => vec![horizon],
+            None => self.config.change_columns.clone(),
+        }
+    }
+
+    // The configured change columns, then 24h, 7d and 30d in a single column
+    // and back, leaving out a step that looks the same as the configured ones
+    pub fn cycle_change_horizon(&mut self) {
+        loop {
+            self.change_horizon = match self.change_horizon {
+                None => Some(CYCLED_HORIZONS[0]),
+                Some(horizon) => CYCLED_HORIZONS
+                    .iter()
+                    .position(|cycled| *cycled == horizon)
+                    .and_then(|index| CYCLED_HORIZONS.get(index + 1))
+                    .copied(),
+            };
+            if self.change_horizon.is_none() || self.change_columns() != self.config.change_columns
+            {
+                break;
+            }
+        }
+        // Sorted by a change column that went away, sort by the one shown now
+        if let SortKey::Change(_) = self.sort_key
+            && !self.shows_sort_key(self.sort_key)
+            && let Some(horizon) = self.change_columns().first()
+        {
+            self.sort_key = SortKey::Change(*horizon);
+            self.sort();
+        }
+    }
+
+    // Change of `coin` over `horizon` in the display currency. Only the 24h
+    // one is fetched for the other currencies
+    pub fn displayed_change(&self, coin: &Coin, horizon: ChangeHorizon) -> Option<f64> {
+        match horizon {
+            ChangeHorizon::Day => self.displayed_quote(coin).map(|quote| quote.change_24h),
+            _ if self.display_currency() == self.config.currency => horizon.of(coin),
+            _ => None,
+        }
+    }
+
+    pub fn cycle_sort_key(&mut self) {
+        self.sort_key = self.sort_key.next();
+        while !self.shows_sort_key(self.sort_key) {
+            self.sort_key = self.sort_key.next();
+        }
+        self.sort();
+    }
+
+    pub fn reverse_sort(&mut self) {
+        self.sort_dir = self.sort_dir.reversed();
+        self.sort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::parse_alert_rule;
+    use crate::api::PartialFailure;
+    use crate::demo::DemoSource;
+    use crate::sinks::{AlertSink, FiredAlert};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn coin(id: &str, name: &str, price: f64) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: name.to_string(),
+            symbol: id[..3].to_uppercase(),
+            current_price: price,
+            ..Default::default()
+        }
+    }
+
+    fn test_app() -> App {
+        let config = Config {
+            coins: vec![
+                "bitcoin".to_string(),
+                "ethereum".to_string(),
+                "solana".to_string(),
+            ],
+            ..Config::default()
+        };
+        let mut app = App::new(config, None, Arc::new(DemoSource::new(0)));
+        app.apply_refresh(vec![
+            coin("bitcoin", "Bitcoin", 65000.0),
+            coin("ethereum", "Ethereum", 3000.0),
+            coin("solana", "Solana", 150.0),
+        ]);
+        app
+    }
+
+    #[test]
+    fn streamed_ticks_move_the_prices_until_the_stream_drops() {
+        use crate::stream::StreamTick;
+        let mut app = test_app();
+        app.config.alerts = vec![parse_alert_rule("bitcoin above 70000").unwrap()];
+        assert!(!app.finish_stream(StreamEvent::Connected));
+        assert!(app.streaming);
+        let ticks = vec![StreamTick {
+            coin_id: "bitcoin".to_string(),
+            price: 71000.0,
+            change_24h: 2.5,
+            volume: 1e9,
+        }];
+        app.finish_stream(StreamEvent::Prices(ticks));
+        let bitcoin = app.coins.iter().find(|coin| coin.id == "bitcoin").unwrap();
+        assert_eq!(bitcoin.current_price, 71000.0);
+        assert_eq!(bitcoin.total_volume, Some(1e9));
+        assert!(!app.alert_sinks.banner.messages.is_empty());
+        assert_eq!(app.streamed_refresh().coins.len(), 3);
+
+        // Only dropping a stream that was up asks for a poll
+        assert!(app.finish_stream(StreamEvent::Dropped("gone".to_string())));
+        assert!(!app.finish_stream(StreamEvent::Dropped("still gone".to_string())));
+        assert!(!app.streaming);
+    }
+
+    #[test]
+    fn alerts_show_a_banner_until_dismissed() {
+        let mut app = test_app();
+        app.config.alerts = vec![parse_alert_rule("bitcoin above 70000").unwrap()];
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 71000.0)]);
+        let banner = &app.alert_sinks.banner;
+        assert_eq!(banner.messages, ["ALERT: BIT crossed above $70,000.00"]);
+        assert_eq!(banner.coin_ids, ["bitcoin"]);
+        // Still above on the next refresh, nothing new
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 72000.0)]);
+        assert_eq!(app.alert_sinks.banner.messages.len(), 1);
+        app.dismiss_alerts();
+        assert!(app.alert_sinks.banner.messages.is_empty());
+        assert!(app.alert_sinks.banner.coin_ids.is_empty());
+    }
+
+    #[test]
+    fn new_session_extremes_flash_once_per_cooldown() {
+        let mut app = test_app();
+        app.apply_refresh(vec![
+            coin("bitcoin", "Bitcoin", 66000.0),
+            coin("solana", "Solana", 140.0),
+        ]);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("BIT new session high · SOL new session low")
+        );
+        app.status_message = None;
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 67000.0)]);
+        assert_eq!(app.status_message, None);
+        assert_eq!(app.history.range("bitcoin").unwrap().high, 67000.0);
+
+        // Removing a coin forgets its range and its cooldown
+        app.remove_coin("bitcoin");
+        app.config.coins.push("bitcoin".to_string());
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 60000.0)]);
+        assert_eq!(app.history.range("bitcoin").unwrap().high, 60000.0);
+        app.status_message = None;
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 61000.0)]);
+        assert_eq!(app.status_message.as_deref(), Some("BIT new session high"));
+    }
+
+    #[test]
+    fn saved_prices_seed_the_trend_and_the_saved_chart() {
+        let mut app = test_app();
+        let now = millis(SystemTime::now());
+        let hour = 60 * 60 * 1000;
+        let sample = |hours_ago: u64, price| Sample {
+            time: now - hours_ago * hour,
+            price,
+        };
+        let saved = HashMap::from([
+            (
+                "bitcoin".to_string(),
+                vec![sample(48, 60000.0), sample(2, 64000.0)],
+            ),
+            // Removed while the files were read
+            ("dogecoin".to_string(), vec![sample(1, 0.1)]),
+        ]);
+        app.finish_history_load(Ok(saved));
+        let trend: Vec<f64> = app
+            .history
+            .get("bitcoin")
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(trend, [60000.0, 64000.0, 65000.0]);
+        assert!(app.history.get("dogecoin").is_none());
+        // Only the last day of it for the 1 day chart
+        assert_eq!(app.saved_chart("bitcoin", ChartRange::Week).len(), 2);
+        assert_eq!(
+            app.saved_chart("bitcoin", ChartRange::Day),
+            [(sample(2, 0.0).time as f64, 64000.0)]
+        );
+
+        app.finish_history_load(Err("permission denied".to_string()));
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("could not read the saved prices: permission denied")
+        );
+    }
+
+    #[test]
+    fn y_copies_the_price_and_shift_y_the_line() {
+        let mut app = test_app();
+        app.coins[0].price_change_24h = 2.3;
+        app.coins[0].current_price = 65321.18;
+        assert_eq!(
+            app.handle_key(KeyCode::Char('y').into()),
+            KeyAction::Copy("65321.18".to_string())
+        );
+        assert_eq!(
+            app.handle_key(KeyCode::Char('Y').into()),
+            KeyAction::Copy("BIT $65,321.18 +2.30%".to_string())
+        );
+        app.finish_copy("65321.18", Ok(()));
+        assert_eq!(app.status_message.as_deref(), Some("copied 65321.18"));
+        app.finish_copy("65321.18", Err("no xclip".to_string()));
+        assert_eq!(app.status_message.as_deref(), Some("no xclip"));
+        // Nothing to copy without coins
+        let mut app = App::new(Config::default(), None, Arc::new(DemoSource::new(0)));
+        assert_eq!(
+            app.handle_key(KeyCode::Char('y').into()),
+            KeyAction::Nothing
+        );
+    }
+
+    // Remembers what it was asked to send
+    struct RecordingSink(Rc<RefCell<Vec<String>>>);
+
+    impl AlertSink for RecordingSink {
+        fn send(&mut self, alert: &FiredAlert) -> Result<(), String> {
+            let sent = format!("{}: {}", alert.summary, alert.body);
+            self.0.borrow_mut().push(sent);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn other_sinks_respect_the_cooldown() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let mut app = test_app();
+        app.alert_sinks
+            .others
+            .push(Box::new(RecordingSink(sent.clone())));
+        app.config.alerts = vec![parse_alert_rule("bitcoin above 70000").unwrap()];
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 70500.0)]);
+        assert_eq!(
+            *sent.borrow(),
+            ["ALERT: BIT crossed above $70,000.00: BIT is at $70,500.00"]
+        );
+        // Back under and over again right away fires the alert but only the banner shows it
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 69000.0)]);
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 71000.0)]);
+        assert_eq!(app.alert_sinks.banner.messages.len(), 2);
+        assert_eq!(sent.borrow().len(), 1);
+    }
+
+    fn type_into_alert_prompt(app: &mut App, text: &str) {
+        for c in text.chars() {
+            app.handle_alert_key(KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn alert_prompt_rejects_unknown_coins_and_bad_thresholds() {
+        let mut app = test_app();
+        app.start_add_alert();
+        type_into_alert_prompt(&mut app, "dogecoin");
+        app.handle_alert_key(KeyCode::Enter);
+        assert_eq!(
+            app.add_message.as_deref(),
+            Some("`dogecoin` is not in the watchlist")
+        );
+        app.add_input.clear();
+        // The symbol picks the coin too
+        type_into_alert_prompt(&mut app, "BIT");
+        app.handle_alert_key(KeyCode::Enter);
+        assert_eq!(
+            app.alert_prompt,
+            AlertPrompt::Direction("bitcoin".to_string())
+        );
+        app.handle_alert_key(KeyCode::Char('a'));
+        type_into_alert_prompt(&mut app, "70k");
+        app.handle_alert_key(KeyCode::Enter);
+        assert_eq!(app.add_message.as_deref(), Some("`70k` is not a number"));
+        assert_eq!(app.input_mode, InputMode::AddAlert);
+        assert!(app.config.alerts.is_empty());
+    }
+
+    #[test]
+    fn alerts_added_in_the_tab_are_saved() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-app-alerts", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("alerts.json");
+        let mut app = test_app();
+        app.use_alert_state(path.clone());
+        app.start_add_alert();
+        app.handle_alert_key(KeyCode::Down);
+        assert_eq!(app.add_input, "bitcoin");
+        app.handle_alert_key(KeyCode::Enter);
+        app.handle_alert_key(KeyCode::Char('b'));
+        type_into_alert_prompt(&mut app, "60000");
+        app.handle_alert_key(KeyCode::Enter);
+        assert_eq!(app.input_mode, InputMode::Normal);
+
+        // The next start uses the saved rules, not the config file's
+        let mut app = test_app();
+        app.config.alerts = vec![parse_alert_rule("solana above 200").unwrap()];
+        app.use_alert_state(path.clone());
+        assert_eq!(
+            app.config.alerts,
+            [parse_alert_rule("bitcoin below 60000").unwrap()]
+        );
+        app.delete_selected_alert();
+        assert!(load_alert_state(&path).rules.unwrap().is_empty());
+    }
+
+    #[test]
+    fn rearming_a_triggered_alert() {
+        let mut app = test_app();
+        app.config.alerts = vec![parse_alert_rule("bitcoin above 70000").unwrap()];
+        app.rearm_selected_alert();
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("bitcoin above 70000 is already armed")
+        );
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 71000.0)]);
+        assert!(app.alert_state.is_triggered(&app.config.alerts[0]));
+        app.rearm_selected_alert();
+        assert!(!app.alert_state.is_triggered(&app.config.alerts[0]));
+        // Still above, so it fires again on the next refresh
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 71000.0)]);
+        assert_eq!(app.alert_sinks.banner.messages.len(), 2);
+    }
+
+    #[test]
+    fn cycling_the_currency_uses_the_fetched_quotes() {
+        let mut app = test_app();
+        app.config.extra_currencies = vec!["usd".to_string(), "eur".to_string()];
+        let mut bitcoin = coin("bitcoin", "Bitcoin", 65000.0);
+        let euros = Quote {
+            price: 60000.0,
+            change_24h: -1.0,
+        };
+        bitcoin.quotes.insert("eur".to_string(), euros);
+        // usd is the main currency already, so it only comes up once
+        app.cycle_currency();
+        assert_eq!(app.display_currency(), "eur");
+        assert_eq!(app.displayed_quote(&bitcoin), Some(euros));
+        assert_eq!(app.displayed_quote(&coin("solana", "Solana", 150.0)), None);
+        app.cycle_currency();
+        assert_eq!(app.display_currency(), "usd");
+        assert_eq!(app.displayed_quote(&bitcoin).unwrap().price, 65000.0);
+    }
+
+    #[test]
+    fn btc_denomination_asks_for_btc_prices() {
+        let mut app = test_app();
+        assert!(app.fetch_currencies().is_empty());
+        assert!(app.toggle_btc());
+        assert_eq!(app.display_currency(), "btc");
+        assert_eq!(app.fetch_currencies(), ["btc"]);
+        let bitcoin = coin("bitcoin", "Bitcoin", 65000.0);
+        assert_eq!(app.displayed_quote(&bitcoin).unwrap().price, 1.0);
+        assert_eq!(app.displayed_quote(&coin("solana", "Solana", 150.0)), None);
+        assert!(!app.toggle_btc());
+        assert_eq!(app.display_currency(), "usd");
+    }
+
+    #[test]
+    fn cached_coins_are_shown_until_a_refresh() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-app-cache", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("coins.json");
+        let mut app = test_app();
+        app.use_cache(path.clone());
+        // Nothing cached yet, but the coins that were on screen get saved next time
+        assert!(app.cached_at.is_none());
+        app.apply_refresh(vec![coin("solana", "Solana", 140.0)]);
+
+        let mut app = App::new(test_app().config, None, Arc::new(DemoSource::new(0)));
+        app.use_cache(path);
+        assert!(app.cached_at.is_some());
+        assert_eq!(visible_ids(&app), ["solana"]);
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 65000.0)]);
+        assert!(app.cached_at.is_none());
+    }
+
+    fn visible_ids(app: &App) -> Vec<&str> {
+        app.visible_coins()
+            .iter()
+            .map(|coin| coin.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn refresh_drops_coins_that_are_no_longer_tracked() {
+        let mut app = test_app();
+        app.config.coins.retain(|id| id != "ethereum");
+        app.apply_refresh(vec![
+            coin("bitcoin", "Bitcoin", 1.0),
+            coin("ethereum", "Ethereum", 1.0),
+        ]);
+        assert_eq!(visible_ids(&app), ["bitcoin"]);
+        assert!(app.history.get("ethereum").is_none());
+    }
+
+    #[test]
+    fn selection_follows_the_coin_when_sorting() {
+        let mut app = test_app();
+        app.select_last();
+        assert_eq!(app.selected_coin().unwrap().id, "solana");
+        // Price descending puts solana last as well, ascending puts it first
+        app.sort_key = SortKey::Price;
+        app.sort_dir = SortDir::Ascending;
+        app.sort();
+        assert_eq!(app.selected(), Some(0));
+        assert_eq!(app.selected_coin().unwrap().id, "solana");
+    }
+
+    #[test]
+    fn sorting_skips_hidden_columns() {
+        let mut app = test_app();
+        app.config.show_market_cap = false;
+        app.sort_key = SortKey::Change(ChangeHorizon::Day);
+        app.cycle_sort_key();
+        assert_eq!(app.sort_key, SortKey::Volume);
+        app.config.show_volume = false;
+        app.sort_key = SortKey::Change(ChangeHorizon::Day);
+        app.cycle_sort_key();
+        assert_eq!(app.sort_key, SortKey::Name);
+    }
+
+    #[test]
+    fn change_column_cycles_through_the_horizons() {
+        let mut app = test_app();
+        app.sort_key = SortKey::Change(ChangeHorizon::Day);
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            app.cycle_change_horizon();
+            seen.push(app.change_columns());
+        }
+        assert_eq!(
+            seen,
+            [
+                vec![ChangeHorizon::Week],
+                vec![ChangeHorizon::Month],
+                vec![ChangeHorizon::Day],
+            ]
+        );
+        assert_eq!(app.change_horizon, None);
+        // Sorting followed the column along
+        app.cycle_change_horizon();
+        assert_eq!(app.sort_key, SortKey::Change(ChangeHorizon::Week));
+
+        app.config.change_columns = vec![ChangeHorizon::Hour, ChangeHorizon::Day];
+        app.change_horizon = None;
+        app.cycle_change_horizon();
+        assert_eq!(app.change_columns(), [ChangeHorizon::Day]);
+    }
+
+    fn trending_coin(id: &str, score: u32) -> TrendingCoin {
+        TrendingCoin {
+            id: id.to_string(),
+            symbol: id[..3].to_uppercase(),
+            name: id.to_string(),
+            market_cap_rank: None,
+            score,
+        }
+    }
+
+    #[test]
+    fn failed_trending_fetch_keeps_the_last_coins() {
+        let mut app = test_app();
+        app.view = View::Trending;
+        app.fetching_trending = true;
+        app.finish_trending(Ok(vec![
+            trending_coin("pepe", 0),
+            trending_coin("bitcoin", 1),
+        ]));
+        app.select_next_trending();
+        app.select_next_trending();
+        assert_eq!(app.selected_trending, 1);
+        app.finish_trending(Err(TuickerError::RateLimited(Duration::from_secs(30))));
+        assert_eq!(app.trending.len(), 2);
+        assert_eq!(app.trending_error.as_deref(), Some("rate limited"));
+        assert!(!app.fetching_trending);
+        // The price table doesn't hear about it
+        assert!(app.last_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn quitting_gives_up_on_fetches_and_finishes_saves() {
+        let mut app = test_app();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let fetch = tokio::spawn(std::future::pending::<()>());
+        let aborted = fetch.abort_handle();
+        app.background.fetch(fetch);
+        app.background.save(tokio::task::spawn_blocking(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let _ = tx.send(FetchMessage::HistoryFailed("saved".to_string()));
+        }));
+        app.shut_down().await;
+        assert!(matches!(rx.try_recv(), Ok(FetchMessage::HistoryFailed(_))));
+        tokio::task::yield_now().await;
+        assert!(aborted.is_finished());
+    }
+
+    #[test]
+    fn the_window_title_follows_the_refreshed_prices() {
+        let mut app = test_app();
+        app.coins.clear();
+        assert_eq!(app.window_title(), None);
+        let mut bitcoin = coin("bitcoin", "Bitcoin", 65321.18);
+        bitcoin.symbol = "btc".to_string();
+        app.finish_refresh(Refresh {
+            coins: vec![bitcoin],
+            failure: None,
+        });
+        assert_eq!(app.window_title().as_deref(), Some("₿ $65,321.18 +0.0%"));
+        app.config.title_format = "OFF".to_string();
+        assert_eq!(app.window_title(), None);
+    }
+
+    #[test]
+    fn throttled_panel_fetches_are_tried_again_on_the_next_tick() {
+        let mut app = test_app();
+        app.finish_trending(Ok(vec![trending_coin("pepe", 0)]));
+        let fetched_at = app.trending_fetched_at;
+        app.fetching_trending = true;
+        app.finish_trending(Err(TuickerError::Throttled));
+        assert!(!app.fetching_trending);
+        assert_eq!(app.trending_fetched_at, fetched_at);
+        assert!(app.trending_error.is_none());
+
+        app.fetching_global = true;
+        app.finish_global(Err(TuickerError::Throttled));
+        assert!(!app.fetching_global);
+        assert!(app.global_fetched_at.is_none());
+    }
+
+    #[test]
+    fn trending_coins_are_looked_up_before_joining_the_watchlist() {
+        let mut app = test_app();
+        app.finish_trending(Ok(vec![
+            trending_coin("bitcoin", 0),
+            trending_coin("pepe", 1),
+        ]));
+        assert_eq!(app.add_selected_trending(), None);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("bitcoin is already tracked")
+        );
+        app.select_next_trending();
+        assert_eq!(app.add_selected_trending().as_deref(), Some("pepe"));
+        app.finish_coin_lookup("pepe".to_string(), Ok(Refresh::default()));
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("CoinGecko has no coin with id `pepe`")
+        );
+        assert!(!app.config.coins.contains(&"pepe".to_string()));
+    }
+
+    #[tokio::test]
+    async fn exchange_sources_say_why_a_looked_up_coin_is_missing() {
+        use crate::binance::BinanceSource;
+        use crate::config::SourceKind;
+        use crate::source::CoinGeckoSource;
+        let mut app = test_app();
+        app.config.source = SourceKind::Binance;
+        app.finish_trending(Ok(vec![trending_coin("bonk", 0)]));
+        assert_eq!(app.add_selected_trending().as_deref(), Some("bonk"));
+        // Nothing maps bonk to a pair, so no request goes out
+        let binance = BinanceSource {
+            client: Client::new(),
+            api_url: "http://binance.invalid".to_string(),
+            symbols: Default::default(),
+            coingecko: CoinGeckoSource::at(Client::new(), "http://coingecko.invalid"),
+        };
+        let coin_ids = ["bonk".to_string()];
+        let result = binance
+            .refresh(&coin_ids, "usd", &[], RefreshHooks::NONE)
+            .await;
+        app.finish_coin_lookup("bonk".to_string(), result);
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("no Binance price for bonk, see [binance_symbols]")
+        );
+        assert!(!app.config.coins.contains(&"bonk".to_string()));
+        // Without a reason the message names the source
+        app.add_selected_trending();
+        app.finish_coin_lookup("bonk".to_string(), Ok(Refresh::default()));
+        assert_eq!(
+            app.status_message.as_deref(),
+            Some("Binance has no coin with id `bonk`")
+        );
+    }
+
+    #[test]
+    fn selection_stops_at_the_ends() {
+        let mut app = test_app();
+        app.select_first();
+        app.select_previous();
+        assert_eq!(app.selected(), Some(0));
+        app.select_last();
+        app.select_next();
+        assert_eq!(app.selected(), Some(2));
+    }
+
+    #[test]
+    fn filter_matches_name_or_symbol() {
+        let mut app = test_app();
+        app.start_filter();
+        for c in "ETH".chars() {
+            app.handle_filter_key(KeyCode::Char(c));
+        }
+        assert_eq!(visible_ids(&app), ["ethereum"]);
+        app.handle_filter_key(KeyCode::Esc);
+        assert_eq!(visible_ids(&app).len(), 3);
+    }
+
+    #[test]
+    fn rate_limiting_delays_the_next_refresh() {
+        let mut app = test_app();
+        let before = Instant::now();
+        let until = app
+            .refresh_failed(&TuickerError::RateLimited(Duration::from_secs(42)))
+            .unwrap();
+        assert!(until >= before + Duration::from_secs(42));
+        assert!(until <= Instant::now() + Duration::from_secs(42));
+        assert_eq!(app.next_refresh, until);
+        assert!(app.rate_limited());
+        assert_eq!(app.last_error.as_deref(), Some("rate limited"));
+    }
+
+    #[test]
+    fn other_failures_keep_the_schedule() {
+        let mut app = test_app();
+        let next_refresh = app.next_refresh;
+        let error = TuickerError::Api {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+            message: String::new(),
+        };
+        assert!(app.refresh_failed(&error).is_none());
+        assert_eq!(app.next_refresh, next_refresh);
+        assert!(app.last_refresh_failed);
+        // Old prices stay on screen
+        assert_eq!(app.coins.len(), 3);
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 1.0)]);
+        assert!(!app.last_refresh_failed);
+        assert!(app.last_error.is_none());
+    }
+
+    #[test]
+    fn prices_go_stale_after_the_configured_intervals() {
+        let last_ok = Instant::now();
+        let interval = Duration::from_secs(30);
+        let at = |seconds| last_ok + Duration::from_secs(seconds);
+        assert!(!is_stale(last_ok, interval, 3, at(0)));
+        assert!(!is_stale(last_ok, interval, 3, at(90)));
+        assert!(is_stale(last_ok, interval, 3, at(91)));
+        assert!(!is_stale(last_ok, interval, 0, at(20 * 60)));
+        // A clock reading from before the refresh isn't stale either
+        assert!(!is_stale(at(10), interval, 3, last_ok));
+    }
+
+    #[test]
+    fn repeated_failures_take_the_feed_offline() {
+        let mut app = test_app();
+        assert_eq!(app.connection(), Connection::Live);
+        let error = TuickerError::Api {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+            message: String::new(),
+        };
+        for _ in 1..OFFLINE_AFTER_FAILURES {
+            app.refresh_failed(&error);
+            assert_eq!(app.connection(), Connection::Degraded);
+        }
+        app.refresh_failed(&error);
+        assert_eq!(app.connection(), Connection::Offline);
+        assert_eq!(app.consecutive_failures, OFFLINE_AFTER_FAILURES);
+        app.apply_refresh(vec![coin("bitcoin", "Bitcoin", 1.0)]);
+        assert_eq!(app.connection(), Connection::Live);
+        assert_eq!(app.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn a_failed_batch_keeps_the_old_prices_of_its_coins() {
+        let mut app = test_app();
+        app.finish_refresh(Refresh {
+            coins: vec![coin("bitcoin", "Bitcoin", 70000.0)],
+            failure: Some(PartialFailure {
+                fetched: 1,
+                requested: 3,
+                error: TuickerError::RateLimited(Duration::from_secs(60)),
+            }),
+        });
+        let prices: Vec<f64> = app.coins.iter().map(|coin| coin.current_price).collect();
+        assert_eq!(prices, [70000.0, 3000.0, 150.0]);
+        assert!(app.last_refresh_failed);
+        let error = app.last_error.as_deref().unwrap();
+        assert!(error.starts_with("fetched 1/3 coins: "), "{}", error);
+    }
+
+    #[test]
+    fn add_prompt_rejects_tracked_and_invalid_ids() {
+        let mut app = test_app();
+        app.start_add_coin();
+        for c in "Bitcoin".chars() {
+            app.handle_add_key(KeyCode::Char(c));
+        }
+        assert!(app.handle_add_key(KeyCode::Enter).is_none());
+        assert!(app.add_message.is_some());
+
+        app.start_add_coin();
+        for c in "dogecoin".chars() {
+            app.handle_add_key(KeyCode::Char(c));
+        }
+        assert_eq!(
+            app.handle_add_key(KeyCode::Enter).as_deref(),
+            Some("dogecoin")
+        );
+        assert_eq!(app.pending_lookup.as_deref(), Some("dogecoin"));
+    }
+
+    #[test]
+    fn deleting_a_coin_needs_confirmation() {
+        let mut app = test_app();
+        app.select_first();
+        app.start_delete();
+        app.handle_delete_key(KeyCode::Char('n'));
+        assert_eq!(app.coins.len(), 3);
+        app.start_delete();
+        app.handle_delete_key(KeyCode::Char('y'));
+        assert_eq!(visible_ids(&app), ["ethereum", "solana"]);
+        assert_eq!(app.config.coins, ["ethereum", "solana"]);
+    }
+
+    #[test]
+    fn tabs_keep_their_selection_and_are_saved() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-app-tabs", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state.json");
+        let mut app = test_app();
+        app.ui_state_path = Some(path.clone());
+        app.handle_key(KeyCode::Char('j').into());
+        app.handle_key(KeyCode::Char('3').into());
+        assert_eq!(app.view, View::Alerts);
+        assert_eq!(crate::state::load_ui_state(&path).tab, Tab::Alerts);
+        app.handle_key(KeyCode::BackTab.into());
+        assert_eq!(app.view, View::Portfolio);
+        app.handle_key(KeyCode::BackTab.into());
+        assert_eq!(app.selected_coin().unwrap().id, "ethereum");
+        // Wraps around from the first tab to the last
+        app.handle_key(KeyCode::BackTab.into());
+        assert_eq!(app.view, View::Trending);
+        app.handle_key(KeyCode::Tab.into());
+        assert_eq!(app.view, View::Market);
+        assert_eq!(crate::state::load_ui_state(&path).tab, Tab::Market);
+    }
+
+    #[test]
+    fn theme_key_overrides_the_config_preset_and_is_saved() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-app-theme", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state.json");
+        let mut app = test_app();
+        app.ui_state_path = Some(path.clone());
+        app.config.theme.preset = ThemePreset::Light;
+        assert_eq!(app.theme_preset(), ThemePreset::Light);
+        app.handle_key(KeyCode::Char('t').into());
+        assert_eq!(app.theme_preset(), ThemePreset::Dark);
+        assert_eq!(app.theme(), Theme::default());
+        let saved = crate::state::load_ui_state(&path);
+        assert_eq!(saved.theme, Some(ThemePreset::Dark));
+        // Also from the other tabs
+        app.handle_key(KeyCode::Char('2').into());
+        app.handle_key(KeyCode::Char('t').into());
+        assert_eq!(app.theme_preset(), ThemePreset::Light);
+    }
+
+    #[test]
+    fn row_layout_key_switches_the_layout_and_is_saved() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-app-layout", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state.json");
+        let mut app = test_app();
+        app.ui_state_path = Some(path.clone());
+        assert_eq!(app.row_layout(), RowLayout::Compact);
+        app.handle_key(KeyCode::Char('v').into());
+        assert_eq!(app.row_layout(), RowLayout::Detailed);
+        let saved = crate::state::load_ui_state(&path);
+        assert_eq!(saved.row_layout, RowLayout::Detailed);
+        app.handle_key(KeyCode::Char('v').into());
+        assert_eq!(app.row_layout(), RowLayout::Compact);
+    }
+
+    #[test]
+    fn keys_go_to_the_view_on_screen() {
+        let mut app = test_app();
+        app.config.alerts = vec![parse_alert_rule("bitcoin above 70000").unwrap()];
+        assert_eq!(
+            app.handle_key(KeyCode::Char('r').into()),
+            KeyAction::Refresh
+        );
+        app.handle_key(KeyCode::Char('3').into());
+        // Re-arms the rule rather than refreshing, F5 still refreshes everywhere
+        assert_eq!(
+            app.handle_key(KeyCode::Char('r').into()),
+            KeyAction::Nothing
+        );
+        assert_eq!(app.handle_key(KeyCode::F(5).into()), KeyAction::Refresh);
+        assert_eq!(
+            app.handle_key(KeyCode::Char('q').into()),
+            KeyAction::Nothing
+        );
+        assert_eq!(app.view, View::Market);
+        assert_eq!(
+            app.handle_key(KeyCode::Enter.into()),
+            KeyAction::FetchChart("bitcoin".to_string())
+        );
+        // The detail view belongs to the market tab, 'q' only closes it
+        assert_eq!(app.tab(), Tab::Market);
+        assert_eq!(
+            app.handle_key(KeyCode::Char('q').into()),
+            KeyAction::Nothing
+        );
+        assert_eq!(app.handle_key(KeyCode::Char('q').into()), KeyAction::Quit);
+    }
+
+    #[test]
+    fn help_overlay_takes_the_keys_until_closed() {
+        let mut app = test_app();
+        app.handle_key(KeyCode::Char('?').into());
+        assert!(app.show_help);
+        assert_eq!(
+            app.handle_key(KeyCode::Char('q').into()),
+            KeyAction::Nothing
+        );
+        app.handle_key(KeyCode::Char('j').into());
+        assert_eq!(app.selected_coin().unwrap().id, "bitcoin");
+        app.handle_key(KeyCode::Esc.into());
+        assert!(!app.show_help);
+        assert_eq!(app.handle_key(KeyCode::Char('q').into()), KeyAction::Quit);
+    }
+}
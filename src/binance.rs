@@ -0,0 +1,390 @@
+//! Prices from Binance's public market data API, which needs no key and has
+//! far more room than CoinGecko's free tier. Binance only knows trading
+//! pairs, so coins are looked up by the asset their CoinGecko id maps to
+
+use crate::api::{
+    API_MESSAGE_LENGTH, ChartResult, GlobalResult, OhlcResult, PartialFailure, Refresh,
+    RefreshResult, TrendingResult, fetch_with_retry,
+};
+use crate::error::TuickerError;
+use crate::model::{Candle, ChartRange, Coin, truncate};
+use crate::source::{
+    CoinGeckoSource, DataSource, RefreshHooks, exchange_candle, exchange_debug_info, get_text,
+    no_price,
+};
+use futures::future::BoxFuture;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+pub const BINANCE_API_URL: &str = "https://api.binance.com";
+
+// CoinGecko id, Binance asset and name of the coins known without any
+// `[binance_symbols]`
+const BINANCE_ASSETS: &[(&str, &str, &str)] = &[
+    ("bitcoin", "BTC", "Bitcoin"),
+    ("ethereum", "ETH", "Ethereum"),
+    ("binancecoin", "BNB", "BNB"),
+    ("solana", "SOL", "Solana"),
+    ("ripple", "XRP", "XRP"),
+    ("dogecoin", "DOGE", "Dogecoin"),
+    ("cardano", "ADA", "Cardano"),
+    ("tron", "TRX", "TRON"),
+    ("avalanche-2", "AVAX", "Avalanche"),
+    ("shiba-inu", "SHIB", "Shiba Inu"),
+    ("the-open-network", "TON", "Toncoin"),
+    ("chainlink", "LINK", "Chainlink"),
+    ("polkadot", "DOT", "Polkadot"),
+    ("litecoin", "LTC", "Litecoin"),
+    ("bitcoin-cash", "BCH", "Bitcoin Cash"),
+    ("near", "NEAR", "NEAR Protocol"),
+    ("uniswap", "UNI", "Uniswap"),
+    ("stellar", "XLM", "Stellar"),
+    ("cosmos", "ATOM", "Cosmos Hub"),
+    ("sui", "SUI", "Sui"),
+    ("pepe", "PEPE", "Pepe"),
+];
+
+// The asset Binance trades `coin_id` as, the config before the built-ins
+pub fn binance_asset(symbols: &BTreeMap<String, String>, coin_id: &str) -> Option<String> {
+    match symbols.get(coin_id) {
+        Some(asset) => Some(asset.trim().to_uppercase()),
+        None => BINANCE_ASSETS
+            .iter()
+            .find(|(id, ..)| *id == coin_id)
+            .map(|(_, asset, _)| asset.to_string()),
+    }
+}
+
+// Dollars are quoted in USDT, Binance has next to no USD pairs
+pub fn binance_pair(asset: &str, currency: &str) -> String {
+    let quote = match currency {
+        "usd" => "USDT".to_string(),
+        other => other.to_uppercase(),
+    };
+    format!("{}{}", asset, quote)
+}
+
+fn coin_name(coin_id: &str) -> String {
+    match BINANCE_ASSETS.iter().find(|(id, ..)| *id == coin_id) {
+        Some((_, _, name)) => name.to_string(),
+        None => coin_id.to_string(),
+    }
+}
+
+// Binance sends every number as a string, "67187.34000000"
+pub(crate) fn number<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+// One entry of the ticker/24hr response, the rest of it isn't used
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ticker {
+    pub symbol: String,
+    #[serde(deserialize_with = "number")]
+    pub last_price: f64,
+    // Already a percentage like CoinGecko's
+    #[serde(deserialize_with = "number")]
+    pub price_change_percent: f64,
+    // In the quote asset, so in `currency`
+    #[serde(deserialize_with = "number")]
+    pub quote_volume: f64,
+}
+
+// Errors come back as {"code": -1121, "msg": "Invalid symbol."}
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    msg: String,
+}
+
+fn error(status: StatusCode, json_text: &str) -> TuickerError {
+    let message = match serde_json::from_str::<ErrorBody>(json_text) {
+        Ok(body) => body.msg,
+        Err(_) => json_text.to_string(),
+    };
+    TuickerError::Exchange {
+        name: "Binance",
+        status,
+        message: truncate(message.trim(), API_MESSAGE_LENGTH),
+    }
+}
+
+async fn get(client: &Client, url: &str) -> Result<String, TuickerError> {
+    get_text(client, url, error).await
+}
+
+pub fn ticker_url(base_url: &str, pairs: &[String]) -> String {
+    let quoted: Vec<String> = pairs.iter().map(|pair| format!("%22{}%22", pair)).collect();
+    format!(
+        "{}/api/v3/ticker/24hr?symbols=%5B{}%5D",
+        base_url,
+        quoted.join(",")
+    )
+}
+
+pub fn parse_tickers(json_text: &str) -> Result<Vec<Ticker>, TuickerError> {
+    Ok(serde_json::from_str(json_text)?)
+}
+
+// Candle length and count for each range, about as fine as CoinGecko's
+fn kline_interval(range: ChartRange) -> (&'static str, u32) {
+    match range {
+        ChartRange::Day => ("15m", 96),
+        ChartRange::Week => ("1h", 168),
+        ChartRange::Month => ("4h", 180),
+        ChartRange::Quarter => ("12h", 180),
+        ChartRange::Year => ("1d", 365),
+    }
+}
+
+// Each kline is [open time, "open", "high", "low", "close", ...] with the
+// volumes and trade counts after it, oldest first
+pub fn parse_klines(json_text: &str) -> Result<Vec<Candle>, TuickerError> {
+    let klines: Vec<Vec<serde_json::Value>> = serde_json::from_str(json_text)?;
+    klines
+        .iter()
+        .map(|kline| exchange_candle(kline, 1.0))
+        .collect()
+}
+
+pub struct BinanceSource {
+    // Without the CoinGecko key, which has no business going to Binance
+    pub client: Client,
+    pub api_url: String,
+    // `[binance_symbols]` from the config
+    pub symbols: BTreeMap<String, String>,
+    // Binance has no trending coins or market totals, those stay CoinGecko's
+    pub coingecko: CoinGeckoSource,
+}
+
+impl BinanceSource {
+    fn pair(&self, coin_id: &str, currency: &str) -> Result<String, TuickerError> {
+        match binance_asset(&self.symbols, coin_id) {
+            Some(asset) => Ok(binance_pair(&asset, currency)),
+            None => Err(unmapped(&[coin_id.to_string()])),
+        }
+    }
+
+    async fn candles(&self, coin_id: &str, currency: &str, range: ChartRange) -> OhlcResult {
+        let pair = self.pair(coin_id, currency)?;
+        let (interval, limit) = kline_interval(range);
+        let url = format!(
+            "{}/api/v3/klines?symbol={}&interval={}&limit={}",
+            self.api_url, pair, interval, limit
+        );
+        let json_text = get(&self.client, &url).await?;
+        parse_klines(&json_text)
+    }
+
+    async fn ticker_request(
+        &self,
+        pairs: &[String],
+        hooks: &RefreshHooks<'_>,
+    ) -> Result<Vec<Ticker>, TuickerError> {
+        let url = ticker_url(&self.api_url, pairs);
+        let started = Instant::now();
+        let fetched = fetch_with_retry(|| get(&self.client, &url), hooks.on_retry).await;
+        let parsed = match &fetched {
+            Ok(json_text) => parse_tickers(json_text),
+            Err(_) => Ok(Vec::new()),
+        };
+        if let Some(on_response) = hooks.on_response {
+            on_response(exchange_debug_info(url.clone(), started, &fetched, &parsed));
+        }
+        fetched?;
+        parsed
+    }
+
+    // Binance turns down the whole list with "Invalid symbol." for a single
+    // pair it doesn't trade, so then every pair is asked for on its own and
+    // the ones turned down are left out
+    async fn tickers_of(
+        &self,
+        pairs: &[String],
+        hooks: &RefreshHooks<'_>,
+    ) -> Result<Vec<Ticker>, TuickerError> {
+        match self.ticker_request(pairs, hooks).await {
+            Err(e) if is_rejected_pair(&e) && pairs.len() == 1 => {
+                log::debug!("no Binance pair {}: {}", pairs[0], e);
+                Ok(Vec::new())
+            }
+            Err(e) if is_rejected_pair(&e) => {
+                log::debug!("Binance turned down one of {}: {}", pairs.join(","), e);
+                let mut tickers = Vec::new();
+                for pair in pairs {
+                    match self.ticker_request(std::slice::from_ref(pair), hooks).await {
+                        Ok(found) => tickers.extend(found),
+                        Err(e) if is_rejected_pair(&e) => log::debug!("no Binance pair {}", pair),
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(tickers)
+            }
+            result => result,
+        }
+    }
+
+    // The coins in the order asked for. Ids without a Binance asset, or
+    // whose pair Binance doesn't have, are reported instead of failing the
+    // whole refresh
+    async fn tickers(
+        &self,
+        coin_ids: &[String],
+        currency: &str,
+        hooks: RefreshHooks<'_>,
+    ) -> RefreshResult {
+        let mut wanted: Vec<(String, String, String)> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for coin_id in coin_ids {
+            if wanted.iter().any(|(id, ..)| id == coin_id) {
+                continue;
+            }
+            match binance_asset(&self.symbols, coin_id) {
+                Some(asset) => {
+                    let pair = binance_pair(&asset, currency);
+                    wanted.push((coin_id.clone(), asset, pair));
+                }
+                None => missing.push(coin_id.clone()),
+            }
+        }
+        let mut tickers = Vec::new();
+        if !wanted.is_empty() {
+            let pairs: Vec<String> = wanted.iter().map(|(.., pair)| pair.clone()).collect();
+            tickers = self.tickers_of(&pairs, &hooks).await?;
+        }
+        let mut coins = Vec::new();
+        for (coin_id, asset, pair) in wanted {
+            match tickers.iter().find(|ticker| ticker.symbol == pair) {
+                Some(ticker) => coins.push(Coin {
+                    id: coin_id.clone(),
+                    name: coin_name(&coin_id),
+                    symbol: asset,
+                    current_price: ticker.last_price,
+                    price_change_24h: ticker.price_change_percent,
+                    market_cap: None,
+                    market_cap_rank: None,
+                    total_volume: Some(ticker.quote_volume),
+                    change_1h: None,
+                    change_7d: None,
+                    change_30d: None,
+                    quotes: BTreeMap::new(),
+                }),
+                None => missing.push(coin_id),
+            }
+        }
+        let failure = (!missing.is_empty()).then(|| PartialFailure {
+            fetched: coins.len(),
+            requested: coins.len() + missing.len(),
+            error: unmapped(&missing),
+        });
+        Ok(Refresh { coins, failure })
+    }
+}
+
+// A 400 is what a pair Binance doesn't trade gets, -1121 "Invalid symbol."
+fn is_rejected_pair(error: &TuickerError) -> bool {
+    matches!(
+        error,
+        TuickerError::Exchange {
+            name: "Binance",
+            status: StatusCode::BAD_REQUEST,
+            ..
+        }
+    )
+}
+
+fn unmapped(coin_ids: &[String]) -> TuickerError {
+    no_price("Binance", "binance_symbols", coin_ids)
+}
+
+impl DataSource for BinanceSource {
+    // Only `currency` is fetched, Binance has no pairs for most of the
+    // extra currencies
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        _extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        Box::pin(self.tickers(coin_ids, currency, hooks))
+    }
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(async move {
+            let candles = self.candles(coin_id, currency, range).await?;
+            Ok(candles
+                .into_iter()
+                .map(|candle| (candle.time, candle.close))
+                .collect())
+        })
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        Box::pin(self.candles(coin_id, currency, range))
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        self.coingecko.trending()
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        self.coingecko.global()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coin_ids_map_to_pairs_in_the_currency() {
+        let mut symbols = BTreeMap::new();
+        symbols.insert("my-token".to_string(), " myt ".to_string());
+        symbols.insert("ethereum".to_string(), "WETH".to_string());
+        assert_eq!(binance_asset(&symbols, "bitcoin").as_deref(), Some("BTC"));
+        assert_eq!(binance_asset(&symbols, "my-token").as_deref(), Some("MYT"));
+        // The config wins over the built-ins
+        assert_eq!(binance_asset(&symbols, "ethereum").as_deref(), Some("WETH"));
+        assert_eq!(binance_asset(&symbols, "unknown-coin"), None);
+
+        assert_eq!(binance_pair("BTC", "usd"), "BTCUSDT");
+        assert_eq!(binance_pair("ETH", "eur"), "ETHEUR");
+        assert_eq!(binance_pair("ETH", "btc"), "ETHBTC");
+    }
+
+    #[test]
+    fn the_ticker_url_lists_the_pairs() {
+        let pairs = vec!["BTCUSDT".to_string(), "ETHUSDT".to_string()];
+        assert_eq!(
+            ticker_url(BINANCE_API_URL, &pairs),
+            "https://api.binance.com/api/v3/ticker/24hr?symbols=%5B%22BTCUSDT%22,%22ETHUSDT%22%5D"
+        );
+    }
+
+    #[test]
+    fn binance_errors_keep_their_message() {
+        let error = error(
+            StatusCode::BAD_REQUEST,
+            r#"{"code": -1121, "msg": "Invalid symbol."}"#,
+        );
+        assert_eq!(error.to_string(), "Binance: Invalid symbol.");
+        assert!(!error.is_retryable());
+    }
+}
@@ -0,0 +1,105 @@
+//! The last prices that were fetched, shown at startup until a refresh finishes
+
+use crate::config::{write_atomic, xdg_dir};
+use crate::model::Coin;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// ~/.cache/tuicker/coins.json
+pub fn cache_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_CACHE_HOME", ".cache")?.join("coins.json"))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedCoins {
+    // Seconds since the Unix epoch
+    pub fetched_at: u64,
+    // Prices in another currency are no use after --currency changed
+    pub currency: String,
+    pub coins: Vec<Coin>,
+}
+
+impl CachedCoins {
+    pub fn new(currency: &str, coins: &[Coin]) -> Self {
+        let fetched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        CachedCoins {
+            fetched_at,
+            currency: currency.to_string(),
+            coins: coins.to_vec(),
+        }
+    }
+
+    pub fn fetched_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.fetched_at)
+    }
+}
+
+// Written to a temporary file first and renamed over the old cache, so a
+// crash halfway through leaves the previous cache in place
+pub fn save_cache(path: &Path, cached: &CachedCoins) -> Result<(), String> {
+    let text = serde_json::to_string(cached).map_err(|e| e.to_string())?;
+    write_atomic(path, text.as_bytes())
+}
+
+// A missing or unreadable cache just means starting with an empty table
+pub fn load_cache(path: &Path, currency: &str) -> Option<CachedCoins> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let cached: CachedCoins = serde_json::from_str(&text).ok()?;
+    (cached.currency == currency).then_some(cached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("coins.json")
+    }
+
+    fn coin() -> Coin {
+        Coin {
+            id: "bitcoin".to_string(),
+            name: "Bitcoin".to_string(),
+            symbol: "btc".to_string(),
+            current_price: 43250.5,
+            price_change_24h: -1.25,
+            market_cap: Some(850_000_000_000.0),
+            market_cap_rank: Some(1),
+            total_volume: Some(21_000_000_000.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let path = temp_path("cache-round-trip");
+        let cached = CachedCoins::new("eur", &[coin()]);
+        save_cache(&path, &cached).unwrap();
+        let loaded = load_cache(&path, "eur").unwrap();
+        assert_eq!(loaded.fetched_at, cached.fetched_at);
+        assert_eq!(loaded.coins, [coin()]);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn cache_in_another_currency_is_ignored() {
+        let path = temp_path("cache-currency");
+        save_cache(&path, &CachedCoins::new("eur", &[coin()])).unwrap();
+        assert!(load_cache(&path, "usd").is_none());
+    }
+
+    #[test]
+    fn corrupt_or_missing_cache_is_ignored() {
+        let path = temp_path("cache-corrupt");
+        assert!(load_cache(&path, "usd").is_none());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(load_cache(&path, "usd").is_none());
+    }
+}
@@ -0,0 +1,483 @@
+//! Command line arguments
+
+use crate::config::{ApiKey, ApiTier, parse_api_tier, parse_interval};
+use crate::logging::parse_level;
+use crate::model::{parse_coin_ids, parse_currency};
+use crate::output::{OutputFormat, parse_output_format, parse_time};
+use crate::serve::DEFAULT_SERVE_ADDRESS;
+use log::LevelFilter;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub const USAGE: &str =
+    "Usage: tuicker [--coins <id,id,...>] [--currency <code>] [--interval <time>] [--once [--format <format>] | --watch] [--no-cache] [--no-history] [--no-color] [--no-proxy] [--log-level <level>] [--metrics-listen <addr>] [--debug] [--demo]
+       tuicker serve [--listen <addr>] [--coins <id,id,...>] [--currency <code>] [--interval <time>] [--demo]
+       tuicker import --file <trades.csv>
+       tuicker export-history --coin <id> [--from <time>] [--to <time>] [--out <file>]
+
+Options:
+  --coins <ids>      Comma separated CoinGecko coin ids to track,
+                     overrides the config file
+  --currency <code>  Currency to show prices in, e.g. usd or eur
+  --interval <time>  Time between refreshes, e.g. 30, 90s or 2m
+  --api-key <key>    CoinGecko API key, also read from $TUICKER_COINGECKO_KEY
+  --api-tier <tier>  Plan the API key belongs to: demo (default) or pro
+  --once             Print the prices once instead of starting the TUI
+  --format <format>  What --once prints: table (default) or json
+  --watch            Repaint the --once table after every refresh instead
+                     of starting the TUI, until Ctrl-C
+  --no-cache         Don't show or save the prices from the last session
+  --no-history       Don't keep the prices of every refresh for the next sessions
+  --no-color         No colors and only ASCII characters, also set by $NO_COLOR
+  --no-proxy         Connect directly, leaving out the proxy of the config
+                     and of $HTTPS_PROXY or $ALL_PROXY
+  --log-level <level>
+                     Write error, warn, info, debug or trace lines to
+                     ~/.local/state/tuicker/tuicker.log, also set by $RUST_LOG
+  --metrics-listen <addr>
+                     Serve the prices as Prometheus metrics on
+                     http://<addr>/metrics, e.g. 127.0.0.1:9184
+  --debug            Start with the last raw API response on screen, F12
+                     opens and closes it too
+  --demo             Made up prices that move on every refresh, without
+                     any network. Nothing of the session is saved
+  -h, --help         Print this help
+
+Serve refreshes the prices without the TUI and answers GET /coins and
+/coins/<id> with them as JSON on --listen, 127.0.0.1:8080 by default
+
+Import replaces the holdings in ~/.config/tuicker/portfolio.toml of every
+coin in the file with what its rows of date,coin_id,side,quantity,price,fee
+add up to
+
+Export-history writes the saved prices of a coin as timestamp,price rows,
+to stdout unless --out names a file. --from and --to take dates like
+2024-05-01, including the whole --to day, or times like 2024-05-01T12:00:00Z
+
+The config file lives at ~/.config/tuicker/config.toml";
+
+#[derive(Debug)]
+pub struct Cli {
+    // Overrides the config file when set
+    pub coin_ids: Option<Vec<String>>,
+    pub currency: Option<String>,
+    pub refresh_interval: Option<Duration>,
+    pub api_key: Option<ApiKey>,
+    pub api_tier: Option<ApiTier>,
+    // Print one fetch to stdout and exit
+    pub once: bool,
+    pub format: OutputFormat,
+    // The --once table repainted on every refresh
+    pub watch: bool,
+    pub no_cache: bool,
+    pub no_history: bool,
+    pub no_color: bool,
+    pub no_proxy: bool,
+    // Overrides $RUST_LOG, nothing is logged without either
+    pub log_level: Option<LevelFilter>,
+    // Where /metrics is served while the TUI runs
+    pub metrics_listen: Option<SocketAddr>,
+    // Opens the debug view right away
+    pub debug: bool,
+    // Generated prices instead of CoinGecko
+    pub demo: bool,
+    // `serve`: where the JSON API listens instead of starting the TUI
+    pub serve: Option<SocketAddr>,
+    // `import --file`: the trades to build the portfolio from
+    pub import_file: Option<PathBuf>,
+    pub export_history: Option<HistoryExport>,
+    pub help: bool,
+}
+
+// `export-history`: the saved prices of a coin from `from` until before `to`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryExport {
+    pub coin_id: String,
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+    // `-` for stdout
+    pub out: PathBuf,
+}
+
+// A bare date as the end of a range takes the whole day in
+fn parse_range_end(text: &str) -> Result<SystemTime, String> {
+    let time = parse_time(text)?;
+    match text.trim().contains(['T', 't']) {
+        true => Ok(time),
+        false => Ok(time + Duration::from_secs(24 * 60 * 60)),
+    }
+}
+
+// Value of a flag given either inline (`--flag=value`) or as the next argument
+fn flag_value(
+    flag: &str,
+    inline_value: Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    match inline_value {
+        Some(value) => Ok(value),
+        None => args.next().ok_or_else(|| format!("{} needs a value", flag)),
+    }
+}
+
+fn parse_address(flag: &str, value: &str, example: &str) -> Result<SocketAddr, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "{} needs an address like {}, not `{}`",
+            flag, example, value
+        )
+    })
+}
+
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut cli = Cli {
+        coin_ids: None,
+        currency: None,
+        refresh_interval: None,
+        api_key: None,
+        api_tier: None,
+        once: false,
+        format: OutputFormat::Table,
+        watch: false,
+        no_cache: false,
+        no_history: false,
+        no_color: false,
+        no_proxy: false,
+        log_level: None,
+        metrics_listen: None,
+        debug: false,
+        demo: false,
+        serve: None,
+        import_file: None,
+        export_history: None,
+        help: false,
+    };
+    let (mut subcommand, mut listen) = (None, None);
+    let (mut coin_id, mut from, mut to, mut out) = (None, None, None, None);
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        // Accept both `--coins a,b` and `--coins=a,b`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        match flag.as_str() {
+            "--coins" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let coin_ids = parse_coin_ids(&value)?;
+                if coin_ids.is_empty() {
+                    return Err("--coins needs at least one coin id".to_string());
+                }
+                cli.coin_ids = Some(coin_ids);
+            }
+            "--currency" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.currency = Some(parse_currency(&value)?);
+            }
+            "--interval" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.refresh_interval = Some(parse_interval(&value)?);
+            }
+            "--api-key" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                let api_key = ApiKey::new(&value).ok_or("--api-key needs a key")?;
+                cli.api_key = Some(api_key);
+            }
+            "--api-tier" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.api_tier = Some(parse_api_tier(&value)?);
+            }
+            "--once" => cli.once = true,
+            "--watch" => cli.watch = true,
+            "--format" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.format = parse_output_format(&value)?;
+            }
+            "--no-cache" => cli.no_cache = true,
+            "--no-history" => cli.no_history = true,
+            "--no-color" => cli.no_color = true,
+            "--no-proxy" => cli.no_proxy = true,
+            "--debug" => cli.debug = true,
+            "--demo" => cli.demo = true,
+            "--log-level" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.log_level = Some(parse_level(&value)?);
+            }
+            "--metrics-listen" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.metrics_listen = Some(parse_address(&flag, &value, "127.0.0.1:9184")?);
+            }
+            "serve" | "import" | "export-history" => {
+                if let Some(first) = subcommand.replace(flag.clone()) {
+                    return Err(format!("{} and {} don't go together", first, flag));
+                }
+            }
+            "--listen" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                listen = Some(parse_address(&flag, &value, "127.0.0.1:8080")?);
+            }
+            "--file" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                cli.import_file = Some(PathBuf::from(value));
+            }
+            "--coin" => {
+                let value = flag_value(&flag, inline_value, &mut args)?;
+                match parse_coin_ids(&value)?[..] {
+                    [ref id] => coin_id = Some(id.clone()),
+                    _ => return Err("--coin needs a single coin id".to_string()),
+                }
+            }
+            "--from" => from = Some(parse_time(&flag_value(&flag, inline_value, &mut args)?)?),
+            "--to" => {
+                to = Some(parse_range_end(&flag_value(
+                    &flag,
+                    inline_value,
+                    &mut args,
+                )?)?)
+            }
+            "--out" => out = Some(PathBuf::from(flag_value(&flag, inline_value, &mut args)?)),
+            "-h" | "--help" => cli.help = true,
+            _ => return Err(format!("unknown argument `{}`\n\n{}", flag, USAGE)),
+        }
+    }
+    let is = |name: &str| subcommand.as_deref() == Some(name);
+    let (serve, import, export) = (is("serve"), is("import"), is("export-history"));
+    match (import, &cli.import_file) {
+        (true, None) if !cli.help => return Err("import needs --file <trades.csv>".to_string()),
+        (false, Some(_)) => return Err("--file only works with import".to_string()),
+        _ => {}
+    }
+    match (serve, listen) {
+        (true, listen) => cli.serve = Some(listen.unwrap_or(DEFAULT_SERVE_ADDRESS)),
+        (false, Some(_)) => return Err("--listen only works with serve".to_string()),
+        _ => {}
+    }
+    if let Some(subcommand) = &subcommand
+        && (cli.once || cli.watch || cli.metrics_listen.is_some())
+    {
+        return Err(format!(
+            "{} doesn't go together with --once, --watch or --metrics-listen",
+            subcommand
+        ));
+    }
+    if cli.watch && cli.once {
+        return Err("--watch and --once don't go together".to_string());
+    }
+    let range_flags = coin_id.is_some() || from.is_some() || to.is_some() || out.is_some();
+    match (export, coin_id) {
+        (true, None) if !cli.help => return Err("export-history needs --coin <id>".to_string()),
+        (false, _) if range_flags => {
+            return Err("--coin, --from, --to and --out only work with export-history".to_string());
+        }
+        (true, Some(coin_id)) => {
+            if let (Some(from), Some(to)) = (from, to)
+                && from >= to
+            {
+                return Err("--from has to be before --to".to_string());
+            }
+            cli.export_history = Some(HistoryExport {
+                coin_id,
+                from,
+                to,
+                out: out.unwrap_or_else(|| PathBuf::from("-")),
+            });
+        }
+        _ => {}
+    }
+    if cli.format != OutputFormat::Table && !cli.once {
+        return Err("--format only works together with --once".to_string());
+    }
+    if cli.metrics_listen.is_some() && (cli.once || cli.watch) {
+        return Err("--metrics-listen only works with the TUI, not --once or --watch".to_string());
+    }
+    Ok(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_override_nothing() {
+        let cli = parse(&[]).unwrap();
+        assert!(cli.coin_ids.is_none());
+        assert!(cli.currency.is_none());
+        assert!(cli.refresh_interval.is_none());
+        assert!(!cli.once);
+        assert!(!cli.no_cache);
+        assert!(!cli.no_history);
+        assert!(!cli.no_color);
+        assert!(!cli.no_proxy);
+        assert!(cli.log_level.is_none());
+        assert!(!cli.debug);
+        assert!(!cli.demo);
+        assert!(!cli.help);
+    }
+
+    #[test]
+    fn flags_take_inline_or_separate_values() {
+        let cli = parse(&[
+            "--coins=Bitcoin,solana",
+            "--currency",
+            "eur",
+            "--interval",
+            "2m",
+            "--no-cache",
+            "--no-history",
+            "--once",
+            "--log-level=debug",
+        ])
+        .unwrap();
+        assert_eq!(cli.coin_ids.unwrap(), ["bitcoin", "solana"]);
+        assert_eq!(cli.currency.as_deref(), Some("eur"));
+        assert_eq!(cli.refresh_interval, Some(Duration::from_secs(120)));
+        assert!(cli.no_cache);
+        assert!(cli.no_history);
+        assert!(cli.once);
+        assert_eq!(cli.log_level, Some(LevelFilter::Debug));
+    }
+
+    #[test]
+    fn metrics_listen_takes_an_address() {
+        let cli = parse(&["--metrics-listen=127.0.0.1:9184"]).unwrap();
+        assert_eq!(cli.metrics_listen, Some("127.0.0.1:9184".parse().unwrap()));
+        let error = parse(&["--metrics-listen", "9184"]).unwrap_err();
+        assert!(error.contains("like 127.0.0.1:9184"), "{}", error);
+        assert!(parse(&["--metrics-listen", "[::1]:9184", "--once"]).is_err());
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(parse(&["--coins"]).is_err());
+        assert!(parse(&["--coins", " , "]).is_err());
+        assert!(parse(&["--currency", "xyz"]).is_err());
+        assert!(parse(&["--interval", "soon"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        let error = parse(&["--verbose"]).unwrap_err();
+        assert!(error.starts_with("unknown argument `--verbose`"));
+        assert!(error.contains(USAGE));
+    }
+
+    #[test]
+    fn export_history_takes_a_coin_and_a_range() {
+        let cli = parse(&[
+            "export-history",
+            "--coin",
+            "bitcoin",
+            "--from",
+            "2024-05-01",
+            "--to=2024-05-07",
+            "--out",
+            "btc.csv",
+        ])
+        .unwrap();
+        let export = cli.export_history.unwrap();
+        assert_eq!(export.coin_id, "bitcoin");
+        assert_eq!(export.from, parse_time("2024-05-01").ok());
+        // The whole last day
+        assert_eq!(export.to, parse_time("2024-05-08").ok());
+        assert_eq!(export.out, PathBuf::from("btc.csv"));
+
+        let cli = parse(&[
+            "export-history",
+            "--coin",
+            "bitcoin",
+            "--to",
+            "2024-05-07T12:00:00Z",
+        ]);
+        let export = cli.unwrap().export_history.unwrap();
+        assert_eq!(export.to, parse_time("2024-05-07T12:00:00Z").ok());
+        assert_eq!((export.from, export.out), (None, PathBuf::from("-")));
+    }
+
+    #[test]
+    fn export_history_errors_are_clear() {
+        let reversed = ["export-history", "--coin", "bitcoin"];
+        let reversed = [
+            &reversed[..],
+            &["--from", "2024-05-07", "--to", "2024-05-01"],
+        ]
+        .concat();
+        assert_eq!(
+            parse(&reversed).unwrap_err(),
+            "--from has to be before --to"
+        );
+        let error = parse(&["export-history"]).unwrap_err();
+        assert_eq!(error, "export-history needs --coin <id>");
+        assert!(parse(&["export-history", "--coin", "bitcoin,solana"]).is_err());
+        let error = parse(&["export-history", "--coin", "bitcoin", "--from", "May 1"]);
+        assert!(error.unwrap_err().starts_with("invalid time `May 1`"));
+        assert!(parse(&["--coin", "bitcoin"]).is_err());
+    }
+
+    #[test]
+    fn serve_listens_on_localhost_by_default() {
+        let cli = parse(&["serve"]).unwrap();
+        assert_eq!(cli.serve, Some("127.0.0.1:8080".parse().unwrap()));
+        let cli = parse(&["serve", "--listen=0.0.0.0:9000", "--demo"]).unwrap();
+        assert_eq!(cli.serve, Some("0.0.0.0:9000".parse().unwrap()));
+        assert!(cli.demo);
+        assert_eq!(parse(&[]).unwrap().serve, None);
+        assert!(parse(&["--listen", "127.0.0.1:8080"]).is_err());
+        assert!(parse(&["serve", "--listen", "8080"]).is_err());
+        assert!(parse(&["serve", "--once"]).is_err());
+        // One subcommand at a time, and none with the other modes
+        let error = parse(&["serve", "import", "--file", "trades.csv"]).unwrap_err();
+        assert_eq!(error, "serve and import don't go together");
+        assert!(parse(&["export-history", "--coin", "bitcoin", "serve"]).is_err());
+        assert!(parse(&["serve", "serve"]).is_err());
+        let error = parse(&["import", "--file", "trades.csv", "--once"]).unwrap_err();
+        assert_eq!(
+            error,
+            "import doesn't go together with --once, --watch or --metrics-listen"
+        );
+        assert!(parse(&["export-history", "--coin", "bitcoin", "--watch"]).is_err());
+        assert!(parse(&["import", "--file=a.csv", "--metrics-listen=127.0.0.1:9184"]).is_err());
+    }
+
+    #[test]
+    fn watch_stands_on_its_own() {
+        assert!(parse(&["--watch", "--coins", "bitcoin"]).unwrap().watch);
+        assert!(!parse(&[]).unwrap().watch);
+        assert!(parse(&["--watch", "--once"]).is_err());
+        assert!(parse(&["--watch", "--format", "json"]).is_err());
+        assert!(parse(&["--watch", "--metrics-listen", "127.0.0.1:9184"]).is_err());
+        assert!(parse(&["serve", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn import_needs_a_file() {
+        let cli = parse(&["import", "--file", "trades.csv"]).unwrap();
+        assert_eq!(cli.import_file, Some(PathBuf::from("trades.csv")));
+        assert!(parse(&["import"]).is_err());
+        assert!(parse(&["--file", "trades.csv"]).is_err());
+    }
+
+    #[test]
+    fn format_needs_once() {
+        let cli = parse(&["--once", "--format=json"]).unwrap();
+        assert_eq!(cli.format, OutputFormat::Json);
+        assert!(parse(&["--format", "json"]).is_err());
+        assert!(parse(&["--once", "--format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn api_key_flags() {
+        let cli = parse(&["--api-key", "CG-secret", "--api-tier=PRO"]).unwrap();
+        assert_eq!(cli.api_key.unwrap().expose(), "CG-secret");
+        assert_eq!(cli.api_tier, Some(ApiTier::Pro));
+        assert!(parse(&["--api-key="]).is_err());
+        assert!(parse(&["--api-tier", "gold"]).is_err());
+    }
+
+    #[test]
+    fn help_flag() {
+        assert!(parse(&["-h"]).unwrap().help);
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+}
@@ -0,0 +1,115 @@
+//! Copying text to the system clipboard, through the desktop's own tool or
+//! the OSC 52 escape sequence the terminal passes on, also over SSH
+
+use serde::Deserialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardMethod {
+    // pbcopy, wl-copy or xclip
+    #[default]
+    Native,
+    // Asks the terminal to do it, the way that reaches the local clipboard
+    // from a remote session
+    Osc52,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | u32::from(*byte) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(BASE64[(group >> (18 - 6 * index) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+// Sets the clipboard ("c") to `text`
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+#[cfg(target_os = "macos")]
+fn native_command() -> Command {
+    Command::new("pbcopy")
+}
+
+#[cfg(not(target_os = "macos"))]
+fn native_command() -> Command {
+    match std::env::var_os("WAYLAND_DISPLAY") {
+        Some(_) => Command::new("wl-copy"),
+        None => {
+            let mut command = Command::new("xclip");
+            command.args(["-selection", "clipboard"]);
+            command
+        }
+    }
+}
+
+fn copy_native(text: &str) -> Result<(), String> {
+    let failed = |e: String| {
+        format!(
+            "could not copy to the clipboard: {} (clipboard = \"osc52\" works over SSH)",
+            e
+        )
+    };
+    let mut child = native_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| failed(e.to_string()))?;
+    }
+    // The tools hand the text to a process of their own and exit right away
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(failed(status.to_string())),
+        Err(e) => Err(failed(e.to_string())),
+    }
+}
+
+pub fn copy(method: ClipboardMethod, text: &str) -> Result<(), String> {
+    match method {
+        ClipboardMethod::Native => copy_native(text),
+        ClipboardMethod::Osc52 => {
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(osc52(text).as_bytes())
+                .and_then(|()| stdout.flush())
+                .map_err(|e| format!("could not copy to the clipboard: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("65321.18 ₿".as_bytes()), "NjUzMjEuMTgg4oK/");
+    }
+
+    #[test]
+    fn osc52_sets_the_clipboard_selection() {
+        assert_eq!(osc52("65321.18"), "\x1b]52;c;NjUzMjEuMTg=\x07");
+    }
+}
@@ -0,0 +1,385 @@
+//! Prices from CoinMarketCap, for keys of its plans. Coins are asked for by
+//! their CoinMarketCap slug, which is the CoinGecko id for most of them, or
+//! by symbol, and every quote is nested under the currency it is in
+
+use crate::api::{
+    API_MESSAGE_LENGTH, ChartResult, DEFAULT_RATE_LIMIT_DELAY, GlobalResult, GlobalStats,
+    OhlcResult, PartialFailure, Refresh, RefreshResult, TrendingResult, client_builder,
+    fetch_with_retry,
+};
+use crate::config::{CMC_KEY_ENV, Config};
+use crate::error::TuickerError;
+use crate::model::{ChartRange, Coin, truncate};
+use crate::source::{DataSource, RefreshHooks, exchange_debug_info, get_text, no_price};
+use futures::future::{BoxFuture, ready};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+pub const CMC_API_URL: &str = "https://pro-api.coinmarketcap.com";
+
+// CoinGecko ids whose CoinMarketCap slug is a different one
+const CMC_SLUGS: &[(&str, &str)] = &[
+    ("ripple", "xrp"),
+    ("binancecoin", "bnb"),
+    ("avalanche-2", "avalanche"),
+    ("polkadot", "polkadot-new"),
+    ("the-open-network", "toncoin"),
+    ("matic-network", "polygon"),
+    ("near", "near-protocol"),
+    ("sui", "sui"),
+];
+
+// How `coin_id` is asked for, the config before the built-ins. Symbols are
+// upper case on CoinMarketCap and slugs lower case, which tells them apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CmcCoin {
+    Slug(String),
+    Symbol(String),
+}
+
+pub fn cmc_coin(coins: &BTreeMap<String, String>, coin_id: &str) -> CmcCoin {
+    let name = match coins.get(coin_id) {
+        Some(name) => name.trim().to_string(),
+        None => CMC_SLUGS
+            .iter()
+            .find(|(id, _)| *id == coin_id)
+            .map_or(coin_id, |(_, slug)| slug)
+            .to_string(),
+    };
+    match name.chars().any(|c| c.is_ascii_lowercase()) {
+        true => CmcCoin::Slug(name),
+        false => CmcCoin::Symbol(name),
+    }
+}
+
+// One coin of the quotes/latest response
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Listing {
+    pub name: String,
+    pub symbol: String,
+    pub slug: String,
+    pub cmc_rank: Option<u32>,
+    // By upper case currency code, only the one asked for is there
+    pub quote: HashMap<String, CmcQuote>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CmcQuote {
+    pub price: Option<f64>,
+    pub volume_24h: Option<f64>,
+    pub market_cap: Option<f64>,
+    pub percent_change_1h: Option<f64>,
+    pub percent_change_24h: Option<f64>,
+    pub percent_change_7d: Option<f64>,
+    pub percent_change_30d: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+// Errors are {"status": {"error_code": 1001, "error_message": "..."}}
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    status: ErrorStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorStatus {
+    error_message: Option<String>,
+}
+
+// 402 is a used up monthly quota, which is waited out like a 429
+fn error(status: StatusCode, json_text: &str) -> TuickerError {
+    if status == StatusCode::PAYMENT_REQUIRED {
+        return TuickerError::RateLimited(DEFAULT_RATE_LIMIT_DELAY);
+    }
+    let message = match serde_json::from_str::<ErrorBody>(json_text) {
+        Ok(body) => body.status.error_message.unwrap_or_default(),
+        Err(_) => json_text.to_string(),
+    };
+    TuickerError::Exchange {
+        name: "CoinMarketCap",
+        status,
+        message: truncate(message.trim(), API_MESSAGE_LENGTH),
+    }
+}
+
+async fn get(client: &Client, url: &str) -> Result<String, TuickerError> {
+    get_text(client, url, error).await
+}
+
+// Keyed by CoinMarketCap id when asked by slug and by symbol otherwise,
+// either way every listing says both
+pub fn parse_quotes(json_text: &str) -> Result<Vec<Listing>, TuickerError> {
+    let parsed: Response<HashMap<String, Listing>> = serde_json::from_str(json_text)?;
+    Ok(parsed.data.into_values().collect())
+}
+
+// The coin in `currency`, None when CoinMarketCap has no price for it
+pub fn listing_coin(coin_id: &str, listing: &Listing, currency: &str) -> Option<Coin> {
+    let quote = listing.quote.get(&currency.to_uppercase())?;
+    Some(Coin {
+        id: coin_id.to_string(),
+        name: listing.name.clone(),
+        symbol: listing.symbol.clone(),
+        current_price: quote.price?,
+        price_change_24h: quote.percent_change_24h.unwrap_or(0.0),
+        market_cap: quote.market_cap,
+        market_cap_rank: listing.cmc_rank,
+        total_volume: quote.volume_24h,
+        change_1h: quote.percent_change_1h,
+        change_7d: quote.percent_change_7d,
+        change_30d: quote.percent_change_30d,
+        quotes: BTreeMap::new(),
+    })
+}
+
+// The global-metrics/quotes/latest figures, with the market cap in
+// `currency` only
+#[derive(Debug, Deserialize)]
+struct GlobalMetrics {
+    btc_dominance: f64,
+    eth_dominance: Option<f64>,
+    quote: HashMap<String, GlobalQuote>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GlobalQuote {
+    total_market_cap: f64,
+    total_market_cap_yesterday_percentage_change: Option<f64>,
+}
+
+pub fn parse_global_metrics(json_text: &str) -> GlobalResult {
+    let parsed: Response<GlobalMetrics> = serde_json::from_str(json_text)?;
+    let metrics = parsed.data;
+    let mut market_cap_percentage = BTreeMap::from([("btc".to_string(), metrics.btc_dominance)]);
+    if let Some(eth) = metrics.eth_dominance {
+        market_cap_percentage.insert("eth".to_string(), eth);
+    }
+    let mut change = 0.0;
+    let mut total_market_cap = BTreeMap::new();
+    for (currency, quote) in metrics.quote {
+        change = quote
+            .total_market_cap_yesterday_percentage_change
+            .unwrap_or(change);
+        total_market_cap.insert(currency.to_lowercase(), quote.total_market_cap);
+    }
+    Ok(GlobalStats {
+        total_market_cap,
+        market_cap_percentage,
+        market_cap_change_24h: change,
+    })
+}
+
+// The key goes into every request as a default header, on a client of its
+// own so it never reaches another host
+pub fn build_cmc_client(config: &Config) -> Result<Client, TuickerError> {
+    let Some(api_key) = &config.cmc_api_key else {
+        return Err(TuickerError::Config(format!(
+            "source = \"coinmarketcap\" needs cmc_api_key in the config or ${}",
+            CMC_KEY_ENV
+        )));
+    };
+    let mut value = HeaderValue::from_str(api_key.expose()).map_err(|_| {
+        TuickerError::Config(
+            "the CoinMarketCap key contains characters that aren't allowed".to_string(),
+        )
+    })?;
+    value.set_sensitive(true);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-cmc_pro_api_key", value);
+    Ok(client_builder(config)?.default_headers(headers).build()?)
+}
+
+fn unsupported(what: &str) -> TuickerError {
+    TuickerError::Exchange {
+        name: "CoinMarketCap",
+        status: StatusCode::NOT_IMPLEMENTED,
+        message: format!("no {} with this source", what),
+    }
+}
+
+pub struct CoinMarketCapSource {
+    // With the key as a default header
+    pub client: Client,
+    pub api_url: String,
+    // `[cmc_coins]` from the config
+    pub coins: BTreeMap<String, String>,
+}
+
+impl CoinMarketCapSource {
+    // The listings of one request by slug or by symbol. skip_invalid keeps
+    // an unknown coin from failing the others
+    async fn quotes(
+        &self,
+        param: &str,
+        names: &[&str],
+        currency: &str,
+        hooks: &RefreshHooks<'_>,
+    ) -> Result<Vec<Listing>, TuickerError> {
+        let url = format!(
+            "{}/v1/cryptocurrency/quotes/latest?{}={}&convert={}&skip_invalid=true",
+            self.api_url,
+            param,
+            names.join(","),
+            currency.to_uppercase()
+        );
+        let started = Instant::now();
+        let fetched = fetch_with_retry(|| get(&self.client, &url), hooks.on_retry).await;
+        let parsed = match &fetched {
+            Ok(json_text) => parse_quotes(json_text),
+            Err(_) => Ok(Vec::new()),
+        };
+        if let Some(on_response) = hooks.on_response {
+            on_response(exchange_debug_info(url.clone(), started, &fetched, &parsed));
+        }
+        fetched?;
+        parsed
+    }
+
+    // The coins in the order asked for, the ones CoinMarketCap doesn't know
+    // are reported instead of failing the whole refresh
+    async fn listings(
+        &self,
+        coin_ids: &[String],
+        currency: &str,
+        hooks: RefreshHooks<'_>,
+    ) -> RefreshResult {
+        let mut wanted: Vec<(String, CmcCoin)> = Vec::new();
+        for coin_id in coin_ids {
+            if !wanted.iter().any(|(id, _)| id == coin_id) {
+                wanted.push((coin_id.clone(), cmc_coin(&self.coins, coin_id)));
+            }
+        }
+        let names = |slugs: bool| -> Vec<&str> {
+            wanted
+                .iter()
+                .filter_map(|(_, coin)| match (coin, slugs) {
+                    (CmcCoin::Slug(name), true) | (CmcCoin::Symbol(name), false) => {
+                        Some(name.as_str())
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        let mut listings = Vec::new();
+        for (param, names) in [("slug", names(true)), ("symbol", names(false))] {
+            if !names.is_empty() {
+                listings.extend(self.quotes(param, &names, currency, &hooks).await?);
+            }
+        }
+        let mut coins = Vec::new();
+        let mut missing = Vec::new();
+        for (coin_id, coin) in &wanted {
+            let listing = listings.iter().find(|listing| match coin {
+                CmcCoin::Slug(slug) => listing.slug == *slug,
+                CmcCoin::Symbol(symbol) => listing.symbol == *symbol,
+            });
+            match listing.and_then(|listing| listing_coin(coin_id, listing, currency)) {
+                Some(coin) => coins.push(coin),
+                None => missing.push(coin_id.clone()),
+            }
+        }
+        let failure = (!missing.is_empty()).then(|| PartialFailure {
+            fetched: coins.len(),
+            requested: wanted.len(),
+            error: no_price("CoinMarketCap", "cmc_coins", &missing),
+        });
+        Ok(Refresh { coins, failure })
+    }
+
+    async fn global_metrics(&self) -> GlobalResult {
+        let url = format!("{}/v1/global-metrics/quotes/latest", self.api_url);
+        parse_global_metrics(&get(&self.client, &url).await?)
+    }
+}
+
+impl DataSource for CoinMarketCapSource {
+    // Only `currency` is fetched, the Basic plan converts to one at a time
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        _extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        Box::pin(self.listings(coin_ids, currency, hooks))
+    }
+
+    // Price history is only on the paid plans
+    fn chart<'a>(
+        &'a self,
+        _coin_id: &'a str,
+        _currency: &'a str,
+        _range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(ready(Err(unsupported("charts"))))
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        _coin_id: &'a str,
+        _currency: &'a str,
+        _range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        Box::pin(ready(Err(unsupported("charts"))))
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        Box::pin(ready(Err(unsupported("trending coins"))))
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        Box::pin(self.global_metrics())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coin_ids_are_asked_for_by_slug_or_symbol() {
+        let coins = BTreeMap::from([
+            ("my-token".to_string(), "MYT".to_string()),
+            ("other-token".to_string(), "other-slug".to_string()),
+        ]);
+        assert_eq!(cmc_coin(&coins, "bitcoin"), CmcCoin::Slug("bitcoin".into()));
+        assert_eq!(cmc_coin(&coins, "ripple"), CmcCoin::Slug("xrp".into()));
+        assert_eq!(cmc_coin(&coins, "my-token"), CmcCoin::Symbol("MYT".into()));
+        assert_eq!(
+            cmc_coin(&coins, "other-token"),
+            CmcCoin::Slug("other-slug".into())
+        );
+    }
+
+    #[test]
+    fn global_metrics_become_the_header_figures() {
+        let json_text = r#"{"data": {"btc_dominance": 52.31, "eth_dominance": 16.2,
+            "quote": {"USD": {"total_market_cap": 2311879541330.5,
+            "total_market_cap_yesterday_percentage_change": 1.41}}}}"#;
+        let global = parse_global_metrics(json_text).unwrap();
+        assert_eq!(global.total_market_cap["usd"], 2311879541330.5);
+        assert_eq!(global.btc_dominance(), Some(52.31));
+        assert_eq!(global.market_cap_change_24h, 1.41);
+    }
+
+    #[test]
+    fn quota_errors_are_rate_limiting() {
+        let body = r#"{"status": {"error_code": 1010, "error_message": "monthly limit"}}"#;
+        let quota = error(StatusCode::PAYMENT_REQUIRED, body);
+        assert!(matches!(quota, TuickerError::RateLimited(_)), "{:?}", quota);
+        let body =
+            r#"{"status": {"error_code": 1001, "error_message": "This API Key is invalid."}}"#;
+        let invalid = error(StatusCode::UNAUTHORIZED, body);
+        assert_eq!(
+            invalid.to_string(),
+            "CoinMarketCap: This API Key is invalid."
+        );
+    }
+}
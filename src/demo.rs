@@ -0,0 +1,453 @@
+//! The generated market of --demo: a handful of well known coins whose
+//! prices take a small random step on every refresh, without any network
+
+use crate::api::{
+    ChartResult, FetchDebugInfo, GlobalResult, GlobalStats, OhlcResult, Refresh, RefreshResult,
+    TrendingResult, pretty_json,
+};
+use crate::model::{BTC, Candle, ChartRange, Coin, Quote, TrendingCoin};
+use crate::source::{DataSource, RefreshHooks};
+use futures::future::{BoxFuture, ready};
+use reqwest::StatusCode;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Id, symbol, name, USD price and market cap, in market cap order
+const DEMO_COINS: &[(&str, &str, &str, f64, f64)] = &[
+    ("bitcoin", "BTC", "Bitcoin", 67187.34, 1.32e12),
+    ("ethereum", "ETH", "Ethereum", 3150.20, 3.79e11),
+    ("solana", "SOL", "Solana", 152.48, 7.05e10),
+    ("ripple", "XRP", "XRP", 0.5234, 2.89e10),
+    ("dogecoin", "DOGE", "Dogecoin", 0.1241, 1.79e10),
+    ("cardano", "ADA", "Cardano", 0.4512, 1.60e10),
+    ("polkadot", "DOT", "Polkadot", 6.82, 9.8e9),
+    ("litecoin", "LTC", "Litecoin", 72.15, 5.4e9),
+];
+
+// Rough rates from USD, for `currency` and `extra_currencies`
+const DEMO_RATES: &[(&str, f64)] = &[
+    ("usd", 1.0),
+    ("eur", 0.92),
+    ("gbp", 0.79),
+    ("jpy", 151.3),
+    ("inr", 83.4),
+    ("krw", 1362.0),
+    ("try", 32.2),
+    ("brl", 5.1),
+    ("cny", 7.24),
+    ("cad", 1.37),
+    ("aud", 1.52),
+    ("chf", 0.91),
+];
+
+// A refresh moves every price by up to this fraction either way
+const STEP: f64 = 0.004;
+const CHART_POINTS: usize = 96;
+const CANDLE_POINTS: usize = 4;
+const TRENDING_COUNT: usize = 7;
+
+// xorshift64, plenty for made up prices and the same for the same seed
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would stay zero forever
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    // In 0.0..1.0
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // In -1.0..1.0
+    fn signed(&mut self) -> f64 {
+        self.next() * 2.0 - 1.0
+    }
+}
+
+// FNV-1a, so coins outside DEMO_COINS get the same made up figures on
+// every run
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn rate(currency: &str) -> f64 {
+    DEMO_RATES
+        .iter()
+        .find(|(code, _)| *code == currency)
+        .map_or(1.0, |(_, rate)| *rate)
+}
+
+// The USD price, market cap and rank a coin starts out with. Any id works,
+// the ones not in DEMO_COINS are named after it
+fn listing(coin_id: &str) -> (String, String, f64, f64, Option<u32>) {
+    if let Some(index) = DEMO_COINS.iter().position(|(id, ..)| *id == coin_id) {
+        let (_, symbol, name, price, market_cap) = DEMO_COINS[index];
+        return (
+            symbol.to_string(),
+            name.to_string(),
+            price,
+            market_cap,
+            Some(index as u32 + 1),
+        );
+    }
+    let mut rng = Rng::new(hash(coin_id));
+    let price = 10f64.powf(rng.next() * 5.0 - 2.0);
+    let market_cap = 1e7 * 10f64.powf(rng.next() * 3.0);
+    let symbol: String = coin_id
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(4)
+        .collect();
+    let mut name = coin_id.replace('-', " ");
+    if let Some(first) = name.get(..1) {
+        name = first.to_uppercase() + &name[1..];
+    }
+    let rank = 100 + (hash(coin_id) % 400) as u32;
+    (symbol.to_uppercase(), name, price, market_cap, Some(rank))
+}
+
+// Where a coin has walked to since the demo started
+#[derive(Debug, Clone)]
+struct Walk {
+    symbol: String,
+    name: String,
+    rank: Option<u32>,
+    // In USD
+    price: f64,
+    supply: f64,
+    // Prices of 1 hour, 24 hours, 7 and 30 days ago, for the changes
+    ago: [f64; 4],
+}
+
+impl Walk {
+    fn new(coin_id: &str, rng: &mut Rng) -> Self {
+        let (symbol, name, price, market_cap, rank) = listing(coin_id);
+        let ago = [0.01, 0.05, 0.12, 0.25].map(|spread| price * (1.0 + rng.signed() * spread));
+        Walk {
+            symbol,
+            name,
+            rank,
+            price,
+            supply: market_cap / price,
+            ago,
+        }
+    }
+
+    fn change(&self, index: usize) -> f64 {
+        (self.price / self.ago[index] - 1.0) * 100.0
+    }
+
+    fn coin(&self, coin_id: &str, currency: &str, quotes: BTreeMap<String, Quote>) -> Coin {
+        let rate = rate(currency);
+        let market_cap = self.price * self.supply * rate;
+        Coin {
+            id: coin_id.to_string(),
+            name: self.name.clone(),
+            symbol: self.symbol.clone(),
+            current_price: self.price * rate,
+            price_change_24h: self.change(1),
+            market_cap: Some(market_cap),
+            market_cap_rank: self.rank,
+            total_volume: Some(market_cap * 0.04),
+            change_1h: Some(self.change(0)),
+            change_7d: Some(self.change(2)),
+            change_30d: Some(self.change(3)),
+            quotes,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Market {
+    rng: Rng,
+    walks: HashMap<String, Walk>,
+}
+
+impl Market {
+    fn walk(&mut self, coin_id: &str) -> &mut Walk {
+        let rng = &mut self.rng;
+        self.walks
+            .entry(coin_id.to_string())
+            .or_insert_with(|| Walk::new(coin_id, rng))
+    }
+
+    // Every coin asked for takes a step, new ones start at their listing
+    fn step(&mut self, coin_ids: &[String]) {
+        for coin_id in coin_ids {
+            let step = 1.0 + self.rng.signed() * STEP;
+            self.walk(coin_id).price *= step;
+        }
+    }
+}
+
+pub struct DemoSource {
+    // The same seed makes the same prices, for tests
+    seed: u64,
+    market: Mutex<Market>,
+}
+
+fn now_millis() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as f64
+}
+
+impl DemoSource {
+    pub fn new(seed: u64) -> Self {
+        DemoSource {
+            seed,
+            market: Mutex::new(Market {
+                rng: Rng::new(seed),
+                walks: HashMap::new(),
+            }),
+        }
+    }
+
+    // One step of the walk for every coin, priced like the markets request
+    pub fn coins(
+        &self,
+        coin_ids: &[String],
+        currency: &str,
+        extra_currencies: &[String],
+    ) -> Vec<Coin> {
+        let Ok(mut market) = self.market.lock() else {
+            return Vec::new();
+        };
+        market.step(coin_ids);
+        let bitcoin = market.walk("bitcoin").price;
+        coin_ids
+            .iter()
+            .map(|coin_id| {
+                let walk = market.walk(coin_id);
+                let quotes = extra_currencies
+                    .iter()
+                    .map(|code| {
+                        let price = match code.as_str() {
+                            BTC => walk.price / bitcoin,
+                            _ => walk.price * rate(code),
+                        };
+                        let change_24h = walk.change(1);
+                        (code.clone(), Quote { price, change_24h })
+                    })
+                    .collect();
+                walk.coin(coin_id, currency, quotes)
+            })
+            .collect()
+    }
+
+    // Walks back from the current price, the same way for the same coin
+    // and range
+    pub fn points(&self, coin_id: &str, currency: &str, range: ChartRange) -> Vec<(f64, f64)> {
+        let price = self
+            .market
+            .lock()
+            .map(|mut market| market.walk(coin_id).price * rate(currency))
+            .unwrap_or(1.0);
+        let mut rng = Rng::new(self.seed ^ hash(coin_id) ^ u64::from(range.days()));
+        let span = Duration::from_secs(u64::from(range.days()) * 24 * 60 * 60);
+        let spacing = span.as_millis() as f64 / CHART_POINTS as f64;
+        let now = now_millis();
+        let mut prices = vec![price];
+        let step = STEP * f64::from(range.days()).sqrt();
+        for _ in 1..CHART_POINTS {
+            let last = prices[prices.len() - 1];
+            prices.push(last * (1.0 + rng.signed() * step));
+        }
+        prices
+            .into_iter()
+            .rev()
+            .enumerate()
+            .map(|(index, price)| (now - spacing * (CHART_POINTS - 1 - index) as f64, price))
+            .collect()
+    }
+
+    fn trending_coins(&self) -> Vec<TrendingCoin> {
+        DEMO_COINS
+            .iter()
+            .rev()
+            .take(TRENDING_COUNT)
+            .enumerate()
+            .map(|(score, (id, symbol, name, ..))| TrendingCoin {
+                id: id.to_string(),
+                symbol: symbol.to_string(),
+                name: name.to_string(),
+                market_cap_rank: DEMO_COINS
+                    .iter()
+                    .position(|(other, ..)| other == id)
+                    .map(|index| index as u32 + 1),
+                score: score as u32,
+            })
+            .collect()
+    }
+
+    // The listed coins are about 60% of the whole market
+    fn global_stats(&self) -> GlobalStats {
+        let ids: Vec<String> = DEMO_COINS.iter().map(|(id, ..)| id.to_string()).collect();
+        let Ok(mut market) = self.market.lock() else {
+            return GlobalStats {
+                total_market_cap: BTreeMap::new(),
+                market_cap_percentage: BTreeMap::new(),
+                market_cap_change_24h: 0.0,
+            };
+        };
+        let walks: Vec<Walk> = ids.iter().map(|id| market.walk(id).clone()).collect();
+        let caps: Vec<f64> = walks.iter().map(|walk| walk.price * walk.supply).collect();
+        let total = caps.iter().sum::<f64>() / 0.6;
+        let total_market_cap = DEMO_RATES
+            .iter()
+            .map(|(code, rate)| (code.to_string(), total * rate))
+            .collect();
+        let market_cap_percentage = walks
+            .iter()
+            .zip(&caps)
+            .map(|(walk, cap)| (walk.symbol.to_lowercase(), cap / total * 100.0))
+            .collect();
+        let weighted: f64 = walks
+            .iter()
+            .zip(&caps)
+            .map(|(walk, cap)| walk.change(1) * cap)
+            .sum();
+        GlobalStats {
+            total_market_cap,
+            market_cap_percentage,
+            market_cap_change_24h: weighted / caps.iter().sum::<f64>(),
+        }
+    }
+}
+
+impl DataSource for DemoSource {
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        let coins = self.coins(coin_ids, currency, extra_currencies);
+        if let Some(on_response) = hooks.on_response {
+            let body = serde_json::to_string(&coins).unwrap_or_default();
+            on_response(FetchDebugInfo {
+                url: "demo://coins/markets".to_string(),
+                status: Some(StatusCode::OK),
+                elapsed: Duration::ZERO,
+                body: pretty_json(&body),
+                outcome: format!("parsed {} coins", coins.len()),
+            });
+        }
+        Box::pin(ready(Ok(Refresh {
+            coins,
+            failure: None,
+        })))
+    }
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(ready(Ok(self.points(coin_id, currency, range))))
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        let points = self.points(coin_id, currency, range);
+        let candles = points
+            .chunks(CANDLE_POINTS)
+            .map(|chunk| {
+                let prices = chunk.iter().map(|(_, price)| *price);
+                Candle {
+                    time: chunk[0].0,
+                    open: chunk[0].1,
+                    high: prices.clone().fold(f64::MIN, f64::max),
+                    low: prices.fold(f64::MAX, f64::min),
+                    close: chunk[chunk.len() - 1].1,
+                }
+            })
+            .collect();
+        Box::pin(ready(Ok(candles)))
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        Box::pin(ready(Ok(self.trending_coins())))
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        Box::pin(ready(Ok(self.global_stats())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(coin_ids: &[&str]) -> Vec<String> {
+        coin_ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn the_same_seed_walks_the_same_way() {
+        let coin_ids = ids(&["bitcoin", "ethereum", "some-new-coin"]);
+        let (first, second) = (DemoSource::new(7), DemoSource::new(7));
+        for _ in 0..3 {
+            assert_eq!(
+                first.coins(&coin_ids, "usd", &[]),
+                second.coins(&coin_ids, "usd", &[])
+            );
+        }
+        let other = DemoSource::new(8).coins(&coin_ids, "usd", &[]);
+        assert_ne!(first.coins(&coin_ids, "usd", &[]), other);
+    }
+
+    #[test]
+    fn every_refresh_moves_the_price_a_little() {
+        let source = DemoSource::new(1);
+        let coin_ids = ids(&["bitcoin"]);
+        let before = source.coins(&coin_ids, "usd", &[])[0].current_price;
+        let after = source.coins(&coin_ids, "usd", &[])[0].current_price;
+        assert_ne!(before, after);
+        assert!((after / before - 1.0).abs() <= STEP);
+    }
+
+    #[test]
+    fn any_coin_id_gets_a_listing_and_quotes() {
+        let source = DemoSource::new(3);
+        let currencies = ids(&["eur", BTC]);
+        let coins = source.coins(&ids(&["bitcoin", "my-token"]), "usd", &currencies);
+        let token = &coins[1];
+        assert_eq!(
+            (token.symbol.as_str(), token.name.as_str()),
+            ("MYTO", "My token")
+        );
+        assert!(token.market_cap_rank.is_some_and(|rank| rank >= 100));
+        let bitcoin = &coins[0];
+        assert_eq!(bitcoin.quotes[BTC].price, 1.0);
+        let eur = bitcoin.quotes["eur"].price / bitcoin.current_price;
+        assert!((eur - 0.92).abs() < 1e-9);
+    }
+
+    #[test]
+    fn charts_end_at_the_current_price() {
+        let source = DemoSource::new(5);
+        let price = source.coins(&ids(&["solana"]), "usd", &[])[0].current_price;
+        let points = source.points("solana", "usd", ChartRange::Week);
+        assert_eq!(points.len(), CHART_POINTS);
+        assert_eq!(points[points.len() - 1].1, price);
+        assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(points, source.points("solana", "usd", ChartRange::Week));
+    }
+}
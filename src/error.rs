@@ -0,0 +1,240 @@
+//! The error type shared by the whole app
+
+use reqwest::StatusCode;
+use std::io;
+use std::time::Duration;
+
+// Everything that can go wrong in tuicker, the Display text is short
+// enough to show in the footer
+#[derive(Debug)]
+pub enum TuickerError {
+    // The request never got a response: DNS, connect, a broken connection
+    Http(reqwest::Error),
+    // Opening the connection took longer than `connect_timeout`, or the
+    // whole request longer than `request_timeout`
+    Timeout(reqwest::Error),
+    // The API answered with an error status
+    Api {
+        status: StatusCode,
+        message: String,
+    },
+    // A price source other than CoinGecko answered with an error status
+    Exchange {
+        name: &'static str,
+        status: StatusCode,
+        message: String,
+    },
+    // HTTP 429, with how long the API asked us to wait
+    RateLimited(Duration),
+    // Our own limit of requests a minute is used up, and the request was
+    // one that is dropped rather than made to wait
+    Throttled,
+    // The response wasn't the JSON we expected
+    Parse(serde_json::Error),
+    // Terminal or file system failures
+    Io(io::Error),
+    Config(String),
+}
+
+impl TuickerError {
+    // Network problems and server side errors usually go away on their own,
+    // any other client error will fail the same way every time. Rate limiting
+    // is waited out by the refresh schedule instead of retrying right away
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TuickerError::Http(error) => {
+                error.is_connect() || error.is_request() || error.is_body()
+            }
+            // Often the network being slow only for a moment, like a failed connect
+            TuickerError::Timeout(_) => true,
+            TuickerError::Api { status, .. } | TuickerError::Exchange { status, .. } => {
+                status.is_server_error()
+            }
+            _ => false,
+        }
+    }
+
+    // No connection could be opened, which is the proxy's doing when one
+    // is set
+    pub fn is_connect(&self) -> bool {
+        matches!(self, TuickerError::Http(error) | TuickerError::Timeout(error) if error.is_connect())
+    }
+
+    // The app can't keep running, as opposed to a failed refresh that
+    // leaves the previous prices on screen
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, TuickerError::Io(_) | TuickerError::Config(_))
+    }
+
+    // The short text followed by every cause below it that it doesn't
+    // already say, for the connection panel
+    pub fn detail(&self) -> String {
+        let mut detail = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(inner) = source {
+            let cause = inner.to_string();
+            if !detail.contains(&cause) {
+                detail = format!("{}: {}", detail, cause);
+            }
+            source = inner.source();
+        }
+        detail
+    }
+}
+
+impl std::fmt::Display for TuickerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TuickerError::Timeout(error) if error.is_connect() => write!(f, "connection timed out"),
+            TuickerError::Timeout(_) => write!(f, "request timed out"),
+            TuickerError::Http(error) if error.is_connect() => {
+                // The DNS failure is buried a few levels down the source chain
+                let mut source = std::error::Error::source(error);
+                while let Some(inner) = source {
+                    if inner.to_string().contains("dns error") {
+                        return write!(f, "DNS lookup failed");
+                    }
+                    source = inner.source();
+                }
+                write!(f, "could not connect")
+            }
+            TuickerError::Http(error) => write!(f, "network error: {}", error),
+            // CoinGecko only answers 401 for missing or invalid keys
+            TuickerError::Api { status, message } if *status == StatusCode::UNAUTHORIZED => {
+                match message.is_empty() {
+                    true => write!(f, "CoinGecko: API key rejected"),
+                    false => write!(f, "CoinGecko: API key rejected ({})", message),
+                }
+            }
+            TuickerError::Api { status, message } if message.is_empty() => {
+                write!(f, "HTTP error {}", status)
+            }
+            TuickerError::Api { message, .. } => write!(f, "CoinGecko: {}", message),
+            TuickerError::Exchange {
+                name,
+                status,
+                message,
+            } if message.is_empty() => write!(f, "{}: HTTP error {}", name, status),
+            TuickerError::Exchange { name, message, .. } => write!(f, "{}: {}", name, message),
+            TuickerError::RateLimited(_) => write!(f, "rate limited"),
+            TuickerError::Throttled => write!(f, "throttled"),
+            TuickerError::Parse(error) => write!(f, "unexpected response: {}", error),
+            TuickerError::Io(error) => write!(f, "I/O error: {}", error),
+            TuickerError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for TuickerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TuickerError::Http(error) | TuickerError::Timeout(error) => Some(error),
+            TuickerError::Parse(error) => Some(error),
+            TuickerError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for TuickerError {
+    fn from(error: reqwest::Error) -> Self {
+        match error.is_timeout() {
+            true => TuickerError::Timeout(error),
+            false => TuickerError::Http(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for TuickerError {
+    fn from(error: serde_json::Error) -> Self {
+        TuickerError::Parse(error)
+    }
+}
+
+impl From<io::Error> for TuickerError {
+    fn from(error: io::Error) -> Self {
+        TuickerError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_map_to_parse() {
+        let error: TuickerError = serde_json::from_str::<Vec<u32>>("{").unwrap_err().into();
+        assert!(matches!(error, TuickerError::Parse(_)));
+        assert!(error.to_string().starts_with("unexpected response: "));
+        assert!(!error.is_retryable());
+        assert!(!error.is_fatal());
+    }
+
+    #[test]
+    fn io_errors_map_to_io_and_are_fatal() {
+        let error: TuickerError = io::Error::other("broken pipe").into();
+        assert!(matches!(error, TuickerError::Io(_)));
+        assert_eq!(error.to_string(), "I/O error: broken pipe");
+        assert!(error.is_fatal());
+    }
+
+    #[test]
+    fn api_errors_show_status_and_message() {
+        let error = TuickerError::Api {
+            status: StatusCode::NOT_FOUND,
+            message: "coin not found".to_string(),
+        };
+        assert_eq!(error.to_string(), "CoinGecko: coin not found");
+        assert!(!error.is_retryable());
+
+        let error = TuickerError::Api {
+            status: StatusCode::BAD_GATEWAY,
+            message: String::new(),
+        };
+        assert_eq!(error.to_string(), "HTTP error 502 Bad Gateway");
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn unauthorized_means_the_key_was_rejected() {
+        let error = TuickerError::Api {
+            status: StatusCode::UNAUTHORIZED,
+            message: "Invalid API Key".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "CoinGecko: API key rejected (Invalid API Key)"
+        );
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn rate_limiting_is_not_retried_right_away() {
+        let error = TuickerError::RateLimited(Duration::from_secs(30));
+        assert_eq!(error.to_string(), "rate limited");
+        assert!(!error.is_retryable());
+        assert!(!error.is_fatal());
+    }
+
+    #[test]
+    fn config_errors_are_fatal() {
+        let error = TuickerError::Config("key `currency`: unsupported".to_string());
+        assert_eq!(error.to_string(), "key `currency`: unsupported");
+        assert!(error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn connection_failures_map_to_http() {
+        // Nothing listens on port 1
+        let error: TuickerError = reqwest::get("http://127.0.0.1:1/")
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(error, TuickerError::Http(_)));
+        assert_eq!(error.to_string(), "could not connect");
+        assert!(error.is_retryable());
+        let detail = error.detail();
+        assert!(detail.starts_with("could not connect: "), "{}", detail);
+        assert!(detail.contains("127.0.0.1:1"), "{}", detail);
+    }
+}
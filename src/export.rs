@@ -0,0 +1,138 @@
+//! Saving the coins on screen, or the saved prices of one, as a CSV file
+
+use crate::history::Sample;
+use crate::model::Coin;
+use crate::output::rfc3339;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Quoted only when needed, with quotes inside doubled
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    fields.join(",") + "\r\n"
+}
+
+// Numbers stay unformatted whatever `number_locale` is, so spreadsheets can
+// do math on them
+fn format_csv(coins: &[Coin], currency: &str, time: SystemTime) -> String {
+    let timestamp = rfc3339(time);
+    let header = [
+        "symbol".to_string(),
+        "name".to_string(),
+        format!("price_{}", currency),
+        "change_24h".to_string(),
+        "timestamp".to_string(),
+    ];
+    let mut text = csv_row(&header);
+    for coin in coins {
+        text += &csv_row(&[
+            coin.symbol.clone(),
+            coin.name.clone(),
+            coin.current_price.to_string(),
+            coin.price_change_24h.to_string(),
+            timestamp.clone(),
+        ]);
+    }
+    text
+}
+
+// A timestamp,price row per sample for export-history
+pub fn format_history_csv(samples: &[Sample]) -> String {
+    let mut text = csv_row(&["timestamp".to_string(), "price".to_string()]);
+    for sample in samples {
+        let time = UNIX_EPOCH + Duration::from_millis(sample.time);
+        text += &csv_row(&[rfc3339(time), sample.price.to_string()]);
+    }
+    text
+}
+
+// tuicker-2024-05-01T120000.csv, no colons so it works on every file system
+fn export_file_name(time: SystemTime) -> String {
+    let timestamp = rfc3339(time).replace(':', "");
+    format!("tuicker-{}.csv", timestamp.trim_end_matches('Z'))
+}
+
+// Writes the coins to a new file in `dir` and returns its path
+pub fn export_csv(dir: &Path, coins: &[Coin], currency: &str) -> Result<PathBuf, String> {
+    let now = SystemTime::now();
+    let path = dir.join(export_file_name(now));
+    std::fs::write(&path, format_csv(coins, currency, now))
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(name: &str) -> Coin {
+        Coin {
+            id: "bitcoin".to_string(),
+            name: name.to_string(),
+            symbol: "BTC".to_string(),
+            current_price: 65000.5,
+            price_change_24h: -1.25,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_escaped() {
+        assert_eq!(csv_field("Bitcoin"), "Bitcoin");
+        assert_eq!(csv_field("Wrapped, Bitcoin"), "\"Wrapped, Bitcoin\"");
+        assert_eq!(csv_field("The \"Coin\""), "\"The \"\"Coin\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row_per_coin() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+        let text = format_csv(&[coin("Bitcoin"), coin("A, B")], "eur", time);
+        assert_eq!(
+            text,
+            "symbol,name,price_eur,change_24h,timestamp\r\n\
+             BTC,Bitcoin,65000.5,-1.25,2024-05-01T12:00:00Z\r\n\
+             BTC,\"A, B\",65000.5,-1.25,2024-05-01T12:00:00Z\r\n"
+        );
+    }
+
+    #[test]
+    fn history_csv_has_a_row_per_sample() {
+        let samples = [
+            Sample {
+                time: 1_714_564_800_000,
+                price: 60123.45,
+            },
+            Sample {
+                time: 1_714_564_830_500,
+                price: 60100.0,
+            },
+        ];
+        assert_eq!(
+            format_history_csv(&samples),
+            "timestamp,price\r\n2024-05-01T12:00:00Z,60123.45\r\n2024-05-01T12:00:30Z,60100\r\n"
+        );
+        assert_eq!(format_history_csv(&[]), "timestamp,price\r\n");
+    }
+
+    #[test]
+    fn export_file_name_has_no_colons() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+        assert_eq!(export_file_name(time), "tuicker-2024-05-01T120000.csv");
+    }
+
+    #[test]
+    fn export_to_a_missing_directory_fails() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-no-such-dir", std::process::id()));
+        let error = export_csv(&dir, &[coin("Bitcoin")], "usd").unwrap_err();
+        assert!(error.starts_with("could not write"), "{}", error);
+    }
+}
@@ -0,0 +1,354 @@
+//! `sources = [...]`: every request goes to the first source that isn't
+//! failing, each of them behind a breaker that opens after a few failed
+//! price refreshes in a row and lets a refresh through again once its
+//! cooldown is over
+
+use crate::api::{ChartResult, GlobalResult, OhlcResult, RefreshResult, TrendingResult};
+use crate::config::SourceKind;
+use crate::error::TuickerError;
+use crate::model::ChartRange;
+use crate::source::{DataSource, RefreshHooks};
+use futures::future::BoxFuture;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Failures in a row that take a source out, like the feed going offline
+pub const FAILURES_TO_OPEN: u32 = 3;
+// How long a source that was taken out is skipped before it gets another go
+pub const COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+// Closed while the source works, open while it is skipped. A rate limited
+// source is skipped for as long as it asked right away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Breaker {
+    pub failures: u32,
+    pub open_until: Option<Instant>,
+}
+
+impl Breaker {
+    // Once the cooldown is over a single request is let through, which
+    // closes the breaker or opens it for another cooldown
+    pub fn available(&self, now: Instant) -> bool {
+        self.open_until.is_none_or(|until| now >= until)
+    }
+
+    pub fn succeeded(&mut self) {
+        *self = Breaker::default();
+    }
+
+    // A request our own limit held back never reached the source
+    pub fn failed(&mut self, error: &TuickerError, now: Instant) {
+        if matches!(error, TuickerError::Throttled) {
+            return;
+        }
+        self.failures += 1;
+        if let TuickerError::RateLimited(delay) = error {
+            self.open_until = Some(now + *delay);
+        } else if self.failures >= FAILURES_TO_OPEN {
+            self.open_until = Some(now + COOLDOWN);
+        }
+    }
+}
+
+// The order to try the sources in: the available ones by priority, or
+// when none is, the one that comes back first
+pub fn try_order(breakers: &[Breaker], now: Instant) -> Vec<usize> {
+    let available: Vec<usize> = (0..breakers.len())
+        .filter(|&index| breakers[index].available(now))
+        .collect();
+    match available.is_empty() {
+        true => (0..breakers.len())
+            .min_by_key(|&index| breakers[index].open_until)
+            .into_iter()
+            .collect(),
+        false => available,
+    }
+}
+
+struct State {
+    breakers: Vec<Breaker>,
+    // The source that answered the last refresh
+    supplier: Option<usize>,
+}
+
+pub struct FailoverSource {
+    sources: Vec<(SourceKind, Arc<dyn DataSource>)>,
+    state: Mutex<State>,
+}
+
+impl FailoverSource {
+    pub fn new(sources: Vec<(SourceKind, Arc<dyn DataSource>)>) -> Self {
+        let breakers = vec![Breaker::default(); sources.len()];
+        FailoverSource {
+            sources,
+            state: Mutex::new(State {
+                breakers,
+                supplier: None,
+            }),
+        }
+    }
+
+    fn order(&self) -> Vec<usize> {
+        match self.state.lock() {
+            Ok(state) => try_order(&state.breakers, Instant::now()),
+            Err(_) => (0..self.sources.len()).collect(),
+        }
+    }
+
+    // Only the price refreshes move the breakers: a chart of a coin the
+    // exchange doesn't list, or CoinGecko's trending coins being rate
+    // limited behind an exchange, say nothing about its prices
+    fn record<T>(&self, index: usize, result: &Result<T, TuickerError>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let breaker = &mut state.breakers[index];
+        let label = self.sources[index].0.label();
+        match result {
+            Ok(_) => {
+                if breaker.failures > 0 {
+                    log::info!("{} is answering again", label);
+                }
+                breaker.succeeded();
+                state.supplier = Some(index);
+            }
+            Err(error) => {
+                breaker.failed(error, Instant::now());
+                match breaker.open_until {
+                    Some(_) => log::warn!("{} failed, skipping it for now: {}", label, error),
+                    None => log::info!("{} failed, trying the next source: {}", label, error),
+                }
+            }
+        }
+    }
+
+    // The first source that succeeds, in `order()`. The answers to a
+    // refresh move the breakers, the error of the last one tried is
+    // returned when all of them failed
+    async fn first_ok<'a, T>(
+        &'a self,
+        refresh: bool,
+        call: impl Fn(&'a dyn DataSource) -> BoxFuture<'a, Result<T, TuickerError>>,
+    ) -> Result<T, TuickerError> {
+        let mut last_error = None;
+        for index in self.order() {
+            let result = call(self.sources[index].1.as_ref()).await;
+            match (refresh, &result) {
+                (true, _) => self.record(index, &result),
+                (false, Err(error)) => {
+                    let label = self.sources[index].0.label();
+                    log::info!("{} failed, trying the next source: {}", label, error);
+                }
+                (false, Ok(_)) => {}
+            }
+            match result {
+                Ok(value) => return Ok(value),
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| TuickerError::Config("no sources".to_string())))
+    }
+}
+
+impl DataSource for FailoverSource {
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        Box::pin(self.first_ok(true, move |source| {
+            source.refresh(coin_ids, currency, extra_currencies, hooks)
+        }))
+    }
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(self.first_ok(false, move |source| source.chart(coin_id, currency, range)))
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        Box::pin(self.first_ok(false, move |source| source.ohlc(coin_id, currency, range)))
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        Box::pin(self.first_ok(false, |source| source.trending()))
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        Box::pin(self.first_ok(false, |source| source.global()))
+    }
+
+    fn supplier(&self) -> Option<&'static str> {
+        let state = self.state.lock().ok()?;
+        Some(self.sources[state.supplier?].0.label())
+    }
+
+    fn throttled(&self) -> bool {
+        self.sources.iter().any(|(_, source)| source.throttled())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Refresh;
+    use crate::demo::DemoSource;
+    use futures::future::ready;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    #[test]
+    fn the_breaker_opens_after_failures_in_a_row_and_closes_after_the_cooldown() {
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+        let error = TuickerError::Config("down".to_string());
+        for _ in 1..FAILURES_TO_OPEN {
+            breaker.failed(&error, now);
+            assert!(breaker.available(now));
+        }
+        breaker.failed(&error, now);
+        assert!(!breaker.available(now));
+        assert!(!breaker.available(now + COOLDOWN - Duration::from_secs(1)));
+        assert!(breaker.available(now + COOLDOWN));
+
+        // The one request let through fails, so it's another cooldown
+        let later = now + COOLDOWN;
+        breaker.failed(&error, later);
+        assert!(!breaker.available(later + Duration::from_secs(1)));
+        breaker.succeeded();
+        assert_eq!(breaker, Breaker::default());
+
+        // Rate limiting opens it right away, for the delay asked for
+        breaker.failed(&TuickerError::RateLimited(Duration::from_secs(30)), now);
+        assert!(!breaker.available(now + Duration::from_secs(29)));
+        assert!(breaker.available(now + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn sources_are_tried_by_priority_or_by_who_comes_back_first() {
+        let now = Instant::now();
+        let open = |secs| Breaker {
+            failures: FAILURES_TO_OPEN,
+            open_until: Some(now + Duration::from_secs(secs)),
+        };
+        assert_eq!(try_order(&[Breaker::default(); 3], now), [0, 1, 2]);
+        assert_eq!(try_order(&[open(60), Breaker::default()], now), [1]);
+        assert_eq!(try_order(&[open(60), open(10), open(30)], now), [1]);
+    }
+
+    // Fails while `down` is set, counting the refreshes it was asked for.
+    // Charts fail with `charts_down`
+    struct Flaky {
+        down: AtomicBool,
+        charts_down: bool,
+        calls: AtomicU32,
+    }
+
+    impl DataSource for Flaky {
+        fn refresh<'a>(
+            &'a self,
+            _coin_ids: &'a [String],
+            _currency: &'a str,
+            _extra_currencies: &'a [String],
+            _hooks: RefreshHooks<'a>,
+        ) -> BoxFuture<'a, RefreshResult> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(ready(match self.down.load(Ordering::SeqCst) {
+                true => Err(TuickerError::Config("down".to_string())),
+                false => Ok(Refresh::default()),
+            }))
+        }
+
+        fn chart<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a str,
+            _: ChartRange,
+        ) -> BoxFuture<'a, ChartResult> {
+            Box::pin(ready(match self.charts_down {
+                true => Err(TuickerError::Config("no chart".to_string())),
+                false => Ok(Vec::new()),
+            }))
+        }
+
+        fn ohlc<'a>(&'a self, _: &'a str, _: &'a str, _: ChartRange) -> BoxFuture<'a, OhlcResult> {
+            Box::pin(ready(Ok(Vec::new())))
+        }
+
+        fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+            Box::pin(ready(Ok(Vec::new())))
+        }
+
+        fn global(&self) -> BoxFuture<'_, GlobalResult> {
+            Box::pin(ready(Err(TuickerError::Config("no figures".to_string()))))
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_primary_hands_over_until_it_answers_again() {
+        let primary = Arc::new(Flaky {
+            down: AtomicBool::new(true),
+            charts_down: false,
+            calls: AtomicU32::new(0),
+        });
+        let source = FailoverSource::new(vec![
+            (SourceKind::CoinGecko, primary.clone()),
+            (SourceKind::Demo, Arc::new(DemoSource::new(7))),
+        ]);
+        let ids = vec!["bitcoin".to_string()];
+        let refresh = || source.refresh(&ids, "usd", &[], RefreshHooks::NONE);
+        assert_eq!(source.supplier(), None);
+        for _ in 0..FAILURES_TO_OPEN {
+            assert_eq!(refresh().await.unwrap().coins.len(), 1);
+        }
+        assert_eq!(source.supplier(), Some("demo"));
+        // Open now, so the primary isn't asked until the cooldown is over
+        refresh().await.unwrap();
+        assert_eq!(primary.calls.load(Ordering::SeqCst), FAILURES_TO_OPEN);
+
+        primary.down.store(false, Ordering::SeqCst);
+        source.state.lock().unwrap().breakers[0].open_until = Some(Instant::now());
+        assert!(refresh().await.unwrap().coins.is_empty());
+        assert_eq!(source.supplier(), Some("CoinGecko"));
+        assert_eq!(source.state.lock().unwrap().breakers[0], Breaker::default());
+
+        // Charts and the rest fail over too, without moving the supplier
+        assert!(source.global().await.is_ok());
+        assert_eq!(source.supplier(), Some("CoinGecko"));
+    }
+
+    #[tokio::test]
+    async fn failing_charts_leave_the_prices_where_they_are() {
+        let primary = Arc::new(Flaky {
+            down: AtomicBool::new(false),
+            charts_down: true,
+            calls: AtomicU32::new(0),
+        });
+        let source = FailoverSource::new(vec![
+            (SourceKind::Binance, primary.clone()),
+            (SourceKind::Demo, Arc::new(DemoSource::new(7))),
+        ]);
+        for _ in 0..=FAILURES_TO_OPEN {
+            let chart = source.chart("bitcoin", "usd", ChartRange::Week).await;
+            assert!(!chart.unwrap().is_empty());
+        }
+        assert_eq!(source.state.lock().unwrap().breakers[0], Breaker::default());
+        let ids = vec!["bitcoin".to_string()];
+        source
+            .refresh(&ids, "usd", &[], RefreshHooks::NONE)
+            .await
+            .unwrap();
+        assert_eq!(primary.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(source.supplier(), Some("Binance"));
+    }
+}
@@ -0,0 +1,136 @@
+//! The Fear & Greed index from alternative.me, shown next to the header
+
+use crate::api::get;
+use crate::error::TuickerError;
+use reqwest::Client;
+use serde::Deserialize;
+use serde::de::Error as _;
+
+// Public, no key needed. Only the latest value is asked for
+pub const FEAR_GREED_URL: &str = "https://api.alternative.me/fng/?limit=1";
+
+pub type FearGreedResult = Result<u8, TuickerError>;
+
+// The bands alternative.me labels the index with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sentiment {
+    ExtremeFear,
+    Fear,
+    Neutral,
+    Greed,
+    ExtremeGreed,
+}
+
+impl Sentiment {
+    // 0-24 extreme fear, 25-44 fear, 45-55 neutral, 56-75 greed, 76-100 extreme greed
+    pub fn of(value: u8) -> Self {
+        match value {
+            0..=24 => Sentiment::ExtremeFear,
+            25..=44 => Sentiment::Fear,
+            45..=55 => Sentiment::Neutral,
+            56..=75 => Sentiment::Greed,
+            _ => Sentiment::ExtremeGreed,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Sentiment::ExtremeFear => "Extreme Fear",
+            Sentiment::Fear => "Fear",
+            Sentiment::Neutral => "Neutral",
+            Sentiment::Greed => "Greed",
+            Sentiment::ExtremeGreed => "Extreme Greed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    data: Vec<IndexEntry>,
+}
+
+// The value comes as a string, e.g. "73"
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    value: String,
+}
+
+// Uses the client without the CoinGecko key, the index lives on another host
+pub async fn fetch_fear_greed(client: &Client, url: &str) -> FearGreedResult {
+    let response = get(client, url).await?;
+    parse_fear_greed(&response.text().await?)
+}
+
+pub fn parse_fear_greed(json_text: &str) -> FearGreedResult {
+    let index: Index = serde_json::from_str(json_text)?;
+    let entry = index
+        .data
+        .first()
+        .ok_or_else(|| serde_json::Error::custom("no index value in the response"))?;
+    let value: u8 = serde_json::from_str(&entry.value)?;
+    if value > 100 {
+        return Err(serde_json::Error::custom("index value above 100").into());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_latest_value() {
+        let body = r#"{
+            "name": "Fear and Greed Index",
+            "data": [{
+                "value": "73",
+                "value_classification": "Greed",
+                "timestamp": "1728864000",
+                "time_until_update": "41361"
+            }],
+            "metadata": {"error": null}
+        }"#;
+        assert_eq!(parse_fear_greed(body).unwrap(), 73);
+    }
+
+    #[test]
+    fn odd_values_are_parse_errors() {
+        for body in [
+            r#"{"data": []}"#,
+            r#"{"data": [{"value": "lots"}]}"#,
+            r#"{"data": [{"value": "180"}]}"#,
+            r#"{"metadata": {"error": "limit too high"}}"#,
+        ] {
+            let error = parse_fear_greed(body).unwrap_err();
+            assert!(
+                matches!(error, TuickerError::Parse(_)),
+                "{}: {:?}",
+                body,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn values_fall_into_the_labeled_bands() {
+        let labels: Vec<&str> = [0, 24, 25, 44, 45, 55, 56, 75, 76, 100]
+            .into_iter()
+            .map(|value| Sentiment::of(value).label())
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                "Extreme Fear",
+                "Extreme Fear",
+                "Fear",
+                "Fear",
+                "Neutral",
+                "Neutral",
+                "Greed",
+                "Greed",
+                "Extreme Greed",
+                "Extreme Greed",
+            ]
+        );
+    }
+}
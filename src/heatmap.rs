@@ -0,0 +1,220 @@
+//! Lays the coins of the market out as a heatmap, a block per coin sized by
+//! its market cap and colored by its 24h change
+
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+
+// Room for the symbol and the change, plus a column and line of gap
+pub const MIN_CELL_WIDTH: u16 = 8;
+pub const MIN_CELL_HEIGHT: u16 = 3;
+
+// Changes at or beyond this many percent get the deepest color
+const FULL_CHANGE: f64 = 10.0;
+const NEUTRAL: (u8, u8, u8) = (48, 48, 48);
+const DEEP_RED: (u8, u8, u8) = (170, 20, 20);
+const DEEP_GREEN: (u8, u8, u8) = (20, 150, 40);
+
+// Gray for no change, turning red or green the further it goes
+pub fn heat_color(change: f64) -> Color {
+    let target = match change < 0.0 {
+        true => DEEP_RED,
+        false => DEEP_GREEN,
+    };
+    let t = (change.abs() / FULL_CHANGE).min(1.0);
+    let mix = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * t).round() as u8;
+    Color::Rgb(
+        mix(NEUTRAL.0, target.0),
+        mix(NEUTRAL.1, target.1),
+        mix(NEUTRAL.2, target.2),
+    )
+}
+
+// Splits `total` into one size per weight, each at least `min` when there is
+// room for that, and the rest by weight. The sizes always add up to `total`
+pub fn split_by_weight(total: u16, weights: &[f64], min: u16) -> Vec<u16> {
+    let count = weights.len() as u16;
+    if count == 0 {
+        return Vec::new();
+    }
+    let base = match min.saturating_mul(count) <= total {
+        true => min,
+        false => total / count,
+    };
+    let rest = total - base * count;
+    let sum: f64 = weights.iter().sum();
+    let shares: Vec<f64> = weights
+        .iter()
+        .map(|weight| match sum > 0.0 {
+            true => rest as f64 * weight / sum,
+            false => rest as f64 / count as f64,
+        })
+        .collect();
+    let mut sizes: Vec<u16> = shares.iter().map(|share| base + *share as u16).collect();
+    // What the rounding down left over goes to the largest remainders
+    let mut by_remainder: Vec<usize> = (0..sizes.len()).collect();
+    by_remainder.sort_by(|&a, &b| shares[b].fract().total_cmp(&shares[a].fract()));
+    let left = total - sizes.iter().sum::<u16>();
+    for index in by_remainder.into_iter().cycle().take(left as usize) {
+        sizes[index] += 1;
+    }
+    sizes
+}
+
+// One cell per weight, in order, as long as they fit at the least size:
+// rows of a similar total weight, the heavier rows taller and the heavier
+// cells of a row wider
+pub fn grid(area: Rect, weights: &[f64]) -> Vec<Rect> {
+    let per_row = (area.width / MIN_CELL_WIDTH) as usize;
+    let max_rows = (area.height / MIN_CELL_HEIGHT) as usize;
+    let count = weights.len().min(per_row * max_rows);
+    if count == 0 {
+        return Vec::new();
+    }
+    let weights: Vec<f64> = match weights[..count].iter().any(|weight| *weight > 0.0) {
+        true => weights[..count]
+            .iter()
+            .map(|weight| weight.max(0.0))
+            .collect(),
+        false => vec![1.0; count],
+    };
+    // About square cells, a character being twice as high as wide
+    let square = (2.0 * count as f64 * area.height as f64 / area.width as f64).sqrt();
+    let rows = (square.round() as usize).clamp(count.div_ceil(per_row), count.min(max_rows));
+
+    let mut groups: Vec<&[f64]> = Vec::new();
+    let mut rest = &weights[..];
+    for row in 0..rows {
+        let rows_left = rows - row - 1;
+        let least = rest.len().saturating_sub(rows_left * per_row).max(1);
+        let most = per_row.min(rest.len() - rows_left);
+        let target = rest.iter().sum::<f64>() / (rows_left + 1) as f64;
+        let mut taken = least;
+        while taken < most && rest[..taken].iter().sum::<f64>() < target {
+            taken += 1;
+        }
+        let (group, remaining) = rest.split_at(taken);
+        groups.push(group);
+        rest = remaining;
+    }
+
+    let row_weights: Vec<f64> = groups.iter().map(|group| group.iter().sum()).collect();
+    let heights = split_by_weight(area.height, &row_weights, MIN_CELL_HEIGHT);
+    let mut cells = Vec::with_capacity(count);
+    let mut y = area.y;
+    for (group, height) in groups.iter().zip(heights) {
+        let mut x = area.x;
+        for width in split_by_weight(area.width, group, MIN_CELL_WIDTH) {
+            cells.push(Rect::new(x, y, width, height));
+            x += width;
+        }
+        y += height;
+    }
+    cells
+}
+
+// The cell in the next row up or down from `index`, the one straight above
+// or below its middle if there is one
+pub fn vertical_neighbour(cells: &[Rect], index: usize, down: bool) -> Option<usize> {
+    let cell = cells.get(index)?;
+    let row_y = cells
+        .iter()
+        .map(|other| other.y)
+        .filter(|&y| match down {
+            true => y > cell.y,
+            false => y < cell.y,
+        })
+        .reduce(|a, b| match down {
+            true => a.min(b),
+            false => a.max(b),
+        })?;
+    let middle = cell.x + cell.width / 2;
+    let distance = |other: &Rect| match middle {
+        x if x < other.x => other.x - x,
+        x if x >= other.right() => x + 1 - other.right(),
+        _ => 0,
+    };
+    (0..cells.len())
+        .filter(|&other| cells[other].y == row_y)
+        .min_by_key(|&other| distance(&cells[other]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_go_from_red_through_gray_to_green() {
+        assert_eq!(heat_color(0.0), Color::Rgb(48, 48, 48));
+        assert_eq!(heat_color(-10.0), Color::Rgb(170, 20, 20));
+        assert_eq!(heat_color(-25.0), Color::Rgb(170, 20, 20));
+        assert_eq!(heat_color(10.0), Color::Rgb(20, 150, 40));
+        assert_eq!(heat_color(5.0), Color::Rgb(34, 99, 44));
+    }
+
+    #[test]
+    fn splits_add_up_and_keep_the_least_size() {
+        assert_eq!(split_by_weight(30, &[2.0, 1.0], 8), [17, 13]);
+        assert_eq!(split_by_weight(30, &[100.0, 0.0, 0.0], 8), [14, 8, 8]);
+        assert_eq!(split_by_weight(10, &[1.0, 1.0, 1.0], 0), [4, 3, 3]);
+        // Too small for the least size, shared out evenly
+        assert_eq!(split_by_weight(10, &[5.0, 1.0, 1.0], 8), [4, 3, 3]);
+        assert!(split_by_weight(10, &[], 8).is_empty());
+    }
+
+    fn area_of(cells: &[Rect]) -> u32 {
+        cells
+            .iter()
+            .map(|cell| cell.width as u32 * cell.height as u32)
+            .sum()
+    }
+
+    #[test]
+    fn grid_fills_the_area_with_heavier_coins_bigger() {
+        let area = Rect::new(1, 1, 80, 20);
+        let cells = grid(area, &[60.0, 20.0, 10.0, 5.0, 5.0]);
+        assert_eq!(cells.len(), 5);
+        assert_eq!(area_of(&cells), 80 * 20);
+        assert!(cells.iter().all(|cell| area.contains(cell.as_position())));
+        // The biggest coin gets a row to itself
+        assert_eq!(cells[0], Rect::new(1, 1, 80, 11));
+        assert!(cells[1].width > cells[3].width);
+        for pair in cells.windows(2) {
+            assert!(pair[0].intersection(pair[1]).is_empty());
+        }
+    }
+
+    #[test]
+    fn grid_shows_as_many_cells_as_fit() {
+        let cells = grid(Rect::new(0, 0, 24, 6), &[1.0; 10]);
+        assert_eq!(cells.len(), 6);
+        assert!(cells.iter().all(|cell| cell.width == 8 && cell.height == 3));
+        // Without market caps every coin weighs the same
+        let cells = grid(Rect::new(0, 0, 40, 6), &[0.0; 4]);
+        assert_eq!(cells.len(), 4);
+        assert!(
+            cells
+                .iter()
+                .all(|cell| cell.width == 10 && cell.height == 6)
+        );
+        assert!(grid(Rect::new(0, 0, 7, 20), &[1.0]).is_empty());
+        assert!(grid(Rect::new(0, 0, 80, 20), &[]).is_empty());
+    }
+
+    #[test]
+    fn neighbours_are_found_across_rows() {
+        // Two wide cells above three narrow ones
+        let cells = [
+            Rect::new(0, 0, 30, 3),
+            Rect::new(30, 0, 30, 3),
+            Rect::new(0, 3, 20, 3),
+            Rect::new(20, 3, 20, 3),
+            Rect::new(40, 3, 20, 3),
+        ];
+        assert_eq!(vertical_neighbour(&cells, 0, true), Some(2));
+        assert_eq!(vertical_neighbour(&cells, 1, true), Some(4));
+        assert_eq!(vertical_neighbour(&cells, 2, false), Some(0));
+        assert_eq!(vertical_neighbour(&cells, 4, false), Some(1));
+        assert_eq!(vertical_neighbour(&cells, 0, false), None);
+        assert_eq!(vertical_neighbour(&cells, 3, true), None);
+    }
+}
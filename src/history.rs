@@ -0,0 +1,363 @@
+//! Prices of every refresh kept on disk, so the trend column and the chart
+//! start out with the recent past after a restart
+
+use crate::config::{write_atomic, xdg_dir};
+use crate::model::Coin;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Written as the first line of every file. Files of another version are
+// migrated line by line, or started over when that isn't possible
+pub const HISTORY_VERSION: u32 = 1;
+
+// ~/.local/share/tuicker/history
+pub fn history_dir() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_DATA_HOME", ".local/share")?.join("history"))
+}
+
+// The price of a coin at one refresh
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    // Milliseconds since the Unix epoch, like the times of the chart API
+    pub time: u64,
+    pub price: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+pub fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// A line of a file written with `version`. No older version exists yet, so
+// anything else can't be read
+fn migrate(version: u32, line: &str) -> Option<Sample> {
+    match version {
+        HISTORY_VERSION => serde_json::from_str(line).ok(),
+        _ => None,
+    }
+}
+
+// The samples of a file, and whether it has to be written again because it
+// was of another version or had a line that could not be read
+fn read_samples(path: &Path) -> (Vec<Sample>, bool) {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return (Vec::new(), false);
+    };
+    let mut lines = text.lines();
+    let header = lines
+        .next()
+        .and_then(|line| serde_json::from_str::<Header>(line).ok());
+    let Some(Header { version }) = header else {
+        return (Vec::new(), true);
+    };
+    let mut rewrite = version != HISTORY_VERSION;
+    let mut samples = Vec::new();
+    for line in lines {
+        match migrate(version, line) {
+            Some(sample) => samples.push(sample),
+            None => rewrite = true,
+        }
+    }
+    (samples, rewrite)
+}
+
+// Replaces the file with `write_atomic`, or removes it when there
+// is nothing left to keep
+fn write_samples(path: &Path, samples: &[Sample]) -> Result<(), String> {
+    if samples.is_empty() {
+        return std::fs::remove_file(path)
+            .map_err(|e| format!("could not remove {}: {}", path.display(), e));
+    }
+    let mut text = serde_json::to_string(&Header {
+        version: HISTORY_VERSION,
+    })
+    .map_err(|e| e.to_string())?;
+    for sample in samples {
+        text.push('\n');
+        text.push_str(&serde_json::to_string(sample).map_err(|e| e.to_string())?);
+    }
+    text.push('\n');
+    write_atomic(path, text.as_bytes())
+}
+
+// An append-only JSONL file per coin, in a directory per currency. Cheap to
+// clone into the background tasks that write it
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    dir: PathBuf,
+    retention: Duration,
+    // Appending and pruning from several tasks take turns
+    lock: Arc<Mutex<()>>,
+}
+
+impl HistoryStore {
+    pub fn new(dir: &Path, currency: &str, retention: Duration) -> Self {
+        HistoryStore {
+            dir: dir.join(currency),
+            retention,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    fn path(&self, coin_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", coin_id))
+    }
+
+    // One line per coin at `time`, the header first in a new file
+    pub fn append(&self, coins: &[Coin], time: SystemTime) -> Result<(), String> {
+        let _turn = self.lock.lock().map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("could not create {}: {}", self.dir.display(), e))?;
+        for coin in coins {
+            let path = self.path(&coin.id);
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+            let is_new = file.metadata().map(|meta| meta.len() == 0).unwrap_or(true);
+            let mut text = String::new();
+            if is_new {
+                let header = Header {
+                    version: HISTORY_VERSION,
+                };
+                text = serde_json::to_string(&header).map_err(|e| e.to_string())? + "\n";
+            }
+            let sample = Sample {
+                time: millis(time),
+                price: coin.current_price,
+            };
+            text += &(serde_json::to_string(&sample).map_err(|e| e.to_string())? + "\n");
+            file.write_all(text.as_bytes())
+                .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    // The saved prices of a coin from `from` until before `to`, oldest
+    // first, for export-history. The files are only read
+    pub fn samples_between(
+        &self,
+        coin_id: &str,
+        from: Option<SystemTime>,
+        to: Option<SystemTime>,
+    ) -> Result<Vec<Sample>, String> {
+        let path = self.path(coin_id);
+        if !path.exists() {
+            return Err(format!("no prices of `{}` are saved", coin_id));
+        }
+        let (mut samples, _) = read_samples(&path);
+        let range = from.map_or(0, millis)..to.map_or(u64::MAX, millis);
+        samples.retain(|sample| range.contains(&sample.time));
+        samples.sort_by_key(|sample| sample.time);
+        match samples.is_empty() {
+            true => Err(format!(
+                "no prices of `{}` are saved in that range",
+                coin_id
+            )),
+            false => Ok(samples),
+        }
+    }
+
+    // Prunes every file to the retention and returns the samples of
+    // `coin_ids` up to `now`, oldest first. Files of coins no longer tracked
+    // are pruned too and go once they are empty
+    pub fn load(
+        &self,
+        coin_ids: &[String],
+        now: SystemTime,
+    ) -> Result<HashMap<String, Vec<Sample>>, String> {
+        let _turn = self.lock.lock().map_err(|e| e.to_string())?;
+        let mut loaded = HashMap::new();
+        // Nothing saved yet
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Ok(loaded);
+        };
+        let cutoff = millis(now.checked_sub(self.retention).unwrap_or(UNIX_EPOCH));
+        let end = millis(now);
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(coin_id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".jsonl"))
+            else {
+                continue;
+            };
+            let (mut samples, mut rewrite) = read_samples(&path);
+            let count = samples.len();
+            samples.retain(|sample| sample.time >= cutoff);
+            rewrite |= samples.len() < count;
+            if rewrite {
+                write_samples(&path, &samples)?;
+            }
+            if coin_ids.iter().any(|id| id == coin_id) {
+                samples.retain(|sample| sample.time <= end);
+                samples.sort_by_key(|sample| sample.time);
+                loaded.insert(coin_id.to_string(), samples);
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn coin(id: &str, price: f64) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: id.to_string(),
+            symbol: id[..3].to_uppercase(),
+            current_price: price,
+            ..Default::default()
+        }
+    }
+
+    fn at(days: u32) -> SystemTime {
+        UNIX_EPOCH + DAY * 20_000 + DAY * days
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn samples_are_appended_and_read_back_per_currency() {
+        let dir = temp_dir("history-round-trip");
+        let store = HistoryStore::new(&dir, "usd", DAY * 7);
+        store.append(&[coin("bitcoin", 100.0)], at(0)).unwrap();
+        store
+            .append(&[coin("bitcoin", 110.0), coin("ethereum", 5.0)], at(1))
+            .unwrap();
+        let loaded = store.load(&ids(&["bitcoin", "ethereum"]), at(2)).unwrap();
+        let prices: Vec<f64> = loaded["bitcoin"].iter().map(|s| s.price).collect();
+        assert_eq!(prices, [100.0, 110.0]);
+        assert_eq!(loaded["ethereum"][0].time, millis(at(1)));
+        // A single header even after several appends
+        let text = std::fs::read_to_string(dir.join("usd/bitcoin.jsonl")).unwrap();
+        assert_eq!(text.lines().next(), Some(r#"{"version":1}"#));
+        assert_eq!(text.lines().count(), 3);
+        // Prices in another currency are kept apart
+        let eur = HistoryStore::new(&dir, "eur", DAY * 7);
+        assert!(eur.load(&ids(&["bitcoin"]), at(2)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn samples_past_the_retention_are_pruned_from_the_files() {
+        let dir = temp_dir("history-prune");
+        let store = HistoryStore::new(&dir, "usd", DAY * 7);
+        store.append(&[coin("bitcoin", 1.0)], at(0)).unwrap();
+        store.append(&[coin("solana", 1.0)], at(1)).unwrap();
+        store.append(&[coin("bitcoin", 2.0)], at(5)).unwrap();
+        // Only some of bitcoin is older than 7 days, all of solana is
+        let loaded = store.load(&ids(&["bitcoin"]), at(9)).unwrap();
+        assert_eq!(
+            loaded["bitcoin"],
+            [Sample {
+                time: millis(at(5)),
+                price: 2.0
+            }]
+        );
+        let text = std::fs::read_to_string(dir.join("usd/bitcoin.jsonl")).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(!dir.join("usd/solana.jsonl").exists());
+        assert!(!dir.join("usd/bitcoin.jsonl.tmp").exists());
+    }
+
+    #[test]
+    fn samples_after_the_load_started_are_left_for_the_session() {
+        let dir = temp_dir("history-later");
+        let store = HistoryStore::new(&dir, "usd", DAY * 7);
+        store.append(&[coin("bitcoin", 1.0)], at(1)).unwrap();
+        store.append(&[coin("bitcoin", 2.0)], at(3)).unwrap();
+        let loaded = store.load(&ids(&["bitcoin"]), at(2)).unwrap();
+        assert_eq!(loaded["bitcoin"].len(), 1);
+    }
+
+    #[test]
+    fn files_of_another_version_or_unreadable_start_over() {
+        let dir = temp_dir("history-version");
+        let store = HistoryStore::new(&dir, "usd", DAY * 7);
+        std::fs::create_dir_all(dir.join("usd")).unwrap();
+        let sample = format!(r#"{{"time":{},"price":1.0}}"#, millis(at(0)));
+        let future = format!("{{\"version\":99}}\n{}\n", sample);
+        std::fs::write(dir.join("usd/bitcoin.jsonl"), future).unwrap();
+        std::fs::write(dir.join("usd/cardano.jsonl"), "not json\n").unwrap();
+        let broken = format!("{{\"version\":1}}\n{}\nnot json\n", sample);
+        std::fs::write(dir.join("usd/ethereum.jsonl"), broken).unwrap();
+
+        let loaded = store
+            .load(&ids(&["bitcoin", "cardano", "ethereum"]), at(1))
+            .unwrap();
+        assert!(loaded["bitcoin"].is_empty());
+        assert!(!dir.join("usd/bitcoin.jsonl").exists());
+        assert!(!dir.join("usd/cardano.jsonl").exists());
+        // The lines that could be read stay
+        assert_eq!(loaded["ethereum"].len(), 1);
+        let text = std::fs::read_to_string(dir.join("usd/ethereum.jsonl")).unwrap();
+        assert_eq!(text.lines().count(), 2);
+
+        // The next append starts with a current header
+        store.append(&[coin("bitcoin", 3.0)], at(1)).unwrap();
+        let loaded = store.load(&ids(&["bitcoin"]), at(1)).unwrap();
+        assert_eq!(loaded["bitcoin"].len(), 1);
+    }
+
+    #[test]
+    fn samples_between_picks_the_range_of_one_coin() {
+        let dir = temp_dir("history-between");
+        let store = HistoryStore::new(&dir, "usd", DAY * 7);
+        for day in 0..5 {
+            store
+                .append(&[coin("bitcoin", 100.0 + day as f64)], at(day))
+                .unwrap();
+        }
+        store.append(&[coin("solana", 1.0)], at(2)).unwrap();
+        let samples = store
+            .samples_between("bitcoin", Some(at(1)), Some(at(3)))
+            .unwrap();
+        let prices: Vec<f64> = samples.iter().map(|sample| sample.price).collect();
+        assert_eq!(prices, [101.0, 102.0]);
+        // Open ended on either side
+        assert_eq!(
+            store.samples_between("bitcoin", None, None).unwrap().len(),
+            5
+        );
+        let samples = store.samples_between("bitcoin", Some(at(4)), None).unwrap();
+        assert_eq!(samples[0].price, 104.0);
+
+        let error = store.samples_between("dogecoin", None, None).unwrap_err();
+        assert_eq!(error, "no prices of `dogecoin` are saved");
+        let error = store
+            .samples_between("bitcoin", Some(at(10)), Some(at(11)))
+            .unwrap_err();
+        assert_eq!(error, "no prices of `bitcoin` are saved in that range");
+    }
+
+    #[test]
+    fn missing_directory_loads_nothing() {
+        let store = HistoryStore::new(&temp_dir("history-missing"), "usd", DAY);
+        assert!(store.load(&ids(&["bitcoin"]), at(0)).unwrap().is_empty());
+    }
+}
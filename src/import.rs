@@ -0,0 +1,259 @@
+//! Building portfolio holdings from a CSV of trades
+
+use crate::model::is_valid_coin_id;
+use crate::portfolio::{Holding, Portfolio};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+// One row of `date,coin_id,side,quantity,price,fee`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    // YYYY-MM-DD, already checked to be a real date
+    pub date: String,
+    pub coin_id: String,
+    pub side: Side,
+    pub quantity: f64,
+    pub price: f64,
+    pub fee: f64,
+    // Where the trade came from, for error messages
+    pub line: usize,
+}
+
+// Splits a CSV line into fields, undoing the quoting `export` does
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+// Dates are YYYY-MM-DD, which also sorts correctly as text
+fn parse_date(text: &str) -> Result<String, String> {
+    let invalid = || format!("invalid date `{}`, expected YYYY-MM-DD", text);
+    let parts: Vec<&str> = text.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return Err(invalid());
+    }
+    let year: u32 = year.parse().map_err(|_| invalid())?;
+    let month: u32 = month.parse().map_err(|_| invalid())?;
+    let day: u32 = day.parse().map_err(|_| invalid())?;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => return Err(invalid()),
+    };
+    if day == 0 || day > days_in_month {
+        return Err(invalid());
+    }
+    Ok(text.to_string())
+}
+
+fn parse_amount(name: &str, text: &str) -> Result<f64, String> {
+    let amount: f64 = text
+        .parse()
+        .map_err(|_| format!("{} `{}` is not a number", name, text))?;
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(format!("{} can't be negative", name));
+    }
+    Ok(amount)
+}
+
+fn parse_trade(fields: &[String], line: usize) -> Result<Trade, String> {
+    let fields: Vec<&str> = fields.iter().map(|field| field.trim()).collect();
+    // The fee column may be left out or empty
+    let (date, coin_id, side, quantity, price, fee) = match fields[..] {
+        [date, coin_id, side, quantity, price] => (date, coin_id, side, quantity, price, ""),
+        [date, coin_id, side, quantity, price, fee] => (date, coin_id, side, quantity, price, fee),
+        _ => {
+            return Err(format!(
+                "expected 6 columns (date,coin_id,side,quantity,price,fee), found {}",
+                fields.len()
+            ));
+        }
+    };
+    let coin_id = coin_id.to_lowercase();
+    if !is_valid_coin_id(&coin_id) {
+        return Err(format!("invalid coin id `{}`", coin_id));
+    }
+    let side = match side.to_lowercase().as_str() {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        _ => return Err(format!("unknown side `{}`, expected buy or sell", side)),
+    };
+    Ok(Trade {
+        date: parse_date(date)?,
+        coin_id,
+        side,
+        quantity: parse_amount("quantity", quantity)?,
+        price: parse_amount("price", price)?,
+        fee: match fee {
+            "" => 0.0,
+            fee => parse_amount("fee", fee)?,
+        },
+        line,
+    })
+}
+
+// Every bad row is reported, each with its line number, so a spreadsheet
+// can be fixed in one go
+pub fn parse_trades(text: &str) -> Result<Vec<Trade>, Vec<String>> {
+    let mut trades = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = split_csv_line(line).and_then(|fields| {
+            // A header row is allowed at the top
+            if trades.is_empty() && errors.is_empty() && fields[0].trim() == "date" {
+                return Ok(None);
+            }
+            parse_trade(&fields, line_number).map(Some)
+        });
+        match result {
+            Ok(Some(trade)) => trades.push(trade),
+            Ok(None) => {}
+            Err(e) => errors.push(format!("line {}: {}", line_number, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(trades)
+    } else {
+        Err(errors)
+    }
+}
+
+// Replays the trades in date order into a quantity and average cost per
+// coin. Fees are part of what a buy cost; selling keeps the average cost
+// of what is left. Coins sold down to nothing are left out
+pub fn fold_trades(trades: &[Trade]) -> Result<BTreeMap<String, Holding>, Vec<String>> {
+    let mut trades: Vec<&Trade> = trades.iter().collect();
+    // Stable, so trades on the same day keep their order in the file
+    trades.sort_by(|a, b| a.date.cmp(&b.date));
+    // Quantity held and what it cost in total
+    let mut positions: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for trade in trades {
+        let (quantity, cost) = positions.entry(trade.coin_id.clone()).or_default();
+        match trade.side {
+            Side::Buy => {
+                *quantity += trade.quantity;
+                *cost += trade.quantity * trade.price + trade.fee;
+            }
+            // Leave a little room for rounding in quantities from exchanges
+            Side::Sell if trade.quantity > *quantity + 1e-9 => errors.push(format!(
+                "line {}: selling {} {} but only {} held on {}",
+                trade.line, trade.quantity, trade.coin_id, quantity, trade.date
+            )),
+            Side::Sell => {
+                let average_cost = if *quantity > 0.0 {
+                    *cost / *quantity
+                } else {
+                    0.0
+                };
+                *quantity = (*quantity - trade.quantity).max(0.0);
+                *cost = average_cost * *quantity;
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(positions
+        .into_iter()
+        .filter(|(_, (quantity, _))| *quantity > 1e-9)
+        .map(|(coin_id, (quantity, cost))| {
+            let holding = Holding {
+                quantity,
+                cost_basis: Some(cost / quantity),
+            };
+            (coin_id, holding)
+        })
+        .collect())
+}
+
+// The trades fully describe the coins they mention, so those are replaced
+// instead of added to. Importing the same file twice gives the same result
+pub fn apply_import(portfolio: &mut Portfolio, trades: &[Trade]) -> Result<usize, Vec<String>> {
+    let holdings = fold_trades(trades)?;
+    for trade in trades {
+        portfolio.holdings.remove(&trade.coin_id);
+    }
+    let count = holdings.len();
+    portfolio.holdings.extend(holdings);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_csv_line_handles_quotes() {
+        assert_eq!(split_csv_line("a,b,,c").unwrap(), ["a", "b", "", "c"]);
+        assert_eq!(
+            split_csv_line("\"a, b\",\"say \"\"hi\"\"\"").unwrap(),
+            ["a, b", "say \"hi\""]
+        );
+        assert!(split_csv_line("\"open").is_err());
+    }
+
+    #[test]
+    fn parse_date_checks_the_calendar() {
+        assert!(parse_date("2024-02-29").is_ok());
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2024-13-01").is_err());
+        assert!(parse_date("2024-4-01").is_err());
+        assert!(parse_date("01/04/2024").is_err());
+    }
+
+    #[test]
+    fn fees_count_towards_the_cost_basis() {
+        let trades = parse_trades("2024-01-01,bitcoin,buy,2,100,10\n").unwrap();
+        let holdings = fold_trades(&trades).unwrap();
+        assert_eq!(holdings["bitcoin"].quantity, 2.0);
+        assert_eq!(holdings["bitcoin"].cost_basis, Some(105.0));
+    }
+
+    #[test]
+    fn importing_twice_gives_the_same_portfolio() {
+        let trades = parse_trades("2024-01-01,bitcoin,buy,1,100,\n").unwrap();
+        let mut portfolio = Portfolio::default();
+        apply_import(&mut portfolio, &trades).unwrap();
+        let once = portfolio.clone();
+        apply_import(&mut portfolio, &trades).unwrap();
+        assert_eq!(portfolio, once);
+    }
+}
@@ -0,0 +1,936 @@
+//! Every key of the app outside the prompts, used both to dispatch key
+//! presses and to list them in the help overlay. The `[keys]` table of the
+//! config file can move any action to other keys
+
+use crate::model::ChartRange;
+use crate::state::Tab;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// What a binding does, run by `App::run_action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Refresh,
+    ToggleHelp,
+    // The panel with the state of the price feed
+    ToggleConnection,
+    ToggleDebug,
+    NextTab,
+    PreviousTab,
+    ShowTab(Tab),
+    // Back to the market from any other view
+    Back,
+    // Move the selection of the list on screen
+    SelectNext,
+    SelectPrevious,
+    // Across the heatmap, in the table the same as up and down
+    SelectLeft,
+    SelectRight,
+    SelectFirst,
+    SelectLast,
+    // A screenful of coins at a time
+    PageDown,
+    PageUp,
+    OpenDetail,
+    // Line chart or candlesticks in the detail view
+    ToggleChartMode,
+    // Log10 or linear prices on the line chart
+    ToggleLogScale,
+    SetChartRange(ChartRange),
+    // To the next longer range when true
+    CycleChartRange(bool),
+    CycleCurrency,
+    ToggleBtc,
+    CycleChangeHorizon,
+    CycleSort,
+    ReverseSort,
+    StartFilter,
+    ClearFilter,
+    AddCoin,
+    DeleteCoin,
+    Export,
+    // The price of the selected coin, or with true its whole line
+    Copy(bool),
+    DismissAlerts,
+    ToggleTheme,
+    ToggleRowLayout,
+    // Stops the automatic refresh, 'r' still fetches
+    TogglePause,
+    ToggleHeatmap,
+    AddAlert,
+    DeleteAlert,
+    RearmAlert,
+    AddTrending,
+    // Back to the shell like any job, until `fg`
+    #[cfg(unix)]
+    Suspend,
+}
+
+// Where a binding applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Everywhere,
+    Market,
+    Detail,
+    Portfolio,
+    Alerts,
+    Trending,
+}
+
+// The groups of the help overlay, in the order they are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Navigation,
+    Actions,
+    Views,
+}
+
+impl Category {
+    pub const ALL: [Category; 3] = [Category::Navigation, Category::Actions, Category::Views];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Category::Navigation => "Navigation",
+            Category::Actions => "Actions",
+            Category::Views => "Views",
+        }
+    }
+}
+
+// A key with or without ctrl. Shift only shows in the character itself,
+// 'S', or as Shift-Tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+}
+
+const fn key(code: KeyCode) -> Key {
+    Key { code, ctrl: false }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Key {
+            code: event.code,
+            ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+        }
+    }
+}
+
+// Names of the keys that aren't a single character, as written in the config
+const NAMED_KEYS: [(&str, KeyCode); 15] = [
+    ("tab", KeyCode::Tab),
+    ("shift-tab", KeyCode::BackTab),
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("space", KeyCode::Char(' ')),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+// e.g. "q", "S", "F5", "ctrl-f", "shift-tab" or "esc". Names are not case
+// sensitive, single characters are
+pub fn parse_key(text: &str) -> Result<Key, String> {
+    let unknown = || format!("unknown key \"{}\"", text);
+    let lower = text.to_lowercase();
+    let (ctrl, rest) = match lower.strip_prefix("ctrl-") {
+        Some(_) => (true, &text["ctrl-".len()..]),
+        None => (false, text),
+    };
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(if ctrl { c.to_ascii_lowercase() } else { c }),
+        _ => {
+            let name = rest.to_lowercase();
+            if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                match number {
+                    1..=12 => KeyCode::F(number),
+                    _ => return Err(unknown()),
+                }
+            } else {
+                NAMED_KEYS
+                    .iter()
+                    .find(|(key_name, _)| *key_name == name)
+                    .map(|(_, code)| *code)
+                    .ok_or_else(unknown)?
+            }
+        }
+    };
+    Ok(Key { code, ctrl })
+}
+
+// e.g. "j", "↓", "Shift-Tab" or "ctrl-f"
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "ctrl-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::BackTab => write!(f, "Shift-Tab"),
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+// e.g. "↓/j"
+pub fn keys_label(keys: &[Key]) -> String {
+    let labels: Vec<String> = keys.iter().map(Key::to_string).collect();
+    labels.join("/")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    // What the action is called in the `[keys]` table
+    pub name: &'static str,
+    pub keys: Vec<Key>,
+    pub scopes: &'static [Scope],
+    pub action: Action,
+    pub category: Category,
+    pub description: &'static str,
+}
+
+struct DefaultBinding {
+    name: &'static str,
+    keys: &'static [Key],
+    scopes: &'static [Scope],
+    action: Action,
+    category: Category,
+    description: &'static str,
+}
+
+const EVERYWHERE: &[Scope] = &[Scope::Everywhere];
+const LISTS: &[Scope] = &[Scope::Market, Scope::Alerts, Scope::Trending];
+const NOT_MARKET: &[Scope] = &[
+    Scope::Detail,
+    Scope::Portfolio,
+    Scope::Alerts,
+    Scope::Trending,
+];
+
+const fn binding(
+    name: &'static str,
+    keys: &'static [Key],
+    scopes: &'static [Scope],
+    action: Action,
+    category: Category,
+    description: &'static str,
+) -> DefaultBinding {
+    DefaultBinding {
+        name,
+        keys,
+        scopes,
+        action,
+        category,
+        description,
+    }
+}
+
+use Category::{Actions, Navigation, Views};
+use KeyCode::{BackTab, Char, Down, End, Enter, Esc, F, Home, Left, Right, Up};
+
+// The keys without a `[keys]` table. The first binding whose key and scope
+// match wins, so the ones of a single view go before the same key everywhere
+const DEFAULT_BINDINGS: &[DefaultBinding] = &[
+    // Over the tab keys in the detail view, which is part of the market tab
+    binding(
+        "range_1d",
+        &[key(Char('1'))],
+        &[Scope::Detail],
+        Action::SetChartRange(ChartRange::Day),
+        Navigation,
+        "chart the last day",
+    ),
+    binding(
+        "range_7d",
+        &[key(Char('7'))],
+        &[Scope::Detail],
+        Action::SetChartRange(ChartRange::Week),
+        Navigation,
+        "chart the last 7 days",
+    ),
+    binding(
+        "range_30d",
+        &[key(Char('3'))],
+        &[Scope::Detail],
+        Action::SetChartRange(ChartRange::Month),
+        Navigation,
+        "chart the last 30 days",
+    ),
+    binding(
+        "range_90d",
+        &[key(Char('9'))],
+        &[Scope::Detail],
+        Action::SetChartRange(ChartRange::Quarter),
+        Navigation,
+        "chart the last 90 days",
+    ),
+    binding(
+        "range_1y",
+        &[key(Char('y'))],
+        &[Scope::Detail],
+        Action::SetChartRange(ChartRange::Year),
+        Navigation,
+        "chart the last year",
+    ),
+    binding(
+        "longer_range",
+        &[key(Right)],
+        &[Scope::Detail],
+        Action::CycleChartRange(true),
+        Navigation,
+        "next chart range",
+    ),
+    binding(
+        "shorter_range",
+        &[key(Left)],
+        &[Scope::Detail],
+        Action::CycleChartRange(false),
+        Navigation,
+        "previous chart range",
+    ),
+    binding(
+        "help",
+        &[key(Char('?'))],
+        EVERYWHERE,
+        Action::ToggleHelp,
+        Views,
+        "show or hide this help",
+    ),
+    binding(
+        "connection",
+        &[key(Char('i'))],
+        EVERYWHERE,
+        Action::ToggleConnection,
+        Views,
+        "show or hide the connection details",
+    ),
+    binding(
+        "debug",
+        &[key(F(12))],
+        EVERYWHERE,
+        Action::ToggleDebug,
+        Views,
+        "show or hide the last raw API response",
+    ),
+    binding(
+        "next_tab",
+        &[key(KeyCode::Tab)],
+        EVERYWHERE,
+        Action::NextTab,
+        Views,
+        "next tab",
+    ),
+    binding(
+        "previous_tab",
+        &[key(BackTab)],
+        EVERYWHERE,
+        Action::PreviousTab,
+        Views,
+        "previous tab",
+    ),
+    binding(
+        "show_market",
+        &[key(Char('1'))],
+        EVERYWHERE,
+        Action::ShowTab(Tab::Market),
+        Views,
+        "market",
+    ),
+    binding(
+        "show_portfolio",
+        &[key(Char('2'))],
+        EVERYWHERE,
+        Action::ShowTab(Tab::Portfolio),
+        Views,
+        "portfolio",
+    ),
+    binding(
+        "show_alerts",
+        &[key(Char('3'))],
+        EVERYWHERE,
+        Action::ShowTab(Tab::Alerts),
+        Views,
+        "alerts",
+    ),
+    binding(
+        "show_trending",
+        &[key(Char('4'))],
+        EVERYWHERE,
+        Action::ShowTab(Tab::Trending),
+        Views,
+        "trending coins",
+    ),
+    binding(
+        "dismiss_alerts",
+        &[key(Char('x'))],
+        EVERYWHERE,
+        Action::DismissAlerts,
+        Actions,
+        "dismiss the alert banner",
+    ),
+    binding(
+        "toggle_theme",
+        &[key(Char('t'))],
+        EVERYWHERE,
+        Action::ToggleTheme,
+        Actions,
+        "light or dark colors",
+    ),
+    binding(
+        "back",
+        &[key(Char('q')), key(Esc)],
+        NOT_MARKET,
+        Action::Back,
+        Views,
+        "back to the market",
+    ),
+    binding(
+        "quit",
+        &[key(Char('q'))],
+        &[Scope::Market],
+        Action::Quit,
+        Actions,
+        "quit",
+    ),
+    #[cfg(unix)]
+    binding(
+        "suspend",
+        &[Key {
+            code: Char('z'),
+            ctrl: true,
+        }],
+        EVERYWHERE,
+        Action::Suspend,
+        Actions,
+        "suspend to the shell",
+    ),
+    binding(
+        "chart",
+        &[key(Enter)],
+        &[Scope::Market],
+        Action::OpenDetail,
+        Views,
+        "chart of the selected coin",
+    ),
+    binding(
+        "candles",
+        &[key(Char('c'))],
+        &[Scope::Detail],
+        Action::ToggleChartMode,
+        Views,
+        "candlesticks or the line chart",
+    ),
+    binding(
+        "log_scale",
+        &[key(Char('l'))],
+        &[Scope::Detail],
+        Action::ToggleLogScale,
+        Views,
+        "log or linear price axis",
+    ),
+    binding(
+        "down",
+        &[key(Down), key(Char('j'))],
+        LISTS,
+        Action::SelectNext,
+        Navigation,
+        "move down",
+    ),
+    binding(
+        "up",
+        &[key(Up), key(Char('k'))],
+        LISTS,
+        Action::SelectPrevious,
+        Navigation,
+        "move up",
+    ),
+    binding(
+        "left",
+        &[key(Left)],
+        &[Scope::Market],
+        Action::SelectLeft,
+        Navigation,
+        "previous coin of the heatmap",
+    ),
+    binding(
+        "right",
+        &[key(Right)],
+        &[Scope::Market],
+        Action::SelectRight,
+        Navigation,
+        "next coin of the heatmap",
+    ),
+    binding(
+        "first",
+        &[key(Home)],
+        &[Scope::Market],
+        Action::SelectFirst,
+        Navigation,
+        "first coin",
+    ),
+    binding(
+        "last",
+        &[key(End)],
+        &[Scope::Market],
+        Action::SelectLast,
+        Navigation,
+        "last coin",
+    ),
+    binding(
+        "page_down",
+        &[key(KeyCode::PageDown)],
+        &[Scope::Market],
+        Action::PageDown,
+        Navigation,
+        "a page down",
+    ),
+    binding(
+        "page_up",
+        &[key(KeyCode::PageUp)],
+        &[Scope::Market],
+        Action::PageUp,
+        Navigation,
+        "a page up",
+    ),
+    binding(
+        "search",
+        &[key(Char('/'))],
+        &[Scope::Market],
+        Action::StartFilter,
+        Navigation,
+        "filter the coins",
+    ),
+    binding(
+        "clear_search",
+        &[key(Esc)],
+        &[Scope::Market],
+        Action::ClearFilter,
+        Navigation,
+        "clear the filter",
+    ),
+    binding(
+        "sort",
+        &[key(Char('s'))],
+        &[Scope::Market],
+        Action::CycleSort,
+        Navigation,
+        "sort by the next column",
+    ),
+    binding(
+        "reverse_sort",
+        &[key(Char('S'))],
+        &[Scope::Market],
+        Action::ReverseSort,
+        Navigation,
+        "reverse the sort",
+    ),
+    binding(
+        "rearm_alert",
+        &[key(Char('r'))],
+        &[Scope::Alerts],
+        Action::RearmAlert,
+        Actions,
+        "re-arm the selected alert",
+    ),
+    // Only F5 refreshes in the alerts view, where 'r' re-arms
+    binding(
+        "refresh",
+        &[key(Char('r')), key(F(5))],
+        EVERYWHERE,
+        Action::Refresh,
+        Actions,
+        "refresh",
+    ),
+    binding(
+        "pause",
+        &[key(Char(' ')), key(Char('p'))],
+        EVERYWHERE,
+        Action::TogglePause,
+        Actions,
+        "pause or resume the automatic refresh",
+    ),
+    binding(
+        "add_coin",
+        &[key(Char('a'))],
+        &[Scope::Market],
+        Action::AddCoin,
+        Actions,
+        "add a coin",
+    ),
+    binding(
+        "delete_coin",
+        &[key(Char('d'))],
+        &[Scope::Market],
+        Action::DeleteCoin,
+        Actions,
+        "remove the selected coin",
+    ),
+    binding(
+        "export",
+        &[key(Char('e'))],
+        &[Scope::Market],
+        Action::Export,
+        Actions,
+        "export the coins as CSV",
+    ),
+    binding(
+        "copy_price",
+        &[key(Char('y'))],
+        &[Scope::Market],
+        Action::Copy(false),
+        Actions,
+        "copy the price of the coin",
+    ),
+    binding(
+        "copy_line",
+        &[key(Char('Y'))],
+        &[Scope::Market],
+        Action::Copy(true),
+        Actions,
+        "copy the symbol, price and change",
+    ),
+    binding(
+        "next_currency",
+        &[key(Char('c'))],
+        &[Scope::Market],
+        Action::CycleCurrency,
+        Actions,
+        "next currency",
+    ),
+    binding(
+        "toggle_btc",
+        &[key(Char('b'))],
+        &[Scope::Market],
+        Action::ToggleBtc,
+        Actions,
+        "prices in BTC",
+    ),
+    binding(
+        "toggle_layout",
+        &[key(Char('v'))],
+        &[Scope::Market],
+        Action::ToggleRowLayout,
+        Actions,
+        "one or two lines per coin",
+    ),
+    binding(
+        "heatmap",
+        &[key(Char('m'))],
+        &[Scope::Market],
+        Action::ToggleHeatmap,
+        Views,
+        "heatmap of the 24h changes",
+    ),
+    binding(
+        "next_change",
+        &[key(Char('h'))],
+        &[Scope::Market],
+        Action::CycleChangeHorizon,
+        Actions,
+        "next change column",
+    ),
+    binding(
+        "add_alert",
+        &[key(Char('a'))],
+        &[Scope::Alerts],
+        Action::AddAlert,
+        Actions,
+        "add an alert",
+    ),
+    binding(
+        "delete_alert",
+        &[key(Char('d'))],
+        &[Scope::Alerts],
+        Action::DeleteAlert,
+        Actions,
+        "delete the selected alert",
+    ),
+    binding(
+        "add_trending",
+        &[key(Char('a'))],
+        &[Scope::Trending],
+        Action::AddTrending,
+        Actions,
+        "add the selected coin to the watchlist",
+    ),
+];
+
+// One key or a list of them in the `[keys]` table
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeySetting {
+    One(String),
+    Many(Vec<String>),
+}
+
+// The bindings in use, the defaults with the `[keys]` table on top
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|binding| Binding {
+                name: binding.name,
+                keys: binding.keys.to_vec(),
+                scopes: binding.scopes,
+                action: binding.action,
+                category: binding.category,
+                description: binding.description,
+            })
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+fn scopes_overlap(a: &[Scope], b: &[Scope]) -> bool {
+    a.iter()
+        .any(|scope| *scope == Scope::Everywhere || b.contains(scope))
+        || b.contains(&Scope::Everywhere)
+}
+
+// The defaults share a few keys on purpose, a key of one view winning over
+// the same key everywhere, like 'r' re-arming alerts in the Alerts tab
+fn shared_by_default(first: &str, second: &str, key: &Key) -> bool {
+    let default_keys = |name: &str| {
+        DEFAULT_BINDINGS
+            .iter()
+            .find(|binding| binding.name == name)
+            .map_or(&[][..], |binding| binding.keys)
+    };
+    default_keys(first).contains(key) && default_keys(second).contains(key)
+}
+
+impl Keymap {
+    // Moves the named actions to other keys, e.g. `quit = "ctrl-c"` or
+    // `down = ["j", "down"]`
+    fn remap(settings: BTreeMap<String, KeySetting>) -> Result<Self, String> {
+        let mut keymap = Keymap::default();
+        for (name, setting) in settings {
+            let binding = keymap
+                .bindings
+                .iter_mut()
+                .find(|binding| binding.name == name)
+                .ok_or_else(|| format!("unknown action \"{}\"", name))?;
+            let texts = match setting {
+                KeySetting::One(text) => vec![text],
+                KeySetting::Many(texts) => texts,
+            };
+            if texts.is_empty() {
+                return Err(format!("{}: no key given", name));
+            }
+            binding.keys = texts
+                .iter()
+                .map(|text| parse_key(text).map_err(|e| format!("{}: {}", name, e)))
+                .collect::<Result<_, _>>()?;
+        }
+        keymap.check_conflicts()?;
+        Ok(keymap)
+    }
+
+    fn check_conflicts(&self) -> Result<(), String> {
+        for (index, first) in self.bindings.iter().enumerate() {
+            for second in &self.bindings[index + 1..] {
+                if !scopes_overlap(first.scopes, second.scopes) {
+                    continue;
+                }
+                let clash = first.keys.iter().find(|key| {
+                    second.keys.contains(key) && !shared_by_default(first.name, second.name, key)
+                });
+                if let Some(key) = clash {
+                    return Err(format!(
+                        "{} and {} are both bound to {}",
+                        first.name, second.name, key
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn binding_for(&self, key: Key, scope: Scope) -> Option<&Binding> {
+        self.bindings.iter().find(|binding| {
+            binding.keys.contains(&key)
+                && binding
+                    .scopes
+                    .iter()
+                    .any(|&s| s == Scope::Everywhere || s == scope)
+        })
+    }
+
+    // The bindings that work in `scope`, each with the keys of it another
+    // binding of the view doesn't take over
+    pub fn bindings_in(&self, scope: Scope) -> Vec<(&Binding, Vec<Key>)> {
+        let mut shown = Vec::new();
+        for binding in &self.bindings {
+            let keys: Vec<Key> = binding
+                .keys
+                .iter()
+                .copied()
+                .filter(|key| {
+                    self.binding_for(*key, scope)
+                        .is_some_and(|found| found.name == binding.name)
+                })
+                .collect();
+            if !keys.is_empty() {
+                shown.push((binding, keys));
+            }
+        }
+        shown
+    }
+}
+
+// The `[keys]` table of the config file
+pub fn deserialize_keymap<'de, D>(deserializer: D) -> Result<Keymap, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let settings = BTreeMap::<String, KeySetting>::deserialize(deserializer)?;
+    Keymap::remap(settings).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remap(text: &str) -> Result<Keymap, String> {
+        Keymap::remap(toml::from_str(text).unwrap())
+    }
+
+    fn action(keymap: &Keymap, key: Key, scope: Scope) -> Option<Action> {
+        keymap.binding_for(key, scope).map(|binding| binding.action)
+    }
+
+    #[test]
+    fn every_action_has_a_description() {
+        for binding in &Keymap::default().bindings {
+            assert!(!binding.keys.is_empty(), "{} has no key", binding.name);
+            assert!(
+                !binding.scopes.is_empty(),
+                "{} applies nowhere",
+                binding.name
+            );
+            assert!(
+                !binding.description.trim().is_empty(),
+                "{} has no description",
+                binding.name
+            );
+        }
+        Keymap::default().check_conflicts().unwrap();
+    }
+
+    #[test]
+    fn keys_go_to_the_binding_of_their_view() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            action(&keymap, key(Char('r')), Scope::Alerts),
+            Some(Action::RearmAlert)
+        );
+        assert_eq!(
+            action(&keymap, key(Char('r')), Scope::Market),
+            Some(Action::Refresh)
+        );
+        assert_eq!(
+            action(&keymap, key(Char('q')), Scope::Market),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            action(&keymap, key(Char('q')), Scope::Detail),
+            Some(Action::Back)
+        );
+        assert_eq!(action(&keymap, key(Char('j')), Scope::Portfolio), None);
+        #[cfg(unix)]
+        assert_eq!(
+            action(&keymap, parse_key("ctrl-z").unwrap(), Scope::Trending),
+            Some(Action::Suspend)
+        );
+        assert_eq!(keys_label(&[key(Down), key(Char('j'))]), "↓/j");
+        assert_eq!(keys_label(&[key(BackTab)]), "Shift-Tab");
+        assert_eq!(keys_label(&[key(F(5))]), "F5");
+    }
+
+    #[test]
+    fn key_names_are_parsed() {
+        let ctrl = |c| Key {
+            code: Char(c),
+            ctrl: true,
+        };
+        assert_eq!(parse_key("q"), Ok(key(Char('q'))));
+        assert_eq!(parse_key("S"), Ok(key(Char('S'))));
+        assert_eq!(parse_key("F5"), Ok(key(F(5))));
+        assert_eq!(parse_key("f12"), Ok(key(F(12))));
+        assert_eq!(parse_key("ctrl-f"), Ok(ctrl('f')));
+        assert_eq!(parse_key("Ctrl-F"), Ok(ctrl('f')));
+        assert_eq!(parse_key("shift-tab"), Ok(key(BackTab)));
+        assert_eq!(parse_key("Esc"), Ok(key(Esc)));
+        assert_eq!(parse_key("space"), Ok(key(Char(' '))));
+        for text in ["", "ctrl-", "F13", "qq", "hyper-x"] {
+            assert_eq!(
+                parse_key(text),
+                Err(format!("unknown key \"{}\"", text)),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(ctrl('f').to_string(), "ctrl-f");
+    }
+
+    #[test]
+    fn remapped_keys_replace_the_defaults() {
+        let keymap = remap("quit = \"ctrl-c\"\nsearch = [\"ctrl-f\", \"/\"]").unwrap();
+        let ctrl_c = parse_key("ctrl-c").unwrap();
+        assert_eq!(action(&keymap, ctrl_c, Scope::Market), Some(Action::Quit));
+        assert_eq!(action(&keymap, key(Char('q')), Scope::Market), None);
+        let ctrl_f = parse_key("ctrl-f").unwrap();
+        assert_eq!(
+            action(&keymap, ctrl_f, Scope::Market),
+            Some(Action::StartFilter)
+        );
+        // Everything else keeps its keys
+        assert_eq!(
+            action(&keymap, key(F(5)), Scope::Market),
+            Some(Action::Refresh)
+        );
+        assert_eq!(remap("").unwrap(), Keymap::default());
+    }
+
+    #[test]
+    fn bad_key_tables_are_rejected() {
+        assert_eq!(
+            remap("refresh = \"F5\"\nquit = \"F5\""),
+            Err("quit and refresh are both bound to F5".to_string())
+        );
+        assert_eq!(
+            remap("add_alert = \"r\""),
+            Err("rearm_alert and add_alert are both bound to r".to_string())
+        );
+        assert_eq!(
+            remap("launch = \"l\""),
+            Err("unknown action \"launch\"".to_string())
+        );
+        assert_eq!(
+            remap("quit = \"ctrl-\""),
+            Err("quit: unknown key \"ctrl-\"".to_string())
+        );
+        assert_eq!(remap("quit = []"), Err("quit: no key given".to_string()));
+    }
+}
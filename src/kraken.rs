@@ -0,0 +1,461 @@
+//! Prices from Kraken's public market data API. Kraken names bitcoin XBT,
+//! and answers for its older pairs under X- and Z-prefixed asset codes,
+//! XXBTZUSD for the XBTUSD that was asked for
+
+use crate::api::{
+    API_MESSAGE_LENGTH, ChartResult, GlobalResult, OhlcResult, PartialFailure, Refresh,
+    RefreshResult, TrendingResult, fetch_with_retry,
+};
+use crate::error::TuickerError;
+use crate::model::{Candle, ChartRange, Coin, truncate};
+use crate::source::{
+    CoinGeckoSource, DataSource, RefreshHooks, exchange_candle, exchange_debug_info, get_text,
+    no_price,
+};
+use futures::future::BoxFuture;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+pub const KRAKEN_API_URL: &str = "https://api.kraken.com";
+
+// CoinGecko id, Kraken asset and name of the coins known without any
+// `[kraken_symbols]`
+const KRAKEN_ASSETS: &[(&str, &str, &str)] = &[
+    ("bitcoin", "XBT", "Bitcoin"),
+    ("ethereum", "ETH", "Ethereum"),
+    ("solana", "SOL", "Solana"),
+    ("ripple", "XRP", "XRP"),
+    ("dogecoin", "XDG", "Dogecoin"),
+    ("cardano", "ADA", "Cardano"),
+    ("tron", "TRX", "TRON"),
+    ("avalanche-2", "AVAX", "Avalanche"),
+    ("shiba-inu", "SHIB", "Shiba Inu"),
+    ("the-open-network", "TON", "Toncoin"),
+    ("chainlink", "LINK", "Chainlink"),
+    ("polkadot", "DOT", "Polkadot"),
+    ("litecoin", "LTC", "Litecoin"),
+    ("bitcoin-cash", "BCH", "Bitcoin Cash"),
+    ("near", "NEAR", "NEAR Protocol"),
+    ("uniswap", "UNI", "Uniswap"),
+    ("stellar", "XLM", "Stellar"),
+    ("cosmos", "ATOM", "Cosmos Hub"),
+    ("sui", "SUI", "Sui"),
+    ("pepe", "PEPE", "Pepe"),
+];
+
+// The asset Kraken trades `coin_id` as, the config before the built-ins
+pub fn kraken_asset(symbols: &BTreeMap<String, String>, coin_id: &str) -> Option<String> {
+    match symbols.get(coin_id) {
+        Some(asset) => Some(asset.trim().to_uppercase()),
+        None => KRAKEN_ASSETS
+            .iter()
+            .find(|(id, ..)| *id == coin_id)
+            .map(|(_, asset, _)| asset.to_string()),
+    }
+}
+
+// Prices in bitcoin are quoted in XBT like the asset itself
+pub fn kraken_pair(asset: &str, currency: &str) -> String {
+    let quote = match currency {
+        "btc" => "XBT".to_string(),
+        other => other.to_uppercase(),
+    };
+    format!("{}{}", asset, quote)
+}
+
+// The pair a response key stands for. The legacy pairs come back as X plus
+// the crypto asset and Z plus the fiat one (XXBTZUSD, XETHZEUR) or X twice
+// (XETHXXBT), the newer ones as they were asked for (SOLUSD)
+pub fn normalize_pair(key: &str) -> String {
+    let bytes = key.as_bytes();
+    // Sliced by bytes below, which only line up with characters in ASCII
+    let legacy = key.is_ascii() && bytes.len() == 8;
+    match legacy && bytes[0] == b'X' && matches!(bytes[4], b'X' | b'Z') {
+        true => format!("{}{}", &key[1..4], &key[5..]),
+        false => key.to_string(),
+    }
+}
+
+// Kraken has no percentage, the change is the last trade against today's
+// opening price
+pub fn change_percent(open: f64, last: f64) -> f64 {
+    match open == 0.0 {
+        true => 0.0,
+        false => (last - open) / open * 100.0,
+    }
+}
+
+// Every response is {"error": [...], "result": ...}, with the errors in
+// the array even when the status is 200
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    #[serde(default)]
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+// One pair of the Ticker response, numbers come as strings: `c` is the last
+// trade and its volume, `o` today's opening price, `v` and `p` the volume
+// and average price of today and of the last 24 hours
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Ticker {
+    #[serde(rename = "c")]
+    last: (String, String),
+    #[serde(rename = "o")]
+    open: String,
+    #[serde(rename = "v")]
+    volume: (String, String),
+    #[serde(rename = "p")]
+    average: (String, String),
+}
+
+// The last price, its change and the volume in the quote currency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub price: f64,
+    pub change_24h: f64,
+    pub volume: f64,
+}
+
+fn number(text: &str) -> Result<f64, TuickerError> {
+    text.parse().map_err(|_| {
+        TuickerError::Parse(serde::de::Error::custom(format!(
+            "`{}` is not a number",
+            text
+        )))
+    })
+}
+
+impl Ticker {
+    pub fn price(&self) -> Result<Price, TuickerError> {
+        let price = number(&self.last.0)?;
+        Ok(Price {
+            price,
+            change_24h: change_percent(number(&self.open)?, price),
+            volume: number(&self.volume.1)? * number(&self.average.1)?,
+        })
+    }
+}
+
+fn api_error(errors: &[String]) -> TuickerError {
+    TuickerError::Exchange {
+        name: "Kraken",
+        status: StatusCode::OK,
+        message: truncate(&errors.join(", "), API_MESSAGE_LENGTH),
+    }
+}
+
+// The result, unless the error array has anything in it
+fn parse_response<T: serde::de::DeserializeOwned>(json_text: &str) -> Result<T, TuickerError> {
+    let parsed: Response<T> = serde_json::from_str(json_text)?;
+    match (parsed.error.is_empty(), parsed.result) {
+        (true, Some(result)) => Ok(result),
+        (true, None) => Err(api_error(&["no result".to_string()])),
+        (false, _) => Err(api_error(&parsed.error)),
+    }
+}
+
+// Quotes by the pair they were asked for
+pub fn parse_tickers(json_text: &str) -> Result<Vec<(String, Price)>, TuickerError> {
+    let result: HashMap<String, Ticker> = parse_response(json_text)?;
+    result
+        .iter()
+        .map(|(key, ticker)| Ok((normalize_pair(key), ticker.price()?)))
+        .collect()
+}
+
+fn error(status: StatusCode, json_text: &str) -> TuickerError {
+    match parse_response::<serde_json::Value>(json_text) {
+        Err(TuickerError::Exchange { message, .. }) => TuickerError::Exchange {
+            name: "Kraken",
+            status,
+            message,
+        },
+        _ => TuickerError::Exchange {
+            name: "Kraken",
+            status,
+            message: truncate(json_text.trim(), API_MESSAGE_LENGTH),
+        },
+    }
+}
+
+async fn get(client: &Client, url: &str) -> Result<String, TuickerError> {
+    get_text(client, url, error).await
+}
+
+// Candle length in minutes and how many of them, Kraken sends up to 720
+fn ohlc_interval(range: ChartRange) -> (u32, usize) {
+    match range {
+        ChartRange::Day => (15, 96),
+        ChartRange::Week => (60, 168),
+        ChartRange::Month => (240, 180),
+        ChartRange::Quarter => (1440, 90),
+        ChartRange::Year => (1440, 365),
+    }
+}
+
+// The OHLC result holds the candles under the pair's key next to `last`,
+// each [time in seconds, "open", "high", "low", "close", ...]
+pub fn parse_ohlc(json_text: &str) -> Result<Vec<Candle>, TuickerError> {
+    let result: HashMap<String, serde_json::Value> = parse_response(json_text)?;
+    let candles = result
+        .into_iter()
+        .find_map(|(key, value)| (key != "last").then_some(value))
+        .unwrap_or_else(|| serde_json::Value::Array(Vec::new()));
+    let candles: Vec<Vec<serde_json::Value>> = serde_json::from_value(candles)?;
+    candles
+        .iter()
+        .map(|candle| exchange_candle(candle, 1000.0))
+        .collect()
+}
+
+fn coin_name(coin_id: &str) -> String {
+    match KRAKEN_ASSETS.iter().find(|(id, ..)| *id == coin_id) {
+        Some((_, _, name)) => name.to_string(),
+        None => coin_id.to_string(),
+    }
+}
+
+// Shown for the coin instead of Kraken's own asset codes
+fn display_symbol(asset: &str) -> String {
+    match asset {
+        "XBT" => "BTC".to_string(),
+        "XDG" => "DOGE".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn is_unknown_pair(error: &TuickerError) -> bool {
+    matches!(
+        error,
+        TuickerError::Exchange { name: "Kraken", message, .. }
+            if message.contains("Unknown asset pair")
+    )
+}
+
+fn unmapped(coin_ids: &[String]) -> TuickerError {
+    no_price("Kraken", "kraken_symbols", coin_ids)
+}
+
+pub struct KrakenSource {
+    // Without the CoinGecko key, which has no business going to Kraken
+    pub client: Client,
+    pub api_url: String,
+    // `[kraken_symbols]` from the config
+    pub symbols: BTreeMap<String, String>,
+    // Kraken has no trending coins or market totals, those stay CoinGecko's
+    pub coingecko: CoinGeckoSource,
+}
+
+impl KrakenSource {
+    async fn candles(&self, coin_id: &str, currency: &str, range: ChartRange) -> OhlcResult {
+        let Some(asset) = kraken_asset(&self.symbols, coin_id) else {
+            return Err(unmapped(&[coin_id.to_string()]));
+        };
+        let (interval, count) = ohlc_interval(range);
+        let url = format!(
+            "{}/0/public/OHLC?pair={}&interval={}",
+            self.api_url,
+            kraken_pair(&asset, currency),
+            interval
+        );
+        let mut candles = parse_ohlc(&get(&self.client, &url).await?)?;
+        candles.drain(..candles.len().saturating_sub(count));
+        Ok(candles)
+    }
+
+    async fn ticker_request(
+        &self,
+        pairs: &[&str],
+        hooks: &RefreshHooks<'_>,
+    ) -> Result<Vec<(String, Price)>, TuickerError> {
+        let url = format!("{}/0/public/Ticker?pair={}", self.api_url, pairs.join(","));
+        let started = Instant::now();
+        let fetched = fetch_with_retry(|| get(&self.client, &url), hooks.on_retry).await;
+        let parsed = match &fetched {
+            Ok(json_text) => parse_tickers(json_text),
+            Err(_) => Ok(Vec::new()),
+        };
+        if let Some(on_response) = hooks.on_response {
+            on_response(exchange_debug_info(url.clone(), started, &fetched, &parsed));
+        }
+        fetched?;
+        parsed
+    }
+
+    // Kraken answers "EQuery:Unknown asset pair" for the whole request when
+    // a single pair doesn't exist, so then every pair is asked for on its
+    // own and the unknown ones are left out
+    async fn quotes_of(
+        &self,
+        pairs: &[&str],
+        hooks: &RefreshHooks<'_>,
+    ) -> Result<Vec<(String, Price)>, TuickerError> {
+        match self.ticker_request(pairs, hooks).await {
+            Err(e) if is_unknown_pair(&e) && pairs.len() == 1 => {
+                log::debug!("no Kraken pair {}: {}", pairs[0], e);
+                Ok(Vec::new())
+            }
+            Err(e) if is_unknown_pair(&e) => {
+                log::debug!("Kraken doesn't know one of {}: {}", pairs.join(","), e);
+                let mut quotes = Vec::new();
+                for pair in pairs {
+                    match self.ticker_request(&[pair], hooks).await {
+                        Ok(found) => quotes.extend(found),
+                        Err(e) if is_unknown_pair(&e) => log::debug!("no Kraken pair {}", pair),
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(quotes)
+            }
+            result => result,
+        }
+    }
+
+    // The coins in the order asked for. Ids without a Kraken asset, or
+    // whose pair Kraken doesn't have, are reported instead of failing the
+    // whole refresh
+    async fn tickers(
+        &self,
+        coin_ids: &[String],
+        currency: &str,
+        hooks: RefreshHooks<'_>,
+    ) -> RefreshResult {
+        let mut wanted: Vec<(String, String, String)> = Vec::new();
+        let mut missing: Vec<String> = Vec::new();
+        for coin_id in coin_ids {
+            if wanted.iter().any(|(id, ..)| id == coin_id) {
+                continue;
+            }
+            match kraken_asset(&self.symbols, coin_id) {
+                Some(asset) => {
+                    let pair = kraken_pair(&asset, currency);
+                    wanted.push((coin_id.clone(), asset, pair));
+                }
+                None => missing.push(coin_id.clone()),
+            }
+        }
+        let mut quotes = Vec::new();
+        if !wanted.is_empty() {
+            let pairs: Vec<&str> = wanted.iter().map(|(.., pair)| pair.as_str()).collect();
+            quotes = self.quotes_of(&pairs, &hooks).await?;
+        }
+        let mut coins = Vec::new();
+        for (coin_id, asset, pair) in wanted {
+            match quotes.iter().find(|(key, _)| *key == pair) {
+                Some((_, quote)) => coins.push(Coin {
+                    id: coin_id.clone(),
+                    name: coin_name(&coin_id),
+                    symbol: display_symbol(&asset),
+                    current_price: quote.price,
+                    price_change_24h: quote.change_24h,
+                    market_cap: None,
+                    market_cap_rank: None,
+                    total_volume: Some(quote.volume),
+                    change_1h: None,
+                    change_7d: None,
+                    change_30d: None,
+                    quotes: BTreeMap::new(),
+                }),
+                None => missing.push(coin_id),
+            }
+        }
+        let failure = (!missing.is_empty()).then(|| PartialFailure {
+            fetched: coins.len(),
+            requested: coins.len() + missing.len(),
+            error: unmapped(&missing),
+        });
+        Ok(Refresh { coins, failure })
+    }
+}
+
+impl DataSource for KrakenSource {
+    // Only `currency` is fetched, like on Binance
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        _extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        Box::pin(self.tickers(coin_ids, currency, hooks))
+    }
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(async move {
+            let candles = self.candles(coin_id, currency, range).await?;
+            Ok(candles
+                .into_iter()
+                .map(|candle| (candle.time, candle.close))
+                .collect())
+        })
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        Box::pin(self.candles(coin_id, currency, range))
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        self.coingecko.trending()
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        self.coingecko.global()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_codes_are_normalized_to_the_pair_asked_for() {
+        assert_eq!(normalize_pair("XXBTZUSD"), "XBTUSD");
+        assert_eq!(normalize_pair("XETHZEUR"), "ETHEUR");
+        assert_eq!(normalize_pair("XETHXXBT"), "ETHXBT");
+        assert_eq!(normalize_pair("SOLUSD"), "SOLUSD");
+        // Newer pairs of eight letters keep them all
+        assert_eq!(normalize_pair("AVAXUSDT"), "AVAXUSDT");
+        // Eight bytes, but not eight letters
+        assert_eq!(normalize_pair("XéTZUSD"), "XéTZUSD");
+
+        let symbols = BTreeMap::new();
+        let asset = kraken_asset(&symbols, "bitcoin").unwrap();
+        assert_eq!(kraken_pair(&asset, "usd"), "XBTUSD");
+        assert_eq!(kraken_pair("ETH", "btc"), "ETHXBT");
+        assert_eq!(display_symbol(&asset), "BTC");
+    }
+
+    #[test]
+    fn the_change_comes_from_the_open_and_last_price() {
+        assert_eq!(change_percent(100.0, 102.5), 2.5);
+        assert_eq!(change_percent(200.0, 150.0), -25.0);
+        assert_eq!(change_percent(0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn errors_in_the_body_are_api_errors() {
+        let json_text = r#"{"error": ["EQuery:Unknown asset pair"]}"#;
+        let unknown = parse_tickers(json_text).unwrap_err();
+        assert_eq!(unknown.to_string(), "Kraken: EQuery:Unknown asset pair");
+        assert!(!unknown.is_retryable());
+        let unavailable = error(
+            StatusCode::BAD_GATEWAY,
+            r#"{"error": ["EService:Unavailable"]}"#,
+        );
+        assert_eq!(unavailable.to_string(), "Kraken: EService:Unavailable");
+        assert!(unavailable.is_retryable());
+    }
+}
@@ -0,0 +1,39 @@
+//! Crypto price tracker for the terminal, the binary in main.rs only sets
+//! up the terminal and runs the event loop
+
+pub mod alerts;
+pub mod api;
+pub mod app;
+pub mod binance;
+pub mod cache;
+pub mod cli;
+pub mod clipboard;
+pub mod coinmarketcap;
+pub mod config;
+pub mod demo;
+pub mod error;
+pub mod export;
+pub mod failover;
+pub mod fear_greed;
+pub mod heatmap;
+pub mod history;
+pub mod import;
+pub mod keymap;
+pub mod kraken;
+pub mod limiter;
+pub mod listener;
+pub mod logging;
+pub mod metrics;
+pub mod model;
+pub mod output;
+pub mod portfolio;
+pub mod serve;
+pub mod sinks;
+pub mod source;
+pub mod state;
+pub mod stream;
+pub mod theme;
+pub mod title;
+pub mod ui;
+pub mod watch;
+pub mod watchlist;
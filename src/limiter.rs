@@ -0,0 +1,164 @@
+//! A token bucket every CoinGecko request takes a token from, so the price
+//! refreshes, charts, trending coins and market totals together stay under
+//! `max_requests_per_minute`
+
+use crate::error::TuickerError;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // Waits for a token, like the prices and the chart that was asked for
+    Queued,
+    // Fails with `Throttled` when no token is free, the trending coins and
+    // market totals are fetched again later anyway
+    Droppable,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    // A full minute's worth can go out at once, the rest is refilled evenly
+    capacity: f64,
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+    // Taken in turn by the queued requests, which wait holding it
+    queue: tokio::sync::Mutex<()>,
+    waiting: AtomicUsize,
+}
+
+// Counts a queued request as waiting until it got its token or was dropped
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        let capacity = per_minute.max(1) as f64;
+        RateLimiter {
+            capacity,
+            per_second: capacity / 60.0,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+            queue: tokio::sync::Mutex::new(()),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    // A queued request is waiting for its token
+    pub fn throttled(&self) -> bool {
+        self.waiting.load(Ordering::Relaxed) > 0
+    }
+
+    pub async fn acquire(&self, priority: Priority) -> Result<(), TuickerError> {
+        if priority == Priority::Droppable {
+            // No cutting in front of the queued requests either
+            let Ok(_turn) = self.queue.try_lock() else {
+                return Err(TuickerError::Throttled);
+            };
+            return match self.take() {
+                None => Ok(()),
+                Some(_) => Err(TuickerError::Throttled),
+            };
+        }
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.waiting);
+        let _turn = self.queue.lock().await;
+        while let Some(wait) = self.take() {
+            log::debug!("throttled, next request in {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    // Takes a token, or says how long until the next one is there
+    fn take(&self) -> Option<Duration> {
+        let Ok(mut bucket) = self.bucket.lock() else {
+            return None;
+        };
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(self.capacity);
+        bucket.refilled = now;
+        match bucket.tokens >= 1.0 {
+            true => {
+                bucket.tokens -= 1.0;
+                None
+            }
+            false => Some(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_come_back_at_the_rate_of_the_limit() {
+        let limiter = RateLimiter::new(6);
+        let started = Instant::now();
+        for _ in 0..6 {
+            limiter.acquire(Priority::Queued).await.unwrap();
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        // One token every 10 seconds once the burst is spent
+        limiter.acquire(Priority::Queued).await.unwrap();
+        assert_eq!(started.elapsed().as_secs(), 10);
+        limiter.acquire(Priority::Queued).await.unwrap();
+        assert_eq!(started.elapsed().as_secs(), 20);
+
+        // A minute of quiet fills it up again, and no further
+        tokio::time::advance(Duration::from_secs(120)).await;
+        let started = Instant::now();
+        for _ in 0..6 {
+            limiter.acquire(Priority::Queued).await.unwrap();
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert!(matches!(
+            limiter.acquire(Priority::Droppable).await,
+            Err(TuickerError::Throttled)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn droppable_requests_do_not_wait_or_cut_in_line() {
+        let limiter = Arc::new(RateLimiter::new(1));
+        limiter.acquire(Priority::Droppable).await.unwrap();
+        assert!(matches!(
+            limiter.acquire(Priority::Droppable).await,
+            Err(TuickerError::Throttled)
+        ));
+
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Priority::Queued).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(limiter.throttled());
+        // The token that comes back is the queued request's
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(matches!(
+            limiter.acquire(Priority::Droppable).await,
+            Err(TuickerError::Throttled)
+        ));
+        queued.await.unwrap().unwrap();
+        assert!(!limiter.throttled());
+    }
+}
@@ -0,0 +1,161 @@
+//! Just enough of an HTTP/1.1 server for `--metrics-listen` and `serve`:
+//! one GET per connection, answered by a plain function and then closed
+
+use reqwest::StatusCode;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// A client that sends nothing for this long is let go of
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Request lines and headers past this are not read
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    // Without the query string
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: StatusCode, content_type: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    // 404 for other paths, 405 for anything but GET
+    pub fn not_found(request: &Request) -> Self {
+        match request.method == "GET" {
+            true => Response::new(
+                StatusCode::NOT_FOUND,
+                "text/plain; charset=utf-8",
+                format!("nothing at {}\n", request.path),
+            ),
+            false => Response::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "text/plain; charset=utf-8",
+                String::new(),
+            ),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        [head.into_bytes(), self.body.into_bytes()].concat()
+    }
+}
+
+// "GET /coins?x=1 HTTP/1.1" -> GET /coins
+pub fn parse_request_line(line: &str) -> Option<Request> {
+    let mut parts = line.split(' ');
+    let (method, target) = (parts.next()?, parts.next()?);
+    let path = target.split('?').next().unwrap_or_default();
+    match path.starts_with('/') {
+        true => Some(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+        }),
+        false => None,
+    }
+}
+
+// Answers connections until the task is aborted, which drops the listener
+pub async fn serve_http<H>(listener: TcpListener, handler: H)
+where
+    H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::warn!("could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(REQUEST_TIMEOUT, answer(stream, handler.as_ref())).await {
+                Ok(Err(e)) => log::debug!("request failed: {}", e),
+                Err(_) => log::debug!("the request never came"),
+                Ok(Ok(())) => {}
+            }
+        });
+    }
+}
+
+// Reads up to the end of the headers, a request line that doesn't parse
+// gets a 400
+async fn answer<H>(mut stream: TcpStream, handler: &H) -> io::Result<()>
+where
+    H: Fn(&Request) -> Response,
+{
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() > MAX_REQUEST_LENGTH {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let text = String::from_utf8_lossy(&request);
+    let response = match parse_request_line(text.lines().next().unwrap_or_default()) {
+        Some(request) => {
+            log::debug!("{} {}", request.method, request.path);
+            handler(&request)
+        }
+        None => Response::new(
+            StatusCode::BAD_REQUEST,
+            "text/plain; charset=utf-8",
+            String::new(),
+        ),
+    };
+    stream.write_all(&response.into_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_lines_lose_their_query() {
+        assert_eq!(
+            parse_request_line("GET /coins/bitcoin?pretty=1 HTTP/1.1"),
+            Some(Request {
+                method: "GET".to_string(),
+                path: "/coins/bitcoin".to_string(),
+            })
+        );
+        assert_eq!(parse_request_line("GET"), None);
+        assert_eq!(parse_request_line("GET coins HTTP/1.1"), None);
+    }
+
+    #[test]
+    fn responses_have_their_length_and_close() {
+        let response = Response::new(StatusCode::OK, "text/plain", "hi\n".to_string());
+        assert_eq!(
+            String::from_utf8(response.into_bytes()).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\nConnection: close\r\n\r\nhi\n"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! The log file. While the TUI is up the terminal is the screen, so this is
+//! the only place anything tuicker has to say about requests, refreshes and
+//! alerts can go
+
+use crate::config::xdg_dir;
+use crate::output::rfc3339;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Also read when --log-level isn't given
+pub const LOG_ENV: &str = "RUST_LOG";
+// The file is moved to tuicker.log.1 once it grows past this, replacing the
+// one before, so the two never take more than twice the cap
+pub const LOG_SIZE_CAP: u64 = 1024 * 1024;
+
+pub fn log_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_STATE_HOME", ".local/state")?.join("tuicker.log"))
+}
+
+pub fn parse_level(text: &str) -> Result<LevelFilter, String> {
+    text.trim().parse().map_err(|_| {
+        format!(
+            "unknown log level `{}`, expected off, error, warn, info, debug or trace",
+            text
+        )
+    })
+}
+
+// `debug`, or directives like `tuicker=debug,reqwest=warn` of which only a
+// bare level or the one for tuicker count
+pub fn level_from_env(value: &str) -> Option<LevelFilter> {
+    value
+        .split(',')
+        .rev()
+        .find_map(|directive| match directive.split_once('=') {
+            Some((target, level)) if target.trim() == "tuicker" => parse_level(level).ok(),
+            Some(_) => None,
+            None => parse_level(directive).ok(),
+        })
+}
+
+// Query parameters that could carry a key are replaced, in case one ever
+// ends up in a URL instead of a header
+pub fn redact_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let params: Vec<String> = query
+        .split('&')
+        .map(|param| match param.split_once('=') {
+            Some((name, _)) if name.to_ascii_lowercase().contains("key") => {
+                format!("{}=REDACTED", name)
+            }
+            _ => param.to_string(),
+        })
+        .collect();
+    format!("{}?{}", base, params.join("&"))
+}
+
+struct FileLogger {
+    path: PathBuf,
+    cap: u64,
+    file: Mutex<File>,
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl FileLogger {
+    // Past the cap the file starts over, with the old one kept beside it
+    fn rotate(&self, file: &mut File) -> std::io::Result<()> {
+        if file.metadata()?.len() < self.cap {
+            return Ok(());
+        }
+        std::fs::rename(&self.path, self.path.with_extension("log.1"))?;
+        *file = open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Log for FileLogger {
+    // Libraries like reqwest log through the same crate, their lines aren't
+    // what the file is for
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("tuicker")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:5} {}: {}\n",
+            rfc3339(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // A log that can't be written is given up on quietly, there is
+        // nowhere to report it
+        if let Ok(mut file) = self.file.lock() {
+            let _ = self.rotate(&mut file);
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+// Sends the `log` macros of the whole app to `path` from here on. Nothing
+// is opened with LevelFilter::Off
+pub fn init(path: &Path, level: LevelFilter) -> Result<(), String> {
+    if level == LevelFilter::Off {
+        return Ok(());
+    }
+    let failed = |e: &dyn std::fmt::Display| format!("could not open {}: {}", path.display(), e);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| failed(&e))?;
+    }
+    let logger = FileLogger {
+        path: path.to_path_buf(),
+        cap: LOG_SIZE_CAP,
+        file: Mutex::new(open(path).map_err(|e| failed(&e))?),
+    };
+    // Lives as long as the process, like any logger
+    log::set_logger(Box::leak(Box::new(logger))).map_err(|e| failed(&e))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn levels_come_from_the_flag_or_rust_log() {
+        assert_eq!(parse_level("debug"), Ok(LevelFilter::Debug));
+        assert_eq!(parse_level(" WARN "), Ok(LevelFilter::Warn));
+        let error = parse_level("loud").unwrap_err();
+        assert!(error.starts_with("unknown log level `loud`"), "{}", error);
+
+        assert_eq!(level_from_env("info"), Some(LevelFilter::Info));
+        let directives = "tuicker=trace,reqwest=warn";
+        assert_eq!(level_from_env(directives), Some(LevelFilter::Trace));
+        assert_eq!(level_from_env("reqwest=debug"), None);
+        assert_eq!(level_from_env(""), None);
+    }
+
+    #[test]
+    fn keys_are_redacted_from_urls() {
+        assert_eq!(
+            redact_url(
+                "https://api.coingecko.com/api/v3/coins/markets?vs_currency=usd&x_cg_demo_api_key=CG-secret"
+            ),
+            "https://api.coingecko.com/api/v3/coins/markets?vs_currency=usd&x_cg_demo_api_key=REDACTED"
+        );
+        let url = "https://api.coingecko.com/api/v3/global";
+        assert_eq!(redact_url(url), url);
+    }
+
+    #[test]
+    fn the_file_is_rotated_past_the_cap() {
+        let dir = std::env::temp_dir().join(format!("tuicker-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tuicker.log");
+        let logger = FileLogger {
+            path: path.clone(),
+            cap: 100,
+            file: Mutex::new(open(&path).unwrap()),
+        };
+        let line = format_args!("{}", "x".repeat(80));
+        let record = Record::builder()
+            .args(line)
+            .level(Level::Info)
+            .target("tuicker::api")
+            .build();
+        logger.log(&record);
+        logger.log(&record);
+        let rotated = std::fs::read_to_string(path.with_extension("log.1")).unwrap();
+        assert_eq!(rotated.lines().count(), 1);
+        assert!(rotated.contains("INFO  tuicker::api: xxx"), "{}", rotated);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        let other = Record::builder()
+            .args(format_args!("pool"))
+            .target("hyper::client")
+            .build();
+        logger.log(&other);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,214 +1,702 @@
 use crossterm::{
-    event::{self, Event, EventStream, KeyCode},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream},
     execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+    style::Print,
+    terminal::{
+        EnterAlternateScreen, LeaveAlternateScreen, SetTitle, disable_raw_mode, enable_raw_mode,
+    },
 };
 use futures::StreamExt;
-use ratatui::{
-    Frame, Terminal,
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout, Rect},
-    widgets::{Block, Borders, Paragraph},
-};
-use reqwest::Response;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::io;
+use ratatui::{Terminal, backend::CrosstermBackend};
+use std::io::{self, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
 use tokio::select;
-use tokio::time::{Duration, Interval};
+use tokio::sync::mpsc;
+use tuicker::alerts::alert_state_path;
+use tuicker::api::{build_client, build_webhook_client};
+use tuicker::app::{
+    App, FetchMessage, KeyAction, request_fear_greed, request_global, request_refresh,
+    request_trending, spawn_chart_fetch, spawn_coin_lookup, spawn_history_load, spawn_history_save,
+    spawn_ohlc_fetch,
+};
+use tuicker::cache::cache_path;
+use tuicker::cli::{HistoryExport, USAGE, parse_args};
+use tuicker::clipboard::copy;
+use tuicker::config::{
+    API_KEY_ENV, CMC_KEY_ENV, Config, NO_COLOR_ENV, SourceKind, load_config, numeric_locale_env,
+    proxy_env,
+};
+use tuicker::error::TuickerError;
+use tuicker::export::format_history_csv;
+use tuicker::history::{HistoryStore, history_dir};
+use tuicker::import::{apply_import, parse_trades};
+use tuicker::logging::{LOG_ENV, level_from_env, log_path};
+use tuicker::metrics::{SharedMetrics, serve_metrics};
+use tuicker::model::{SortDir, SortKey, sort_coins};
+use tuicker::output::{OutputFormat, render};
+use tuicker::portfolio::{load_portfolio, portfolio_path, save_portfolio};
+use tuicker::serve::{Server, run_refreshes, serve_coins};
+use tuicker::sinks::{Bell, DesktopNotifier, Webhook};
+use tuicker::source::{DataSource, RefreshHooks, build_source};
+use tuicker::state::{load_ui_state, ui_state_path};
+use tuicker::stream::{
+    BINANCE_STREAM_URL, build_stream_client, run_stream, stream_pairs, stream_url,
+};
+use tuicker::ui::ui;
+use tuicker::watch::Watch;
+use tuicker::watchlist::{load_watchlist, watchlist_path};
 
-#[derive(Debug, Clone)]
-struct Coin {
-    id: String,
-    name: String,
-    symbol: String,
-    current_price: f64,
-    price_change_24h: f64,
-}
+// How often the screen is redrawn without any other event
+const UI_TICK: Duration = Duration::from_millis(100);
 
-#[derive(Debug, Deserialize)]
-struct CoinGeckoData {
-    usd: f64,
-    usd_24h_change: f64,
+// Puts the terminal into raw mode on the alternate screen, with mouse
+// capture unless it is turned off, and restores it when dropped, so early
+// `?` returns clean up too
+struct TerminalGuard {
+    // restore_terminal, unless a test is counting the calls
+    restore: fn() -> io::Result<()>,
 }
 
-impl Coin {
-    fn price_formatted(&self) -> String {
-        format!("${:.2}", self.current_price)
+impl TerminalGuard {
+    fn new(mouse: bool) -> io::Result<Self> {
+        enter_terminal(mouse)?;
+        Ok(TerminalGuard {
+            restore: restore_terminal,
+        })
     }
+}
 
-    fn change_24h_formatted(&self) -> String {
-        format!("{:+.2}%", self.price_change_24h * 100.0)
+fn enter_terminal(mouse: bool) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    if mouse {
+        execute!(io::stdout(), EnableMouseCapture)?;
     }
+    Ok(())
+}
 
-    fn is_up(&self) -> bool {
-        self.price_change_24h > 0.0
-    }
+fn leave_terminal() -> io::Result<()> {
+    disable_raw_mode()?;
+    // Harmless when capture was never turned on
+    execute!(io::stdout(), DisableMouseCapture, LeaveAlternateScreen)?;
+    Ok(())
 }
 
-async fn fetch_coin_prices() -> Result<String, reqwest::Error> {
-    // 1. Define the API URL
-    let api_url: String = "https://api.coingecko.com/api/v3/simple/price?".to_string();
-    // Hard coded values for now
-    let coin_ids: String = "ids=bitcoin,ethereum,cardano&".to_string();
-    let vs_currency: String = "vs_currencies=usd&".to_string();
-    let include_24_hour_change: String = "include_24hr_change=true".to_string();
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = (self.restore)();
+    }
+}
 
-    let url: String = format!(
-        "{}{}{}{}",
-        api_url, coin_ids, vs_currency, include_24_hour_change
-    );
-    // 2. Make HTTP GET request
-    let response: Response = reqwest::get(url).await?;
+// Set by whichever of the guard and the panic hook restores the terminal
+// first, so a panic on the way out doesn't tear it down a second time
+static TERMINAL_RESTORED: AtomicBool = AtomicBool::new(false);
+// The title from before `title_format` was pushed on the terminal's stack
+static TITLE_SAVED: AtomicBool = AtomicBool::new(false);
 
-    // 3. Get response text
-    let response_text = response.text().await?;
+// xterm's title stack. Terminals without one ignore both, they are left
+// with the empty title, which most of them replace by their own
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
 
-    Ok(response_text)
+fn restore_terminal() -> io::Result<()> {
+    if TERMINAL_RESTORED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    if TITLE_SAVED.load(Ordering::SeqCst) {
+        execute!(io::stdout(), SetTitle(""), Print(POP_TITLE))?;
+    }
+    leave_terminal()
 }
 
-fn parse_coin_response(
-    json_text: &str,
-) -> Result<HashMap<String, CoinGeckoData>, serde_json::Error> {
-    let parsed: HashMap<String, CoinGeckoData> = serde_json::from_str(json_text)?;
-    Ok(parsed)
+fn save_title() -> io::Result<()> {
+    execute!(io::stdout(), Print(PUSH_TITLE))?;
+    TITLE_SAVED.store(true, Ordering::SeqCst);
+    Ok(())
 }
 
-fn convert_to_coins(coin_map: HashMap<String, CoinGeckoData>) -> Vec<Coin> {
-    let mut coins: Vec<Coin> = Vec::new();
+// Written only when it changed, `shown` is the title written last
+fn update_title(app: &App, shown: &mut Option<String>) {
+    let title = app.window_title();
+    if title.is_none() || title == *shown {
+        return;
+    }
+    if let Some(text) = &title
+        && let Err(e) = execute!(io::stdout(), SetTitle(text))
+    {
+        log::warn!("could not set the window title: {}", e);
+    }
+    *shown = title;
+}
 
-    for (coin_id, coin_data) in coin_map {
-        let coin = Coin {
-            id: coin_id.clone(),
-            symbol: coin_id.to_uppercase(),
-            name: coin_id.clone(),
-            current_price: coin_data.usd,
-            price_change_24h: coin_data.usd_24h_change,
-        };
-        coins.push(coin);
+// Ctrl-Z: raw mode turned the signal into a key, so the terminal is handed
+// back and the job stopped the way the shell would. The process doesn't
+// run at all until `fg` sends SIGCONT, then the screen is drawn anew
+#[cfg(unix)]
+fn suspend(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, mouse: bool) -> io::Result<()> {
+    leave_terminal()?;
+    // SAFETY: kill only sends a signal, 0 is our own process group
+    if unsafe { libc::kill(0, libc::SIGTSTP) } != 0 {
+        log::warn!("could not suspend: {}", io::Error::last_os_error());
     }
-    coins
+    enter_terminal(mouse)?;
+    terminal.clear()
 }
 
-/*
-fn get_sample_coins() -> Vec<Coin> {
-    vec![
-        Coin {
-            id: "bitcoin".to_string(),
-            name: "Bitcoin".to_string(),
-            symbol: "BTC".to_string(),
-            current_price: 11000.320,
-            price_change_24h: -0.05,
-        },
-        Coin {
-            id: "ethereum".to_string(),
-            name: "Ethereum".to_string(),
-            symbol: "ETH".to_string(),
-            current_price: 6000.23,
-            price_change_24h: -0.05,
-        },
-        Coin {
-            id: "cardano".to_string(),
-            name: "Cardano".to_string(),
-            symbol: "ADA".to_string(),
-            current_price: 672.320,
-            price_change_24h: 0.27,
-        },
-    ]
+// Restore the terminal before the panic message is printed,
+// otherwise it ends up garbled on the alternate screen
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = restore_terminal();
+        default_hook(info);
+    }));
 }
-*/
 
-fn format_coins(coins: &[Coin]) -> String {
-    let mut lines: Vec<String> = Vec::new();
-    for coin in coins {
-        let line = format!(
-            "{:6} {:12} ${:>10.2} {:>6.2}%",
-            coin.symbol, coin.name, coin.current_price, coin.price_change_24h
-        );
-        lines.push(line);
+// SIGINT and SIGTERM, which in raw mode only come from `kill` or another
+// shell, would end the process without restoring the terminal. Resolves
+// with the name of the first one
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{SignalKind, signal};
+    let (Ok(mut interrupt), Ok(mut terminate)) = (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) else {
+        log::warn!("could not listen for SIGINT and SIGTERM");
+        return std::future::pending().await;
+    };
+    select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
     }
-    lines.join("\n")
 }
 
-async fn refresh_output() -> Result<Vec<Coin>, Box<dyn std::error::Error>> {
-    let json_text = fetch_coin_prices().await?;
-    let coin_map = parse_coin_response(&json_text)?;
-    Ok(convert_to_coins(coin_map))
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => "Ctrl-C",
+        Err(e) => {
+            log::warn!("could not listen for Ctrl-C: {}", e);
+            std::future::pending().await
+        }
+    }
 }
 
-fn ui(frame: &mut Frame, coins: &[Coin]) {
-    // Create the area
-    // Then split the area into chunks
-    let area = frame.area();
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(1), // Header (fixed 1 line)
-            Constraint::Min(1),    // Main area (grows)
-            Constraint::Length(1), // Footer (fixed 1 line)
-        ])
-        .split(area);
+// `--watch`: repaints the table after every refresh until SIGINT or
+// SIGTERM, which only ever come in between two frames, or until the
+// reader of a pipe is gone
+async fn watch(config: &Config, source: &dyn DataSource) {
+    let terminal = io::stdout().is_terminal();
+    let mut watch = Watch::new(config);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let wait = select! {
+            wait = watch.refresh(config, source) => wait,
+            signal = &mut shutdown => {
+                log::info!("{}, stopping", signal);
+                return;
+            }
+        };
+        print_stdout(format_args!("{}", watch.frame(config, terminal)));
+        select! {
+            _ = tokio::time::sleep(wait) => {}
+            signal = &mut shutdown => {
+                log::info!("{}, stopping", signal);
+                return;
+            }
+        }
+    }
+}
 
-    let header_area = chunks[0];
-    let main_area = chunks[1];
-    let footer_area = chunks[2];
+// `serve`: refreshes in the background and answers the JSON API until
+// SIGINT or SIGTERM
+async fn serve(config: Config, source: Arc<dyn DataSource>, address: SocketAddr) {
+    let listener = match tokio::net::TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("tuicker: could not listen on {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+    let server = Arc::new(Server::new(config));
+    let refreshes = tokio::spawn(run_refreshes(server.clone(), source));
+    let answers = tokio::spawn(serve_coins(listener, server));
+    print_stdout(format_args!(
+        "serving the prices on http://{}/coins\n",
+        address
+    ));
+    let signal = shutdown_signal().await;
+    log::info!("{}, stopping", signal);
+    refreshes.abort();
+    answers.abort();
+}
 
-    // HEADER
-    let header = Block::default().title("Crypto Tracker");
-    frame.render_widget(header, header_area);
+// `import --file`: folds the trades into the portfolio file. Returns the
+// messages to print on failure, one per line
+fn import_trades(file: &Path) -> Result<String, Vec<String>> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| vec![format!("could not read {}: {}", file.display(), e)])?;
+    let trades = parse_trades(&text)?;
+    let path = portfolio_path().ok_or(vec!["could not find the home directory".to_string()])?;
+    let mut portfolio = load_portfolio().map_err(|e| vec![e.to_string()])?;
+    let coins = apply_import(&mut portfolio, &trades)?;
+    save_portfolio(&path, &portfolio).map_err(|e| vec![e])?;
+    Ok(format!(
+        "imported {} trades ({} coins held) into {}",
+        trades.len(),
+        coins,
+        path.display()
+    ))
+}
 
-    // MAIH
-    // Initial Refresh
-    let text = format_coins(&coins);
-    let paragraph = Paragraph::new(text).block(Block::default().borders(Borders::ALL));
-    frame.render_widget(paragraph, main_area);
+// `export-history`: the saved prices of a coin as CSV, written to stdout or
+// to a file with a summary to print
+fn export_history(config: &Config, export: &HistoryExport) -> Result<Option<String>, String> {
+    let dir = history_dir().ok_or("could not find the home directory")?;
+    let retention = Duration::from_secs(config.history_retention_days * 24 * 60 * 60);
+    let store = HistoryStore::new(&dir, &config.currency, retention);
+    let samples = store.samples_between(&export.coin_id, export.from, export.to)?;
+    let text = format_history_csv(&samples);
+    if export.out == Path::new("-") {
+        print_stdout(format_args!("{}", text));
+        return Ok(None);
+    }
+    std::fs::write(&export.out, text)
+        .map_err(|e| format!("could not write {}: {}", export.out.display(), e))?;
+    Ok(Some(format!(
+        "exported {} prices of {} to {}",
+        samples.len(),
+        export.coin_id,
+        export.out.display()
+    )))
+}
 
-    // FOOTER
-    let help_message = Paragraph::new("q: quit\t r: refresh");
+// --once: a single fetch printed as plain text. Stays clear of crossterm
+// so it works in pipes and cron jobs
+async fn print_once(
+    config: &Config,
+    source: &dyn DataSource,
+    format: OutputFormat,
+) -> Result<(), TuickerError> {
+    let refresh = source
+        .refresh(&config.coins, &config.currency, &[], RefreshHooks::NONE)
+        .await?;
+    // The coins that came through are still worth printing
+    if let Some(failure) = &refresh.failure {
+        eprintln!("tuicker: {}", failure);
+    }
+    let mut coins = refresh.coins;
+    sort_coins(
+        &mut coins,
+        SortKey::Watchlist,
+        SortDir::Ascending,
+        &config.coins,
+    );
+    let color = io::stdout().is_terminal() && !config.no_color;
+    let output = render(
+        format,
+        &coins,
+        &config.currency,
+        SystemTime::now(),
+        color,
+        config.number_locale(),
+    );
+    print_stdout(format_args!("{}\n", output));
+    Ok(())
+}
 
-    frame.render_widget(help_message, footer_area);
+// Output for pipes ends when the reader does, `| head` closing its end
+// early is no error. Anything else that stops the writing is one
+fn print_stdout(text: std::fmt::Arguments) {
+    let mut stdout = io::stdout().lock();
+    match stdout.write_fmt(text).and_then(|()| stdout.flush()) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => {
+            eprintln!("tuicker: could not write the output: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), TuickerError> {
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("tuicker: {}", message);
+            std::process::exit(2);
+        }
+    };
+    if cli.help {
+        print_stdout(format_args!("{}\n", USAGE));
+        return Ok(());
+    }
+    // Reported in the footer later, stderr belongs to the TUI by then
+    let level = cli
+        .log_level
+        .or_else(|| level_from_env(&std::env::var(LOG_ENV).ok()?));
+    let log_message = match (level, log_path()) {
+        (Some(level), Some(path)) => tuicker::logging::init(&path, level).err(),
+        (Some(_), None) => Some("could not find the home directory for the log".to_string()),
+        (None, _) => None,
+    };
+    log::info!("tuicker {} starting", env!("CARGO_PKG_VERSION"));
+    if let Some(file) = &cli.import_file {
+        match import_trades(file) {
+            Ok(summary) => print_stdout(format_args!("{}\n", summary)),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("tuicker: {}", error);
+                }
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let mut config = match load_config() {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("tuicker: {}", message);
+            std::process::exit(1);
+        }
+    };
+    // The saved watchlist is used unless --coins picks the coins for this session
+    let mut watchlist = None;
+    let mut watchlist_message = None;
+    if cli.coin_ids.is_none()
+        && let Some(path) = watchlist_path()
+    {
+        let (loaded, message) = load_watchlist(&path, &config.coins);
+        config.coins = loaded.ids();
+        watchlist = Some(loaded);
+        watchlist_message = message;
+    }
+    config.apply_env(std::env::var(API_KEY_ENV).ok().as_deref());
+    config.apply_cmc_env(std::env::var(CMC_KEY_ENV).ok().as_deref());
+    config.apply_locale_env(numeric_locale_env().as_deref());
+    config.apply_no_color_env(std::env::var(NO_COLOR_ENV).ok().as_deref());
+    config.apply_cli(&cli);
+    // --no-proxy is there to get around a broken one, so don't even look
+    if !cli.no_proxy
+        && let Err(e) = config.apply_proxy_env(proxy_env().as_deref())
+    {
+        eprintln!("tuicker: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(export) = &cli.export_history {
+        match export_history(&config, export) {
+            Ok(summary) => summary
+                .into_iter()
+                .for_each(|summary| print_stdout(format_args!("{}\n", summary))),
+            Err(e) => {
+                eprintln!("tuicker: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let (client, webhook_client) = match (build_client(&config), build_webhook_client(&config)) {
+        (Ok(client), Ok(webhook_client)) => (client, webhook_client),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("tuicker: could not set up the HTTP client: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let source = match build_source(&config, client, webhook_client.clone()) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("tuicker: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let demo = config.source == SourceKind::Demo;
+    if cli.once {
+        if let Err(e) = print_once(&config, source.as_ref(), cli.format).await {
+            eprintln!("tuicker: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.watch {
+        watch(&config, source.as_ref()).await;
+        return Ok(());
+    }
+    if let Some(address) = cli.serve {
+        serve(config, source, address).await;
+        return Ok(());
+    }
+
+    let portfolio = match load_portfolio() {
+        Ok(portfolio) => portfolio,
+        Err(message) => {
+            eprintln!("tuicker: {}", message);
+            std::process::exit(1);
+        }
+    };
+
+    // A taken port is found out before the screen is taken over
+    let metrics_listener = match cli.metrics_listen {
+        Some(address) => match tokio::net::TcpListener::bind(address).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                eprintln!("tuicker: could not listen on {}: {}", address, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
     // Set up terminal
-    enable_raw_mode()?;
+    install_panic_hook();
+    let guard = TerminalGuard::new(config.mouse)?;
+    if config.title_template().is_some() {
+        save_title()?;
+    }
     let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
-    execute!(io::stdout(), EnterAlternateScreen)?;
 
     // Start Event Stream render
     let mut reader = EventStream::new();
+    // Fetch results come back over this channel
+    let (refresh_tx, mut refresh_rx) = mpsc::unbounded_channel();
+    // Made up prices have no place in the files of real sessions, coins
+    // added in the demo are only kept until it ends
+    let watchlist = watchlist.filter(|_| !demo);
+    let mut app = App::new(config, watchlist, source);
+    app.portfolio = portfolio;
+    app.debug = cli.debug;
+    if app.config.notifications {
+        app.alert_sinks.others.push(Box::new(DesktopNotifier));
+    }
+    // Only a terminal knows what to do with the bell character
+    if app.config.alert_bell && io::stdout().is_terminal() {
+        app.alert_sinks.others.push(Box::new(Bell));
+    }
+    if app.config.show_fear_greed && !demo {
+        app.fear_greed_client = Some(webhook_client.clone());
+    }
+    if let Some(url) = app.config.webhook_url.clone().filter(|_| !demo) {
+        app.alert_sinks.others.push(Box::new(Webhook {
+            client: webhook_client,
+            url,
+            tx: refresh_tx.clone(),
+        }));
+    }
+    if let Some(listener) = metrics_listener {
+        let metrics = SharedMetrics::default();
+        app.metrics = Some(metrics.clone());
+        // Stopped with the fetches on the way out
+        app.background
+            .fetch(tokio::spawn(serve_metrics(listener, metrics)));
+    }
+    if let Some(path) = alert_state_path().filter(|_| !demo) {
+        app.use_alert_state(path);
+    }
+    if let Some(path) = ui_state_path() {
+        app.ui_state = load_ui_state(&path);
+        app.ui_state_path = Some(path);
+        // Reopen the tab the last session ended on
+        app.show_tab(app.ui_state.tab);
+    }
+    if let Some(path) = cache_path().filter(|_| !cli.no_cache && !demo) {
+        app.use_cache(path);
+    }
+    let retention = Duration::from_secs(app.config.history_retention_days * 24 * 60 * 60);
+    let keep_history = !cli.no_history && !demo && !retention.is_zero();
+    if let Some(dir) = history_dir().filter(|_| keep_history) {
+        app.history_store = Some(HistoryStore::new(&dir, &app.config.currency, retention));
+        spawn_history_load(&app, &refresh_tx);
+    }
+    for message in [log_message, watchlist_message].into_iter().flatten() {
+        app.status_message = Some(match app.status_message.take() {
+            Some(warning) => format!("{}  {}", message, warning),
+            None => message,
+        });
+    }
     // Initial refresh to fetch coins
-    let mut coins = refresh_output().await?;
+    request_refresh(&mut app, &refresh_tx);
+    if app.config.streams() {
+        match build_stream_client(&app.config) {
+            Ok(client) => {
+                let pairs = stream_pairs(&app.config);
+                let url = stream_url(BINANCE_STREAM_URL, &pairs);
+                let stream = tokio::spawn(run_stream(client, url, pairs, refresh_tx.clone()));
+                app.background.fetch(stream);
+            }
+            Err(e) => log::warn!("no price stream: {}", e),
+        }
+    }
     // Set up interval for refresh
-    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    let mut interval = tokio::time::interval(app.refresh_interval);
     interval.tick().await;
+    app.schedule_next_refresh();
+    // Redraws the footer so the spinner and refresh countdown keep moving
+    let mut ui_tick = tokio::time::interval(UI_TICK);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut title = None;
 
-    // Event loop
-    loop {
+    // Event loop, left the same way for `q`, a signal and a fatal error
+    let outcome = loop {
         terminal.draw(|frame| {
-            ui(frame, &coins); // call the custom UI function
+            ui(frame, &app); // call the custom UI function
         })?;
 
         // Poll for events with timeout
         select! {
             _ = interval.tick() => {
-            coins = refresh_output().await?;
+                app.schedule_next_refresh();
+                match (app.paused, app.streaming) {
+                    (true, _) => log::debug!("refresh skipped: paused"),
+                    // The stream keeps the prices current, they only go
+                    // into the history here
+                    (false, true) => {
+                        let refresh = app.streamed_refresh();
+                        spawn_history_save(&app, &refresh_tx, &refresh.coins);
+                        app.finish_refresh(refresh);
+                        update_title(&app, &mut title);
+                    }
+                    (false, false) => {
+                        request_refresh(&mut app, &refresh_tx);
+                    }
+                }
+            }
+            _ = ui_tick.tick() => {
+                app.tick();
+                request_trending(&mut app, &refresh_tx);
+                request_global(&mut app, &refresh_tx);
+                request_fear_greed(&mut app, &refresh_tx);
+            }
+            Some(message) = refresh_rx.recv() => match message {
+                FetchMessage::Retrying(attempt) => app.retrying(attempt),
+                FetchMessage::Refreshed(result) => {
+                    app.fetching = false;
+                    app.fetch_attempt = 1;
+                    // Keep showing the previous coins if the refresh fails
+                    match result {
+                        Ok(refresh) => {
+                            spawn_history_save(&app, &refresh_tx, &refresh.coins);
+                            app.finish_refresh(refresh);
+                            update_title(&app, &mut title);
+                        }
+                        Err(error) if error.is_fatal() => break Err(error),
+                        Err(error) => {
+                            if let Some(until) = app.refresh_failed(&error) {
+                                // Hold off the automatic refresh until the cool-down is over
+                                interval.reset_at(until.into());
+                            }
+                        }
+                    }
+                }
+                FetchMessage::CoinLookup(coin_id, result) => {
+                    app.finish_coin_lookup(coin_id, result);
+                }
+                FetchMessage::Chart(coin_id, range, result) => {
+                    app.finish_chart(coin_id, range, result);
+                }
+                FetchMessage::Ohlc(coin_id, range, result) => {
+                    app.finish_ohlc(coin_id, range, result);
+                }
+                FetchMessage::WebhookFailed(error) => app.status_message = Some(error),
+                FetchMessage::Trending(result) => app.finish_trending(result),
+                FetchMessage::Global(result) => app.finish_global(result),
+                FetchMessage::FearGreed(result) => app.finish_fear_greed(result),
+                FetchMessage::HistoryLoaded(result) => app.finish_history_load(result),
+                FetchMessage::HistoryFailed(error) => app.status_message = Some(error),
+                FetchMessage::Debug(info) => app.finish_debug(info),
+                FetchMessage::Stream(event) => {
+                    if app.finish_stream(event) {
+                        request_refresh(&mut app, &refresh_tx);
+                        interval.reset();
+                        app.schedule_next_refresh();
+                    }
+                }
+            },
+            signal = &mut shutdown => {
+                log::info!("{} received, quitting", signal);
+                break Ok(());
             }
             event = reader.next() => {
-                if let Some(Ok(Event::Key(key))) = event {
-                    match key.code {
-                        KeyCode::Char('q') => break,
-                        KeyCode::Char('r') => coins = refresh_output().await?,
-                        _ => continue,
+                let action = match event {
+                    Some(Ok(Event::Key(key))) => app.handle_key(key),
+                    Some(Ok(Event::Mouse(mouse))) => app.handle_mouse(mouse),
+                    _ => KeyAction::Nothing,
+                };
+                match action {
+                    KeyAction::Nothing => {}
+                    KeyAction::Quit => break Ok(()),
+                    #[cfg(unix)]
+                    KeyAction::Suspend => {
+                        suspend(&mut terminal, app.config.mouse)?;
+                        // The ticks missed while stopped would all fire at once
+                        interval.reset();
+                        app.schedule_next_refresh();
+                        ui_tick.reset();
+                    }
+                    KeyAction::Refresh => {
+                        if request_refresh(&mut app, &refresh_tx) {
+                            // Count the next automatic refresh from now
+                            interval.reset();
+                            app.schedule_next_refresh();
+                        }
+                    }
+                    KeyAction::LookupCoin(coin_id) => {
+                        spawn_coin_lookup(&app, &refresh_tx, coin_id);
+                    }
+                    KeyAction::FetchChart(coin_id) => {
+                        spawn_chart_fetch(&app, &refresh_tx, coin_id);
+                    }
+                    KeyAction::FetchOhlc(coin_id) => spawn_ohlc_fetch(&app, &refresh_tx, coin_id),
+                    KeyAction::Copy(text) => {
+                        let result = copy(app.config.clipboard, &text);
+                        app.finish_copy(&text, result);
                     }
                 }
             }
         }
+    };
+    // The shell gets the terminal back before the saves are waited for
+    drop(terminal);
+    drop(guard);
+    app.shut_down().await;
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static RESTORES: AtomicU32 = AtomicU32::new(0);
+
+    fn count_restore() -> io::Result<()> {
+        RESTORES.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // What main does between taking over the terminal and the loop
+    fn run(fail: bool) -> io::Result<()> {
+        let _guard = TerminalGuard {
+            restore: count_restore,
+        };
+        if fail {
+            Err(io::Error::other("no terminal"))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn the_guard_restores_the_terminal_however_it_is_left() {
+        run(false).unwrap();
+        assert_eq!(RESTORES.load(Ordering::SeqCst), 1);
+        assert!(run(true).is_err());
+        assert_eq!(RESTORES.load(Ordering::SeqCst), 2);
     }
-    // Clean up
-    disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
-    Ok(())
 }
@@ -0,0 +1,247 @@
+//! `--metrics-listen`: the prices of the last refresh served on /metrics in
+//! the Prometheus text format, for graphing them next to the TUI
+
+use crate::listener::{Request, Response, serve_http};
+use crate::model::Coin;
+use reqwest::StatusCode;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+
+// What the app tells the exporter after every refresh
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub currency: String,
+    pub coins: Vec<Coin>,
+    pub last_refresh: Option<SystemTime>,
+    pub refreshes: u64,
+    pub failed_refreshes: u64,
+}
+
+pub type SharedMetrics = Arc<Mutex<MetricsSnapshot>>;
+
+// Backslashes, double quotes and line breaks are the only escapes of
+// label values
+fn escape_label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// One metric with its HELP and TYPE lines, a sample per coin that has it
+fn coin_metric(
+    text: &mut String,
+    name: &str,
+    help: &str,
+    coins: &[Coin],
+    value: impl Fn(&Coin) -> Option<f64>,
+) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} gauge", name);
+    for coin in coins {
+        if let Some(value) = value(coin).filter(|value| value.is_finite()) {
+            let _ = writeln!(
+                text,
+                "{}{{coin=\"{}\",symbol=\"{}\"}} {}",
+                name,
+                escape_label(&coin.id),
+                escape_label(&coin.symbol.to_uppercase()),
+                value
+            );
+        }
+    }
+}
+
+fn metric(text: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(text, "# HELP {} {}", name, help);
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+    let _ = writeln!(text, "{} {}", name, value);
+}
+
+// The body of /metrics. Prices are named after the currency they are in,
+// like tuicker_coin_price_usd
+pub fn render_metrics(snapshot: &MetricsSnapshot) -> String {
+    let mut text = String::new();
+    let currency = &snapshot.currency;
+    coin_metric(
+        &mut text,
+        &format!("tuicker_coin_price_{}", currency),
+        &format!("Price in {}", currency.to_uppercase()),
+        &snapshot.coins,
+        |coin| Some(coin.current_price),
+    );
+    coin_metric(
+        &mut text,
+        "tuicker_coin_change_24h_percent",
+        "Price change over the last 24 hours",
+        &snapshot.coins,
+        |coin| Some(coin.price_change_24h),
+    );
+    coin_metric(
+        &mut text,
+        &format!("tuicker_coin_market_cap_{}", currency),
+        &format!("Market cap in {}", currency.to_uppercase()),
+        &snapshot.coins,
+        |coin| coin.market_cap,
+    );
+    if let Some(last_refresh) = snapshot.last_refresh {
+        let seconds = last_refresh
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+        metric(
+            &mut text,
+            "tuicker_last_refresh_timestamp_seconds",
+            "gauge",
+            "When the last refresh got through",
+            seconds,
+        );
+    }
+    metric(
+        &mut text,
+        "tuicker_refreshes_total",
+        "counter",
+        "Refreshes that got through, also with only some coins",
+        snapshot.refreshes,
+    );
+    metric(
+        &mut text,
+        "tuicker_refresh_failures_total",
+        "counter",
+        "Refreshes that got no coins",
+        snapshot.failed_refreshes,
+    );
+    text
+}
+
+// GET /metrics, 404 for anything else
+pub fn metrics_response(request: &Request, metrics: &SharedMetrics) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::new(
+            StatusCode::OK,
+            "text/plain; version=0.0.4; charset=utf-8",
+            match metrics.lock() {
+                Ok(snapshot) => render_metrics(&snapshot),
+                Err(_) => String::new(),
+            },
+        ),
+        _ => Response::not_found(request),
+    }
+}
+
+// Answers scrapes until the task is aborted, which drops the listener
+pub async fn serve_metrics(listener: TcpListener, metrics: SharedMetrics) {
+    serve_http(listener, move |request| metrics_response(request, &metrics)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+
+    fn coin(id: &str, price: f64, market_cap: Option<f64>) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: id.to_string(),
+            symbol: id[..3].to_string(),
+            current_price: price,
+            price_change_24h: -1.5,
+            market_cap,
+            ..Default::default()
+        }
+    }
+
+    fn snapshot() -> MetricsSnapshot {
+        MetricsSnapshot {
+            currency: "usd".to_string(),
+            coins: vec![
+                coin("bitcoin", 65321.18, Some(1.28e12)),
+                coin("dogecoin", 0.12, None),
+            ],
+            last_refresh: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            refreshes: 12,
+            failed_refreshes: 1,
+        }
+    }
+
+    #[test]
+    fn metrics_are_in_the_prometheus_text_format() {
+        let text = render_metrics(&snapshot());
+        assert!(text.contains(
+            "# TYPE tuicker_coin_price_usd gauge\n\
+             tuicker_coin_price_usd{coin=\"bitcoin\",symbol=\"BIT\"} 65321.18\n\
+             tuicker_coin_price_usd{coin=\"dogecoin\",symbol=\"DOG\"} 0.12\n"
+        ));
+        assert!(
+            text.contains(
+                "tuicker_coin_change_24h_percent{coin=\"bitcoin\",symbol=\"BIT\"} -1.5\n"
+            )
+        );
+        // Coins without a market cap have no sample
+        assert!(text.contains(
+            "tuicker_coin_market_cap_usd{coin=\"bitcoin\",symbol=\"BIT\"} 1280000000000\n"
+        ));
+        assert!(!text.contains("tuicker_coin_market_cap_usd{coin=\"dogecoin\""));
+        assert!(text.contains("tuicker_last_refresh_timestamp_seconds 1700000000\n"));
+        assert!(
+            text.contains("# TYPE tuicker_refreshes_total counter\ntuicker_refreshes_total 12\n")
+        );
+        assert!(text.contains("tuicker_refresh_failures_total 1\n"));
+        // Every line is a comment or a sample, and the last one ends too
+        assert!(text.ends_with("1\n") && !text.contains("\n\n"));
+        for line in text.lines() {
+            assert!(
+                line.starts_with("# ") || line.starts_with("tuicker_"),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+        let snapshot = MetricsSnapshot {
+            currency: "eur".to_string(),
+            coins: vec![coin("we\"ird\\coin", 1.0, None)],
+            ..MetricsSnapshot::default()
+        };
+        assert!(
+            render_metrics(&snapshot).contains(
+                "tuicker_coin_price_eur{coin=\"we\\\"ird\\\\coin\",symbol=\"WE\\\"\"} 1\n"
+            )
+        );
+        // Nothing was refreshed yet
+        assert!(!render_metrics(&snapshot).contains("timestamp"));
+    }
+
+    #[tokio::test]
+    async fn the_listener_serves_metrics_and_404s_the_rest() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_metrics(listener, Arc::new(Mutex::new(snapshot()))));
+
+        let response = reqwest::get(format!("http://{}/metrics", address))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let content_type = response.headers()["content-type"].to_str().unwrap();
+        assert!(content_type.starts_with("text/plain; version=0.0.4"));
+        assert_eq!(response.text().await.unwrap(), render_metrics(&snapshot()));
+        let response = reqwest::get(format!("http://{}/", address)).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        // Quitting aborts the task, which closes the port
+        server.abort();
+        let _ = server.await;
+        assert!(TcpStream::connect(address).await.is_err());
+    }
+}
@@ -0,0 +1,1211 @@
+//! Coins, currencies and sorting
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+// Currencies prices can be shown in, with their display symbol and how many
+// decimals are usual. Currencies without a common glyph are shown with their
+// ISO code
+const CURRENCIES: [(&str, Option<&str>, usize); 12] = [
+    ("usd", Some("$"), 2),
+    ("eur", Some("€"), 2),
+    ("gbp", Some("£"), 2),
+    ("jpy", Some("¥"), 0),
+    ("inr", Some("₹"), 2),
+    ("krw", Some("₩"), 0),
+    ("try", Some("₺"), 2),
+    ("brl", Some("R$"), 2),
+    ("cny", None, 2),
+    ("cad", None, 2),
+    ("aud", None, 2),
+    ("chf", None, 2),
+];
+
+pub fn parse_currency(currency: &str) -> Result<String, String> {
+    let currency = currency.trim().to_lowercase();
+    if CURRENCIES.iter().any(|(code, ..)| *code == currency) {
+        return Ok(currency);
+    }
+    let supported: Vec<&str> = CURRENCIES.iter().map(|(code, ..)| *code).collect();
+    Err(format!(
+        "unsupported currency `{}`, expected one of: {}",
+        currency,
+        supported.join(", ")
+    ))
+}
+
+// Ids go straight into the query string, so only allow characters CoinGecko uses
+pub fn is_valid_coin_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+// Trim, lowercase and dedupe the ids, keeping the order they were given in
+pub fn parse_coin_ids(list: &str) -> Result<Vec<String>, String> {
+    let mut coin_ids: Vec<String> = Vec::new();
+    for id in list.split(',') {
+        let id = id.trim().to_lowercase();
+        if id.is_empty() {
+            continue;
+        }
+        if !is_valid_coin_id(&id) {
+            return Err(format!("invalid coin id `{}`", id));
+        }
+        if !coin_ids.contains(&id) {
+            coin_ids.push(id);
+        }
+    }
+    Ok(coin_ids)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    // Market cap rank, the order the app starts with
+    Rank,
+    // Same order as the requested coin ids
+    Watchlist,
+    Price,
+    Change(ChangeHorizon),
+    MarketCap,
+    Volume,
+    Name,
+}
+
+impl SortKey {
+    // Order keys are cycled through with 's'
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Rank => SortKey::Watchlist,
+            SortKey::Watchlist => SortKey::Price,
+            SortKey::Price => SortKey::Change(ChangeHorizon::ALL[0]),
+            SortKey::Change(horizon) => match horizon.next() {
+                Some(horizon) => SortKey::Change(horizon),
+                None => SortKey::MarketCap,
+            },
+            SortKey::MarketCap => SortKey::Volume,
+            SortKey::Volume => SortKey::Name,
+            SortKey::Name => SortKey::Rank,
+        }
+    }
+}
+
+// How far back a percentage change looks, named in the config like
+// CoinGecko's `price_change_percentage` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ChangeHorizon {
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl ChangeHorizon {
+    pub const ALL: [ChangeHorizon; 4] = [
+        ChangeHorizon::Hour,
+        ChangeHorizon::Day,
+        ChangeHorizon::Week,
+        ChangeHorizon::Month,
+    ];
+
+    // The next longer horizon
+    pub fn next(self) -> Option<Self> {
+        let index = ChangeHorizon::ALL.iter().position(|h| *h == self)?;
+        ChangeHorizon::ALL.get(index + 1).copied()
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ChangeHorizon::Hour => "1H",
+            ChangeHorizon::Day => "24H",
+            ChangeHorizon::Week => "7D",
+            ChangeHorizon::Month => "30D",
+        }
+    }
+
+    // The change of `coin` in the configured currency
+    pub fn of(self, coin: &Coin) -> Option<f64> {
+        match self {
+            ChangeHorizon::Hour => coin.change_1h,
+            ChangeHorizon::Day => Some(coin.price_change_24h),
+            ChangeHorizon::Week => coin.change_7d,
+            ChangeHorizon::Month => coin.change_30d,
+        }
+    }
+}
+
+// How many days the detail chart covers, picked with the range keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ChartRange {
+    Day,
+    #[default]
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl ChartRange {
+    pub const ALL: [ChartRange; 5] = [
+        ChartRange::Day,
+        ChartRange::Week,
+        ChartRange::Month,
+        ChartRange::Quarter,
+        ChartRange::Year,
+    ];
+
+    // The `days` parameter of market_chart and ohlc
+    pub fn days(self) -> u32 {
+        match self {
+            ChartRange::Day => 1,
+            ChartRange::Week => 7,
+            ChartRange::Month => 30,
+            ChartRange::Quarter => 90,
+            ChartRange::Year => 365,
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ChartRange::Day => "1D",
+            ChartRange::Week => "7D",
+            ChartRange::Month => "30D",
+            ChartRange::Quarter => "90D",
+            ChartRange::Year => "1Y",
+        }
+    }
+
+    // The next longer or shorter range, wrapping around
+    pub fn cycled(self, forward: bool) -> Self {
+        let index = ChartRange::ALL.iter().position(|r| *r == self).unwrap_or(0);
+        let count = ChartRange::ALL.len();
+        let next = match forward {
+            true => (index + 1) % count,
+            false => (index + count - 1) % count,
+        };
+        ChartRange::ALL[next]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDir {
+    Ascending,
+    Descending,
+}
+
+impl SortDir {
+    pub fn reversed(self) -> Self {
+        match self {
+            SortDir::Ascending => SortDir::Descending,
+            SortDir::Descending => SortDir::Ascending,
+        }
+    }
+
+    pub fn arrow(self) -> &'static str {
+        match self {
+            SortDir::Ascending => "▲",
+            SortDir::Descending => "▼",
+        }
+    }
+}
+
+// Price of a coin in one currency
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub price: f64,
+    // Percentage like `price_change_24h`
+    pub change_24h: f64,
+}
+
+// Also what the offline cache stores
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Coin {
+    pub id: String,
+    pub name: String,
+    pub symbol: String,
+    // In the configured currency, which alerts and the portfolio use too
+    pub current_price: f64,
+    // Already a percentage, -2.34 means -2.34%
+    pub price_change_24h: f64,
+    // In the configured currency as well
+    pub market_cap: Option<f64>,
+    pub market_cap_rank: Option<u32>,
+    // Traded in the last 24 hours, in the configured currency. Caches from
+    // before it was fetched have none
+    #[serde(default)]
+    pub total_volume: Option<f64>,
+    // Percentages like `price_change_24h` over the other horizons, None
+    // for coins too new to have one
+    #[serde(default)]
+    pub change_1h: Option<f64>,
+    #[serde(default)]
+    pub change_7d: Option<f64>,
+    #[serde(default)]
+    pub change_30d: Option<f64>,
+    // Prices in the `extra_currencies` from the config, by currency code
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quotes: BTreeMap<String, Quote>,
+}
+
+// One entry of the coins/markets response
+#[derive(Debug, Deserialize)]
+pub struct MarketCoin {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    pub current_price: Option<f64>,
+    pub price_change_percentage_24h: Option<f64>,
+    pub market_cap: Option<f64>,
+    pub market_cap_rank: Option<u32>,
+    pub total_volume: Option<f64>,
+    pub price_change_percentage_1h_in_currency: Option<f64>,
+    pub price_change_percentage_7d_in_currency: Option<f64>,
+    pub price_change_percentage_30d_in_currency: Option<f64>,
+}
+
+// One coin of the search/trending response
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TrendingCoin {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    pub market_cap_rank: Option<u32>,
+    // Position in the trending list, 0 is the most searched
+    pub score: u32,
+}
+
+// One candle of the coins/{id}/ohlc response, which comes as arrays of
+// [timestamp in milliseconds, open, high, low, close]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "(f64, f64, f64, f64, f64)")]
+pub struct Candle {
+    // When the candle opened
+    pub time: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+impl From<(f64, f64, f64, f64, f64)> for Candle {
+    fn from((time, open, high, low, close): (f64, f64, f64, f64, f64)) -> Self {
+        Candle {
+            time,
+            open,
+            high,
+            low,
+            close,
+        }
+    }
+}
+
+impl Candle {
+    pub fn is_up(&self) -> bool {
+        self.close >= self.open
+    }
+}
+
+impl Coin {
+    pub fn price_formatted(&self, currency: &str, locale: NumberLocale) -> String {
+        format_price(self.current_price, currency, locale)
+    }
+
+    pub fn change_24h_formatted(&self) -> String {
+        format!("{:+.2}%", self.price_change_24h)
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.price_change_24h > 0.0
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.price_change_24h < 0.0
+    }
+}
+
+pub fn convert_to_coins(market_coins: Vec<MarketCoin>) -> Vec<Coin> {
+    let mut coins: Vec<Coin> = Vec::new();
+
+    for market_coin in market_coins {
+        // Coins that are not trading have no price to show
+        let Some(current_price) = market_coin.current_price else {
+            continue;
+        };
+        let coin = Coin {
+            id: market_coin.id,
+            symbol: market_coin.symbol.to_uppercase(),
+            name: market_coin.name,
+            current_price,
+            price_change_24h: market_coin.price_change_percentage_24h.unwrap_or(0.0),
+            market_cap: market_coin.market_cap,
+            market_cap_rank: market_coin.market_cap_rank,
+            total_volume: market_coin.total_volume,
+            change_1h: market_coin.price_change_percentage_1h_in_currency,
+            change_7d: market_coin.price_change_percentage_7d_in_currency,
+            change_30d: market_coin.price_change_percentage_30d_in_currency,
+            quotes: BTreeMap::new(),
+        };
+        coins.push(coin);
+    }
+    coins
+}
+
+// Put the coins in a deterministic order. Ties are always broken by id in
+// ascending order so equal values don't shuffle between refreshes, coins
+// without a rank keep the watchlist order among themselves first
+pub fn sort_coins(coins: &mut [Coin], key: SortKey, dir: SortDir, coin_ids: &[String]) {
+    let position = |coin: &Coin| {
+        coin_ids
+            .iter()
+            .position(|id| *id == coin.id)
+            .unwrap_or(usize::MAX)
+    };
+    let value = |coin: &Coin| match key {
+        SortKey::Rank => coin.market_cap_rank.map(f64::from),
+        SortKey::Change(horizon) => horizon.of(coin),
+        SortKey::MarketCap => coin.market_cap,
+        SortKey::Volume => coin.total_volume,
+        _ => None,
+    };
+    coins.sort_by(|a, b| {
+        // Coins without the value go last whichever way the table is sorted
+        let missing = value(a).is_none().cmp(&value(b).is_none());
+        let ordering = match key {
+            SortKey::Watchlist => position(a).cmp(&position(b)),
+            SortKey::Price => a.current_price.total_cmp(&b.current_price),
+            SortKey::Rank | SortKey::Change(_) | SortKey::MarketCap | SortKey::Volume => {
+                value(a).unwrap_or(0.0).total_cmp(&value(b).unwrap_or(0.0))
+            }
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        };
+        let ordering = match dir {
+            SortDir::Ascending => ordering,
+            SortDir::Descending => ordering.reverse(),
+        };
+        let unranked = match key {
+            SortKey::Rank => position(a).cmp(&position(b)),
+            _ => Ordering::Equal,
+        };
+        missing
+            .then(ordering)
+            .then(unranked)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+fn currency_symbol(currency: &str) -> String {
+    match CURRENCIES.iter().find(|(code, ..)| *code == currency) {
+        Some((_, Some(symbol), _)) => symbol.to_string(),
+        _ => format!("{} ", currency.to_uppercase()),
+    }
+}
+
+// Significant figures kept for prices below one unit, so meme coins
+// don't all show up as $0.00
+const SMALL_PRICE_DIGITS: i32 = 4;
+// More decimals than this would only widen the column, $0.0000000001
+const MAX_PRICE_DECIMALS: usize = 10;
+
+// The currency's usual decimals from one unit up, yen and won have none.
+// Below that enough for SMALL_PRICE_DIGITS significant figures
+fn currency_decimals(value: f64, currency: &str) -> usize {
+    let value = value.abs();
+    if value >= 1.0 {
+        return match CURRENCIES.iter().find(|(code, ..)| *code == currency) {
+            Some((.., decimals)) => *decimals,
+            None => 2,
+        };
+    }
+    if value == 0.0 || !value.is_finite() {
+        return 2;
+    }
+    // log10 is -1.x for 0.0x, which needs SMALL_PRICE_DIGITS + 1 decimals
+    let leading_zeros = -(value.log10().floor() as i32) - 1;
+    ((leading_zeros + SMALL_PRICE_DIGITS) as usize).min(MAX_PRICE_DECIMALS)
+}
+
+// Code of the BTC denomination, which isn't a `currency` of its own
+pub const BTC: &str = "btc";
+
+const SATS_PER_BTC: f64 = 100_000_000.0;
+// Below this a BTC amount is mostly leading zeros, so it is shown in sats
+const SATS_THRESHOLD: f64 = 0.001;
+
+// How numbers are written on screen, `number_locale` in the config. Exports
+// always use the machine format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberLocale {
+    // 1,234.56
+    #[default]
+    En,
+    // 1.234,56
+    De,
+    // 1 234,56, grouped with a narrow no-break space
+    Fr,
+}
+
+// Languages of the environment's locale that write numbers like one of ours
+const NUMBER_LOCALES: [(&str, NumberLocale); 16] = [
+    ("en", NumberLocale::En),
+    ("c", NumberLocale::En),
+    ("posix", NumberLocale::En),
+    ("de", NumberLocale::De),
+    ("da", NumberLocale::De),
+    ("es", NumberLocale::De),
+    ("id", NumberLocale::De),
+    ("it", NumberLocale::De),
+    ("nl", NumberLocale::De),
+    ("pt", NumberLocale::De),
+    ("fr", NumberLocale::Fr),
+    ("cs", NumberLocale::Fr),
+    ("fi", NumberLocale::Fr),
+    ("pl", NumberLocale::Fr),
+    ("ru", NumberLocale::Fr),
+    ("sv", NumberLocale::Fr),
+];
+
+impl NumberLocale {
+    pub fn decimal_separator(self) -> char {
+        match self {
+            NumberLocale::En => '.',
+            NumberLocale::De | NumberLocale::Fr => ',',
+        }
+    }
+
+    pub fn group_separator(self) -> char {
+        match self {
+            NumberLocale::En => ',',
+            NumberLocale::De => '.',
+            NumberLocale::Fr => '\u{202f}',
+        }
+    }
+
+    // A POSIX locale name like "de_DE.UTF-8", None for languages not in
+    // the table
+    pub fn from_posix(name: &str) -> Option<Self> {
+        let language = name.split(['_', '.', '@']).next()?.to_lowercase();
+        NUMBER_LOCALES
+            .iter()
+            .find(|(code, _)| *code == language)
+            .map(|(_, locale)| *locale)
+    }
+}
+
+// "1234567" -> "1,234,567"
+fn group_digits(digits: &str, separator: char) -> String {
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// e.g. -65321.18 with 2 decimals -> "-65,321.18", or "-65.321,18" in German
+pub fn format_thousands(value: f64, decimals: usize, locale: NumberLocale) -> String {
+    let text = format!("{:.*}", decimals, value.abs());
+    let (whole, fraction) = match text.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (text.as_str(), None),
+    };
+    let mut grouped = group_digits(whole, locale.group_separator());
+    if let Some(fraction) = fraction {
+        grouped.push(locale.decimal_separator());
+        grouped.push_str(fraction);
+    }
+    // Something like -0.001 rounds to zero, which has no sign
+    let is_zero = text.chars().all(|c| c == '0' || c == '.');
+    match value < 0.0 && !is_zero {
+        true => format!("-{}", grouped),
+        false => grouped,
+    }
+}
+
+// Suffixes for large amounts like market caps, smallest first
+const COMPACT_UNITS: [(f64, &str); 4] = [(1e3, "K"), (1e6, "M"), (1e9, "B"), (1e12, "T")];
+
+// e.g. "$842.3B", one decimal with a suffix. Amounts below a thousand are
+// shown like a price
+pub fn format_compact(value: f64, currency: &str, locale: NumberLocale) -> String {
+    format_compact_with(value, currency, 1, locale)
+}
+
+// Like `format_compact` with more decimals, e.g. "$2.31T" for the whole market
+pub fn format_compact_with(
+    value: f64,
+    currency: &str,
+    decimals: usize,
+    locale: NumberLocale,
+) -> String {
+    let sign = if value < 0.0 { "-" } else { "" };
+    let amount = value.abs();
+    let Some(mut index) = COMPACT_UNITS.iter().rposition(|(size, _)| amount >= *size) else {
+        return format!("{}{}", sign, format_price(amount, currency, locale));
+    };
+    let factor = 10f64.powi(decimals as i32);
+    let scaled = |index: usize| (amount / COMPACT_UNITS[index].0 * factor).round() / factor;
+    // 999.95B rounds to 1000.0B, which is 1.0T
+    if scaled(index) >= 1000.0 && index + 1 < COMPACT_UNITS.len() {
+        index += 1;
+    }
+    format!(
+        "{}{}{}{}",
+        sign,
+        currency_symbol(currency),
+        format_thousands(scaled(index), decimals, locale),
+        COMPACT_UNITS[index].1
+    )
+}
+
+// e.g. "0.05231900 BTC", or "5,230 sats" below 0.001 BTC or always with
+// `prefer_sats`. Sats are rounded to whole ones, dust below one sat is
+// shown as "<1 sat" rather than rounding it away to nothing
+pub fn format_btc_amount(value: f64, prefer_sats: bool, locale: NumberLocale) -> String {
+    if !prefer_sats && value.abs() >= SATS_THRESHOLD {
+        return format!("{} BTC", format_thousands(value, 8, locale));
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    let sats = value.abs() * SATS_PER_BTC;
+    match sats.round() as u64 {
+        0 if sats > 0.0 => format!("{}<1 sat", sign),
+        1 => format!("{}1 sat", sign),
+        sats => format!(
+            "{}{} sats",
+            sign,
+            group_digits(&sats.to_string(), locale.group_separator())
+        ),
+    }
+}
+
+pub fn format_price(value: f64, currency: &str, locale: NumberLocale) -> String {
+    if currency == BTC {
+        return format_btc_amount(value, false, locale);
+    }
+    let decimals = currency_decimals(value, currency);
+    let amount = format_thousands(value, decimals, locale);
+    format!("{}{}", currency_symbol(currency), amount)
+}
+
+// Gains and losses, with the sign in front of the currency symbol
+pub fn format_price_change(value: f64, currency: &str, locale: NumberLocale) -> String {
+    let sign = if value < 0.0 { "-" } else { "+" };
+    format!("{}{}", sign, format_price(value.abs(), currency, locale))
+}
+
+// ANSI escapes for the plain text table, the TUI gets its colors from ratatui
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+// Plain text version of the table, the TUI draws `coin_table` instead.
+// `color` marks gains green and losses red
+pub fn format_coins(coins: &[Coin], currency: &str, color: bool, locale: NumberLocale) -> String {
+    let mut lines = vec![format!(
+        "{:6} {:12} {:>14} {:>8}",
+        "SYMBOL", "NAME", "PRICE", "24H"
+    )];
+    for coin in coins {
+        // Padded before coloring so the escapes don't count towards the width
+        let change = format!("{:>8}", coin.change_24h_formatted());
+        let change = match (color, coin.is_up(), coin.is_down()) {
+            (true, true, _) => format!("{}{}{}", ANSI_GREEN, change, ANSI_RESET),
+            (true, _, true) => format!("{}{}{}", ANSI_RED, change, ANSI_RESET),
+            _ => change,
+        };
+        let line = format!(
+            "{:6} {:12} {:>14} {}",
+            coin.symbol,
+            coin.name,
+            coin.price_formatted(currency, locale),
+            change
+        );
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+// Price samples kept per coin for the trend column
+const HISTORY_LENGTH: usize = 60;
+
+// A price above or below every other one seen this session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extreme {
+    High,
+    Low,
+}
+
+impl Extreme {
+    pub fn label(self) -> &'static str {
+        match self {
+            Extreme::High => "high",
+            Extreme::Low => "low",
+        }
+    }
+
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Extreme::High => "↑",
+            Extreme::Low => "↓",
+        }
+    }
+}
+
+// Highest and lowest price of a coin this session, and which of them the
+// latest price set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionRange {
+    pub high: f64,
+    pub low: f64,
+    pub latest: Option<Extreme>,
+}
+
+impl SessionRange {
+    // The first price is both, without being new
+    fn new(price: f64) -> Self {
+        SessionRange {
+            high: price,
+            low: price,
+            latest: None,
+        }
+    }
+
+    fn update(&mut self, price: f64) {
+        self.latest = if price > self.high {
+            self.high = price;
+            Some(Extreme::High)
+        } else if price < self.low {
+            self.low = price;
+            Some(Extreme::Low)
+        } else {
+            None
+        };
+    }
+}
+
+// Prices seen this session, oldest first, keyed by coin id
+#[derive(Debug, Default)]
+pub struct PriceHistory {
+    pub samples: HashMap<String, VecDeque<f64>>,
+    // Over the whole session, not just the samples kept
+    pub ranges: HashMap<String, SessionRange>,
+}
+
+impl PriceHistory {
+    // Returns the coins whose price is a new session high or low
+    pub fn record(&mut self, coins: &[Coin]) -> Vec<(String, Extreme)> {
+        let mut extremes = Vec::new();
+        for coin in coins {
+            let samples = self.samples.entry(coin.id.clone()).or_default();
+            if samples.len() == HISTORY_LENGTH {
+                samples.pop_front();
+            }
+            samples.push_back(coin.current_price);
+            let range = self
+                .ranges
+                .entry(coin.id.clone())
+                .and_modify(|range| range.update(coin.current_price))
+                .or_insert_with(|| SessionRange::new(coin.current_price));
+            if let Some(extreme) = range.latest {
+                extremes.push((coin.id.clone(), extreme));
+            }
+        }
+        extremes
+    }
+
+    // Forget coins that are no longer tracked, so one added again starts
+    // a new range
+    pub fn retain(&mut self, coin_ids: &[String]) {
+        self.samples.retain(|id, _| coin_ids.contains(id));
+        self.ranges.retain(|id, _| coin_ids.contains(id));
+    }
+
+    // Prices of earlier sessions go in front of the ones seen in this one,
+    // as many as there is room for
+    pub fn seed(&mut self, coin_id: &str, prices: &[f64]) {
+        let samples = self.samples.entry(coin_id.to_string()).or_default();
+        let room = HISTORY_LENGTH.saturating_sub(samples.len());
+        for price in prices.iter().rev().take(room) {
+            samples.push_front(*price);
+        }
+    }
+
+    pub fn range(&self, coin_id: &str) -> Option<&SessionRange> {
+        self.ranges.get(coin_id)
+    }
+
+    pub fn get(&self, coin_id: &str) -> Option<&VecDeque<f64>> {
+        self.samples.get(coin_id)
+    }
+}
+
+// Shorten text to `width` characters, marking the cut with an ellipsis
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut truncated: String = text.chars().take(width - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market_coin(id: &str, price: Option<f64>, change: Option<f64>) -> MarketCoin {
+        MarketCoin {
+            id: id.to_string(),
+            symbol: id[..3].to_string(),
+            name: id.to_string(),
+            current_price: price,
+            price_change_percentage_24h: change,
+            market_cap: None,
+            market_cap_rank: None,
+            total_volume: None,
+            price_change_percentage_1h_in_currency: None,
+            price_change_percentage_7d_in_currency: None,
+            price_change_percentage_30d_in_currency: None,
+        }
+    }
+
+    fn coin(id: &str, name: &str, price: f64, change: f64) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: name.to_string(),
+            symbol: id[..3].to_uppercase(),
+            current_price: price,
+            price_change_24h: change,
+            ..Default::default()
+        }
+    }
+
+    fn ids(coins: &[Coin]) -> Vec<&str> {
+        coins.iter().map(|coin| coin.id.as_str()).collect()
+    }
+
+    #[test]
+    fn convert_to_coins_uppercases_symbols() {
+        let coins = convert_to_coins(vec![market_coin("bitcoin", Some(65000.5), Some(-1.25))]);
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].symbol, "BIT");
+        assert_eq!(coins[0].current_price, 65000.5);
+        assert_eq!(coins[0].price_change_24h, -1.25);
+    }
+
+    #[test]
+    fn convert_to_coins_skips_coins_without_a_price() {
+        let coins = convert_to_coins(vec![
+            market_coin("bitcoin", None, Some(1.0)),
+            market_coin("ethereum", Some(3000.0), None),
+        ]);
+        assert_eq!(ids(&coins), ["ethereum"]);
+        // A missing change counts as unchanged
+        assert_eq!(coins[0].price_change_24h, 0.0);
+    }
+
+    #[test]
+    fn format_coins_aligns_columns() {
+        let coins = vec![
+            coin("bitcoin", "Bitcoin", 65000.0, 2.5),
+            coin("cardano", "Cardano", 0.45, -3.1),
+        ];
+        let text = format_coins(&coins, "usd", false, NumberLocale::En);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "SYMBOL NAME                  PRICE      24H");
+        assert_eq!(lines[1], "BIT    Bitcoin          $65,000.00   +2.50%");
+        assert_eq!(lines[2], "CAR    Cardano             $0.4500   -3.10%");
+    }
+
+    #[test]
+    fn format_coins_colors_the_change() {
+        let coins = vec![
+            coin("bitcoin", "Bitcoin", 65000.0, 2.5),
+            coin("cardano", "Cardano", 0.45, -3.1),
+        ];
+        let text = format_coins(&coins, "usd", true, NumberLocale::En);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(
+            lines[1].ends_with("\x1b[32m  +2.50%\x1b[0m"),
+            "{:?}",
+            lines[1]
+        );
+        assert!(
+            lines[2].ends_with("\x1b[31m  -3.10%\x1b[0m"),
+            "{:?}",
+            lines[2]
+        );
+    }
+
+    #[test]
+    fn format_coins_uses_the_currency_code_without_a_symbol() {
+        let text = format_coins(
+            &[coin("bitcoin", "Bitcoin", 1.0, 0.0)],
+            "chf",
+            false,
+            NumberLocale::En,
+        );
+        assert!(text.contains("CHF 1.00"), "{}", text);
+    }
+
+    #[test]
+    fn format_price_follows_the_currency_decimals() {
+        assert_eq!(
+            format_price(10_512_345.6, "jpy", NumberLocale::En),
+            "¥10,512,346"
+        );
+        assert_eq!(format_price(0.42, "krw", NumberLocale::En), "₩0.4200");
+        assert_eq!(
+            format_price(61_234.5, "eur", NumberLocale::En),
+            "€61,234.50"
+        );
+        assert_eq!(
+            format_price_change(-1500.4, "jpy", NumberLocale::En),
+            "-¥1,500"
+        );
+        assert_eq!(
+            format_price(0.052319, BTC, NumberLocale::En),
+            "0.05231900 BTC"
+        );
+    }
+
+    #[test]
+    fn format_thousands_groups_the_whole_part() {
+        assert_eq!(format_thousands(65321.18, 2, NumberLocale::En), "65,321.18");
+        assert_eq!(
+            format_thousands(-1234567.891, 2, NumberLocale::En),
+            "-1,234,567.89"
+        );
+        assert_eq!(format_thousands(999.999, 2, NumberLocale::En), "1,000.00");
+        assert_eq!(format_thousands(999.5, 0, NumberLocale::De), "1.000");
+        assert_eq!(format_thousands(12.5, 2, NumberLocale::En), "12.50");
+        assert_eq!(format_thousands(-0.001, 2, NumberLocale::En), "0.00");
+        assert_eq!(
+            format_thousands(1_000_000.0, 0, NumberLocale::Fr),
+            "1\u{202f}000\u{202f}000"
+        );
+    }
+
+    #[test]
+    fn number_locales_swap_the_separators() {
+        assert_eq!(
+            format_price(65_321.18, "eur", NumberLocale::De),
+            "€65.321,18"
+        );
+        assert_eq!(
+            format_price(65_321.18, "eur", NumberLocale::Fr),
+            "€65\u{202f}321,18"
+        );
+        assert_eq!(format_price(0.35, "usd", NumberLocale::De), "$0,3500");
+        assert_eq!(format_compact(842.31e9, "usd", NumberLocale::De), "$842,3B");
+        assert_eq!(
+            format_btc_amount(0.0000523, false, NumberLocale::De),
+            "5.230 sats"
+        );
+        assert_eq!(
+            format_btc_amount(0.052319, false, NumberLocale::Fr),
+            "0,05231900 BTC"
+        );
+        assert_eq!(
+            format_price_change(-1500.4, "jpy", NumberLocale::De),
+            "-¥1.500"
+        );
+    }
+
+    #[test]
+    fn number_locale_comes_from_the_language_of_a_posix_locale() {
+        assert_eq!(
+            NumberLocale::from_posix("de_DE.UTF-8"),
+            Some(NumberLocale::De)
+        );
+        assert_eq!(NumberLocale::from_posix("fr_CA"), Some(NumberLocale::Fr));
+        assert_eq!(NumberLocale::from_posix("C"), Some(NumberLocale::En));
+        assert_eq!(
+            NumberLocale::from_posix("en_US.UTF-8"),
+            Some(NumberLocale::En)
+        );
+        assert_eq!(NumberLocale::from_posix("ja_JP.UTF-8"), None);
+        assert_eq!(NumberLocale::from_posix(""), None);
+    }
+
+    #[test]
+    fn format_compact_picks_the_suffix_after_rounding() {
+        assert_eq!(format_compact(1.28e12, "usd", NumberLocale::En), "$1.3T");
+        assert_eq!(format_compact(842.31e9, "usd", NumberLocale::En), "$842.3B");
+        assert_eq!(
+            format_compact(53_120_000.0, "eur", NumberLocale::En),
+            "€53.1M"
+        );
+        assert_eq!(format_compact(999.95e9, "usd", NumberLocale::En), "$1.0T");
+        assert_eq!(format_compact(999.94e9, "usd", NumberLocale::En), "$999.9B");
+        assert_eq!(
+            format_compact(-2_500_000.0, "usd", NumberLocale::En),
+            "-$2.5M"
+        );
+        assert_eq!(format_compact(999.0, "usd", NumberLocale::En), "$999.00");
+        assert_eq!(
+            format_compact_with(2.3119e12, "usd", 2, NumberLocale::En),
+            "$2.31T"
+        );
+    }
+
+    #[test]
+    fn small_prices_keep_four_significant_figures() {
+        assert_eq!(format_price(0.0, "usd", NumberLocale::En), "$0.00");
+        assert_eq!(format_price(1.0, "usd", NumberLocale::En), "$1.00");
+        assert_eq!(format_price(0.9999, "usd", NumberLocale::En), "$0.9999");
+        assert_eq!(format_price(0.35, "usd", NumberLocale::En), "$0.3500");
+        assert_eq!(
+            format_price(0.00002431, "usd", NumberLocale::En),
+            "$0.00002431"
+        );
+        assert_eq!(
+            format_price(0.000001234567, "eur", NumberLocale::En),
+            "€0.000001235"
+        );
+        // Capped, the rest would only widen the column
+        assert_eq!(
+            format_price(0.0000000000123, "usd", NumberLocale::En),
+            "$0.0000000000"
+        );
+        assert_eq!(
+            format_price(123_456_789.129, "usd", NumberLocale::En),
+            "$123,456,789.13"
+        );
+    }
+
+    #[test]
+    fn format_btc_amount_switches_to_sats_below_a_thousandth() {
+        assert_eq!(
+            format_btc_amount(0.001, false, NumberLocale::En),
+            "0.00100000 BTC"
+        );
+        assert_eq!(
+            format_btc_amount(0.001, true, NumberLocale::En),
+            "100,000 sats"
+        );
+        assert_eq!(
+            format_btc_amount(0.0009999, false, NumberLocale::En),
+            "99,990 sats"
+        );
+        assert_eq!(
+            format_btc_amount(0.0000523, false, NumberLocale::En),
+            "5,230 sats"
+        );
+        assert_eq!(
+            format_btc_amount(1.0, true, NumberLocale::En),
+            "100,000,000 sats"
+        );
+        assert_eq!(
+            format_btc_amount(0.00000001, false, NumberLocale::En),
+            "1 sat"
+        );
+        // Dust rounds to the nearest sat, but never to nothing
+        assert_eq!(
+            format_btc_amount(0.000000016, false, NumberLocale::En),
+            "2 sats"
+        );
+        assert_eq!(
+            format_btc_amount(0.000000004, false, NumberLocale::En),
+            "<1 sat"
+        );
+        assert_eq!(format_btc_amount(0.0, false, NumberLocale::En), "0 sats");
+    }
+
+    #[test]
+    fn sort_coins_breaks_ties_by_id() {
+        let mut coins = vec![
+            coin("solana", "Solana", 10.0, 1.0),
+            coin("bitcoin", "Bitcoin", 10.0, 5.0),
+            coin("cardano", "Cardano", 1.0, -2.0),
+        ];
+        sort_coins(&mut coins, SortKey::Price, SortDir::Descending, &[]);
+        assert_eq!(ids(&coins), ["bitcoin", "solana", "cardano"]);
+        sort_coins(
+            &mut coins,
+            SortKey::Change(ChangeHorizon::Day),
+            SortDir::Ascending,
+            &[],
+        );
+        assert_eq!(ids(&coins), ["cardano", "solana", "bitcoin"]);
+    }
+
+    fn sorted(key: SortKey, dir: SortDir) -> Vec<String> {
+        let mut coins = vec![
+            coin("solana", "Solana", 145.2, -7.25),
+            coin("bitcoin", "bitcoin", 67187.34, 2.0),
+            coin("cardano", "Cardano", 0.35, -0.5),
+            coin("ripple", "XRP", 0.52, 0.0),
+        ];
+        let coin_ids: Vec<String> = ["ripple", "bitcoin", "solana", "cardano"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        sort_coins(&mut coins, key, dir, &coin_ids);
+        coins.into_iter().map(|coin| coin.id).collect()
+    }
+
+    #[test]
+    fn sort_coins_by_every_key_both_ways() {
+        use SortDir::{Ascending, Descending};
+        let cases = [
+            (
+                SortKey::Watchlist,
+                ["ripple", "bitcoin", "solana", "cardano"],
+            ),
+            (SortKey::Price, ["cardano", "ripple", "solana", "bitcoin"]),
+            // Losses sort below no change at all
+            (
+                SortKey::Change(ChangeHorizon::Day),
+                ["solana", "cardano", "ripple", "bitcoin"],
+            ),
+            // Names compare without case
+            (SortKey::Name, ["bitcoin", "cardano", "solana", "ripple"]),
+        ];
+        for (key, ascending) in cases {
+            assert_eq!(sorted(key, Ascending), ascending, "{:?}", key);
+            let mut descending = ascending;
+            descending.reverse();
+            assert_eq!(sorted(key, Descending), descending, "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn missing_market_caps_sort_last_both_ways() {
+        let mut coins = vec![
+            coin("bitcoin", "Bitcoin", 1.0, 0.0),
+            coin("solana", "Solana", 1.0, 0.0),
+            coin("cardano", "Cardano", 1.0, 0.0),
+        ];
+        coins[0].market_cap = Some(1.3e12);
+        coins[2].market_cap = Some(1.6e10);
+        sort_coins(&mut coins, SortKey::MarketCap, SortDir::Descending, &[]);
+        assert_eq!(ids(&coins), ["bitcoin", "cardano", "solana"]);
+        sort_coins(&mut coins, SortKey::MarketCap, SortDir::Ascending, &[]);
+        assert_eq!(ids(&coins), ["cardano", "bitcoin", "solana"]);
+        coins[1].total_volume = Some(2.1e10);
+        sort_coins(&mut coins, SortKey::Volume, SortDir::Descending, &[]);
+        assert_eq!(ids(&coins), ["bitcoin", "cardano", "solana"]);
+    }
+
+    #[test]
+    fn coins_without_a_rank_go_last_in_watchlist_order() {
+        let mut coins = vec![
+            coin("pepe", "Pepe", 1.0, 0.0),
+            coin("ethereum", "Ethereum", 1.0, 0.0),
+            coin("newcoin", "Newcoin", 1.0, 0.0),
+            coin("bitcoin", "Bitcoin", 1.0, 0.0),
+        ];
+        coins[1].market_cap_rank = Some(2);
+        coins[3].market_cap_rank = Some(1);
+        let order: Vec<String> = ["pepe", "newcoin", "bitcoin", "ethereum"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        sort_coins(&mut coins, SortKey::Rank, SortDir::Ascending, &order);
+        assert_eq!(ids(&coins), ["bitcoin", "ethereum", "pepe", "newcoin"]);
+        sort_coins(&mut coins, SortKey::Rank, SortDir::Descending, &order);
+        assert_eq!(ids(&coins), ["ethereum", "bitcoin", "pepe", "newcoin"]);
+        // Coins missing from the watchlist too fall back to their id
+        sort_coins(&mut coins, SortKey::Rank, SortDir::Ascending, &[]);
+        assert_eq!(ids(&coins), ["bitcoin", "ethereum", "newcoin", "pepe"]);
+    }
+
+    #[test]
+    fn sort_coins_by_watchlist_order() {
+        let mut coins = vec![
+            coin("bitcoin", "Bitcoin", 1.0, 0.0),
+            coin("solana", "Solana", 1.0, 0.0),
+        ];
+        let order = vec!["solana".to_string(), "bitcoin".to_string()];
+        sort_coins(&mut coins, SortKey::Watchlist, SortDir::Ascending, &order);
+        assert_eq!(ids(&coins), ["solana", "bitcoin"]);
+    }
+
+    #[test]
+    fn parse_coin_ids_trims_lowercases_and_dedupes() {
+        assert_eq!(
+            parse_coin_ids(" Bitcoin, ethereum,,bitcoin ").unwrap(),
+            ["bitcoin", "ethereum"]
+        );
+        assert!(parse_coin_ids("bit coin").is_err());
+        assert!(parse_coin_ids("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_currency_is_case_insensitive() {
+        assert_eq!(parse_currency("usd").unwrap(), "usd");
+        assert_eq!(parse_currency(" EUR ").unwrap(), "eur");
+        assert_eq!(parse_currency("JPY").unwrap(), "jpy");
+        assert!(parse_currency("xyz").unwrap_err().contains("usd, eur"));
+    }
+
+    #[test]
+    fn price_history_keeps_the_latest_samples() {
+        let mut history = PriceHistory::default();
+        for price in 0..HISTORY_LENGTH + 5 {
+            history.record(&[coin("bitcoin", "Bitcoin", price as f64, 0.0)]);
+        }
+        let samples = history.get("bitcoin").unwrap();
+        assert_eq!(samples.len(), HISTORY_LENGTH);
+        assert_eq!(samples.front(), Some(&5.0));
+        history.retain(&[]);
+        assert!(history.get("bitcoin").is_none());
+    }
+
+    #[test]
+    fn seeded_prices_go_before_the_session() {
+        let mut history = PriceHistory::default();
+        history.record(&[coin("bitcoin", "Bitcoin", 5.0, 0.0)]);
+        let saved: Vec<f64> = (0..HISTORY_LENGTH).map(|price| price as f64).collect();
+        history.seed("bitcoin", &saved);
+        let samples = history.get("bitcoin").unwrap();
+        assert_eq!(samples.len(), HISTORY_LENGTH);
+        assert_eq!(samples.front(), Some(&1.0));
+        assert_eq!(samples.back(), Some(&5.0));
+        // Not part of the session range
+        assert!(history.record(&[coin("bitcoin", "Bitcoin", 4.0, 0.0)])[0].1 == Extreme::Low);
+    }
+
+    #[test]
+    fn session_range_reports_each_new_extreme() {
+        let mut history = PriceHistory::default();
+        let mut record = |price| history.record(&[coin("bitcoin", "Bitcoin", price, 0.0)]);
+        assert!(record(100.0).is_empty());
+        assert_eq!(record(110.0), [("bitcoin".to_string(), Extreme::High)]);
+        assert!(record(105.0).is_empty());
+        assert_eq!(record(90.0), [("bitcoin".to_string(), Extreme::Low)]);
+        // Matching the high is not a new one
+        assert!(record(110.0).is_empty());
+        let range = history.range("bitcoin").unwrap();
+        assert_eq!((range.high, range.low, range.latest), (110.0, 90.0, None));
+
+        // Removed and added again, the range starts over
+        history.retain(&[]);
+        assert!(history.range("bitcoin").is_none());
+        assert!(
+            history
+                .record(&[coin("bitcoin", "Bitcoin", 50.0, 0.0)])
+                .is_empty()
+        );
+        assert_eq!(history.range("bitcoin").unwrap().high, 50.0);
+    }
+
+    #[test]
+    fn truncate_marks_the_cut() {
+        assert_eq!(truncate("Bitcoin", 10), "Bitcoin");
+        assert_eq!(truncate("Bitcoin Cash", 8), "Bitcoin…");
+        assert_eq!(truncate("Bitcoin", 0), "");
+    }
+}
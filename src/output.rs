@@ -0,0 +1,272 @@
+//! What --once prints, for people and for scripts
+
+use crate::model::{Coin, NumberLocale, format_coins};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+pub fn parse_output_format(text: &str) -> Result<OutputFormat, String> {
+    match text.trim().to_lowercase().as_str() {
+        "table" => Ok(OutputFormat::Table),
+        "json" => Ok(OutputFormat::Json),
+        _ => Err(format!(
+            "unknown output format `{}`, expected table or json",
+            text
+        )),
+    }
+}
+
+// Days since 1970-01-01 of a civil date, Howard Hinnant's days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The date of YYYY-MM-DD as days since 1970-01-01, if it is on the calendar
+fn parse_civil_date(text: &str) -> Option<i64> {
+    let parts: Vec<&str> = text.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return None;
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, i64, i64) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    (1..=days_in_month)
+        .contains(&day)
+        .then(|| days_from_civil(year, month, day))
+}
+
+// HH:MM:SS with an optional fraction, as seconds of the day
+fn parse_clock(text: &str) -> Option<f64> {
+    let parts: Vec<&str> = text.split(':').collect();
+    let [hours, minutes, seconds] = parts[..] else {
+        return None;
+    };
+    if hours.len() != 2 || minutes.len() != 2 || seconds.split('.').next()?.len() != 2 {
+        return None;
+    }
+    let (hours, minutes, seconds): (u32, u32, f64) = (
+        hours.parse().ok()?,
+        minutes.parse().ok()?,
+        seconds.parse().ok()?,
+    );
+    (hours < 24 && minutes < 60 && (0.0..61.0).contains(&seconds))
+        .then(|| f64::from(hours * 3600 + minutes * 60) + seconds)
+}
+
+// A bare date like 2024-05-01 for its midnight in UTC, or a full RFC 3339
+// time like 2024-05-01T12:00:00Z or 2024-05-01T14:00:00.5+02:00
+pub fn parse_time(text: &str) -> Result<SystemTime, String> {
+    let invalid = || {
+        format!(
+            "invalid time `{}`, expected a date like 2024-05-01 or 2024-05-01T12:00:00Z",
+            text
+        )
+    };
+    let (date, time) = match text.trim().split_once(['T', 't']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text.trim(), None),
+    };
+    let days = parse_civil_date(date).ok_or_else(invalid)?;
+    let mut seconds = (days * 86_400) as f64;
+    if let Some(time) = time {
+        let (clock, offset) = match time.strip_suffix(['Z', 'z']) {
+            Some(clock) => (clock, 0.0),
+            None => {
+                let sign_at = time.rfind(['+', '-']).ok_or_else(invalid)?;
+                let (clock, sign_offset) = time.split_at(sign_at);
+                let (hours, minutes) = sign_offset[1..].split_once(':').ok_or_else(invalid)?;
+                let offset = parse_clock(&format!("{}:{}:00", hours, minutes));
+                let offset = offset.ok_or_else(invalid)?;
+                match sign_offset.starts_with('-') {
+                    true => (clock, -offset),
+                    false => (clock, offset),
+                }
+            }
+        };
+        seconds += parse_clock(clock).ok_or_else(invalid)? - offset;
+    }
+    if seconds < 0.0 {
+        return Err(format!("`{}` is before 1970", text));
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
+
+// One coin in the JSON output, of --once and of `serve`. Scripts depend on
+// these key names, so they stay the same even if `Coin` changes
+#[derive(Debug, Serialize)]
+pub(crate) struct CoinOutput<'a> {
+    id: &'a str,
+    symbol: &'a str,
+    name: &'a str,
+    price: f64,
+    change_24h: f64,
+    currency: &'a str,
+    fetched_at: &'a str,
+}
+
+impl<'a> CoinOutput<'a> {
+    pub(crate) fn new(coin: &'a Coin, currency: &'a str, fetched_at: &'a str) -> Self {
+        CoinOutput {
+            id: &coin.id,
+            symbol: &coin.symbol,
+            name: &coin.name,
+            price: coin.current_price,
+            change_24h: coin.price_change_24h,
+            currency,
+            fetched_at,
+        }
+    }
+}
+
+// UTC timestamp like 2024-03-09T14:05:00Z
+pub fn rfc3339(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, time_of_day) = (seconds / 86_400, seconds % 86_400);
+    // Civil date from days since 1970-01-01, Howard Hinnant's civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+fn format_json(coins: &[Coin], currency: &str, fetched_at: SystemTime) -> String {
+    let fetched_at = rfc3339(fetched_at);
+    let output: Vec<CoinOutput> = coins
+        .iter()
+        .map(|coin| CoinOutput::new(coin, currency, &fetched_at))
+        .collect();
+    serde_json::to_string_pretty(&output).expect("coins always serialize")
+}
+
+// `color` and `locale` only apply to the table, JSON is for machines
+pub fn render(
+    format: OutputFormat,
+    coins: &[Coin],
+    currency: &str,
+    fetched_at: SystemTime,
+    color: bool,
+    locale: NumberLocale,
+) -> String {
+    match format {
+        OutputFormat::Table => format_coins(coins, currency, color, locale),
+        OutputFormat::Json => format_json(coins, currency, fetched_at),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn coin() -> Coin {
+        Coin {
+            id: "bitcoin".to_string(),
+            name: "Bitcoin".to_string(),
+            symbol: "btc".to_string(),
+            current_price: 65000.5,
+            price_change_24h: -1.25,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rfc3339_formats_utc() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(rfc3339(leap_day), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn parse_time_reads_dates_and_rfc3339() {
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(parse_time("2024-02-29T12:34:56Z").unwrap(), leap_day);
+        assert_eq!(parse_time("2024-02-29T14:34:56+02:00").unwrap(), leap_day);
+        assert_eq!(parse_time("2024-02-29t10:04:56-02:30").unwrap(), leap_day);
+        let midday = parse_time("2024-02-29T12:34:56.250Z").unwrap();
+        assert_eq!(midday, leap_day + Duration::from_millis(250));
+        let midnight = parse_time("2024-02-29").unwrap();
+        assert_eq!(rfc3339(midnight), "2024-02-29T00:00:00Z");
+        assert_eq!(parse_time("1970-01-01").unwrap(), UNIX_EPOCH);
+        for bad in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-5-01",
+            "05/01/2024",
+            "2024-05-01T25:00:00Z",
+            "2024-05-01T12:00:00",
+            "2024-05-01T12:00Z",
+        ] {
+            let error = parse_time(bad).unwrap_err();
+            assert!(error.contains("expected a date like 2024-05-01"), "{}", bad);
+        }
+        assert!(
+            parse_time("1969-12-31")
+                .unwrap_err()
+                .contains("before 1970")
+        );
+    }
+
+    #[test]
+    fn json_keys_and_numbers_are_stable() {
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let text = format_json(&[coin()], "usd", fetched_at);
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([{
+                "id": "bitcoin",
+                "symbol": "btc",
+                "name": "Bitcoin",
+                "price": 65000.5,
+                "change_24h": -1.25,
+                "currency": "usd",
+                "fetched_at": "2023-11-14T22:13:20Z",
+            }])
+        );
+    }
+
+    #[test]
+    fn parse_output_format_rejects_unknown_formats() {
+        assert_eq!(parse_output_format("JSON"), Ok(OutputFormat::Json));
+        assert_eq!(parse_output_format("table"), Ok(OutputFormat::Table));
+        assert!(parse_output_format("csv").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Coins held and what they are worth at the fetched prices
+
+use crate::config::{write_atomic, xdg_dir};
+use crate::error::TuickerError;
+use crate::model::Coin;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// ~/.config/tuicker/portfolio.toml, one table per coin id:
+//
+//     [bitcoin]
+//     quantity = 0.5
+//     cost_basis = 26000.0
+pub fn portfolio_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("portfolio.toml"))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Holding {
+    pub quantity: f64,
+    // Average price paid per coin, for profit and loss
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_basis: Option<f64>,
+}
+
+// Holdings by coin id, listed in id order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Portfolio {
+    pub holdings: BTreeMap<String, Holding>,
+}
+
+fn parse_portfolio(text: &str) -> Result<Portfolio, String> {
+    let portfolio: Portfolio = toml::from_str(text).map_err(|e| e.to_string())?;
+    for (coin_id, holding) in &portfolio.holdings {
+        if !holding.quantity.is_finite() || holding.quantity < 0.0 {
+            return Err(format!("`{}`: quantity must be zero or more", coin_id));
+        }
+        if holding
+            .cost_basis
+            .is_some_and(|cost_basis| !cost_basis.is_finite() || cost_basis < 0.0)
+        {
+            return Err(format!("`{}`: cost_basis must be zero or more", coin_id));
+        }
+    }
+    Ok(portfolio)
+}
+
+// No portfolio file just means nothing is held
+pub fn load_portfolio() -> Result<Portfolio, TuickerError> {
+    let Some(path) = portfolio_path() else {
+        return Ok(Portfolio::default());
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Portfolio::default()),
+        Err(e) => {
+            let message = format!("could not read {}: {}", path.display(), e);
+            return Err(TuickerError::Config(message));
+        }
+    };
+    parse_portfolio(&text).map_err(|e| TuickerError::Config(format!("{}: {}", path.display(), e)))
+}
+
+// Rewrites the whole file, comments in it are not kept. Goes through a
+// temporary file so a failed write leaves the old portfolio in place
+pub fn save_portfolio(path: &Path, portfolio: &Portfolio) -> Result<(), String> {
+    let text = toml::to_string(portfolio).map_err(|e| e.to_string())?;
+    write_atomic(path, text.as_bytes())
+}
+
+// One holding valued at the latest fetched price. The price and values
+// are None when the coin wasn't in the last refresh
+#[derive(Debug, PartialEq)]
+pub struct Position<'a> {
+    pub coin_id: &'a str,
+    pub coin: Option<&'a Coin>,
+    pub quantity: f64,
+    pub value: Option<f64>,
+    pub change_24h: Option<f64>,
+    // What the position cost, None without a cost basis
+    pub cost: Option<f64>,
+    // Unrealized profit or loss, None without a cost basis or a price
+    pub pnl: Option<f64>,
+}
+
+impl Position<'_> {
+    pub fn pnl_percent(&self) -> Option<f64> {
+        percent_of(self.pnl?, self.cost?)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Totals {
+    pub value: f64,
+    pub change_24h: f64,
+    // Some holdings had no price and are left out
+    pub partial: bool,
+    // Only positions with both a cost basis and a price count towards these
+    pub cost: f64,
+    pub pnl: Option<f64>,
+}
+
+impl Totals {
+    // Weighted by what each position cost, not an average of their percentages
+    pub fn pnl_percent(&self) -> Option<f64> {
+        percent_of(self.pnl?, self.cost)
+    }
+}
+
+// None for a zero cost, a position that was free has no meaningful percentage
+fn percent_of(pnl: f64, cost: f64) -> Option<f64> {
+    (cost > 0.0).then(|| pnl / cost * 100.0)
+}
+
+// How much a position's value moved over the last 24h, from its current
+// value and the price change in percent
+fn value_change(value: f64, change_percent: f64) -> f64 {
+    value - value / (1.0 + change_percent / 100.0)
+}
+
+impl Portfolio {
+    pub fn positions<'a>(&'a self, coins: &'a [Coin]) -> Vec<Position<'a>> {
+        self.holdings
+            .iter()
+            .map(|(coin_id, holding)| {
+                let coin = coins.iter().find(|coin| coin.id == *coin_id);
+                let value = coin.map(|coin| coin.current_price * holding.quantity);
+                let cost = holding
+                    .cost_basis
+                    .map(|cost_basis| cost_basis * holding.quantity);
+                Position {
+                    coin_id,
+                    coin,
+                    quantity: holding.quantity,
+                    value,
+                    change_24h: coin
+                        .zip(value)
+                        .map(|(coin, value)| value_change(value, coin.price_change_24h)),
+                    cost,
+                    pnl: value.zip(cost).map(|(value, cost)| value - cost),
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn totals(positions: &[Position]) -> Totals {
+    let mut totals = Totals::default();
+    for position in positions {
+        match (position.value, position.change_24h) {
+            (Some(value), Some(change_24h)) => {
+                totals.value += value;
+                totals.change_24h += change_24h;
+            }
+            _ => totals.partial = true,
+        }
+        if let (Some(pnl), Some(cost)) = (position.pnl, position.cost) {
+            totals.pnl = Some(totals.pnl.unwrap_or(0.0) + pnl);
+            totals.cost += cost;
+        }
+    }
+    totals
+}
+
+// Positions below this share of the total are grouped into "other"
+const ALLOCATION_OTHER_THRESHOLD: f64 = 0.02;
+
+#[derive(Debug, PartialEq)]
+pub struct Allocation {
+    // Coin symbol, or "other"
+    pub label: String,
+    // Share of the total value, 0.0 to 1.0
+    pub weight: f64,
+}
+
+// Share of the total value per position, largest first. Holdings without a
+// price are left out, and there is nothing to show when none have one
+pub fn allocation(positions: &[Position]) -> Vec<Allocation> {
+    let valued: Vec<(String, f64)> = positions
+        .iter()
+        .filter_map(|position| {
+            let label = match position.coin {
+                Some(coin) => coin.symbol.clone(),
+                None => position.coin_id.to_string(),
+            };
+            Some((label, position.value?))
+        })
+        .collect();
+    let total: f64 = valued.iter().map(|(_, value)| value).sum();
+    if total <= 0.0 {
+        return Vec::new();
+    }
+    let mut allocation: Vec<Allocation> = valued
+        .into_iter()
+        .map(|(label, value)| Allocation {
+            label,
+            weight: value / total,
+        })
+        .collect();
+    allocation.sort_by(|a, b| {
+        b.weight
+            .total_cmp(&a.weight)
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    // A single small position keeps its own name, grouping only helps with several
+    let small = allocation
+        .iter()
+        .filter(|entry| entry.weight < ALLOCATION_OTHER_THRESHOLD)
+        .count();
+    if small > 1 {
+        let other: f64 = allocation
+            .iter()
+            .filter(|entry| entry.weight < ALLOCATION_OTHER_THRESHOLD)
+            .map(|entry| entry.weight)
+            .sum();
+        allocation.retain(|entry| entry.weight >= ALLOCATION_OTHER_THRESHOLD);
+        allocation.push(Allocation {
+            label: "other".to_string(),
+            weight: other,
+        });
+    }
+    allocation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(id: &str, price: f64, change: f64) -> Coin {
+        Coin {
+            id: id.to_string(),
+            name: id.to_string(),
+            symbol: id[..3].to_uppercase(),
+            current_price: price,
+            price_change_24h: change,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_portfolio_reads_quantities() {
+        let portfolio =
+            parse_portfolio("[bitcoin]\nquantity = 0.5\n[solana]\nquantity = 10\n").unwrap();
+        assert_eq!(portfolio.holdings["bitcoin"].quantity, 0.5);
+        assert_eq!(portfolio.holdings["solana"].quantity, 10.0);
+        assert!(parse_portfolio("[bitcoin]\nquantity = -1\n").is_err());
+        assert!(parse_portfolio("[bitcoin]\namount = 1\n").is_err());
+        assert!(parse_portfolio("[bitcoin]\nquantity = 1\ncost_basis = -5\n").is_err());
+    }
+
+    #[test]
+    fn pnl_is_weighted_by_cost() {
+        let portfolio = parse_portfolio(
+            "[bitcoin]\nquantity = 1\ncost_basis = 50000\n\
+             [ethereum]\nquantity = 10\ncost_basis = 400\n\
+             [solana]\nquantity = 5\n",
+        )
+        .unwrap();
+        let coins = vec![
+            coin("bitcoin", 60000.0, 0.0),
+            coin("ethereum", 300.0, 0.0),
+            coin("solana", 100.0, 0.0),
+        ];
+        let positions = portfolio.positions(&coins);
+        // Winning position
+        assert_eq!(positions[0].pnl, Some(10000.0));
+        assert_eq!(positions[0].pnl_percent(), Some(20.0));
+        // Losing position
+        assert_eq!(positions[1].pnl, Some(-1000.0));
+        assert_eq!(positions[1].pnl_percent(), Some(-25.0));
+        // No cost basis, so no P&L rather than zero
+        assert_eq!(positions[2].pnl, None);
+        assert_eq!(positions[2].pnl_percent(), None);
+
+        let totals = totals(&positions);
+        assert_eq!(totals.pnl, Some(9000.0));
+        // 9000 on 54000 paid, not the average of 20% and -25%
+        let percent = totals.pnl_percent().unwrap();
+        assert!((percent - 9000.0 / 54000.0 * 100.0).abs() < 1e-9);
+    }
+
+    fn holdings(quantities: &[(&str, f64)]) -> Portfolio {
+        let holdings = quantities
+            .iter()
+            .map(|(coin_id, quantity)| {
+                let holding = Holding {
+                    quantity: *quantity,
+                    cost_basis: None,
+                };
+                (coin_id.to_string(), holding)
+            })
+            .collect();
+        Portfolio { holdings }
+    }
+
+    #[test]
+    fn allocation_is_sorted_and_groups_small_positions() {
+        let portfolio = holdings(&[
+            ("bitcoin", 7.0),
+            ("cardano", 1.0),
+            ("dogecoin", 1.0),
+            ("ethereum", 91.0),
+        ]);
+        let coins = vec![
+            coin("bitcoin", 1.0, 0.0),
+            coin("cardano", 1.0, 0.0),
+            coin("dogecoin", 1.0, 0.0),
+            coin("ethereum", 1.0, 0.0),
+        ];
+        let allocation = allocation(&portfolio.positions(&coins));
+        let labels: Vec<&str> = allocation
+            .iter()
+            .map(|entry| entry.label.as_str())
+            .collect();
+        assert_eq!(labels, ["ETH", "BIT", "other"]);
+        let sum: f64 = allocation.iter().map(|entry| entry.weight).sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+        assert!((allocation[2].weight - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn allocation_of_one_coin_or_no_prices() {
+        let portfolio = holdings(&[("bitcoin", 1.0)]);
+        let coins = vec![coin("bitcoin", 100.0, 0.0)];
+        let allocation = allocation(&portfolio.positions(&coins));
+        assert_eq!(allocation.len(), 1);
+        assert_eq!(allocation[0].weight, 1.0);
+        // Nothing has a price, so nothing to divide by
+        assert!(super::allocation(&portfolio.positions(&[])).is_empty());
+    }
+
+    #[test]
+    fn no_cost_basis_means_no_total_pnl() {
+        let portfolio = parse_portfolio("[bitcoin]\nquantity = 1\n").unwrap();
+        let coins = vec![coin("bitcoin", 100.0, 0.0)];
+        let totals = totals(&portfolio.positions(&coins));
+        assert_eq!(totals.pnl, None);
+        assert_eq!(totals.pnl_percent(), None);
+    }
+
+    #[test]
+    fn saved_portfolio_reads_back_the_same() {
+        let portfolio = parse_portfolio(
+            "[bitcoin]\nquantity = 0.5\ncost_basis = 26000.0\n[solana]\nquantity = 10\n",
+        )
+        .unwrap();
+        let text = toml::to_string(&portfolio).unwrap();
+        assert_eq!(parse_portfolio(&text).unwrap(), portfolio);
+    }
+
+    #[test]
+    fn positions_are_valued_at_the_fetched_prices() {
+        let portfolio = parse_portfolio("[bitcoin]\nquantity = 0.5\n").unwrap();
+        let coins = vec![coin("bitcoin", 60000.0, 20.0)];
+        let positions = portfolio.positions(&coins);
+        assert_eq!(positions[0].value, Some(30000.0));
+        // Up 20% means it was worth 25000 a day ago
+        assert!((positions[0].change_24h.unwrap() - 5000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn missing_prices_make_the_total_partial() {
+        let portfolio =
+            parse_portfolio("[bitcoin]\nquantity = 1\n[solana]\nquantity = 10\n").unwrap();
+        let coins = vec![coin("bitcoin", 100.0, 0.0)];
+        let positions = portfolio.positions(&coins);
+        assert_eq!(positions[1].value, None);
+        let totals = totals(&positions);
+        assert_eq!(totals.value, 100.0);
+        assert!(totals.partial);
+    }
+}
@@ -0,0 +1,241 @@
+//! `tuicker serve`: the prices refreshed in the background without the
+//! TUI, answered as JSON on /coins and /coins/<id> for widgets and scripts
+
+use crate::app::is_stale;
+use crate::config::{Config, clamp_refresh_interval};
+use crate::listener::{Request, Response, serve_http};
+use crate::model::Coin;
+use crate::output::{CoinOutput, rfc3339};
+use crate::source::{DataSource, refresh_watchlist};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpListener;
+
+// Where `serve` listens without --listen
+pub const DEFAULT_SERVE_ADDRESS: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 8080);
+
+const JSON: &str = "application/json";
+
+// The last refresh that got through
+#[derive(Debug, Default)]
+struct Snapshot {
+    coins: Vec<Coin>,
+    fetched_at: Option<(SystemTime, Instant)>,
+}
+
+pub struct Server {
+    config: Config,
+    // The refresh interval after the same clamping as in the TUI
+    interval: Duration,
+    snapshot: Mutex<Snapshot>,
+}
+
+#[derive(Serialize)]
+struct CoinsBody<'a> {
+    fetched_at: Option<String>,
+    stale: bool,
+    coins: Vec<CoinOutput<'a>>,
+}
+
+#[derive(Serialize)]
+struct CoinBody<'a> {
+    fetched_at: Option<String>,
+    stale: bool,
+    coin: CoinOutput<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response {
+    let text = serde_json::to_string(body).expect("answers always serialize");
+    Response::new(status, JSON, text)
+}
+
+impl Server {
+    pub fn new(config: Config) -> Self {
+        let (interval, _) =
+            clamp_refresh_interval(config.refresh_interval, config.min_refresh_interval());
+        Server {
+            config,
+            interval,
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    // One refresh, returns how long to wait for the next one. The lock
+    // isn't held across the request, this is the only task that writes
+    pub async fn refresh(&self, source: &dyn DataSource) -> Duration {
+        let Ok(previous) = self.snapshot.lock().map(|snapshot| snapshot.coins.clone()) else {
+            return self.interval;
+        };
+        let refresh = refresh_watchlist(&self.config, source, &previous, self.interval).await;
+        if let (Some(coins), Ok(mut snapshot)) = (refresh.coins, self.snapshot.lock()) {
+            snapshot.coins = coins;
+            snapshot.fetched_at = Some((SystemTime::now(), Instant::now()));
+        }
+        refresh.wait
+    }
+
+    // GET /coins and /coins/<id>, with when the prices were fetched and
+    // whether that was longer ago than `stale_after_intervals` allows
+    pub fn respond(&self, request: &Request, now: Instant) -> Response {
+        let id = match request.path.as_str() {
+            "/coins" | "/coins/" => None,
+            path => match path.strip_prefix("/coins/") {
+                Some(id) if !id.contains('/') => Some(id),
+                _ => return Response::not_found(request),
+            },
+        };
+        if request.method != "GET" {
+            return Response::not_found(request);
+        }
+        let Ok(snapshot) = self.snapshot.lock() else {
+            return Response::new(StatusCode::INTERNAL_SERVER_ERROR, JSON, String::new());
+        };
+        let fetched_at = snapshot.fetched_at.map(|(time, _)| rfc3339(time));
+        // Nothing fetched yet is as out of date as it gets
+        let stale = snapshot.fetched_at.is_none_or(|(_, at)| {
+            is_stale(at, self.interval, self.config.stale_after_intervals, now)
+        });
+        let timestamp = fetched_at.clone().unwrap_or_default();
+        let output = |coin| CoinOutput::new(coin, &self.config.currency, &timestamp);
+        match id {
+            None => json(
+                StatusCode::OK,
+                &CoinsBody {
+                    fetched_at: fetched_at.clone(),
+                    stale,
+                    coins: snapshot.coins.iter().map(output).collect(),
+                },
+            ),
+            Some(id) => match snapshot.coins.iter().find(|coin| coin.id == id) {
+                Some(coin) => json(
+                    StatusCode::OK,
+                    &CoinBody {
+                        fetched_at: fetched_at.clone(),
+                        stale,
+                        coin: output(coin),
+                    },
+                ),
+                None => json(
+                    StatusCode::NOT_FOUND,
+                    &ErrorBody {
+                        error: format!("{} is not in the watchlist", id),
+                    },
+                ),
+            },
+        }
+    }
+}
+
+// Refreshes until the task is aborted
+pub async fn run_refreshes(server: Arc<Server>, source: Arc<dyn DataSource>) {
+    loop {
+        let wait = server.refresh(source.as_ref()).await;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Answers until the task is aborted, which drops the listener
+pub async fn serve_coins(listener: TcpListener, server: Arc<Server>) {
+    serve_http(listener, move |request| {
+        server.respond(request, Instant::now())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::DemoSource;
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn body(response: &Response) -> serde_json::Value {
+        assert_eq!(response.content_type, JSON);
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    fn server() -> Server {
+        Server::new(Config {
+            coins: vec!["bitcoin".to_string(), "ethereum".to_string()],
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn nothing_fetched_yet_is_an_empty_stale_list() {
+        let response = server().respond(&get("/coins"), Instant::now());
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            body(&response),
+            serde_json::json!({"fetched_at": null, "stale": true, "coins": []})
+        );
+        let response = server().respond(&get("/coins/bitcoin"), Instant::now());
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(body(&response)["error"], "bitcoin is not in the watchlist");
+    }
+
+    #[tokio::test]
+    async fn coins_are_answered_from_the_last_refresh() {
+        let server = server();
+        let wait = server.refresh(&DemoSource::new(7)).await;
+        assert_eq!(wait, server.interval);
+
+        let now = Instant::now();
+        let all = body(&server.respond(&get("/coins"), now));
+        assert_eq!(all["stale"], false);
+        let ids: Vec<&str> = all["coins"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|coin| coin["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["bitcoin", "ethereum"]);
+        assert!(all["fetched_at"].as_str().unwrap().ends_with('Z'));
+
+        let one = body(&server.respond(&get("/coins/ethereum"), now));
+        assert_eq!(one["coin"]["id"], "ethereum");
+        assert_eq!(one["coin"]["currency"], "usd");
+        assert_eq!(one["fetched_at"], all["fetched_at"]);
+        assert!(one["coin"]["price"].as_f64().unwrap() > 0.0);
+
+        // Past `stale_after_intervals` with no refresh in between
+        let later = now + server.interval * 4;
+        assert_eq!(body(&server.respond(&get("/coins"), later))["stale"], true);
+    }
+
+    #[test]
+    fn other_paths_and_methods_are_turned_away() {
+        let server = server();
+        let now = Instant::now();
+        assert_eq!(
+            server.respond(&get("/prices"), now).status,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            server.respond(&get("/coins/bitcoin/chart"), now).status,
+            StatusCode::NOT_FOUND
+        );
+        let post = Request {
+            method: "POST".to_string(),
+            path: "/coins".to_string(),
+        };
+        assert_eq!(
+            server.respond(&post, now).status,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Places a fired alert is announced: the banner, the terminal bell,
+//! desktop notifications and a webhook
+
+use crate::alerts::Direction;
+use crate::app::FetchMessage;
+use crate::output::rfc3339;
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+
+// A price wobbling around a threshold fires its alert over and over,
+// sinks outside the app only hear about it this often
+const SINK_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+// An alert that just fired, worded for every sink
+#[derive(Debug, Clone, PartialEq)]
+pub struct FiredAlert {
+    // Rule key, alerts with the same key share a cooldown
+    pub key: String,
+    pub coin_id: String,
+    pub symbol: String,
+    pub direction: Direction,
+    pub threshold: f64,
+    // The price that set it off
+    pub price: f64,
+    pub fired_at: SystemTime,
+    // e.g. "ALERT: BTC crossed above $70,000.00"
+    pub summary: String,
+    // e.g. "BTC is at $70,512.00"
+    pub body: String,
+}
+
+// Behind a trait so tests can check what was sent without a notification
+// daemon or a terminal
+pub trait AlertSink {
+    fn send(&mut self, alert: &FiredAlert) -> Result<(), String>;
+
+    // Minimum time between two sends for the same alert
+    fn cooldown(&self) -> Duration {
+        SINK_COOLDOWN
+    }
+}
+
+// The line across the top of the screen, kept until dismissed with 'x'
+#[derive(Debug, Default)]
+pub struct Banner {
+    pub messages: Vec<String>,
+    // Coins whose rows are highlighted while the banner is up
+    pub coin_ids: Vec<String>,
+}
+
+impl Banner {
+    pub fn dismiss(&mut self) {
+        self.messages.clear();
+        self.coin_ids.clear();
+    }
+}
+
+impl AlertSink for Banner {
+    fn send(&mut self, alert: &FiredAlert) -> Result<(), String> {
+        self.messages.push(alert.summary.clone());
+        if !self.coin_ids.contains(&alert.coin_id) {
+            self.coin_ids.push(alert.coin_id.clone());
+        }
+        Ok(())
+    }
+
+    // Every firing shows up in the app
+    fn cooldown(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+// Rings the terminal bell, which also works over SSH
+pub struct Bell;
+
+impl AlertSink for Bell {
+    fn send(&mut self, _alert: &FiredAlert) -> Result<(), String> {
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(b"\x07")
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("could not ring the bell: {}", e))
+    }
+}
+
+// Hands the notification to the desktop's own tool, notify-send or on
+// macOS osascript, so no D-Bus client has to be built in
+pub struct DesktopNotifier;
+
+#[cfg(target_os = "macos")]
+fn notification_command(summary: &str, body: &str) -> Command {
+    // AppleScript strings are double quoted with backslash escapes
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let script = format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(summary)
+    );
+    let mut command = Command::new("osascript");
+    command.args(["-e", &script]);
+    command
+}
+
+#[cfg(not(target_os = "macos"))]
+fn notification_command(summary: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name=tuicker", summary, body]);
+    command
+}
+
+impl AlertSink for DesktopNotifier {
+    fn send(&mut self, alert: &FiredAlert) -> Result<(), String> {
+        let mut child = notification_command(&alert.summary, &alert.body)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not send a desktop notification: {}", e))?;
+        // Waited on in the background, without a running daemon the tool
+        // can take a long time to give up and the UI must not wait for it
+        std::thread::spawn(move || {
+            let _ = child.wait();
+        });
+        Ok(())
+    }
+}
+
+// What the webhook is sent. Other programs parse this, so fields are only
+// ever added, never renamed or removed
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub coin: &'a str,
+    pub symbol: &'a str,
+    // "above" or "below"
+    pub condition: &'static str,
+    pub threshold: f64,
+    pub price: f64,
+    // RFC 3339 in UTC, when the alert fired
+    pub time: String,
+}
+
+impl<'a> WebhookPayload<'a> {
+    pub fn new(alert: &'a FiredAlert) -> Self {
+        WebhookPayload {
+            coin: &alert.coin_id,
+            symbol: &alert.symbol,
+            condition: alert.direction.as_str(),
+            threshold: alert.threshold,
+            price: alert.price,
+            time: rfc3339(alert.fired_at),
+        }
+    }
+}
+
+// Wait before the one retry of a failed webhook
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+async fn post_once(client: &Client, url: &str, body: &str) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        // The URL is left out, the path of one is often its secret
+        .map_err(|e| e.without_url().to_string())?;
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(format!("HTTP {}", status)),
+    }
+}
+
+// Tried twice, a receiver that is briefly down shouldn't lose the alert
+pub async fn post_webhook(client: &Client, url: &str, body: &str) -> Result<(), String> {
+    if post_once(client, url, body).await.is_ok() {
+        return Ok(());
+    }
+    tokio::time::sleep(WEBHOOK_RETRY_DELAY).await;
+    post_once(client, url, body)
+        .await
+        .map_err(|e| format!("webhook failed: {}", e))
+        .inspect_err(|e| log::warn!("{}", e))
+}
+
+// POSTs every alert as JSON to `webhook_url` from the config. The request
+// runs on its own task and failures come back over the fetch channel
+pub struct Webhook {
+    pub client: Client,
+    pub url: String,
+    pub tx: UnboundedSender<FetchMessage>,
+}
+
+impl AlertSink for Webhook {
+    fn send(&mut self, alert: &FiredAlert) -> Result<(), String> {
+        let body = serde_json::to_string(&WebhookPayload::new(alert)).map_err(|e| e.to_string())?;
+        let (client, url, tx) = (self.client.clone(), self.url.clone(), self.tx.clone());
+        tokio::spawn(async move {
+            if let Err(e) = post_webhook(&client, &url, &body).await {
+                let _ = tx.send(FetchMessage::WebhookFailed(e));
+            }
+        });
+        Ok(())
+    }
+}
+
+// The banner plus whichever other sinks the config turned on. Each fired
+// alert goes to all of them the same way
+#[derive(Default)]
+pub struct AlertSinks {
+    pub banner: Banner,
+    pub others: Vec<Box<dyn AlertSink>>,
+    // When each sink, by index with the banner first, last sent each alert
+    sent_at: HashMap<(usize, String), Instant>,
+}
+
+impl AlertSinks {
+    // Returns why sinks failed. A failing sink never stops the others
+    pub fn dispatch(&mut self, alert: &FiredAlert) -> Vec<String> {
+        let sinks = std::iter::once(&mut self.banner as &mut dyn AlertSink)
+            .chain(self.others.iter_mut().map(|sink| sink.as_mut()));
+        let mut errors = Vec::new();
+        for (index, sink) in sinks.enumerate() {
+            let sent_key = (index, alert.key.clone());
+            let cooling_down = self
+                .sent_at
+                .get(&sent_key)
+                .is_some_and(|at| at.elapsed() < sink.cooldown());
+            if cooling_down {
+                continue;
+            }
+            if let Err(e) = sink.send(alert) {
+                errors.push(e);
+            }
+            self.sent_at.insert(sent_key, Instant::now());
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use wiremock::matchers::{body_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    struct Recording(Rc<RefCell<Vec<String>>>);
+
+    impl AlertSink for Recording {
+        fn send(&mut self, alert: &FiredAlert) -> Result<(), String> {
+            self.0.borrow_mut().push(alert.summary.clone());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl AlertSink for Failing {
+        fn send(&mut self, _alert: &FiredAlert) -> Result<(), String> {
+            Err("no daemon".to_string())
+        }
+    }
+
+    fn alert() -> FiredAlert {
+        FiredAlert {
+            key: "bitcoin above 70000".to_string(),
+            coin_id: "bitcoin".to_string(),
+            symbol: "BTC".to_string(),
+            direction: Direction::Above,
+            threshold: 70000.0,
+            price: 70123.4,
+            fired_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_564_800),
+            summary: "ALERT: BTC crossed above $70,000.00".to_string(),
+            body: "BTC is at $70,123.40".to_string(),
+        }
+    }
+
+    #[test]
+    fn webhook_payload_format() {
+        let alert = alert();
+        let json = serde_json::to_string(&WebhookPayload::new(&alert)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"coin":"bitcoin","symbol":"BTC","condition":"above","threshold":70000.0,"price":70123.4,"time":"2024-05-01T12:00:00Z"}"#
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn webhook_is_retried_once() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_json(serde_json::json!({"coin": "bitcoin"})))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        let client = Client::new();
+        let body = r#"{"coin":"bitcoin"}"#;
+        assert_eq!(post_webhook(&client, &server.uri(), body).await, Ok(()));
+
+        // Failing twice gives up
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let error = post_webhook(&client, &server.uri(), body)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "webhook failed: HTTP 500 Internal Server Error");
+    }
+
+    #[test]
+    fn banner_always_shows_and_other_sinks_cool_down() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks = AlertSinks::default();
+        sinks.others.push(Box::new(Recording(sent.clone())));
+        assert!(sinks.dispatch(&alert()).is_empty());
+        assert!(sinks.dispatch(&alert()).is_empty());
+        assert_eq!(sinks.banner.messages.len(), 2);
+        assert_eq!(sinks.banner.coin_ids, ["bitcoin"]);
+        assert_eq!(sent.borrow().len(), 1);
+    }
+
+    #[test]
+    fn a_failing_sink_does_not_stop_the_others() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let mut sinks = AlertSinks::default();
+        sinks.others.push(Box::new(Failing));
+        sinks.others.push(Box::new(Recording(sent.clone())));
+        assert_eq!(sinks.dispatch(&alert()), ["no daemon"]);
+        assert_eq!(sent.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,382 @@
+//! Where the prices, charts and market figures come from: CoinGecko, or the
+//! generated market of --demo. The app only talks to a `DataSource`
+
+use crate::api::{
+    ChartResult, EtagCache, FetchDebugInfo, GlobalResult, OhlcResult, RefreshResult,
+    TrendingResult, api_base_url, fetch_global, fetch_market_chart, fetch_ohlc, fetch_trending,
+    pretty_json, refresh_output, retry_after_delay,
+};
+use crate::binance::{BINANCE_API_URL, BinanceSource};
+use crate::coinmarketcap::{CMC_API_URL, CoinMarketCapSource, build_cmc_client};
+use crate::config::{Config, SourceKind};
+use crate::demo::DemoSource;
+use crate::error::TuickerError;
+use crate::failover::FailoverSource;
+use crate::kraken::{KRAKEN_API_URL, KrakenSource};
+use crate::limiter::{Priority, RateLimiter};
+use crate::logging::redact_url;
+use crate::model::{Candle, ChartRange, Coin, SortDir, SortKey, sort_coins};
+use futures::future::BoxFuture;
+use reqwest::{Client, StatusCode};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Told the number of every retry that is about to start, and handed the raw
+// markets response in debug mode
+#[derive(Clone, Copy)]
+pub struct RefreshHooks<'a> {
+    pub on_retry: &'a (dyn Fn(u32) + Sync),
+    pub on_response: Option<&'a (dyn Fn(FetchDebugInfo) + Sync)>,
+}
+
+impl RefreshHooks<'_> {
+    pub const NONE: RefreshHooks<'static> = RefreshHooks {
+        on_retry: &|_| {},
+        on_response: None,
+    };
+}
+
+// Shared by the background tasks, so every method only borrows
+pub trait DataSource: Send + Sync {
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult>;
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult>;
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult>;
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult>;
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult>;
+
+    // The source the last refresh came from, when it isn't always the same
+    fn supplier(&self) -> Option<&'static str> {
+        None
+    }
+
+    // A request is waiting for `max_requests_per_minute` to let it through
+    fn throttled(&self) -> bool {
+        false
+    }
+}
+
+// The body of a GET to an exchange, logged like the CoinGecko requests and
+// with the rate limit kept. `error` makes the error of any other failed
+// status out of its body. 418 is what exchanges send once a client ignored
+// their 429s
+pub(crate) async fn get_text(
+    client: &Client,
+    url: &str,
+    error: fn(StatusCode, &str) -> TuickerError,
+) -> Result<String, TuickerError> {
+    let logged = redact_url(url);
+    log::debug!("GET {}", logged);
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| TuickerError::from(e.without_url()))
+        .inspect_err(|e| log::warn!("GET {} failed: {}", logged, e.detail()))?;
+    let status = response.status();
+    log::debug!("{} from {}", status, logged);
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::IM_A_TEAPOT {
+        return Err(TuickerError::RateLimited(retry_after_delay(
+            response.headers(),
+        )));
+    }
+    let text = response.text().await?;
+    match status.is_client_error() || status.is_server_error() {
+        true => Err(error(status, &text)),
+        false => Ok(text),
+    }
+}
+
+// The error of coins a source has no price for, pointing at the table of
+// the config that maps them
+pub(crate) fn no_price(exchange: &str, table: &str, coin_ids: &[String]) -> TuickerError {
+    TuickerError::Config(format!(
+        "no {} price for {}, see [{}]",
+        exchange,
+        coin_ids.join(", "),
+        table
+    ))
+}
+
+// A candle of the arrays exchanges send them as, [open time, "open",
+// "high", "low", "close", ...] with the prices as strings. The time is
+// multiplied by `time_scale` to get milliseconds
+pub(crate) fn exchange_candle(
+    row: &[serde_json::Value],
+    time_scale: f64,
+) -> Result<Candle, TuickerError> {
+    let field = |index: usize| match row.get(index)? {
+        serde_json::Value::String(text) => text.parse().ok(),
+        value => value.as_f64(),
+    };
+    match (field(0), field(1), field(2), field(3), field(4)) {
+        (Some(time), Some(open), Some(high), Some(low), Some(close)) => Ok(Candle {
+            time: time * time_scale,
+            open,
+            high,
+            low,
+            close,
+        }),
+        _ => Err(TuickerError::Parse(serde::de::Error::custom(
+            "candle without a time and prices",
+        ))),
+    }
+}
+
+// What an exchange's price request sent back, for the debug view
+pub(crate) fn exchange_debug_info<T>(
+    url: String,
+    started: Instant,
+    fetched: &Result<String, TuickerError>,
+    parsed: &Result<Vec<T>, TuickerError>,
+) -> FetchDebugInfo {
+    let (status, body) = match fetched {
+        Ok(json_text) => (Some(StatusCode::OK), pretty_json(json_text)),
+        Err(TuickerError::Exchange {
+            status, message, ..
+        }) => (Some(*status), message.clone()),
+        Err(TuickerError::RateLimited(_)) => (Some(StatusCode::TOO_MANY_REQUESTS), String::new()),
+        Err(_) => (None, String::new()),
+    };
+    let outcome = match (fetched, parsed) {
+        (Err(e), _) | (Ok(_), Err(e)) => e.detail(),
+        (Ok(_), Ok(tickers)) => format!("parsed {} tickers", tickers.len()),
+    };
+    FetchDebugInfo {
+        url: redact_url(&url),
+        status,
+        elapsed: started.elapsed(),
+        body,
+        outcome,
+    }
+}
+
+pub struct CoinGeckoSource {
+    pub client: Client,
+    // The free or the pro host
+    pub api_url: String,
+    // What each markets URL answered last, so the next request can ask
+    // whether anything changed
+    pub etags: EtagCache,
+    // Shared with every other CoinGecko source of the session
+    pub limiter: Arc<RateLimiter>,
+}
+
+impl CoinGeckoSource {
+    pub fn new(config: &Config, client: Client, limiter: &Arc<RateLimiter>) -> Self {
+        CoinGeckoSource {
+            limiter: limiter.clone(),
+            ..CoinGeckoSource::at(client, api_base_url(config))
+        }
+    }
+
+    // Any host, like a mock server's, without a limit
+    pub fn at(client: Client, api_url: &str) -> Self {
+        CoinGeckoSource {
+            client,
+            api_url: api_url.to_string(),
+            etags: EtagCache::default(),
+            limiter: Arc::new(RateLimiter::new(u32::MAX)),
+        }
+    }
+}
+
+// The source `config.source` names, or the failover over `sources` when
+// it lists more than one. `keyless` is the client without the CoinGecko
+// key, for other hosts. The demo market is seeded by the clock, so every
+// demo walks its own way
+pub fn build_source(
+    config: &Config,
+    client: Client,
+    keyless: Client,
+) -> Result<Arc<dyn DataSource>, TuickerError> {
+    let kinds = config.source_kinds();
+    let limiter = Arc::new(RateLimiter::new(config.max_requests_per_minute));
+    let source_of = |kind| source_of(kind, config, &client, &keyless, &limiter);
+    match kinds.len() > 1 {
+        true => {
+            let sources = kinds
+                .into_iter()
+                .map(|kind| Ok((kind, source_of(kind)?)))
+                .collect::<Result<_, TuickerError>>()?;
+            Ok(Arc::new(FailoverSource::new(sources)))
+        }
+        false => source_of(kinds[0]),
+    }
+}
+
+fn source_of(
+    kind: SourceKind,
+    config: &Config,
+    client: &Client,
+    keyless: &Client,
+    limiter: &Arc<RateLimiter>,
+) -> Result<Arc<dyn DataSource>, TuickerError> {
+    let (client, keyless) = (client.clone(), keyless.clone());
+    Ok(match kind {
+        SourceKind::CoinGecko => Arc::new(CoinGeckoSource::new(config, client, limiter)),
+        SourceKind::Binance => Arc::new(BinanceSource {
+            client: keyless,
+            api_url: BINANCE_API_URL.to_string(),
+            symbols: config.binance_symbols.clone(),
+            coingecko: CoinGeckoSource::new(config, client, limiter),
+        }),
+        SourceKind::Kraken => Arc::new(KrakenSource {
+            client: keyless,
+            api_url: KRAKEN_API_URL.to_string(),
+            symbols: config.kraken_symbols.clone(),
+            coingecko: CoinGeckoSource::new(config, client, limiter),
+        }),
+        SourceKind::CoinMarketCap => Arc::new(CoinMarketCapSource {
+            client: build_cmc_client(config)?,
+            api_url: CMC_API_URL.to_string(),
+            coins: config.cmc_coins.clone(),
+        }),
+        SourceKind::Demo => {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+            Arc::new(DemoSource::new(seed))
+        }
+    })
+}
+
+// What a refresh of the loops without the TUI, --watch and serve, leaves
+pub(crate) struct WatchlistRefresh {
+    // In watchlist order, None when the refresh failed and the last coins
+    // still stand
+    pub coins: Option<Vec<Coin>>,
+    // Why the refresh didn't get every coin
+    pub problem: Option<String>,
+    // How long to wait for the next one
+    pub wait: Duration,
+}
+
+// One refresh of the configured coins, the coins of a batch that failed
+// keep the prices they had in `previous` like in the TUI
+pub(crate) async fn refresh_watchlist(
+    config: &Config,
+    source: &dyn DataSource,
+    previous: &[Coin],
+    interval: Duration,
+) -> WatchlistRefresh {
+    let result = source
+        .refresh(&config.coins, &config.currency, &[], RefreshHooks::NONE)
+        .await;
+    let refresh = match result {
+        Ok(refresh) => refresh,
+        Err(e) => {
+            log::warn!("refresh failed: {}", e.detail());
+            let wait = match e {
+                TuickerError::RateLimited(delay) => delay.max(interval),
+                _ => interval,
+            };
+            return WatchlistRefresh {
+                coins: None,
+                problem: Some(format!("refresh failed: {}", e)),
+                wait,
+            };
+        }
+    };
+    let mut coins = refresh.coins;
+    if let Some(failure) = &refresh.failure {
+        log::warn!("refresh got only some coins: {}", failure);
+        let kept: Vec<Coin> = previous
+            .iter()
+            .filter(|old| !coins.iter().any(|coin| coin.id == old.id))
+            .cloned()
+            .collect();
+        coins.extend(kept);
+    }
+    sort_coins(
+        &mut coins,
+        SortKey::Watchlist,
+        SortDir::Ascending,
+        &config.coins,
+    );
+    WatchlistRefresh {
+        coins: Some(coins),
+        problem: refresh.failure.map(|failure| failure.to_string()),
+        wait: interval,
+    }
+}
+
+impl DataSource for CoinGeckoSource {
+    fn refresh<'a>(
+        &'a self,
+        coin_ids: &'a [String],
+        currency: &'a str,
+        extra_currencies: &'a [String],
+        hooks: RefreshHooks<'a>,
+    ) -> BoxFuture<'a, RefreshResult> {
+        Box::pin(refresh_output(
+            self,
+            coin_ids,
+            currency,
+            extra_currencies,
+            hooks.on_retry,
+            hooks.on_response,
+        ))
+    }
+
+    fn chart<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, ChartResult> {
+        Box::pin(async move {
+            self.limiter.acquire(Priority::Queued).await?;
+            fetch_market_chart(&self.client, &self.api_url, coin_id, currency, range).await
+        })
+    }
+
+    fn ohlc<'a>(
+        &'a self,
+        coin_id: &'a str,
+        currency: &'a str,
+        range: ChartRange,
+    ) -> BoxFuture<'a, OhlcResult> {
+        Box::pin(async move {
+            self.limiter.acquire(Priority::Queued).await?;
+            fetch_ohlc(&self.client, &self.api_url, coin_id, currency, range).await
+        })
+    }
+
+    fn trending(&self) -> BoxFuture<'_, TrendingResult> {
+        Box::pin(async move {
+            self.limiter.acquire(Priority::Droppable).await?;
+            fetch_trending(&self.client, &self.api_url).await
+        })
+    }
+
+    fn global(&self) -> BoxFuture<'_, GlobalResult> {
+        Box::pin(async move {
+            self.limiter.acquire(Priority::Droppable).await?;
+            fetch_global(&self.client, &self.api_url).await
+        })
+    }
+
+    fn throttled(&self) -> bool {
+        self.limiter.throttled()
+    }
+}
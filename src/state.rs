@@ -0,0 +1,125 @@
+//! Display settings changed in the app, kept between sessions
+
+use crate::config::xdg_dir;
+use crate::theme::ThemePreset;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// ~/.local/share/tuicker/state.json
+pub fn ui_state_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_DATA_HOME", ".local/share")?.join("state.json"))
+}
+
+// The tabs of the tab bar, in order. The detail view of a coin is part of
+// the market tab
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tab {
+    #[default]
+    Market,
+    Portfolio,
+    Alerts,
+    Trending,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 4] = [Tab::Market, Tab::Portfolio, Tab::Alerts, Tab::Trending];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::Market => "Market",
+            Tab::Portfolio => "Portfolio",
+            Tab::Alerts => "Alerts",
+            Tab::Trending => "Trending",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        Tab::ALL.iter().position(|tab| *tab == self).unwrap_or(0)
+    }
+}
+
+// How much room a coin gets in the market table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RowLayout {
+    // One line per coin with every column
+    #[default]
+    Compact,
+    // Two lines per coin with the name, price, 24h and 7d change and trend
+    Detailed,
+}
+
+impl RowLayout {
+    pub fn toggled(self) -> RowLayout {
+        match self {
+            RowLayout::Compact => RowLayout::Detailed,
+            RowLayout::Detailed => RowLayout::Compact,
+        }
+    }
+
+    // Lines of the terminal a coin takes up
+    pub fn row_height(self) -> u16 {
+        match self {
+            RowLayout::Compact => 1,
+            RowLayout::Detailed => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiState {
+    // Prices in the market view are shown in BTC, toggled with 'b'
+    pub btc_denominated: bool,
+    // The tab the app was left on, where the next session starts
+    pub tab: Tab,
+    // Switched with 't', until then the preset of the config file is used
+    pub theme: Option<ThemePreset>,
+    // Switched with 'v'
+    pub row_layout: RowLayout,
+}
+
+// A missing or corrupt file means the defaults
+pub fn load_ui_state(path: &Path) -> UiState {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_ui_state(path: &Path, state: &UiState) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    }
+    let text = serde_json::to_string_pretty(state).map_err(|e| e.to_string())?;
+    std::fs::write(path, text + "\n")
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ui_state_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-state", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state.json");
+        assert_eq!(load_ui_state(&path), UiState::default());
+        let state = UiState {
+            btc_denominated: true,
+            tab: Tab::Alerts,
+            theme: Some(ThemePreset::Light),
+            row_layout: RowLayout::Detailed,
+        };
+        save_ui_state(&path, &state).unwrap();
+        assert_eq!(load_ui_state(&path), state);
+        // Files from before the tab was saved
+        std::fs::write(&path, r#"{"btc_denominated": true}"#).unwrap();
+        assert_eq!(load_ui_state(&path).tab, Tab::Market);
+        std::fs::write(&path, "{ not json").unwrap();
+        assert_eq!(load_ui_state(&path), UiState::default());
+    }
+}
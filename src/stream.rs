@@ -0,0 +1,803 @@
+//! `stream = true`: Binance's combined <pair>@miniTicker WebSocket streams,
+//! pushed into the same channel as the polled refreshes. There's no
+//! WebSocket crate among the dependencies, the little of the protocol a
+//! client needs is done here over reqwest's HTTP/1.1 upgrade, down to the
+//! SHA-1 of the handshake
+
+use crate::api::proxied;
+use crate::app::FetchMessage;
+use crate::binance::{binance_asset, binance_pair, number};
+use crate::clipboard::base64;
+use crate::config::Config;
+use crate::error::TuickerError;
+use crate::kraken::change_percent;
+use reqwest::header::{CONNECTION, UPGRADE};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const BINANCE_STREAM_URL: &str = "https://stream.binance.com:9443";
+// However many pairs tick, the table is redrawn at most this often
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+// A ping goes out this often, a socket that stayed silent for three of
+// them is given up on
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+const FIRST_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// Far above any ticker, a frame this long means the stream is broken
+const MAX_FRAME_LENGTH: u64 = 1 << 20;
+
+// What the server hashes the key with to show it understood the upgrade
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    Connected,
+    // The latest tick of every coin that moved since the last batch
+    Prices(Vec<StreamTick>),
+    // Why the socket closed or couldn't be opened
+    Dropped(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamTick {
+    pub coin_id: String,
+    pub price: f64,
+    pub change_24h: f64,
+    pub volume: f64,
+}
+
+// The coin ids of the config with the pair each of them is streamed as,
+// coins Binance doesn't know are left to polling
+pub fn stream_pairs(config: &Config) -> Vec<(String, String)> {
+    config
+        .coins
+        .iter()
+        .filter_map(|coin_id| {
+            let asset = binance_asset(&config.binance_symbols, coin_id)?;
+            Some((coin_id.clone(), binance_pair(&asset, &config.currency)))
+        })
+        .collect()
+}
+
+pub fn stream_url(base_url: &str, pairs: &[(String, String)]) -> String {
+    let streams: Vec<String> = pairs
+        .iter()
+        .map(|(_, pair)| format!("{}@miniTicker", pair.to_lowercase()))
+        .collect();
+    format!("{}/stream?streams={}", base_url, streams.join("/"))
+}
+
+// {"stream": "btcusdt@miniTicker", "data": {"s": "BTCUSDT", "c": "67187.34",
+// "o": "66012.00", "q": "1520134.72", ...}}, the close is the last price
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    data: MiniTicker,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MiniTicker {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "c", deserialize_with = "number")]
+    pub close: f64,
+    #[serde(rename = "o", deserialize_with = "number")]
+    pub open: f64,
+    // In the quote asset, like the polled tickers
+    #[serde(rename = "q", deserialize_with = "number")]
+    pub quote_volume: f64,
+}
+
+pub fn parse_message(json_text: &str) -> Result<MiniTicker, TuickerError> {
+    let envelope: Envelope = serde_json::from_str(json_text)?;
+    Ok(envelope.data)
+}
+
+pub fn stream_tick(pairs: &[(String, String)], ticker: &MiniTicker) -> Option<StreamTick> {
+    let (coin_id, _) = pairs.iter().find(|(_, pair)| *pair == ticker.symbol)?;
+    Some(StreamTick {
+        coin_id: coin_id.clone(),
+        price: ticker.close,
+        change_24h: change_percent(ticker.open, ticker.close),
+        volume: ticker.quote_volume,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// A frame as it was sent, with the payload unmasked
+struct RawFrame {
+    // The last frame of its message
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+    // The bytes of the buffer it took
+    length: usize,
+}
+
+// The whole frame at the start of `buffer`, None until all of it has
+// arrived
+fn parse_frame(buffer: &[u8]) -> io::Result<Option<RawFrame>> {
+    let [first, second, ..] = buffer else {
+        return Ok(None);
+    };
+    let fin = first & 0x80 != 0;
+    let opcode = first & 0x0f;
+    let masked = second & 0x80 != 0;
+    let (length, mut start) = match second & 0x7f {
+        126 => match buffer.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buffer.get(2..10).and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => (u64::from_be_bytes(bytes), 10),
+            None => return Ok(None),
+        },
+        length => (length as u64, 2),
+    };
+    if length > MAX_FRAME_LENGTH {
+        return Err(invalid("websocket frame too long"));
+    }
+    let mask = match masked {
+        true => {
+            let Some(mask) = buffer.get(start..start + 4) else {
+                return Ok(None);
+            };
+            start += 4;
+            Some([mask[0], mask[1], mask[2], mask[3]])
+        }
+        false => None,
+    };
+    let end = start + length as usize;
+    let Some(payload) = buffer.get(start..end) else {
+        return Ok(None);
+    };
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask[index % 4])
+            .collect(),
+        None => payload.to_vec(),
+    };
+    Ok(Some(RawFrame {
+        fin,
+        opcode,
+        payload,
+        length: end,
+    }))
+}
+
+// Everything a client sends has to be masked
+pub fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => frame.push(0x80 | length as u8),
+        length @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask[index % 4]),
+    );
+    frame
+}
+
+// Frames out of the bytes read so far, with fragmented messages put back
+// together. Control frames may come between the fragments
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    message: Option<(u8, Vec<u8>)>,
+}
+
+impl Decoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        loop {
+            let Some(RawFrame {
+                fin,
+                opcode,
+                payload,
+                length,
+            }) = parse_frame(&self.buffer)?
+            else {
+                return Ok(None);
+            };
+            self.buffer.drain(..length);
+            let (opcode, payload) = match (opcode, self.message.take()) {
+                (OP_CONTINUATION, Some((first, mut data))) => {
+                    data.extend_from_slice(&payload);
+                    (first, data)
+                }
+                (OP_CONTINUATION, None) => return Err(invalid("continuation of no message")),
+                (opcode, message) => {
+                    self.message = message;
+                    (opcode, payload)
+                }
+            };
+            if !fin {
+                self.message = Some((opcode, payload));
+                continue;
+            }
+            return Ok(Some(match opcode {
+                OP_TEXT => match String::from_utf8(payload) {
+                    Ok(text) => Frame::Text(text),
+                    Err(_) => return Err(invalid("websocket text that isn't UTF-8")),
+                },
+                // Binance only sends text
+                OP_BINARY => continue,
+                OP_CLOSE => Frame::Close,
+                OP_PING => Frame::Ping(payload),
+                OP_PONG => Frame::Pong(payload),
+                _ => return Err(invalid("unknown websocket opcode")),
+            }));
+        }
+    }
+}
+
+// Holds the ticks back so a few redraws a second show all of them, a coin
+// that ticked twice in between only keeps its latest
+#[derive(Debug)]
+pub struct Coalescer {
+    interval: Duration,
+    pending: Vec<StreamTick>,
+    last_sent: Option<Instant>,
+}
+
+impl Coalescer {
+    pub fn new(interval: Duration) -> Self {
+        Coalescer {
+            interval,
+            pending: Vec::new(),
+            last_sent: None,
+        }
+    }
+
+    pub fn push(&mut self, tick: StreamTick) {
+        match self
+            .pending
+            .iter_mut()
+            .find(|pending| pending.coin_id == tick.coin_id)
+        {
+            Some(pending) => *pending = tick,
+            None => self.pending.push(tick),
+        }
+    }
+
+    // When the ticks held back may go out, None while there are none
+    pub fn ready_at(&self, now: Instant) -> Option<Instant> {
+        match self.pending.is_empty() {
+            true => None,
+            false => Some(self.last_sent.map_or(now, |sent| sent + self.interval)),
+        }
+    }
+
+    pub fn take(&mut self, now: Instant) -> Option<Vec<StreamTick>> {
+        if self.ready_at(now)? > now {
+            return None;
+        }
+        self.last_sent = Some(now);
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+// Doubles with every failed reconnect, back to the start once one works
+#[derive(Debug)]
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            next: FIRST_RECONNECT_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (delay * 2).min(MAX_RECONNECT_DELAY);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        *self = Backoff::default();
+    }
+}
+
+// The handshake key and the masks of the frames sent come from the
+// system's random numbers, so nothing on the way can predict them
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes)
+}
+
+// 16 bytes in base64
+fn websocket_key(bytes: [u8; 16]) -> String {
+    base64(&bytes)
+}
+
+// Only used for the handshake, which is what the protocol hashes with
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, next);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+// The Sec-WebSocket-Accept a server that took the upgrade answers `key` with
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()))
+}
+
+// HTTP/1.1 only, the upgrade doesn't exist in HTTP/2. There's no overall
+// timeout, the one request lasts as long as the stream
+pub fn build_stream_client(config: &Config) -> Result<Client, TuickerError> {
+    let builder = Client::builder()
+        .user_agent(concat!("tuicker/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(config.connect_timeout)
+        .http1_only();
+    Ok(proxied(builder, config)?.build()?)
+}
+
+async fn connect(client: &Client, url: &str) -> Result<reqwest::Upgraded, TuickerError> {
+    log::debug!("opening the price stream {}", url);
+    let key = websocket_key(random_bytes()?);
+    let response = client
+        .get(url)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", &key)
+        .send()
+        .await?;
+    // Anything else on the way, like a proxy, could answer 101 too
+    let accepted = response
+        .headers()
+        .get("sec-websocket-accept")
+        .is_some_and(|accept| accept.as_bytes() == accept_key(&key).as_bytes());
+    match response.status() {
+        StatusCode::SWITCHING_PROTOCOLS if accepted => Ok(response.upgrade().await?),
+        StatusCode::SWITCHING_PROTOCOLS => Err(TuickerError::Exchange {
+            name: "Binance",
+            status: StatusCode::SWITCHING_PROTOCOLS,
+            message: "the price stream answered with the wrong Sec-WebSocket-Accept".to_string(),
+        }),
+        status => Err(TuickerError::Exchange {
+            name: "Binance",
+            status,
+            message: "the price stream was refused".to_string(),
+        }),
+    }
+}
+
+async fn send<W: AsyncWrite + Unpin>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mask = random_bytes()?;
+    writer.write_all(&encode_frame(opcode, payload, mask)).await
+}
+
+// Reads the socket until it closes or goes quiet, sending the ticks on in
+// batches. Returns why it stopped, or nothing once the app is gone
+async fn read_ticks<S: AsyncRead + AsyncWrite>(
+    socket: S,
+    pairs: &[(String, String)],
+    tx: &UnboundedSender<FetchMessage>,
+) -> Option<String> {
+    let (mut reader, mut writer) = tokio::io::split(socket);
+    let mut decoder = Decoder::default();
+    let mut coalescer = Coalescer::new(REDRAW_INTERVAL);
+    let mut buffer = vec![0; 16 * 1024];
+    let mut last_heard = Instant::now();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    loop {
+        let ready_at = coalescer.ready_at(Instant::now());
+        let result: io::Result<()> = tokio::select! {
+            read = reader.read(&mut buffer) => match read {
+                Ok(0) => return Some("the price stream closed".to_string()),
+                Ok(read) => {
+                    last_heard = Instant::now();
+                    decoder.push(&buffer[..read]);
+                    let mut result = Ok(());
+                    loop {
+                        match decoder.next_frame() {
+                            Ok(Some(Frame::Text(text))) => match parse_message(&text) {
+                                Ok(ticker) => {
+                                    if let Some(tick) = stream_tick(pairs, &ticker) {
+                                        coalescer.push(tick);
+                                    }
+                                }
+                                Err(e) => log::debug!("unreadable stream message: {}", e),
+                            },
+                            Ok(Some(Frame::Ping(payload))) => {
+                                result = send(&mut writer, OP_PONG, &payload).await;
+                            }
+                            Ok(Some(Frame::Pong(_))) => {}
+                            Ok(Some(Frame::Close)) => {
+                                let _ = send(&mut writer, OP_CLOSE, &[]).await;
+                                return Some("the price stream was closed by Binance".to_string());
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                result = Err(e);
+                                break;
+                            }
+                        }
+                    }
+                    result
+                }
+                Err(e) => Err(e),
+            },
+            _ = ping.tick() => match last_heard.elapsed() > PING_INTERVAL * 3 {
+                true => return Some("the price stream went quiet".to_string()),
+                false => send(&mut writer, OP_PING, &[]).await,
+            },
+            _ = tokio::time::sleep_until(ready_at.unwrap_or_else(Instant::now).into()),
+                if ready_at.is_some() => Ok(()),
+        };
+        if let Err(e) = result {
+            return Some(format!("the price stream failed: {}", e));
+        }
+        if let Some(ticks) = coalescer.take(Instant::now()) {
+            tx.send(FetchMessage::Stream(StreamEvent::Prices(ticks)))
+                .ok()?;
+        }
+    }
+}
+
+// Streams until the app quits, reconnecting with a growing delay whenever
+// the socket drops. The app polls while it's down
+pub async fn run_stream(
+    client: Client,
+    url: String,
+    pairs: Vec<(String, String)>,
+    tx: UnboundedSender<FetchMessage>,
+) {
+    let mut backoff = Backoff::default();
+    loop {
+        let reason = match connect(&client, &url).await {
+            Ok(socket) => {
+                backoff.reset();
+                if tx
+                    .send(FetchMessage::Stream(StreamEvent::Connected))
+                    .is_err()
+                {
+                    return;
+                }
+                match read_ticks(socket, &pairs, &tx).await {
+                    Some(reason) => reason,
+                    None => return,
+                }
+            }
+            Err(e) => e.to_string(),
+        };
+        if tx
+            .send(FetchMessage::Stream(StreamEvent::Dropped(reason)))
+            .is_err()
+        {
+            return;
+        }
+        tokio::time::sleep(backoff.delay()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn pairs() -> Vec<(String, String)> {
+        vec![
+            ("bitcoin".to_string(), "BTCUSDT".to_string()),
+            ("ethereum".to_string(), "ETHUSDT".to_string()),
+        ]
+    }
+
+    fn tick(coin_id: &str, price: f64) -> StreamTick {
+        StreamTick {
+            coin_id: coin_id.to_string(),
+            price,
+            change_24h: 0.0,
+            volume: 0.0,
+        }
+    }
+
+    // What a server sends, which isn't masked
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = encode_frame(opcode, payload, [0; 4]);
+        frame[0] = (fin as u8) << 7 | opcode;
+        frame[1] &= 0x7f;
+        let header = frame.len() - payload.len() - 4;
+        frame.drain(header..header + 4);
+        frame
+    }
+
+    const MESSAGE: &str = r#"{"stream":"btcusdt@miniTicker","data":{"e":"24hrMiniTicker","E":1727000000000,"s":"BTCUSDT","c":"66000.00","o":"60000.00","h":"66500.00","l":"59800.00","v":"1200.5","q":"79233000.00"}}"#;
+
+    #[test]
+    fn the_stream_url_and_pairs_follow_the_config() {
+        let config = Config {
+            coins: vec!["bitcoin".to_string(), "my-token".to_string()],
+            currency: "eur".to_string(),
+            ..Config::default()
+        };
+        let pairs = stream_pairs(&config);
+        assert_eq!(pairs, [("bitcoin".to_string(), "BTCEUR".to_string())]);
+        assert_eq!(
+            stream_url(BINANCE_STREAM_URL, &self::pairs()),
+            "https://stream.binance.com:9443/stream?streams=btcusdt@miniTicker/ethusdt@miniTicker"
+        );
+    }
+
+    #[test]
+    fn mini_tickers_become_ticks_of_the_coins() {
+        let ticker = parse_message(MESSAGE).unwrap();
+        assert_eq!(ticker.symbol, "BTCUSDT");
+        let tick = stream_tick(&pairs(), &ticker).unwrap();
+        assert_eq!(tick.coin_id, "bitcoin");
+        assert_eq!(tick.price, 66000.0);
+        assert_eq!(tick.change_24h, 10.0);
+        assert_eq!(tick.volume, 79233000.0);
+
+        let other = MiniTicker {
+            symbol: "SOLUSDT".to_string(),
+            ..ticker
+        };
+        assert_eq!(stream_tick(&pairs(), &other), None);
+        assert!(parse_message(r#"{"result":null,"id":1}"#).is_err());
+    }
+
+    #[test]
+    fn frames_are_read_whole_and_put_back_together() {
+        let mut decoder = Decoder::default();
+        let text = server_frame(true, OP_TEXT, MESSAGE.as_bytes());
+        // A read can end anywhere in a frame
+        decoder.push(&text[..1]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.push(&text[1..]);
+        assert_eq!(
+            decoder.next_frame().unwrap(),
+            Some(Frame::Text(MESSAGE.to_string()))
+        );
+
+        // Extended lengths, and a ping in the middle of a fragmented message
+        let long = "x".repeat(70_000);
+        decoder.push(&server_frame(false, OP_TEXT, &long.as_bytes()[..300]));
+        decoder.push(&server_frame(true, OP_PING, b"hi"));
+        decoder.push(&server_frame(
+            true,
+            OP_CONTINUATION,
+            &long.as_bytes()[300..],
+        ));
+        assert_eq!(
+            decoder.next_frame().unwrap(),
+            Some(Frame::Ping(b"hi".to_vec()))
+        );
+        assert_eq!(decoder.next_frame().unwrap(), Some(Frame::Text(long)));
+        assert_eq!(decoder.next_frame().unwrap(), None);
+
+        // What the client sends is masked, and reads back the same
+        decoder.push(&encode_frame(OP_PONG, b"hi", [1, 2, 3, 4]));
+        assert_eq!(
+            decoder.next_frame().unwrap(),
+            Some(Frame::Pong(b"hi".to_vec()))
+        );
+        decoder.push(&server_frame(true, OP_CLOSE, &[]));
+        assert_eq!(decoder.next_frame().unwrap(), Some(Frame::Close));
+
+        decoder.push(&server_frame(true, OP_CONTINUATION, b"lost"));
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn ticks_are_held_back_to_a_few_batches_a_second() {
+        let now = Instant::now();
+        let mut coalescer = Coalescer::new(REDRAW_INTERVAL);
+        assert_eq!(coalescer.take(now), None);
+        coalescer.push(tick("bitcoin", 1.0));
+        assert_eq!(coalescer.take(now), Some(vec![tick("bitcoin", 1.0)]));
+
+        coalescer.push(tick("bitcoin", 2.0));
+        coalescer.push(tick("ethereum", 3.0));
+        coalescer.push(tick("bitcoin", 4.0));
+        assert_eq!(coalescer.ready_at(now), Some(now + REDRAW_INTERVAL));
+        assert_eq!(coalescer.take(now + Duration::from_millis(100)), None);
+        assert_eq!(
+            coalescer.take(now + REDRAW_INTERVAL),
+            Some(vec![tick("bitcoin", 4.0), tick("ethereum", 3.0)])
+        );
+        assert_eq!(coalescer.ready_at(now + REDRAW_INTERVAL), None);
+    }
+
+    #[test]
+    fn reconnects_back_off_up_to_a_minute() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<u64> = (0..8).map(|_| backoff.delay().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        backoff.reset();
+        assert_eq!(backoff.delay(), FIRST_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn the_key_is_sixteen_random_bytes_of_base64() {
+        assert_eq!(websocket_key([0; 16]), "AAAAAAAAAAAAAAAAAAAAAA==");
+        assert_eq!(websocket_key([0xff; 16]), "/////////////////////w==");
+        assert_ne!(random_bytes::<16>().unwrap(), random_bytes::<16>().unwrap());
+    }
+
+    #[test]
+    fn the_accept_key_is_the_one_of_the_rfc() {
+        let hex = |digest: [u8; 20]| -> String {
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Two blocks
+        assert_eq!(
+            hex(sha1(&[b'a'; 64])),
+            "0098ba824b5c16427bd7a1122a5a442a25ec644d"
+        );
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stream_sends_its_ticks_and_reports_when_it_drops() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).to_string();
+            assert!(request.to_lowercase().contains("upgrade: websocket"));
+            let key = request
+                .lines()
+                .find_map(|line| line.strip_prefix("sec-websocket-key: "))
+                .unwrap();
+            let answer = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nconnection: upgrade\r\nupgrade: websocket\r\nsec-websocket-accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            socket.write_all(answer.as_bytes()).await.unwrap();
+            socket
+                .write_all(&server_frame(true, OP_TEXT, MESSAGE.as_bytes()))
+                .await
+                .unwrap();
+            socket
+                .write_all(&server_frame(true, OP_PING, b"hi"))
+                .await
+                .unwrap();
+            // The pong comes back masked
+            let mut pong = vec![0; 64];
+            let read = socket.read(&mut pong).await.unwrap();
+            let mut decoder = Decoder::default();
+            decoder.push(&pong[..read]);
+            assert_eq!(
+                decoder.next_frame().unwrap(),
+                Some(Frame::Pong(b"hi".to_vec()))
+            );
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = build_stream_client(&Config::default()).unwrap();
+        tokio::spawn(run_stream(client, url, pairs(), tx));
+        let mut events = Vec::new();
+        while events.len() < 3 {
+            if let Some(FetchMessage::Stream(event)) = rx.recv().await {
+                events.push(event);
+            }
+        }
+        assert_eq!(events[0], StreamEvent::Connected);
+        let StreamEvent::Prices(ticks) = &events[1] else {
+            panic!("no prices in {:?}", events);
+        };
+        assert_eq!(ticks[0].coin_id, "bitcoin");
+        assert!(matches!(events[2], StreamEvent::Dropped(_)));
+    }
+
+    #[tokio::test]
+    async fn a_switch_without_the_right_accept_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            assert!(read > 0);
+            socket
+                .write_all(
+                    b"HTTP/1.1 101 Switching Protocols\r\nconnection: upgrade\r\nupgrade: websocket\r\nsec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n",
+                )
+                .await
+                .unwrap();
+        });
+        let client = build_stream_client(&Config::default()).unwrap();
+        let error = connect(&client, &url).await.unwrap_err();
+        assert!(
+            error.to_string().contains("Sec-WebSocket-Accept"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! The colors of the TUI. A dark and a light preset, either of which the
+//! `[theme]` table of the config file can change single colors of
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+// Picked with `theme = "light"` and switched with 't', which is remembered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl ThemePreset {
+    pub fn theme(self) -> Theme {
+        match self {
+            ThemePreset::Dark => Theme::default(),
+            // The basic colors are too bright on white, these are from the
+            // darker end of the 256 color palette
+            ThemePreset::Light => Theme {
+                gain: Color::Indexed(28),
+                loss: Color::Indexed(124),
+                fear: Color::Indexed(166),
+                greed: Color::Indexed(34),
+                header: Color::Reset,
+                border: Color::Indexed(244),
+                selection_bg: Some(Color::Indexed(252)),
+                accent: Color::Indexed(25),
+                muted: Color::Indexed(244),
+                warning: Color::Indexed(130),
+                error: Color::Indexed(124),
+                banner: Color::Indexed(220),
+                banner_text: Color::Black,
+                monochrome: false,
+            },
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            ThemePreset::Dark => ThemePreset::Light,
+            ThemePreset::Light => ThemePreset::Dark,
+        }
+    }
+}
+
+fn parse_preset(text: &str) -> Result<ThemePreset, String> {
+    match text.trim().to_lowercase().as_str() {
+        "dark" => Ok(ThemePreset::Dark),
+        "light" => Ok(ThemePreset::Light),
+        _ => Err(format!(
+            "unknown theme \"{}\", expected \"dark\" or \"light\"",
+            text
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    // Rising and falling prices, also the ends of the Fear & Greed scale
+    pub gain: Color,
+    pub loss: Color,
+    // The milder steps of the Fear & Greed scale
+    pub fear: Color,
+    pub greed: Color,
+    // Table headers and titles
+    pub header: Color,
+    pub border: Color,
+    // No color keeps the selected row reversed
+    pub selection_bg: Option<Color>,
+    // The allocation gauge
+    pub accent: Color,
+    // Secondary text like the action names in the help
+    pub muted: Color,
+    // Triggered alerts and a stale refresh
+    pub warning: Color,
+    pub error: Color,
+    pub banner: Color,
+    pub banner_text: Color,
+    // --no-color: no colors or text attributes at all, every style is the
+    // terminal's own
+    pub monochrome: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            gain: Color::Green,
+            loss: Color::Red,
+            fear: Color::LightRed,
+            greed: Color::LightGreen,
+            header: Color::Reset,
+            border: Color::Reset,
+            selection_bg: None,
+            accent: Color::Cyan,
+            muted: Color::DarkGray,
+            warning: Color::Yellow,
+            error: Color::Red,
+            banner: Color::Yellow,
+            banner_text: Color::Black,
+            monochrome: false,
+        }
+    }
+}
+
+impl Theme {
+    // Signs, the "> " of the selected row and the text of a state carry
+    // what the colors would have
+    pub fn monochrome() -> Self {
+        Theme {
+            monochrome: true,
+            ..Theme::default()
+        }
+    }
+
+    // Every style of the TUI is made here, so monochrome leaves none behind
+    fn style(&self, style: Style) -> Style {
+        match self.monochrome {
+            true => Style::default(),
+            false => style,
+        }
+    }
+
+    pub fn fg(&self, color: Color) -> Style {
+        self.style(Style::default().fg(color))
+    }
+
+    pub fn bold(&self) -> Style {
+        self.style(Style::default().add_modifier(Modifier::BOLD))
+    }
+
+    pub fn header_style(&self) -> Style {
+        self.fg(self.header).patch(self.bold())
+    }
+
+    pub fn border_style(&self) -> Style {
+        self.fg(self.border)
+    }
+
+    pub fn selection_style(&self) -> Style {
+        self.style(match self.selection_bg {
+            Some(color) => Style::default().bg(color),
+            None => Style::default().add_modifier(Modifier::REVERSED),
+        })
+    }
+
+    // The banner and the rows of the coins it is about
+    pub fn banner_style(&self) -> Style {
+        let style = Style::default().fg(self.banner_text).bg(self.banner);
+        self.style(style).patch(self.bold())
+    }
+
+    pub fn alert_row_style(&self) -> Style {
+        self.style(Style::default().bg(self.banner))
+            .patch(self.bold())
+    }
+
+    // Green for a rise, red for a fall, nothing when flat
+    pub fn change_style(&self, value: f64) -> Style {
+        if value > 0.0 {
+            self.fg(self.gain)
+        } else if value < 0.0 {
+            self.fg(self.loss)
+        } else {
+            Style::default()
+        }
+    }
+
+    fn set(&mut self, name: &str, color: Color) -> Result<(), String> {
+        let slot = match name {
+            "gain" => &mut self.gain,
+            "loss" => &mut self.loss,
+            "fear" => &mut self.fear,
+            "greed" => &mut self.greed,
+            "header" => &mut self.header,
+            "border" => &mut self.border,
+            "selection_bg" => {
+                self.selection_bg = Some(color);
+                return Ok(());
+            }
+            "accent" => &mut self.accent,
+            "muted" => &mut self.muted,
+            "warning" => &mut self.warning,
+            "error" => &mut self.error,
+            "banner" => &mut self.banner,
+            "banner_text" => &mut self.banner_text,
+            _ => return Err(format!("unknown theme color \"{}\"", name)),
+        };
+        *slot = color;
+        Ok(())
+    }
+}
+
+const NAMED_COLORS: &[(&str, Color)] = &[
+    ("reset", Color::Reset),
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("gray", Color::Gray),
+    ("dark_gray", Color::DarkGray),
+    ("light_red", Color::LightRed),
+    ("light_green", Color::LightGreen),
+    ("light_yellow", Color::LightYellow),
+    ("light_blue", Color::LightBlue),
+    ("light_magenta", Color::LightMagenta),
+    ("light_cyan", Color::LightCyan),
+    ("white", Color::White),
+];
+
+// "green", "dark_gray", "color123" for the 256 color palette or "#ff8800"
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    let unknown = || format!("unknown color \"{}\"", text);
+    let lower = text.trim().to_lowercase().replace(['-', ' '], "_");
+    let lower = lower.replace("grey", "gray");
+    if let Some(hex) = lower.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(unknown());
+        }
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| unknown());
+        return Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    if let Some(index) = lower.strip_prefix("color") {
+        return index.parse().map(Color::Indexed).map_err(|_| unknown());
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == lower)
+        .map(|(_, color)| *color)
+        .ok_or_else(unknown)
+}
+
+// The preset from the config file and the colors changed on top of it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThemeConfig {
+    pub preset: ThemePreset,
+    pub overrides: Vec<(String, Color)>,
+}
+
+impl ThemeConfig {
+    // The overrides apply to whichever preset is in use
+    pub fn theme(&self, preset: ThemePreset) -> Theme {
+        let mut theme = preset.theme();
+        for (name, color) in &self.overrides {
+            // Checked when the config was read
+            let _ = theme.set(name, *color);
+        }
+        theme
+    }
+
+    fn read(settings: BTreeMap<String, String>) -> Result<Self, String> {
+        let mut config = ThemeConfig::default();
+        let mut check = Theme::default();
+        for (name, text) in settings {
+            let read = match name.as_str() {
+                "preset" => parse_preset(&text).map(|preset| config.preset = preset),
+                _ => parse_color(&text).and_then(|color| {
+                    check.set(&name, color)?;
+                    config.overrides.push((name.clone(), color));
+                    Ok(())
+                }),
+            };
+            read.map_err(|e| format!("theme.{}: {}", name, e))?;
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ThemeSetting {
+    Preset(String),
+    Table(BTreeMap<String, String>),
+}
+
+// `theme = "light"`, or a `[theme]` table of colors with an optional
+// `preset = "light"`
+pub fn deserialize_theme<'de, D>(deserializer: D) -> Result<ThemeConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let read = match ThemeSetting::deserialize(deserializer)? {
+        ThemeSetting::Preset(text) => parse_preset(&text)
+            .map(|preset| ThemeConfig {
+                preset,
+                ..ThemeConfig::default()
+            })
+            .map_err(|e| format!("theme: {}", e)),
+        ThemeSetting::Table(settings) => ThemeConfig::read(settings),
+    };
+    read.map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_colors_are_read_in_any_spelling() {
+        assert_eq!(parse_color("green"), Ok(Color::Green));
+        assert_eq!(parse_color("dark_gray"), Ok(Color::DarkGray));
+        assert_eq!(parse_color("Dark-Grey"), Ok(Color::DarkGray));
+        assert_eq!(parse_color("light cyan"), Ok(Color::LightCyan));
+        assert_eq!(parse_color(" RESET "), Ok(Color::Reset));
+    }
+
+    #[test]
+    fn indexed_and_hex_colors() {
+        assert_eq!(parse_color("color123"), Ok(Color::Indexed(123)));
+        assert_eq!(parse_color("color0"), Ok(Color::Indexed(0)));
+        assert_eq!(parse_color("#ff8800"), Ok(Color::Rgb(255, 136, 0)));
+        assert_eq!(parse_color("#A0b1C2"), Ok(Color::Rgb(160, 177, 194)));
+    }
+
+    #[test]
+    fn bad_colors_are_refused() {
+        for text in [
+            "", "grean", "color", "color256", "color-1", "#ff88", "#ff88000", "#gg8800", "ff8800",
+        ] {
+            assert_eq!(
+                parse_color(text),
+                Err(format!("unknown color \"{}\"", text)),
+                "{}",
+                text
+            );
+        }
+    }
+
+    fn theme_config(text: &str) -> Result<ThemeConfig, String> {
+        #[derive(Deserialize)]
+        struct File {
+            #[serde(deserialize_with = "deserialize_theme")]
+            theme: ThemeConfig,
+        }
+        toml::from_str::<File>(text)
+            .map(|file| file.theme)
+            .map_err(|e| e.message().to_string())
+    }
+
+    fn theme(text: &str) -> Result<Theme, String> {
+        theme_config(&format!("[theme]\n{}", text)).map(|config| config.theme(ThemePreset::Dark))
+    }
+
+    #[test]
+    fn theme_table_replaces_single_colors() {
+        let theme = theme("gain = \"blue\"\nselection_bg = \"#202020\"").unwrap();
+        assert_eq!(theme.gain, Color::Blue);
+        assert_eq!(
+            theme.selection_style(),
+            Style::default().bg(Color::Rgb(32, 32, 32))
+        );
+        assert_eq!(theme.loss, Theme::default().loss);
+    }
+
+    #[test]
+    fn theme_errors_name_the_key() {
+        assert_eq!(
+            theme("loss = \"crimson\"").unwrap_err(),
+            "theme.loss: unknown color \"crimson\""
+        );
+        assert_eq!(
+            theme("profit = \"green\"").unwrap_err(),
+            "theme.profit: unknown theme color \"profit\""
+        );
+        assert_eq!(
+            theme_config("theme = \"sepia\"").unwrap_err(),
+            "theme: unknown theme \"sepia\", expected \"dark\" or \"light\""
+        );
+    }
+
+    #[test]
+    fn overrides_apply_to_either_preset() {
+        let config = theme_config("theme = \"Light\"").unwrap();
+        assert_eq!(config.preset, ThemePreset::Light);
+        assert_eq!(config.theme(ThemePreset::Light), ThemePreset::Light.theme());
+
+        let config = theme_config("[theme]\npreset = \"light\"\nloss = \"magenta\"").unwrap();
+        assert_eq!(config.preset, ThemePreset::Light);
+        let light = config.theme(ThemePreset::Light);
+        assert_eq!(light.loss, Color::Magenta);
+        assert_eq!(light.gain, ThemePreset::Light.theme().gain);
+        let dark = config.theme(config.preset.toggled());
+        assert_eq!(dark.loss, Color::Magenta);
+        assert_eq!(dark.gain, Color::Green);
+    }
+
+    #[test]
+    fn monochrome_has_no_styles() {
+        let theme = Theme::monochrome();
+        for style in [
+            theme.fg(theme.gain),
+            theme.change_style(-1.0),
+            theme.header_style(),
+            theme.selection_style(),
+            theme.banner_style(),
+            theme.alert_row_style(),
+        ] {
+            assert_eq!(style, Style::default());
+        }
+    }
+
+    #[test]
+    fn default_selection_stays_reversed() {
+        let theme = Theme::default();
+        assert_eq!(
+            theme.selection_style(),
+            Style::default().add_modifier(Modifier::REVERSED)
+        );
+        assert_eq!(theme.change_style(1.0), Style::default().fg(Color::Green));
+        assert_eq!(theme.change_style(0.0), Style::default());
+    }
+}
@@ -0,0 +1,124 @@
+//! `title_format`: the terminal window title written after every refresh,
+//! so a price shows in a background tab or tmux window
+
+use crate::model::{BTC, Coin, NumberLocale, format_price};
+
+// What `title_format = "off"` is spelled as
+pub const TITLE_OFF: &str = "off";
+
+// The parts of a coin a placeholder like `{btc_price}` can name
+const FIELDS: [&str; 3] = ["symbol", "price", "change"];
+
+// Shown for a coin that isn't in the last refresh
+const MISSING: &str = "–";
+
+// The `{...}` placeholders of `template` that aren't `{<symbol>_<field>}`
+// with a field of FIELDS, for the config check
+pub fn unknown_placeholders(template: &str) -> Vec<String> {
+    placeholders(template)
+        .filter(|name| parse_placeholder(name).is_none())
+        .map(|name| format!("{{{}}}", name))
+        .collect()
+}
+
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+}
+
+// "btc_price" -> ("btc", "price")
+fn parse_placeholder(name: &str) -> Option<(&str, &str)> {
+    let (symbol, field) = name.rsplit_once('_')?;
+    match !symbol.is_empty() && FIELDS.contains(&field) {
+        true => Some((symbol, field)),
+        false => None,
+    }
+}
+
+// e.g. "₿ $65,321.18 +2.3%" out of "{btc_symbol} {btc_price} {btc_change}".
+// Coins are found by their symbol, text outside the braces is kept as is
+pub fn render_title(
+    template: &str,
+    coins: &[Coin],
+    currency: &str,
+    locale: NumberLocale,
+) -> String {
+    let mut title = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        title.push_str(&rest[..start]);
+        // An unclosed brace is kept like the other text
+        let Some(length) = rest[start..].find('}') else {
+            title.push_str(&rest[start..]);
+            return title;
+        };
+        let name = &rest[start + 1..start + length];
+        title.push_str(&placeholder_value(name, coins, currency, locale));
+        rest = &rest[start + length + 1..];
+    }
+    title.push_str(rest);
+    title
+}
+
+fn placeholder_value(name: &str, coins: &[Coin], currency: &str, locale: NumberLocale) -> String {
+    let Some((symbol, field)) = parse_placeholder(name) else {
+        return format!("{{{}}}", name);
+    };
+    let coin = coins
+        .iter()
+        .find(|coin| coin.symbol.eq_ignore_ascii_case(symbol));
+    match (coin, field) {
+        (None, _) => MISSING.to_string(),
+        (Some(coin), "symbol") if coin.symbol.eq_ignore_ascii_case(BTC) => "₿".to_string(),
+        (Some(coin), "symbol") => coin.symbol.to_uppercase(),
+        (Some(coin), "price") => format_price(coin.current_price, currency, locale),
+        (Some(coin), _) => format!("{:+.1}%", coin.price_change_24h),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coin(symbol: &str, price: f64, change: f64) -> Coin {
+        Coin {
+            id: symbol.to_string(),
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            current_price: price,
+            price_change_24h: change,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn placeholders_take_the_coin_of_their_symbol() {
+        let coins = [coin("btc", 65321.18, 2.3), coin("eth", 3012.5, -1.24)];
+        let title = |template| render_title(template, &coins, "usd", NumberLocale::En);
+        assert_eq!(
+            title("{btc_symbol} {btc_price} {btc_change}"),
+            "₿ $65,321.18 +2.3%"
+        );
+        assert_eq!(
+            title("{ETH_symbol} {eth_change} · tuicker"),
+            "ETH -1.2% · tuicker"
+        );
+        // A coin that isn't watched, and a placeholder that isn't one
+        assert_eq!(title("{doge_price} {nope} {btc"), "– {nope} {btc");
+        assert_eq!(
+            render_title("{btc_price}", &coins, "eur", NumberLocale::De),
+            "€65.321,18"
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_listed() {
+        assert!(unknown_placeholders("{btc_symbol} {btc_price} {btc_change}").is_empty());
+        assert_eq!(
+            unknown_placeholders("{btc_volume} {price} {_price}"),
+            ["{btc_volume}", "{price}", "{_price}"]
+        );
+    }
+}
@@ -0,0 +1,2099 @@
+//! Drawing the app
+
+use crate::api::MAX_FETCH_ATTEMPTS;
+use crate::app::{
+    AlertPrompt, App, ChartData, ChartMode, Connection, InputMode, TableLayout, View,
+};
+use crate::fear_greed::Sentiment;
+use crate::heatmap;
+use crate::keymap::{Category, keys_label};
+use crate::model::{
+    BTC, Candle, ChangeHorizon, ChartRange, Coin, Extreme, NumberLocale, SortKey,
+    format_btc_amount, format_compact, format_compact_with, format_price, format_price_change,
+    truncate,
+};
+use crate::output::rfc3339;
+use crate::portfolio::{allocation, totals};
+use crate::state::{RowLayout, Tab};
+use crate::theme::Theme;
+use ratatui::{
+    Frame,
+    buffer::Buffer,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    symbols,
+    text::{Line, Span},
+    widgets::{
+        Axis, Block, Borders, Cell, Chart, Clear, Dataset, GraphType, HighlightSpacing, LineGauge,
+        Paragraph, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, Table, TableState, Tabs,
+        Wrap,
+    },
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Rounded down to the largest unit, e.g. 12s, 3m or 2h
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    if seconds < 60 {
+        format!("{}s", seconds)
+    } else if seconds < 60 * 60 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}h", seconds / (60 * 60))
+    }
+}
+
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+pub fn spinner(frame: usize) -> char {
+    SPINNER_FRAMES[frame % SPINNER_FRAMES.len()]
+}
+
+// When the prices were fetched and when they will be fetched next
+fn refresh_status(app: &App) -> String {
+    let status = live_refresh_status(app);
+    // Until the first refresh succeeds the table shows last session's prices
+    let Some(cached_at) = app.cached_at else {
+        return status;
+    };
+    let age = SystemTime::now()
+        .duration_since(cached_at)
+        .unwrap_or_default();
+    let cached = format!("cached {} ago", format_age(age));
+    match status.is_empty() {
+        true => cached,
+        false => format!("{} · {}", status, cached),
+    }
+}
+
+fn stale_note(app: &App) -> &'static str {
+    match app.stale() {
+        true => " (stale)",
+        false => "",
+    }
+}
+
+// Which of the configured sources the prices came from, with failover
+fn supplier_note(app: &App) -> String {
+    app.source
+        .supplier()
+        .map(|supplier| format!(" via {}", supplier))
+        .unwrap_or_default()
+}
+
+fn live_refresh_status(app: &App) -> String {
+    if app.fetching {
+        let spinner = spinner(app.spinner_frame);
+        if app.source.throttled() {
+            return format!("{} throttled…", spinner);
+        }
+        // Half of `request_timeout` has gone by without an answer
+        return match (app.fetch_attempt > 1, app.slow()) {
+            (true, slow) => format!(
+                "{} retrying… (attempt {}/{}){}",
+                spinner,
+                app.fetch_attempt,
+                MAX_FETCH_ATTEMPTS,
+                match slow {
+                    true => " · slow connection…",
+                    false => "",
+                }
+            ),
+            (false, true) => format!("{} slow connection…", spinner),
+            (false, false) => format!("{} refreshing…", spinner),
+        };
+    }
+    let next_in = app.next_refresh.saturating_duration_since(Instant::now());
+    let error = app.last_error.as_deref().unwrap_or("update failed");
+    // Nothing is fetched until 'r', while the age keeps counting
+    if app.paused {
+        return match (app.last_updated, app.last_refresh_failed) {
+            (Some(last_updated), true) => format!(
+                "{} · last update {} ago (stale)",
+                error,
+                format_age(last_updated.elapsed())
+            ),
+            (Some(last_updated), false) => format!(
+                "updated {} ago{}{}",
+                format_age(last_updated.elapsed()),
+                supplier_note(app),
+                stale_note(app)
+            ),
+            (None, true) => error.to_string(),
+            (None, false) => String::new(),
+        };
+    }
+    if app.rate_limited() {
+        return format!("rate limited — retrying in {}", format_age(next_in));
+    }
+    match (app.last_updated, app.last_refresh_failed) {
+        (Some(last_updated), true) => format!(
+            "{} · last update {} ago (stale)",
+            error,
+            format_age(last_updated.elapsed())
+        ),
+        (Some(last_updated), false) if app.streaming => format!(
+            "updated {} ago{}{} · streaming",
+            format_age(last_updated.elapsed()),
+            supplier_note(app),
+            stale_note(app)
+        ),
+        (Some(last_updated), false) => format!(
+            "updated {} ago{}{} · next in {}",
+            format_age(last_updated.elapsed()),
+            supplier_note(app),
+            stale_note(app),
+            format_age(next_in)
+        ),
+        (None, true) => format!("{} · retry in {}", error, format_age(next_in)),
+        (None, false) => String::new(),
+    }
+}
+
+// Scale samples to 0.0..=1.0 by their own min and max. A flat series
+// sits in the middle instead of dividing by zero
+fn normalize(samples: &[f64]) -> Vec<f64> {
+    let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    samples
+        .iter()
+        .map(|sample| {
+            if range > 0.0 {
+                (sample - min) / range
+            } else {
+                0.5
+            }
+        })
+        .collect()
+}
+
+const SPARKLINE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// The last `width` samples drawn with block characters
+fn sparkline(samples: &VecDeque<f64>, width: usize) -> String {
+    let skip = samples.len().saturating_sub(width);
+    let recent: Vec<f64> = samples.iter().skip(skip).copied().collect();
+    normalize(&recent)
+        .iter()
+        .map(|level| {
+            let bar = (level * (SPARKLINE_BARS.len() - 1) as f64).round() as usize;
+            SPARKLINE_BARS[bar]
+        })
+        .collect()
+}
+
+// A column of the market table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Rank,
+    Symbol,
+    Name,
+    Price,
+    // Highest and lowest price this session
+    SessionHigh,
+    SessionLow,
+    Change(ChangeHorizon),
+    MarketCap,
+    Volume,
+    Trend,
+}
+
+impl Column {
+    fn constraint(self) -> Constraint {
+        match self {
+            Column::Rank => Constraint::Length(4),
+            Column::Symbol => Constraint::Length(8),
+            Column::Name => Constraint::Min(NAME_MIN_WIDTH),
+            Column::Price => Constraint::Length(16),
+            Column::SessionHigh | Column::SessionLow => Constraint::Length(14),
+            Column::Change(_) | Column::MarketCap | Column::Volume => Constraint::Length(9),
+            // Gives way first on narrow terminals
+            Column::Trend => Constraint::Max(20),
+        }
+    }
+
+    // The least the column is still readable in
+    fn min_width(self) -> u16 {
+        match self.constraint() {
+            Constraint::Length(width) | Constraint::Min(width) => width,
+            _ => TREND_MIN_WIDTH,
+        }
+    }
+}
+
+const NAME_MIN_WIDTH: u16 = 10;
+const TREND_MIN_WIDTH: u16 = 8;
+
+// Which terminal widths a column is shown at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Breakpoint {
+    Narrow,
+    Medium,
+    Wide,
+}
+
+// The change columns that stay longest, the rest only come with the wide
+// layout
+const CHANGE_PRIORITY: [ChangeHorizon; 4] = [
+    ChangeHorizon::Day,
+    ChangeHorizon::Week,
+    ChangeHorizon::Hour,
+    ChangeHorizon::Month,
+];
+
+// Symbol, price and the first change column always, the name and a
+// second change column from the medium width on
+fn breakpoint(column: Column, columns: &[Column]) -> Breakpoint {
+    match column {
+        Column::Symbol | Column::Price => Breakpoint::Narrow,
+        Column::Name => Breakpoint::Medium,
+        Column::Change(horizon) => {
+            let rank = CHANGE_PRIORITY
+                .iter()
+                .filter(|preferred| columns.contains(&Column::Change(**preferred)))
+                .position(|preferred| *preferred == horizon);
+            match rank {
+                Some(0) => Breakpoint::Narrow,
+                Some(1) => Breakpoint::Medium,
+                _ => Breakpoint::Wide,
+            }
+        }
+        _ => Breakpoint::Wide,
+    }
+}
+
+// Columns at their least, the spacing between them, the borders and the
+// selection symbol
+fn required_width(columns: &[Column]) -> u16 {
+    let widths: u16 = columns.iter().map(|column| column.min_width()).sum();
+    let spacing = columns.len().saturating_sub(1) as u16;
+    widths + spacing + 2 + HIGHLIGHT_SYMBOL.len() as u16
+}
+
+// Out of the configured `columns`, the ones a table `width` wide has room
+// for. Drawn with every frame, so a resized terminal adapts right away
+pub fn visible_columns(width: u16, columns: &[Column]) -> Vec<Column> {
+    for shown in [Breakpoint::Wide, Breakpoint::Medium, Breakpoint::Narrow] {
+        let visible: Vec<Column> = columns
+            .iter()
+            .copied()
+            .filter(|column| breakpoint(*column, columns) <= shown)
+            .collect();
+        if shown == Breakpoint::Narrow || required_width(&visible) <= width {
+            return visible;
+        }
+    }
+    unreachable!("the narrow layout is always returned")
+}
+
+// Rank, symbol, name and price. The session range, change, market cap and
+// volume columns follow when they are shown, then the trend
+fn configured_columns(app: &App) -> Vec<Column> {
+    let mut columns = vec![Column::Rank, Column::Symbol, Column::Name, Column::Price];
+    if app.config.show_session_range {
+        columns.extend([Column::SessionHigh, Column::SessionLow]);
+    }
+    columns.extend(app.change_columns().into_iter().map(Column::Change));
+    if app.config.show_market_cap {
+        columns.push(Column::MarketCap);
+    }
+    if app.config.show_volume {
+        columns.push(Column::Volume);
+    }
+    columns.push(Column::Trend);
+    columns
+}
+
+const HIGHLIGHT_SYMBOL: &str = "> ";
+
+// Gains and losses in the theme's colors. The change is always printed with
+// its sign so the direction is still readable without colors
+fn change_style(theme: &Theme, coin: &Coin) -> Style {
+    theme.change_style(coin.price_change_24h)
+}
+
+// Gray instead of the price colors while the prices are stale
+fn price_style(app: &App, theme: &Theme) -> Style {
+    match app.stale() {
+        true => theme.fg(theme.muted),
+        false => Style::default(),
+    }
+}
+
+fn change_cell_style(app: &App, theme: &Theme, change: f64) -> Style {
+    match app.stale() {
+        true => theme.fg(theme.muted),
+        false => theme.change_style(change),
+    }
+}
+
+// Column title, with an arrow when the table is sorted by that column
+fn column_title(title: &str, column_key: SortKey, app: &App) -> String {
+    if app.sort_key == column_key {
+        format!("{} {}", title, app.sort_dir.arrow())
+    } else {
+        title.to_string()
+    }
+}
+
+// A box around a view in the border color of the theme
+fn bordered<'a>(theme: &Theme) -> Block<'a> {
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(theme.border_style())
+}
+
+pub fn coin_table<'a>(app: &App, coins: &[&Coin], area: Rect) -> Table<'a> {
+    let theme = &app.theme();
+    let currency = app.display_currency();
+    let locale = app.config.number_locale();
+    // Work out how wide the name column ends up so long names can be cut short
+    let block = bordered(theme);
+    let mut inner = block.inner(area);
+    inner.width = inner.width.saturating_sub(HIGHLIGHT_SYMBOL.len() as u16);
+    let shown = visible_columns(area.width, &configured_columns(app));
+    let widths: Vec<Constraint> = shown.iter().map(|column| column.constraint()).collect();
+    let areas = Layout::horizontal(&widths).spacing(1).split(inner);
+    let width_of = |wanted: Column| {
+        let index = shown.iter().position(|column| *column == wanted);
+        index.map(|index| areas[index].width as usize).unwrap_or(0)
+    };
+    let (name_width, trend_width) = (width_of(Column::Name), width_of(Column::Trend));
+
+    // The denomination is easy to miss in the header line alone
+    let price_title = match currency == BTC {
+        true => "PRICE (BTC)",
+        false => "PRICE",
+    };
+    let right_title = |title: &str, key: SortKey| {
+        Cell::from(Line::from(column_title(title, key, app)).right_aligned())
+    };
+    let header = shown.iter().map(|column| match column {
+        Column::Rank => right_title("#", SortKey::Rank),
+        Column::Symbol => Cell::from("SYMBOL"),
+        Column::Name => Cell::from(column_title("NAME", SortKey::Name, app)),
+        Column::Price => right_title(price_title, SortKey::Price),
+        Column::SessionHigh => Cell::from(Line::from("HI").right_aligned()),
+        Column::SessionLow => Cell::from(Line::from("LO").right_aligned()),
+        Column::Change(horizon) => right_title(horizon.title(), SortKey::Change(*horizon)),
+        Column::MarketCap => right_title("MCAP", SortKey::MarketCap),
+        Column::Volume => right_title("VOL 24H", SortKey::Volume),
+        Column::Trend => Cell::from("TREND"),
+    });
+    let header = Row::new(header).style(theme.header_style());
+    // Market caps and volumes are only fetched in the configured currency
+    let compact_cell = |value: Option<f64>| {
+        right_cell(match value {
+            Some(value) => format_compact(value, &app.config.currency, locale),
+            None => MISSING_VALUE.to_string(),
+        })
+    };
+
+    let rows = coins.iter().map(|coin| {
+        let (row_style, symbol) = alert_marks(app, theme, coin);
+        let cells =
+            shown.iter().map(|column| match column {
+                Column::Rank => right_cell(match coin.market_cap_rank {
+                    Some(rank) => rank.to_string(),
+                    None => MISSING_VALUE.to_string(),
+                }),
+                Column::Symbol => Cell::from(symbol.clone()),
+                Column::Name => Cell::from(truncate(&coin.name, name_width)),
+                Column::Price => right_cell(price_text(app, coin)).style(price_style(app, theme)),
+                Column::SessionHigh => right_cell(session_price(app, coin, Extreme::High))
+                    .style(price_style(app, theme)),
+                Column::SessionLow => right_cell(session_price(app, coin, Extreme::Low))
+                    .style(price_style(app, theme)),
+                Column::Change(horizon) => match app.displayed_change(coin, *horizon) {
+                    Some(change) => right_cell(format!("{:+.2}%", change))
+                        .style(change_cell_style(app, theme, change)),
+                    None => right_cell(MISSING_VALUE.to_string()),
+                },
+                Column::MarketCap => compact_cell(coin.market_cap),
+                Column::Volume => compact_cell(coin.total_volume),
+                Column::Trend => Cell::from(trend_text(app, coin, trend_width)),
+            });
+        Row::new(cells.collect::<Vec<_>>()).style(row_style)
+    });
+
+    market_table(rows, widths, header, block, theme)
+}
+
+// Coins with an alert that just fired stand out until it is dismissed, with
+// a "!" before the symbol when there are no colors
+fn alert_marks(app: &App, theme: &Theme, coin: &Coin) -> (Style, String) {
+    let alerted = app.alert_sinks.banner.coin_ids.contains(&coin.id);
+    match (alerted, theme.monochrome) {
+        (true, true) => (Style::default(), format!("!{}", coin.symbol)),
+        (true, false) => (theme.alert_row_style(), coin.symbol.clone()),
+        (false, _) => (Style::default(), coin.symbol.clone()),
+    }
+}
+
+// With an arrow when the latest refresh set a new session high or low
+fn price_text(app: &App, coin: &Coin) -> String {
+    let locale = app.config.number_locale();
+    let price = match app.displayed_quote(coin) {
+        Some(quote) => match app.display_currency() == BTC {
+            true => format_btc_amount(quote.price, app.config.prefer_sats, locale),
+            false => format_price(quote.price, &app.display_currency(), locale),
+        },
+        None => return MISSING_VALUE.to_string(),
+    };
+    match app.history.range(&coin.id).and_then(|range| range.latest) {
+        Some(extreme) => format!("{} {}", extreme.arrow(), price),
+        None => price,
+    }
+}
+
+// The session is only tracked in the configured currency
+fn session_price(app: &App, coin: &Coin, extreme: Extreme) -> String {
+    let range = app.history.range(&coin.id);
+    match (range, app.display_currency() == app.config.currency) {
+        (Some(range), true) => {
+            let price = match extreme {
+                Extreme::High => range.high,
+                Extreme::Low => range.low,
+            };
+            format_price(price, &app.config.currency, app.config.number_locale())
+        }
+        _ => MISSING_VALUE.to_string(),
+    }
+}
+
+fn trend_text(app: &App, coin: &Coin, width: usize) -> String {
+    app.history
+        .get(&coin.id)
+        .map(|samples| sparkline(samples, width))
+        .unwrap_or_default()
+}
+
+fn market_table<'a>(
+    rows: impl IntoIterator<Item = Row<'a>>,
+    widths: Vec<Constraint>,
+    header: Row<'a>,
+    block: Block<'a>,
+    theme: &Theme,
+) -> Table<'a> {
+    Table::new(rows, widths)
+        .header(header)
+        .column_spacing(1)
+        .block(block)
+        .row_highlight_style(theme.selection_style())
+        .highlight_symbol(HIGHLIGHT_SYMBOL)
+        // Keep the columns from shifting when the selection appears
+        .highlight_spacing(HighlightSpacing::Always)
+}
+
+// The coin, price, 24h and 7d change and trend of the two line layout
+const DETAILED_COLUMN_WIDTHS: [Constraint; 4] = [
+    Constraint::Min(12),
+    Constraint::Length(16),
+    Constraint::Length(11),
+    Constraint::Max(20),
+];
+
+// Two lines per coin, the symbol above the name and the 24h change above
+// the 7d one
+pub fn detailed_table<'a>(app: &App, coins: &[&Coin], area: Rect) -> Table<'a> {
+    let theme = &app.theme();
+    let block = bordered(theme);
+    let mut inner = block.inner(area);
+    inner.width = inner.width.saturating_sub(HIGHLIGHT_SYMBOL.len() as u16);
+    let areas = Layout::horizontal(DETAILED_COLUMN_WIDTHS)
+        .spacing(1)
+        .split(inner);
+    let (name_width, trend_width) = (areas[0].width as usize, areas[3].width as usize);
+
+    let price_title = match app.display_currency() == BTC {
+        true => "PRICE (BTC)",
+        false => "PRICE",
+    };
+    let header = Row::new(vec![
+        Cell::from(column_title("COIN", SortKey::Name, app)),
+        Cell::from(Line::from(column_title(price_title, SortKey::Price, app)).right_aligned()),
+        Cell::from(Line::from("24H/7D").right_aligned()),
+        Cell::from("TREND"),
+    ])
+    .style(theme.header_style());
+
+    let change_line = |coin: &Coin, horizon: ChangeHorizon| {
+        let line = match app.displayed_change(coin, horizon) {
+            Some(change) => Line::styled(
+                format!("{:+.2}%", change),
+                change_cell_style(app, theme, change),
+            ),
+            None => Line::from(MISSING_VALUE),
+        };
+        line.right_aligned()
+    };
+    let rows = coins.iter().map(|coin| {
+        let (row_style, symbol) = alert_marks(app, theme, coin);
+        let name = Line::styled(truncate(&coin.name, name_width), theme.fg(theme.muted));
+        Row::new(vec![
+            Cell::from(vec![Line::styled(symbol, theme.bold()), name]),
+            right_cell(price_text(app, coin)).style(price_style(app, theme)),
+            Cell::from(vec![
+                change_line(coin, ChangeHorizon::Day),
+                change_line(coin, ChangeHorizon::Week),
+            ]),
+            Cell::from(trend_text(app, coin, trend_width)),
+        ])
+        .height(RowLayout::Detailed.row_height())
+        .style(row_style)
+    });
+
+    market_table(rows, DETAILED_COLUMN_WIDTHS.to_vec(), header, block, theme)
+}
+
+// Symbol, quantity, price, value, 24h change of the value and profit/loss
+const PORTFOLIO_COLUMN_WIDTHS: [Constraint; 7] = [
+    Constraint::Min(8),
+    Constraint::Length(12),
+    Constraint::Length(14),
+    Constraint::Length(14),
+    Constraint::Length(13),
+    Constraint::Length(13),
+    Constraint::Length(8),
+];
+
+// Shown instead of a value when the coin has no fetched price
+const MISSING_VALUE: &str = "—";
+// Shown in the P&L columns of positions without a cost basis
+const NO_COST_BASIS: &str = "n/a";
+
+// e.g. " · Total: $2.31T (+1.4%) · BTC.D 52.3%", nothing while the /global
+// figures are missing. The dominance is colored by its move since the last fetch
+fn global_spans<'a>(app: &App, currency: &str) -> Vec<Span<'a>> {
+    let Some(global) = &app.global else {
+        return Vec::new();
+    };
+    let theme = &app.theme();
+    let mut spans = Vec::new();
+    if let Some(total) = global.total_market_cap.get(currency) {
+        let change = global.market_cap_change_24h;
+        spans.push(Span::raw(format!(
+            " · Total: {} ",
+            format_compact_with(*total, currency, 2, app.config.number_locale())
+        )));
+        spans.push(Span::styled(
+            format!("({:+.1}%)", change),
+            theme.change_style(change),
+        ));
+    }
+    if let Some(dominance) = global.btc_dominance() {
+        spans.push(Span::raw(" · "));
+        spans.push(Span::styled(
+            format!("BTC.D {:.1}%", dominance),
+            theme.change_style(app.dominance_change.unwrap_or(0.0)),
+        ));
+    }
+    spans
+}
+
+// e.g. " 1 Market │ 2 Portfolio │ 3 Alerts │ 4 Trending ", the number
+// switches to the tab
+fn tab_bar<'a>(app: &App) -> Tabs<'a> {
+    let theme = app.theme();
+    let titles = Tab::ALL.iter().enumerate().map(|(index, tab)| {
+        let title = format!("{} {}", index + 1, tab.title());
+        // Without reverse video the open tab needs a mark of its own
+        match theme.monochrome && *tab == app.tab() {
+            true => format!("[{}]", title),
+            false => title,
+        }
+    });
+    Tabs::new(titles)
+        .select(app.tab().index())
+        .highlight_style(theme.selection_style())
+}
+
+// Red for extreme fear through yellow to green for extreme greed
+fn sentiment_color(theme: &Theme, sentiment: Sentiment) -> Color {
+    match sentiment {
+        Sentiment::ExtremeFear => theme.loss,
+        Sentiment::Fear => theme.fear,
+        Sentiment::Neutral => theme.warning,
+        Sentiment::Greed => theme.greed,
+        Sentiment::ExtremeGreed => theme.gain,
+    }
+}
+
+// e.g. "F&G 73 Greed"
+fn fear_greed_span<'a>(theme: &Theme, value: u8) -> Span<'a> {
+    let sentiment = Sentiment::of(value);
+    Span::styled(
+        format!("F&G {} {}", value, sentiment.label()),
+        theme.fg(sentiment_color(theme, sentiment)),
+    )
+}
+
+fn right_cell<'a>(text: String) -> Cell<'a> {
+    Cell::from(Line::from(text).right_aligned())
+}
+
+fn change_cell<'a>(
+    theme: &Theme,
+    change: Option<f64>,
+    currency: &str,
+    locale: NumberLocale,
+) -> Cell<'a> {
+    match change {
+        Some(change) => right_cell(format_price_change(change, currency, locale))
+            .style(theme.change_style(change)),
+        None => right_cell(MISSING_VALUE.to_string()),
+    }
+}
+
+// P&L needs a cost basis, and a price on top of that
+fn pnl_cells<'a>(
+    theme: &Theme,
+    pnl: Option<f64>,
+    percent: Option<f64>,
+    has_cost_basis: bool,
+    currency: &str,
+    locale: NumberLocale,
+) -> [Cell<'a>; 2] {
+    if !has_cost_basis {
+        return [
+            right_cell(NO_COST_BASIS.to_string()),
+            right_cell(NO_COST_BASIS.to_string()),
+        ];
+    }
+    let style = theme.change_style(pnl.unwrap_or(0.0));
+    let percent = match percent {
+        Some(percent) => format!("{:+.2}%", percent),
+        None => MISSING_VALUE.to_string(),
+    };
+    [
+        change_cell(theme, pnl, currency, locale),
+        right_cell(percent).style(style),
+    ]
+}
+
+fn portfolio_view(frame: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme();
+    let block = bordered(theme).title("Portfolio");
+    if app.portfolio.holdings.is_empty() {
+        let empty_state =
+            Paragraph::new("No holdings — add them to ~/.config/tuicker/portfolio.toml")
+                .block(block);
+        frame.render_widget(empty_state, area);
+        return;
+    }
+    let currency = &app.config.currency;
+    let locale = app.config.number_locale();
+    let price = |value: Option<f64>| match value {
+        Some(value) => format_price(value, currency, locale),
+        None => MISSING_VALUE.to_string(),
+    };
+    let positions = app.portfolio.positions(&app.coins);
+    let totals = totals(&positions);
+
+    let header = Row::new(vec![
+        Cell::from("SYMBOL"),
+        right_cell("QUANTITY".to_string()),
+        right_cell("PRICE".to_string()),
+        right_cell("VALUE".to_string()),
+        right_cell("24H".to_string()),
+        right_cell("P&L".to_string()),
+        right_cell("P&L %".to_string()),
+    ])
+    .style(theme.header_style());
+    let mut rows: Vec<Row> = positions
+        .iter()
+        .map(|position| {
+            let symbol = match position.coin {
+                Some(coin) => coin.symbol.clone(),
+                None => position.coin_id.to_string(),
+            };
+            let [pnl, pnl_percent] = pnl_cells(
+                theme,
+                position.pnl,
+                position.pnl_percent(),
+                position.cost.is_some(),
+                currency,
+                locale,
+            );
+            Row::new(vec![
+                Cell::from(symbol),
+                right_cell(position.quantity.to_string()),
+                right_cell(price(position.coin.map(|coin| coin.current_price))),
+                right_cell(price(position.value)),
+                change_cell(theme, position.change_24h, currency, locale),
+                pnl,
+                pnl_percent,
+            ])
+        })
+        .collect();
+    // Holdings without a price are left out of the total, so say so
+    let total_title = if totals.partial {
+        "TOTAL (partial)"
+    } else {
+        "TOTAL"
+    };
+    let [pnl, pnl_percent] = pnl_cells(
+        theme,
+        totals.pnl,
+        totals.pnl_percent(),
+        totals.pnl.is_some(),
+        currency,
+        locale,
+    );
+    rows.push(
+        Row::new(vec![
+            Cell::from(total_title),
+            Cell::from(""),
+            Cell::from(""),
+            right_cell(format_price(totals.value, currency, locale)),
+            change_cell(theme, Some(totals.change_24h), currency, locale),
+            pnl,
+            pnl_percent,
+        ])
+        .style(theme.bold()),
+    );
+
+    // Holdings, the header and the total row, plus the borders
+    let table_height = positions.len() as u16 + 4;
+    let [table_area, allocation_area] =
+        Layout::vertical([Constraint::Length(table_height), Constraint::Min(0)]).areas(area);
+    let table = Table::new(rows, PORTFOLIO_COLUMN_WIDTHS)
+        .header(header)
+        .column_spacing(1)
+        .block(block);
+    frame.render_widget(table, table_area);
+    allocation_view(frame, app, allocation_area);
+}
+
+// Each position's share of the total value as a bar
+fn allocation_view(frame: &mut Frame, app: &App, area: Rect) {
+    if area.height < 3 {
+        return;
+    }
+    let theme = &app.theme();
+    let block = bordered(theme).title("Allocation");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let allocation = allocation(&app.portfolio.positions(&app.coins));
+    if allocation.is_empty() {
+        frame.render_widget(Paragraph::new("No prices yet"), inner);
+        return;
+    }
+    let rows = Layout::vertical(vec![Constraint::Length(1); allocation.len()]).split(inner);
+    for (entry, row) in allocation.iter().zip(rows.iter()) {
+        let gauge = LineGauge::default()
+            .ratio(entry.weight.clamp(0.0, 1.0))
+            .label(format!("{:8} {:>6.2}%", entry.label, entry.weight * 100.0))
+            .filled_style(theme.fg(theme.accent));
+        frame.render_widget(gauge, *row);
+    }
+}
+
+// Place in the trending list, symbol, name, market cap rank and whether the
+// coin is in the watchlist
+const TRENDING_COLUMN_WIDTHS: [Constraint; 5] = [
+    Constraint::Length(3),
+    Constraint::Length(10),
+    Constraint::Min(10),
+    Constraint::Length(6),
+    Constraint::Length(8),
+];
+
+fn trending_view(frame: &mut Frame, app: &App, area: Rect) {
+    // Only this tab has to know when its own fetch failed
+    let title = match &app.trending_error {
+        Some(error) => format!("Trending · could not refresh: {}", error),
+        None => "Trending".to_string(),
+    };
+    let theme = &app.theme();
+    let block = bordered(theme).title(title);
+    if app.trending.is_empty() {
+        let text = match app.trending_error.is_some() {
+            true => "No trending coins, trying again in a few minutes",
+            false => "Loading trending coins…",
+        };
+        frame.render_widget(Paragraph::new(text).block(block), area);
+        return;
+    }
+    let header = Row::new(vec![
+        right_cell("#".to_string()),
+        Cell::from("SYMBOL"),
+        Cell::from("NAME"),
+        right_cell("RANK".to_string()),
+        Cell::from(""),
+    ])
+    .style(theme.header_style());
+    let rows = app.trending.iter().enumerate().map(|(index, coin)| {
+        let tracked = match app.config.coins.contains(&coin.id) {
+            true => "tracked",
+            false => "",
+        };
+        Row::new(vec![
+            right_cell((index + 1).to_string()),
+            Cell::from(coin.symbol.clone()),
+            Cell::from(coin.name.clone()),
+            right_cell(match coin.market_cap_rank {
+                Some(rank) => format!("#{}", rank),
+                None => MISSING_VALUE.to_string(),
+            }),
+            Cell::from(tracked).style(theme.fg(theme.muted)),
+        ])
+    });
+    let table = Table::new(rows, TRENDING_COLUMN_WIDTHS)
+        .header(header)
+        .column_spacing(1)
+        .block(block)
+        .row_highlight_style(theme.selection_style())
+        .highlight_symbol(HIGHLIGHT_SYMBOL)
+        .highlight_spacing(HighlightSpacing::Always);
+    let mut table_state = TableState::default().with_selected(Some(app.selected_trending));
+    frame.render_stateful_widget(table, area, &mut table_state);
+    record_table(app, area, &table_state, 1);
+}
+
+// Coin, condition, threshold, current price and state
+const ALERT_COLUMN_WIDTHS: [Constraint; 5] = [
+    Constraint::Length(10),
+    Constraint::Length(9),
+    Constraint::Length(16),
+    Constraint::Length(16),
+    Constraint::Min(9),
+];
+
+fn alerts_view(frame: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme();
+    let block = bordered(theme).title("Alerts");
+    if app.config.alerts.is_empty() {
+        let empty_state = Paragraph::new("No alerts — press 'a' to add one").block(block);
+        frame.render_widget(empty_state, area);
+        return;
+    }
+    let currency = &app.config.currency;
+    let locale = app.config.number_locale();
+    let header = Row::new(vec![
+        Cell::from("COIN"),
+        Cell::from("CONDITION"),
+        right_cell("THRESHOLD".to_string()),
+        right_cell("PRICE".to_string()),
+        Cell::from("STATE"),
+    ])
+    .style(theme.header_style());
+    let rows = app.config.alerts.iter().map(|rule| {
+        let coin = app.coins.iter().find(|coin| coin.id == rule.coin_id);
+        let (symbol, price) = match coin {
+            Some(coin) => (
+                coin.symbol.clone(),
+                format_price(coin.current_price, currency, locale),
+            ),
+            None => (rule.coin_id.clone(), MISSING_VALUE.to_string()),
+        };
+        let state = if app.alert_state.is_triggered(rule) {
+            Cell::from("triggered").style(theme.fg(theme.warning))
+        } else {
+            Cell::from("armed")
+        };
+        Row::new(vec![
+            Cell::from(symbol),
+            Cell::from(rule.direction.as_str()),
+            right_cell(format_price(rule.price, currency, locale)),
+            right_cell(price),
+            state,
+        ])
+    });
+    let table = Table::new(rows, ALERT_COLUMN_WIDTHS)
+        .header(header)
+        .column_spacing(1)
+        .block(block)
+        .row_highlight_style(theme.selection_style())
+        .highlight_symbol(HIGHLIGHT_SYMBOL)
+        .highlight_spacing(HighlightSpacing::Always);
+    let mut table_state = TableState::default().with_selected(Some(app.selected_alert));
+    frame.render_stateful_widget(table, area, &mut table_state);
+    record_table(app, area, &table_state, 1);
+}
+
+// The step of the add alert prompt the user is on, with the cursor after
+// the typed text
+fn alert_prompt(frame: &mut Frame, app: &App, area: Rect) {
+    let (prompt, hint) = match &app.alert_prompt {
+        AlertPrompt::Coin => (
+            "alert on coin: ".to_string(),
+            "(↑↓: pick, Enter: next, Esc: cancel)",
+        ),
+        AlertPrompt::Direction(coin_id) => (
+            format!("alert when {} goes ", coin_id),
+            "a: above  b: below  Esc: cancel",
+        ),
+        AlertPrompt::Price(coin_id, direction) => (
+            format!("alert when {} goes {} ", coin_id, direction.as_str()),
+            "(Enter: add, Esc: cancel)",
+        ),
+    };
+    let input = match app.alert_prompt {
+        AlertPrompt::Direction(_) => "",
+        _ => app.add_input.as_str(),
+    };
+    let text = format!("{}{}", prompt, input);
+    let cursor_x = area.x + text.chars().count() as u16;
+    let text = format!("{}  {}", text, app.add_message.as_deref().unwrap_or(hint));
+    frame.render_widget(Paragraph::new(text), area);
+    frame.set_cursor_position((cursor_x.min(area.right()), area.y));
+}
+
+// Axis labels like 65.3k or 1.2M so they fit next to the chart
+fn format_axis_price(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude >= 1e9 {
+        format!("{:.1}B", value / 1e9)
+    } else if magnitude >= 1e6 {
+        format!("{:.1}M", value / 1e6)
+    } else if magnitude >= 1e3 {
+        format!("{:.1}k", value / 1e3)
+    } else if magnitude >= 1.0 {
+        format!("{:.2}", value)
+    } else {
+        format!("{:.4}", value)
+    }
+}
+
+// Labels of the log axis are round powers of ten, so 10k instead of 10.0k
+fn log_axis_label(value: f64) -> String {
+    let label = format_axis_price(value);
+    let digits = label.trim_end_matches(['k', 'M', 'B']);
+    let suffix = &label[digits.len()..];
+    match digits.contains('.') {
+        true => format!(
+            "{}{}",
+            digits.trim_end_matches('0').trim_end_matches('.'),
+            suffix
+        ),
+        false => label,
+    }
+}
+
+// No more log labels than this, skipping decades on wide ranges
+const MAX_LOG_LABELS: usize = 5;
+
+// Bounds in log10 from the power of ten below `min` to the one above `max`,
+// and the prices labeled along it. The labels are spread evenly like the
+// axis draws them, a decade or a few apart, or half one on a single decade
+pub fn log_axis(min: f64, max: f64) -> ([f64; 2], Vec<f64>) {
+    let low = min.log10().floor();
+    let decades = ((max.log10().ceil() - low) as usize).max(1);
+    if decades == 1 {
+        let labels = [0.0, 0.5, 1.0].map(|step| 10f64.powf(low + step));
+        return ([low, low + 1.0], labels.to_vec());
+    }
+    let step = decades.div_ceil(MAX_LOG_LABELS - 1);
+    let count = decades.div_ceil(step);
+    let labels = (0..=count)
+        .map(|index| 10f64.powf(low + (index * step) as f64))
+        .collect();
+    ([low, low + (count * step) as f64], labels)
+}
+
+// Prices as log10 for the log axis, leaving out any at or below zero
+fn log_points(prices: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    prices
+        .iter()
+        .filter(|(_, price)| *price > 0.0 && price.is_finite())
+        .map(|(time, price)| (*time, price.log10()))
+        .collect()
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// A point on the time axis in UTC, the hour like 14:05 for a day, the date
+// like Mar 9 up to 90 days and the month like Mar 2024 for a year
+fn time_label(timestamp: f64, range: ChartRange) -> String {
+    let time = UNIX_EPOCH + Duration::from_millis(timestamp.max(0.0) as u64);
+    // 2024-03-09T14:05:00Z
+    let text = rfc3339(time);
+    let month = text[5..7].parse::<usize>().unwrap_or(1);
+    let month = MONTHS[month.clamp(1, 12) - 1];
+    match range {
+        ChartRange::Day => text[11..16].to_string(),
+        ChartRange::Year => format!("{} {}", month, &text[..4]),
+        _ => format!("{} {}", month, text[8..10].trim_start_matches('0')),
+    }
+}
+
+fn chart_title(range: ChartRange) -> String {
+    match range {
+        ChartRange::Day => "24 hours, UTC".to_string(),
+        ChartRange::Year => "1 year, UTC".to_string(),
+        _ => format!("{} days, UTC", range.days()),
+    }
+}
+
+// Draws `prices` as they are, or as log10 of the prices with `log_scale`
+fn price_chart<'a>(
+    theme: &Theme,
+    prices: &'a [(f64, f64)],
+    range: ChartRange,
+    style: Style,
+    log_scale: bool,
+) -> Chart<'a> {
+    let first_time = prices.first().map(|(time, _)| *time).unwrap_or(0.0);
+    let last_time = prices.last().map(|(time, _)| *time).unwrap_or(0.0);
+    let mut min_price = prices
+        .iter()
+        .map(|(_, price)| *price)
+        .fold(f64::INFINITY, f64::min);
+    let mut max_price = prices
+        .iter()
+        .map(|(_, price)| *price)
+        .fold(f64::NEG_INFINITY, f64::max);
+    if min_price == max_price && !log_scale {
+        // Give a flat line some room instead of an empty axis range
+        min_price *= 0.99;
+        max_price *= 1.01;
+    }
+
+    let x_labels = vec![
+        Span::from(time_label(first_time, range)),
+        Span::from(time_label((first_time + last_time) / 2.0, range)),
+        Span::from(time_label(last_time, range)),
+    ];
+    let (y_bounds, y_labels) = match log_scale {
+        true => {
+            let (bounds, labels) = log_axis(10f64.powf(min_price), 10f64.powf(max_price));
+            let labels = labels.into_iter().map(log_axis_label).map(Span::from);
+            (bounds, labels.collect())
+        }
+        false => (
+            [min_price, max_price],
+            vec![
+                Span::from(format_axis_price(min_price)),
+                Span::from(format_axis_price((min_price + max_price) / 2.0)),
+                Span::from(format_axis_price(max_price)),
+            ],
+        ),
+    };
+    let y_axis = Axis::default().bounds(y_bounds).labels(y_labels);
+    let y_axis = match log_scale {
+        true => y_axis.title("log"),
+        false => y_axis,
+    };
+
+    let dataset = Dataset::default()
+        .marker(symbols::Marker::Braille)
+        .graph_type(GraphType::Line)
+        .style(style)
+        .data(prices);
+    Chart::new(vec![dataset])
+        .block(bordered(theme).title(chart_title(range)))
+        .x_axis(
+            Axis::default()
+                .bounds([first_time, last_time])
+                .labels(x_labels),
+        )
+        .y_axis(y_axis)
+}
+
+// Merges neighbouring candles so there are no more than `slots`, each
+// opening at the first and closing at the last it was made of
+pub fn bucket_candles(candles: &[Candle], slots: usize) -> Vec<Candle> {
+    if slots == 0 {
+        return Vec::new();
+    }
+    if candles.len() <= slots {
+        return candles.to_vec();
+    }
+    let size = candles.len().div_ceil(slots);
+    candles
+        .chunks(size)
+        .map(|chunk| Candle {
+            time: chunk[0].time,
+            open: chunk[0].open,
+            high: chunk
+                .iter()
+                .map(|c| c.high)
+                .fold(f64::NEG_INFINITY, f64::max),
+            low: chunk.iter().map(|c| c.low).fold(f64::INFINITY, f64::min),
+            close: chunk[chunk.len() - 1].close,
+        })
+        .collect()
+}
+
+// A candle per column with a column of gap, the wick from the high to the
+// low and the body from the open to the close. Labeled like the line chart
+fn candle_chart(
+    frame: &mut Frame,
+    theme: &Theme,
+    candles: &[Candle],
+    range: ChartRange,
+    area: Rect,
+) {
+    let block = bordered(theme).title(chart_title(range));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let mut low = candles.iter().map(|c| c.low).fold(f64::INFINITY, f64::min);
+    let mut high = candles
+        .iter()
+        .map(|c| c.high)
+        .fold(f64::NEG_INFINITY, f64::max);
+    if low == high {
+        low *= 0.99;
+        high *= 1.01;
+    }
+    let y_labels = [
+        format_axis_price(high),
+        format_axis_price((low + high) / 2.0),
+        format_axis_price(low),
+    ];
+    let label_width = y_labels.iter().map(String::len).max().unwrap_or(0) as u16;
+    // Room for the prices on the left and the times below
+    let plot = Rect {
+        x: inner.x + label_width + 1,
+        width: inner.width.saturating_sub(label_width + 1),
+        height: inner.height.saturating_sub(1),
+        ..inner
+    };
+    if plot.is_empty() {
+        return;
+    }
+    let first_time = candles.first().map(|c| c.time).unwrap_or(0.0);
+    let last_time = candles.last().map(|c| c.time).unwrap_or(0.0);
+    let shown = bucket_candles(candles, (plot.width as usize).div_ceil(2));
+    let slot = plot.width / shown.len() as u16;
+    let row_of = |price: f64| {
+        let share = (high - price) / (high - low);
+        plot.y + (share * (plot.height - 1) as f64).round() as u16
+    };
+
+    let buffer = frame.buffer_mut();
+    for (index, candle) in shown.iter().enumerate() {
+        let x = plot.x + index as u16 * slot + (slot - 1) / 2;
+        // Without colors the body tells up from down
+        let (style, body) = match (candle.is_up(), theme.monochrome) {
+            (true, false) => (theme.fg(theme.gain), '█'),
+            (false, false) => (theme.fg(theme.loss), '█'),
+            (true, true) => (Style::default(), '+'),
+            (false, true) => (Style::default(), '-'),
+        };
+        let body_rows =
+            row_of(candle.open.max(candle.close))..=row_of(candle.open.min(candle.close));
+        for y in row_of(candle.high)..=row_of(candle.low) {
+            let glyph = match body_rows.contains(&y) {
+                true => body,
+                false => '│',
+            };
+            buffer[(x, y)].set_char(glyph).set_style(style);
+        }
+    }
+    let label_rows = [plot.y, plot.y + (plot.height - 1) / 2, plot.bottom() - 1];
+    for (label, y) in y_labels.iter().zip(label_rows) {
+        let x = inner.x + label_width - label.len() as u16;
+        buffer.set_string(x, y, label, Style::default());
+    }
+    let oldest = time_label(first_time, range);
+    let middle = time_label((first_time + last_time) / 2.0, range);
+    let newest = time_label(last_time, range);
+    let y = plot.bottom();
+    buffer.set_string(plot.x, y, &oldest, Style::default());
+    let middle_x = plot.x + (plot.width.saturating_sub(middle.len() as u16)) / 2;
+    buffer.set_string(middle_x, y, &middle, Style::default());
+    let newest_x = plot.right().saturating_sub(newest.len() as u16);
+    buffer.set_string(newest_x, y, &newest, Style::default());
+}
+
+// What is drawn while a chart loads, failed or came back empty
+fn chart_placeholder<T>(frame: &mut Frame, app: &App, data: Option<&ChartData<T>>, area: Rect) {
+    let theme = &app.theme();
+    match data {
+        Some(ChartData::Loaded(_)) => {
+            let empty = Paragraph::new("No price history available").block(bordered(theme));
+            frame.render_widget(empty, area);
+        }
+        Some(ChartData::Failed(error)) => {
+            let error_panel = Paragraph::new(format!("Could not load the chart: {}", error))
+                .style(theme.fg(theme.error))
+                .block(bordered(theme).title("Error"));
+            frame.render_widget(error_panel, area);
+        }
+        Some(ChartData::Loading) | None => {
+            let text = format!("{} Loading chart…", spinner(app.spinner_frame));
+            let loading = Paragraph::new(text).block(bordered(theme));
+            frame.render_widget(loading, area);
+        }
+    }
+}
+
+// The ranges of the detail chart on one line, the one shown highlighted
+fn range_selector<'a>(app: &App) -> Line<'a> {
+    let theme = &app.theme();
+    let mut spans = Vec::new();
+    for range in ChartRange::ALL {
+        spans.push(Span::raw(" "));
+        spans.push(match (range == app.chart_range, theme.monochrome) {
+            (true, true) => Span::raw(format!("[{}]", range.title())),
+            (true, false) => Span::styled(format!(" {} ", range.title()), theme.selection_style()),
+            (false, _) => Span::raw(format!(" {} ", range.title())),
+        });
+    }
+    Line::from(spans)
+}
+
+fn detail_view(frame: &mut Frame, app: &App, coin_id: &str, area: Rect) {
+    let theme = &app.theme();
+    let [selector_area, area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(area);
+    frame.render_widget(range_selector(app), selector_area);
+    let (key, range) = ((coin_id.to_string(), app.chart_range), app.chart_range);
+    if app.chart_mode == ChartMode::Candles {
+        match app.ohlc.get(&key).map(|entry| &entry.data) {
+            Some(ChartData::Loaded(candles)) if !candles.is_empty() => {
+                candle_chart(frame, theme, candles, range, area)
+            }
+            data => chart_placeholder(frame, app, data, area),
+        }
+        return;
+    }
+    match app.charts.get(&key).map(|entry| &entry.data) {
+        Some(ChartData::Loaded(prices)) if !prices.is_empty() => {
+            line_chart(frame, app, prices, range, None, area)
+        }
+        // Until the chart is fetched, or when it can't be, the prices saved
+        // from the refreshes
+        data if !matches!(data, Some(ChartData::Loaded(_))) => {
+            let saved = app.saved_chart(coin_id, range);
+            let note = match data {
+                Some(ChartData::Failed(_)) => "saved prices, could not load the chart",
+                _ => "saved prices, loading…",
+            };
+            match saved.len() > 1 {
+                true => line_chart(frame, app, &saved, range, Some(note), area),
+                false => chart_placeholder(frame, app, data, area),
+            }
+        }
+        data => chart_placeholder(frame, app, data, area),
+    }
+}
+
+// The prices as a line colored by how they moved over the whole range, with
+// a note after the title when they aren't the fetched chart
+fn line_chart(
+    frame: &mut Frame,
+    app: &App,
+    prices: &[(f64, f64)],
+    range: ChartRange,
+    note: Option<&str>,
+    area: Rect,
+) {
+    let theme = &app.theme();
+    let first = prices.first().map(|(_, price)| *price).unwrap_or(0.0);
+    let last = prices.last().map(|(_, price)| *price).unwrap_or(0.0);
+    let style = if last >= first {
+        theme.fg(theme.gain)
+    } else {
+        theme.fg(theme.loss)
+    };
+    let points = match app.log_scale {
+        true => log_points(prices),
+        false => prices.to_vec(),
+    };
+    if points.is_empty() {
+        let empty = Paragraph::new("No prices above zero for the log axis").block(bordered(theme));
+        frame.render_widget(empty, area);
+        return;
+    }
+    let chart = price_chart(theme, &points, range, style, app.log_scale);
+    let chart = match note {
+        Some(note) => {
+            let title = format!("{} · {}", chart_title(range), note);
+            chart.block(bordered(theme).title(title))
+        }
+        None => chart,
+    };
+    frame.render_widget(chart, area);
+}
+
+// Name, rank, price and 24h change of the coin shown in the detail view
+fn detail_header<'a>(app: &App, coin_id: &str) -> Line<'a> {
+    let Some(coin) = app.coins.iter().find(|coin| coin.id == coin_id) else {
+        return Line::from(coin_id.to_string());
+    };
+    let title = match coin.market_cap_rank {
+        Some(rank) => format!("{} ({}) · #{}", coin.name, coin.symbol, rank),
+        None => format!("{} ({})", coin.name, coin.symbol),
+    };
+    Line::from(vec![
+        Span::styled(title, app.theme().header_style()),
+        Span::from("  "),
+        Span::from(coin.price_formatted(&app.config.currency, app.config.number_locale())),
+        Span::from("  "),
+        Span::styled(
+            coin.change_24h_formatted(),
+            change_style(&app.theme(), coin),
+        ),
+    ])
+}
+
+// The least the header, table and footer are laid out in
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 8;
+
+// Takes the place of the screen until the terminal is big enough again
+fn too_small(frame: &mut Frame) {
+    let area = frame.area();
+    let message = format!("Terminal too small (need at least {MIN_WIDTH}×{MIN_HEIGHT})");
+    let needed = (message.chars().count() as u16).div_ceil(area.width.max(1));
+    let top = area.height.saturating_sub(needed) / 2;
+    let area = Rect {
+        y: area.y + top,
+        height: area.height - top,
+        ..area
+    };
+    let text = Paragraph::new(message).centered().wrap(Wrap { trim: true });
+    frame.render_widget(text, area);
+}
+
+pub fn ui(frame: &mut Frame, app: &App) {
+    app.table_layout.set(None);
+    let area = frame.area();
+    if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+        too_small(frame);
+    } else {
+        screen(frame, app);
+        if app.show_help {
+            help_overlay(frame, app);
+        } else if app.debug {
+            debug_overlay(frame, app);
+        } else if app.show_connection {
+            connection_overlay(frame, app);
+        }
+    }
+    if app.config.no_color {
+        to_ascii(frame.buffer_mut());
+    }
+}
+
+// The eight sparkline bars from low to high
+const ASCII_BARS: [char; 8] = ['_', '.', '-', '~', '=', '+', '*', '#'];
+
+// Stand-ins for the box drawing, arrows, bars and braille in a frame, for
+// terminals and screen readers that only do ASCII. Other text like
+// currency symbols is kept
+fn ascii_glyph(glyph: char) -> Option<char> {
+    let ascii = match glyph {
+        '─' | '━' | '═' | '—' | '–' => '-',
+        '│' | '┃' | '║' | '·' => '|',
+        '\u{2500}'..='\u{257f}' => '+',
+        '▲' | '↑' => '^',
+        '▼' | '↓' => 'v',
+        '←' => '<',
+        '→' => '>',
+        '…' => '.',
+        '×' => 'x',
+        '•' | '●' => '*',
+        // Sparkline bars, the rest of the block elements are scrollbars
+        '▁'..='█' => ASCII_BARS[glyph as usize - '▁' as usize],
+        '\u{2580}'..='\u{259f}' => '#',
+        '\u{2800}' => ' ',
+        '\u{2801}'..='\u{28ff}' => '*',
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+fn to_ascii(buffer: &mut Buffer) {
+    for cell in &mut buffer.content {
+        let mut chars = cell.symbol().chars();
+        if let (Some(glyph), None) = (chars.next(), chars.next())
+            && let Some(ascii) = ascii_glyph(glyph)
+        {
+            cell.set_char(ascii);
+        }
+    }
+}
+
+// A block per coin sized by market cap and colored by the 24h change, with
+// the symbol and change written over it
+fn heatmap(frame: &mut Frame, app: &App, coins: &[&Coin], area: Rect) {
+    let theme = &app.theme();
+    let block = bordered(theme).title("Heatmap 24H");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let weights: Vec<f64> = coins
+        .iter()
+        .map(|coin| coin.market_cap.unwrap_or(0.0))
+        .collect();
+    let cells = heatmap::grid(inner, &weights);
+    let selected = app.selected();
+    for (index, (coin, cell)) in coins.iter().zip(&cells).enumerate() {
+        let change = app.displayed_change(coin, ChangeHorizon::Day);
+        let mut style = Style::default().fg(Color::White);
+        if let Some(change) = change {
+            style = style.bg(heatmap::heat_color(change));
+        }
+        let (mut label, mut label_style) = (coin.symbol.clone(), theme.bold());
+        if selected == Some(index) {
+            label = format!("{}{}", HIGHLIGHT_SYMBOL, label);
+            if !theme.monochrome {
+                label_style = label_style.add_modifier(Modifier::REVERSED);
+            }
+        }
+        let text = vec![
+            Line::styled(label, label_style),
+            Line::from(match change {
+                Some(change) => format!("{:+.2}%", change),
+                None => MISSING_VALUE.to_string(),
+            }),
+        ];
+        // The last column and line are left blank to keep the cells apart
+        let fill = Rect {
+            width: cell.width - 1,
+            height: cell.height - 1,
+            ..*cell
+        };
+        let top = fill.height.saturating_sub(text.len() as u16) / 2;
+        let text_area = Rect {
+            y: fill.y + top,
+            height: fill.height - top,
+            ..fill
+        };
+        match theme.monochrome {
+            true => frame.render_widget(Paragraph::new(text).centered(), text_area),
+            false => {
+                frame.render_widget(Block::default().style(style), fill);
+                frame.render_widget(Paragraph::new(text).centered().style(style), text_area);
+            }
+        }
+    }
+    app.heatmap_cells.replace(cells);
+}
+
+// Keeps where the rows of a bordered table with a one line header were
+// drawn, for mouse clicks
+fn record_table(app: &App, area: Rect, table_state: &TableState, row_height: u16) {
+    app.table_layout.set(Some(TableLayout {
+        rows: table_rows(area),
+        offset: table_state.offset(),
+        row_height,
+    }));
+}
+
+fn table_rows(area: Rect) -> Rect {
+    let mut rows = Block::default().borders(Borders::ALL).inner(area);
+    rows.y += 1;
+    rows.height = rows.height.saturating_sub(1);
+    rows
+}
+
+// Lists the keys of the view on screen by category in a box in the middle
+// of the screen
+fn help_overlay(frame: &mut Frame, app: &App) {
+    let theme = &app.theme();
+    let bindings = app.config.keys.bindings_in(app.key_scope());
+    let mut lines = Vec::new();
+    for category in Category::ALL {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::styled(category.title(), theme.header_style()));
+        for (binding, keys) in bindings
+            .iter()
+            .filter(|(binding, _)| binding.category == category)
+        {
+            lines.push(Line::from(vec![
+                Span::raw(format!(
+                    "{:>10}  {:40}",
+                    keys_label(keys),
+                    binding.description
+                )),
+                Span::styled(binding.name, theme.fg(theme.muted)),
+            ]));
+        }
+    }
+    let area = centered(frame.area(), 72, lines.len() as u16 + 2);
+    let help = Paragraph::new(lines).block(bordered(theme).title(" Keys · Esc or ? to close "));
+    frame.render_widget(Clear, area);
+    frame.render_widget(help, area);
+}
+
+// The last raw markets response over almost the whole screen, scrolled
+// with the arrow keys
+fn debug_overlay(frame: &mut Frame, app: &App) {
+    let theme = &app.theme();
+    let screen = frame.area();
+    let area = centered(
+        screen,
+        screen.width.saturating_sub(4),
+        screen.height.saturating_sub(2),
+    );
+    let block = bordered(theme).title(" Last API response · ↑/↓ scroll · Esc or F12 to close ");
+    let lines = match &app.debug_info {
+        Some(info) => {
+            let status = match info.status {
+                Some(status) => status.to_string(),
+                None => "no response".to_string(),
+            };
+            let summary = format!(
+                "{} · {} ms · {}",
+                status,
+                info.elapsed.as_millis(),
+                info.outcome
+            );
+            let mut lines = vec![
+                Line::styled(format!("GET {}", info.url), theme.header_style()),
+                Line::from(summary),
+                Line::from(""),
+            ];
+            lines.extend(info.body.lines().map(|line| Line::from(line.to_string())));
+            lines
+        }
+        None => vec![Line::styled(
+            "Nothing yet, the next refresh shows up here",
+            theme.fg(theme.muted),
+        )],
+    };
+    let body = Paragraph::new(lines)
+        .block(block)
+        .scroll((app.debug_scroll, 0));
+    frame.render_widget(Clear, area);
+    frame.render_widget(body, area);
+}
+
+// When the automatic refresh tries next, or why it doesn't
+fn next_refresh_text(app: &App) -> String {
+    let next_in = app.next_refresh.saturating_duration_since(Instant::now());
+    if app.fetching {
+        return format!(
+            "running now (attempt {}/{})",
+            app.fetch_attempt, MAX_FETCH_ATTEMPTS
+        );
+    }
+    if app.paused {
+        return "paused, r fetches once".to_string();
+    }
+    match app.rate_limited() {
+        true => format!("in {} (rate limited)", format_age(next_in)),
+        false => format!("in {}", format_age(next_in)),
+    }
+}
+
+// Everything the dot in the footer sums up
+fn connection_overlay(frame: &mut Frame, app: &App) {
+    let theme = &app.theme();
+    let (state, state_style) = connection_state(app);
+    let last_update = match app.last_updated {
+        Some(last_updated) => format!("{} ago", format_age(last_updated.elapsed())),
+        None => "never".to_string(),
+    };
+    let error = app.last_error_detail.as_deref().unwrap_or("none");
+    let rows = [
+        ("Status", Span::styled(state, state_style)),
+        ("Last update", Span::raw(last_update)),
+        (
+            "Failures",
+            Span::raw(format!("{} in a row", app.consecutive_failures)),
+        ),
+        ("Next refresh", Span::raw(next_refresh_text(app))),
+        ("Last error", Span::raw(error)),
+    ];
+    let lines: Vec<Line> = rows
+        .into_iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(format!("{:<14}", label), theme.fg(theme.muted)),
+                value,
+            ])
+        })
+        .collect();
+    // The error is the only row long enough to wrap
+    let width = 64u16.min(frame.area().width);
+    let error_width = 14 + error.chars().count() as u16;
+    let wrapped = error_width.div_ceil(width.saturating_sub(2).max(1));
+    let height = lines.len() as u16 + wrapped.saturating_sub(1) + 2;
+    let area = centered(frame.area(), width, height);
+    let panel = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(bordered(theme).title(" Connection · Esc or i to close "));
+    frame.render_widget(Clear, area);
+    frame.render_widget(panel, area);
+}
+
+// The word and color of the dot in the footer
+fn connection_state(app: &App) -> (&'static str, Style) {
+    let theme = &app.theme();
+    match app.connection() {
+        Connection::Connecting => ("connecting", theme.fg(theme.muted)),
+        Connection::Live => ("live", theme.fg(theme.gain)),
+        Connection::Degraded => ("degraded", theme.fg(theme.warning)),
+        Connection::Offline => ("offline", theme.fg(theme.error)),
+    }
+}
+
+// A `width` by `height` box in the middle of `area`, shrunk to fit
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn screen(frame: &mut Frame, app: &App) {
+    // Create the area
+    // Then split the area into chunks
+    let area = frame.area();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1), // Header (fixed 1 line)
+            Constraint::Length(1), // Tab bar
+            Constraint::Min(1),    // Main area (grows)
+            Constraint::Length(1), // Footer (fixed 1 line)
+        ])
+        .split(area);
+
+    let header_area = chunks[0];
+    frame.render_widget(tab_bar(app), chunks[1]);
+    let main_area = chunks[2];
+    let footer_area = chunks[3];
+
+    if let View::Detail(coin_id) = &app.view {
+        let header = Paragraph::new(detail_header(app, coin_id));
+        frame.render_widget(alert_banner(app).unwrap_or(header), header_area);
+        detail_view(frame, app, coin_id, main_area);
+        let mut help_text = "q/Esc: back  r/F5: refresh".to_string();
+        if let Some(message) = &app.status_message {
+            help_text = format!("{}  {}", message, help_text);
+        }
+        footer(frame, app, help_text, footer_area);
+        return;
+    }
+
+    if app.view == View::Portfolio {
+        let header = Paragraph::new("Crypto Tracker");
+        frame.render_widget(alert_banner(app).unwrap_or(header), header_area);
+        portfolio_view(frame, app, main_area);
+        let mut help_text = "q/Esc: market  Tab/1-4: tabs  r/F5: refresh".to_string();
+        if let Some(message) = &app.status_message {
+            help_text = format!("{}  {}", message, help_text);
+        }
+        footer(frame, app, help_text, footer_area);
+        return;
+    }
+
+    if app.view == View::Alerts {
+        let header = Paragraph::new("Crypto Tracker");
+        frame.render_widget(alert_banner(app).unwrap_or(header), header_area);
+        alerts_view(frame, app, main_area);
+        if app.input_mode == InputMode::AddAlert {
+            alert_prompt(frame, app, footer_area);
+            return;
+        }
+        let mut help_text =
+            "q/Esc: market  Tab/1-4: tabs  ↑↓/jk: move  a: add  d: delete  r: re-arm  F5: refresh"
+                .to_string();
+        if let Some(message) = &app.status_message {
+            help_text = format!("{}  {}", message, help_text);
+        }
+        footer(frame, app, help_text, footer_area);
+        return;
+    }
+
+    if app.view == View::Trending {
+        let header = Paragraph::new("Crypto Tracker");
+        frame.render_widget(alert_banner(app).unwrap_or(header), header_area);
+        trending_view(frame, app, main_area);
+        let mut help_text =
+            "q/Esc: market  Tab/1-4: tabs  ↑↓/jk: move  a: add to watchlist  r/F5: refresh"
+                .to_string();
+        if let Some(message) = &app.status_message {
+            help_text = format!("{}  {}", message, help_text);
+        }
+        footer(frame, app, help_text, footer_area);
+        return;
+    }
+
+    // HEADER
+    let currency = app.display_currency();
+    let mut header = vec![Span::raw("Crypto Tracker")];
+    header.extend(global_spans(app, &currency));
+    if let Some(value) = app.fear_greed.filter(|_| app.config.show_fear_greed) {
+        header.push(Span::raw(" · "));
+        header.push(fear_greed_span(&app.theme(), value));
+    }
+    if currency != app.config.currency {
+        header.push(Span::raw(format!(
+            " · prices in {}",
+            currency.to_uppercase()
+        )));
+    }
+    let header = Paragraph::new(Line::from(header));
+    frame.render_widget(alert_banner(app).unwrap_or(header), header_area);
+
+    // MAIN
+    let theme = &app.theme();
+    let coins = app.visible_coins();
+    if app.config.coins.is_empty() {
+        let empty_state =
+            Paragraph::new("No coins tracked — press 'a' to add one").block(bordered(theme));
+        frame.render_widget(empty_state, main_area);
+    } else if let (true, Some(error)) = (app.coins.is_empty(), &app.last_error) {
+        // Nothing was ever fetched, so there is no old data to fall back on
+        let text = vec![
+            Line::from(format!("Unable to reach CoinGecko: {}", error)),
+            Line::from(""),
+            Line::from("Press r to retry, q to quit. Retrying automatically on the next refresh."),
+        ];
+        let error_panel = Paragraph::new(text)
+            .style(theme.fg(theme.error))
+            .block(bordered(theme).title("Error"));
+        frame.render_widget(error_panel, main_area);
+    } else if coins.is_empty() && !app.filter.is_empty() {
+        let message = format!(
+            "No coins match \"{}\" (Esc to clear the filter)",
+            app.filter
+        );
+        let empty_state = Paragraph::new(message).block(bordered(theme));
+        frame.render_widget(empty_state, main_area);
+    } else if app.show_heatmap {
+        heatmap(frame, app, &coins, main_area);
+    } else {
+        let row_height = app.row_layout().row_height();
+        let table = match app.row_layout() {
+            RowLayout::Compact => coin_table(app, &coins, main_area),
+            RowLayout::Detailed => detailed_table(app, &coins, main_area),
+        };
+        // After the terminal grew, scroll back so no rows are left empty
+        let page = (table_rows(main_area).height / row_height) as usize;
+        let offset = app
+            .market_offset
+            .get()
+            .min(coins.len().saturating_sub(page));
+        let mut table_state = TableState::default()
+            .with_offset(offset)
+            .with_selected(app.selected());
+        frame.render_stateful_widget(table, main_area, &mut table_state);
+        app.market_offset.set(table_state.offset());
+        record_table(app, main_area, &table_state, row_height);
+        if coins.len() > page {
+            let mut scrollbar_state =
+                ScrollbarState::new(coins.len() - page).position(table_state.offset());
+            let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .style(theme.border_style())
+                .begin_symbol(None)
+                .end_symbol(None);
+            // Drawn over the right border, next to the rows
+            let rows = table_rows(main_area);
+            let area = Rect {
+                x: main_area.x,
+                width: main_area.width,
+                ..rows
+            };
+            frame.render_stateful_widget(scrollbar, area, &mut scrollbar_state);
+        }
+    }
+
+    // FOOTER
+    match app.input_mode {
+        InputMode::ConfirmDelete => {
+            let coin_id = app.pending_delete.as_deref().unwrap_or_default();
+            let question = Paragraph::new(format!("delete {}? y/n", coin_id));
+            frame.render_widget(question, footer_area);
+        }
+        InputMode::AddCoin => {
+            let prompt = "add coin: ";
+            let mut text = format!("{}{}", prompt, app.add_input);
+            let cursor_x = footer_area.x + (prompt.len() + app.add_input.chars().count()) as u16;
+            match &app.add_message {
+                Some(message) => text = format!("{}  {}", text, message),
+                None => text = format!("{}  (Enter: add, Esc: cancel)", text),
+            }
+            frame.render_widget(Paragraph::new(text), footer_area);
+            frame.set_cursor_position((cursor_x.min(footer_area.right()), footer_area.y));
+        }
+        InputMode::Filter => {
+            let input = Paragraph::new(format!("/{}", app.filter));
+            frame.render_widget(input, footer_area);
+            // Cursor sits right after the typed text
+            let cursor_x = footer_area.x + 1 + app.filter.chars().count() as u16;
+            frame.set_cursor_position((cursor_x.min(footer_area.right()), footer_area.y));
+        }
+        // Only opened from the Alerts tab
+        InputMode::AddAlert => {}
+        InputMode::Normal => {
+            let mut help_text =
+                "q: quit  r/F5: refresh  ↑↓/jk: move  Enter: chart  s/S: sort  /: filter  a: add  d: delete  e: export  Tab/1-4: tabs  ?: keys"
+                    .to_string();
+            if !app.config.other_currencies().is_empty() {
+                help_text = format!("{}  c: currency", help_text);
+            }
+            help_text = format!("{}  b: BTC  h: change", help_text);
+            if !app.filter.is_empty() {
+                help_text = format!("filter: {}  Esc: clear  {}", app.filter, help_text);
+            }
+            if let Some(message) = &app.status_message {
+                help_text = format!("{}  {}", message, help_text);
+            }
+            footer(frame, app, help_text, footer_area);
+        }
+    }
+}
+
+// Takes the place of the header while alerts are waiting to be dismissed
+fn alert_banner<'a>(app: &App) -> Option<Paragraph<'a>> {
+    let messages = &app.alert_sinks.banner.messages;
+    if messages.is_empty() {
+        return None;
+    }
+    let text = format!("{}  (x: dismiss)", messages.join("  ·  "));
+    Some(Paragraph::new(text).style(app.theme().banner_style()))
+}
+
+// Help text on the left, refresh status on the right
+pub fn footer(frame: &mut Frame, app: &App, help_text: String, area: Rect) {
+    let theme = &app.theme();
+    let status = refresh_status(app);
+    let status_style = if app.last_refresh_failed {
+        theme.fg(theme.warning)
+    } else {
+        Style::default()
+    };
+    // Hard to miss, the numbers aren't moving on their own
+    let paused = match app.paused {
+        true => " PAUSED ",
+        false => "",
+    };
+    // Offline leads into the status, which starts with the error then
+    let (_, dot_style) = connection_state(app);
+    let offline = match (app.connection(), app.fetching) {
+        (Connection::Offline, false) => " offline:",
+        (Connection::Offline, true) => " offline",
+        _ => "",
+    };
+    let status_width = status.chars().count() + paused.len() + offline.len() + 4;
+    let [help_area, status_area] =
+        Layout::horizontal([Constraint::Min(0), Constraint::Length(status_width as u16)])
+            .areas(area);
+    frame.render_widget(Paragraph::new(help_text), help_area);
+    let line = Line::from(vec![
+        Span::styled(paused, theme.banner_style()),
+        Span::raw(" "),
+        Span::styled(format!("●{}", offline), dot_style),
+        Span::raw(" "),
+        Span::styled(status, status_style),
+    ]);
+    frame.render_widget(Paragraph::new(line.right_aligned()), status_area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparkline_scales_to_the_range() {
+        let samples: VecDeque<f64> = [1.0, 2.0, 3.0, 5.0].into_iter().collect();
+        assert_eq!(sparkline(&samples, 10), "▁▃▅█");
+        // Only the most recent samples fit
+        assert_eq!(sparkline(&samples, 2), "▁█");
+    }
+
+    #[test]
+    fn flat_sparkline_sits_in_the_middle() {
+        let samples: VecDeque<f64> = [7.0, 7.0, 7.0].into_iter().collect();
+        assert_eq!(sparkline(&samples, 3), "▅▅▅");
+    }
+
+    #[test]
+    fn sentiment_goes_from_red_to_green() {
+        let colors: Vec<Color> = [10, 30, 50, 70, 90]
+            .into_iter()
+            .map(|value| sentiment_color(&Theme::default(), Sentiment::of(value)))
+            .collect();
+        assert_eq!(
+            colors,
+            [
+                Color::Red,
+                Color::LightRed,
+                Color::Yellow,
+                Color::LightGreen,
+                Color::Green
+            ]
+        );
+        assert_eq!(
+            fear_greed_span(&Theme::default(), 73).content,
+            "F&G 73 Greed"
+        );
+    }
+
+    #[test]
+    fn format_age_rounds_down() {
+        assert_eq!(format_age(Duration::from_secs(12)), "12s");
+        assert_eq!(format_age(Duration::from_secs(179)), "2m");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2h");
+    }
+
+    #[test]
+    fn axis_labels_use_suffixes() {
+        assert_eq!(format_axis_price(65_300.0), "65.3k");
+        assert_eq!(format_axis_price(1_250_000.0), "1.2M");
+        assert_eq!(format_axis_price(3.5), "3.50");
+        assert_eq!(format_axis_price(0.000123), "0.0001");
+    }
+
+    #[test]
+    fn time_labels_follow_the_range() {
+        // 2024-03-09T14:05:00Z
+        let time = 1_709_993_100_000.0;
+        assert_eq!(time_label(time, ChartRange::Day), "14:05");
+        assert_eq!(time_label(time, ChartRange::Week), "Mar 9");
+        assert_eq!(time_label(time, ChartRange::Quarter), "Mar 9");
+        assert_eq!(time_label(time, ChartRange::Year), "Mar 2024");
+    }
+
+    #[test]
+    fn the_spinner_goes_through_its_frames_and_starts_over() {
+        let frames: String = (0..12).map(spinner).collect();
+        assert_eq!(frames, "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏⠋⠙");
+        // The frame counter wraps around too
+        assert_eq!(spinner(usize::MAX), SPINNER_FRAMES[usize::MAX % 10]);
+        assert_eq!(spinner(usize::MAX.wrapping_add(1)), '⠋');
+    }
+
+    #[test]
+    fn log_axis_is_labeled_at_powers_of_ten() {
+        let (bounds, labels) = log_axis(3.2, 6_800.0);
+        assert_eq!(bounds, [0.0, 4.0]);
+        let labels: Vec<String> = labels.into_iter().map(log_axis_label).collect();
+        assert_eq!(labels, ["1", "10", "100", "1k", "10k"]);
+        // Five decades are labeled every other, up to the next label
+        let (bounds, labels) = log_axis(3.2, 68_000.0);
+        assert_eq!(bounds, [0.0, 6.0]);
+        assert_eq!(labels.len(), 4);
+        // Twelve decades are labeled every third
+        let (bounds, labels) = log_axis(0.000_001, 900_000.0);
+        assert_eq!(bounds, [-6.0, 6.0]);
+        assert_eq!(labels.len(), 5);
+        // Within a single decade the middle is labeled too
+        let (bounds, labels) = log_axis(60_000.0, 70_000.0);
+        assert_eq!(bounds, [4.0, 5.0]);
+        let labels: Vec<String> = labels.into_iter().map(log_axis_label).collect();
+        assert_eq!(labels, ["10k", "31.6k", "100k"]);
+        assert_eq!(log_axis(100.0, 100.0).0, [2.0, 3.0]);
+    }
+
+    #[test]
+    fn log_points_skip_prices_at_or_below_zero() {
+        let points = log_points(&[(1.0, 100.0), (2.0, 0.0), (3.0, -5.0), (4.0, 0.01)]);
+        assert_eq!(points, [(1.0, 2.0), (4.0, -2.0)]);
+    }
+
+    fn candle(open: f64, high: f64, low: f64, close: f64) -> Candle {
+        Candle {
+            time: open,
+            open,
+            high,
+            low,
+            close,
+        }
+    }
+
+    #[test]
+    fn candles_are_bucketed_to_fit() {
+        let candles = [
+            candle(1.0, 4.0, 0.5, 2.0),
+            candle(2.0, 3.0, 1.5, 2.5),
+            candle(2.5, 6.0, 2.0, 5.0),
+            candle(5.0, 5.5, 3.0, 3.5),
+            candle(3.5, 4.0, 1.0, 1.5),
+        ];
+        assert_eq!(bucket_candles(&candles, 5), candles);
+        assert_eq!(
+            bucket_candles(&candles, 2),
+            [candle(1.0, 6.0, 0.5, 5.0), candle(5.0, 5.5, 1.0, 1.5)]
+        );
+        assert_eq!(bucket_candles(&candles, 1), [candle(1.0, 6.0, 0.5, 1.5)]);
+        assert!(bucket_candles(&candles, 0).is_empty());
+    }
+
+    const ALL_COLUMNS: [Column; 11] = [
+        Column::Rank,
+        Column::Symbol,
+        Column::Name,
+        Column::Price,
+        Column::Change(ChangeHorizon::Hour),
+        Column::Change(ChangeHorizon::Day),
+        Column::Change(ChangeHorizon::Week),
+        Column::Change(ChangeHorizon::Month),
+        Column::MarketCap,
+        Column::Volume,
+        Column::Trend,
+    ];
+
+    #[test]
+    fn narrow_terminals_keep_the_symbol_price_and_24h() {
+        let narrow = [
+            Column::Symbol,
+            Column::Price,
+            Column::Change(ChangeHorizon::Day),
+        ];
+        assert_eq!(required_width(&narrow), 39);
+        for width in [20, 39, 59] {
+            assert_eq!(visible_columns(width, &ALL_COLUMNS), narrow, "{}", width);
+        }
+    }
+
+    #[test]
+    fn medium_terminals_add_the_name_and_7d() {
+        let medium = [
+            Column::Symbol,
+            Column::Name,
+            Column::Price,
+            Column::Change(ChangeHorizon::Day),
+            Column::Change(ChangeHorizon::Week),
+        ];
+        assert_eq!(required_width(&medium), 60);
+        for width in [60, 80, 113] {
+            assert_eq!(visible_columns(width, &ALL_COLUMNS), medium, "{}", width);
+        }
+    }
+
+    #[test]
+    fn wide_terminals_show_everything() {
+        assert_eq!(required_width(&ALL_COLUMNS), 114);
+        assert_eq!(visible_columns(114, &ALL_COLUMNS), ALL_COLUMNS);
+        assert_eq!(visible_columns(200, &ALL_COLUMNS), ALL_COLUMNS);
+    }
+
+    #[test]
+    fn the_next_change_column_stands_in_for_a_hidden_one() {
+        let columns = [
+            Column::Rank,
+            Column::Symbol,
+            Column::Name,
+            Column::Price,
+            Column::Change(ChangeHorizon::Hour),
+            Column::Change(ChangeHorizon::Month),
+            Column::Trend,
+        ];
+        assert_eq!(
+            visible_columns(40, &columns),
+            [
+                Column::Symbol,
+                Column::Price,
+                Column::Change(ChangeHorizon::Hour)
+            ]
+        );
+        assert_eq!(
+            visible_columns(70, &columns),
+            [
+                Column::Symbol,
+                Column::Name,
+                Column::Price,
+                Column::Change(ChangeHorizon::Hour),
+                Column::Change(ChangeHorizon::Month),
+            ]
+        );
+    }
+}
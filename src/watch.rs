@@ -0,0 +1,144 @@
+//! `--watch`: the --once table repainted in place after every refresh, like
+//! watch(1), for terminals and tmux panes without the TUI
+
+use crate::config::{Config, clamp_refresh_interval};
+use crate::model::{Coin, format_coins};
+use crate::output::rfc3339;
+use crate::source::{DataSource, refresh_watchlist};
+use std::time::{Duration, SystemTime};
+
+// Cursor to the top left, and the whole screen cleared for the first frame
+const HOME: &str = "\x1b[H";
+const CLEAR_SCREEN: &str = "\x1b[2J";
+// What is left of a line after a shorter one, and of the screen below
+const CLEAR_LINE: &str = "\x1b[K";
+const CLEAR_BELOW: &str = "\x1b[J";
+
+#[derive(Debug)]
+pub struct Watch {
+    // The refresh interval after the same clamping as in the TUI
+    pub interval: Duration,
+    coins: Vec<Coin>,
+    fetched_at: Option<SystemTime>,
+    // Why the last refresh didn't get every coin
+    problem: Option<String>,
+    painted: bool,
+}
+
+impl Watch {
+    pub fn new(config: &Config) -> Self {
+        let (interval, _) =
+            clamp_refresh_interval(config.refresh_interval, config.min_refresh_interval());
+        Watch {
+            interval,
+            coins: Vec::new(),
+            fetched_at: None,
+            problem: None,
+            painted: false,
+        }
+    }
+
+    // One refresh, returns how long to wait for the next one
+    pub async fn refresh(&mut self, config: &Config, source: &dyn DataSource) -> Duration {
+        let refresh = refresh_watchlist(config, source, &self.coins, self.interval).await;
+        if let Some(coins) = refresh.coins {
+            self.coins = coins;
+            self.fetched_at = Some(SystemTime::now());
+        }
+        self.problem = refresh.problem;
+        refresh.wait
+    }
+
+    // The next screenful, written in one go so Ctrl-C never lands in the
+    // middle of an escape. Without `terminal` the frames are printed one
+    // after the other with a blank line between them
+    pub fn frame(&mut self, config: &Config, terminal: bool) -> String {
+        let color = terminal && !config.no_color;
+        let updated = match self.fetched_at {
+            Some(time) => rfc3339(time),
+            None => "never".to_string(),
+        };
+        let mut lines = vec![format!(
+            "Every {}s: tuicker, updated {}",
+            self.interval.as_secs(),
+            updated
+        )];
+        lines.push(String::new());
+        lines.extend(
+            format_coins(&self.coins, &config.currency, color, config.number_locale())
+                .lines()
+                .map(str::to_string),
+        );
+        if let Some(problem) = &self.problem {
+            lines.push(String::new());
+            lines.push(problem.clone());
+        }
+        let first = !self.painted;
+        self.painted = true;
+        if !terminal {
+            let separator = if first { "" } else { "\n" };
+            return format!("{}{}\n", separator, lines.join("\n"));
+        }
+        let mut frame = HOME.to_string();
+        if first {
+            frame.push_str(CLEAR_SCREEN);
+        }
+        for line in lines {
+            frame.push_str(&line);
+            frame.push_str(CLEAR_LINE);
+            frame.push('\n');
+        }
+        // A coin that was removed leaves a line behind otherwise
+        frame.push_str(CLEAR_BELOW);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::DemoSource;
+
+    fn config(coins: &[&str]) -> Config {
+        Config {
+            coins: coins.iter().map(|id| id.to_string()).collect(),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_repaint_from_the_top_and_clear_what_is_left() {
+        let source = DemoSource::new(3);
+        let mut config = config(&["bitcoin", "ethereum"]);
+        let mut watch = Watch::new(&config);
+        watch.refresh(&config, &source).await;
+        let first = watch.frame(&config, true);
+        assert!(first.starts_with("\x1b[H\x1b[2JEvery 30s: tuicker, updated 20"));
+        assert!(first.contains("\nBTC "));
+        assert!(first.contains("\nETH "));
+
+        // One coin less, the line it was on gets cleared with the rest
+        config.coins.pop();
+        watch.refresh(&config, &source).await;
+        let second = watch.frame(&config, true);
+        assert!(second.starts_with("\x1b[HEvery 30s"));
+        assert!(!second.contains("ETH"));
+        assert!(second.ends_with("\x1b[K\n\x1b[J"));
+        for line in second.trim_end_matches("\x1b[J").lines() {
+            assert!(line.ends_with("\x1b[K"), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn without_a_terminal_frames_are_plain_text() {
+        let config = config(&["bitcoin"]);
+        let mut watch = Watch::new(&config);
+        let first = watch.frame(&config, false);
+        assert_eq!(
+            first,
+            "Every 30s: tuicker, updated never\n\nSYMBOL NAME                  PRICE      24H\n"
+        );
+        assert!(!first.contains('\x1b'));
+        assert!(watch.frame(&config, false).starts_with("\nEvery 30s"));
+    }
+}
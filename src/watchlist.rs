@@ -0,0 +1,188 @@
+//! The coins being tracked, saved between sessions
+
+use crate::config::xdg_dir;
+use crate::model::is_valid_coin_id;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+// ~/.local/share/tuicker/watchlist.json
+pub fn watchlist_path() -> Option<PathBuf> {
+    Some(xdg_dir("XDG_DATA_HOME", ".local/share")?.join("watchlist.json"))
+}
+
+// The tracked coins as stored on disk. Fields this version doesn't know
+// about are kept in `extra` so they survive a rewrite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watchlist {
+    pub coins: Vec<WatchlistEntry>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchlistEntry {
+    pub id: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Watchlist {
+    fn from_ids(coin_ids: &[String]) -> Self {
+        Watchlist {
+            coins: coin_ids
+                .iter()
+                .map(|id| WatchlistEntry {
+                    id: id.clone(),
+                    extra: serde_json::Map::new(),
+                })
+                .collect(),
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.coins.iter().map(|entry| entry.id.clone()).collect()
+    }
+
+    pub fn add(&mut self, coin_id: &str) {
+        if !self.coins.iter().any(|entry| entry.id == coin_id) {
+            self.coins.push(WatchlistEntry {
+                id: coin_id.to_string(),
+                extra: serde_json::Map::new(),
+            });
+        }
+    }
+
+    pub fn remove(&mut self, coin_id: &str) {
+        self.coins.retain(|entry| entry.id != coin_id);
+    }
+}
+
+fn parse_watchlist(text: &str) -> Result<Watchlist, String> {
+    let mut watchlist: Watchlist = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    // Normalise the ids the same way as --coins and drop duplicates
+    let mut entries: Vec<WatchlistEntry> = Vec::new();
+    for mut entry in std::mem::take(&mut watchlist.coins) {
+        entry.id = entry.id.trim().to_lowercase();
+        if !is_valid_coin_id(&entry.id) {
+            return Err(format!("invalid coin id `{}`", entry.id));
+        }
+        if !entries.iter().any(|existing| existing.id == entry.id) {
+            entries.push(entry);
+        }
+    }
+    watchlist.coins = entries;
+    Ok(watchlist)
+}
+
+pub fn save_watchlist(path: &std::path::Path, watchlist: &Watchlist) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    }
+    let text = serde_json::to_string_pretty(watchlist).map_err(|e| e.to_string())?;
+    std::fs::write(path, text + "\n")
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+// Reads the watchlist, creating it from `default_ids` when it is missing. A corrupt
+// file is moved to watchlist.json.bak and replaced. The message says what happened
+// when something other than a plain load took place
+pub fn load_watchlist(
+    path: &std::path::Path,
+    default_ids: &[String],
+) -> (Watchlist, Option<String>) {
+    let fresh = || Watchlist::from_ids(default_ids);
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let watchlist = fresh();
+            let message = save_watchlist(path, &watchlist).err();
+            return (watchlist, message);
+        }
+        Err(e) => {
+            let message = format!("could not read {}: {}", path.display(), e);
+            return (fresh(), Some(message));
+        }
+    };
+    match parse_watchlist(&text) {
+        Ok(watchlist) => (watchlist, None),
+        Err(parse_error) => {
+            let backup = path.with_extension("json.bak");
+            let watchlist = fresh();
+            let message = match std::fs::rename(path, &backup)
+                .map_err(|e| e.to_string())
+                .and_then(|()| save_watchlist(path, &watchlist))
+            {
+                Ok(()) => format!(
+                    "watchlist was corrupt ({}), saved it to {}",
+                    parse_error,
+                    backup.display()
+                ),
+                Err(e) => format!("watchlist is corrupt ({}): {}", parse_error, e),
+            };
+            (watchlist, Some(message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory per test so they can run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tuicker-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("watchlist.json")
+    }
+
+    fn default_ids() -> Vec<String> {
+        vec!["bitcoin".to_string(), "ethereum".to_string()]
+    }
+
+    #[test]
+    fn parse_watchlist_normalises_ids() {
+        let watchlist = parse_watchlist(
+            r#"{"coins": [{"id": " Bitcoin"}, {"id": "bitcoin"}, {"id": "solana"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(watchlist.ids(), ["bitcoin", "solana"]);
+        assert!(parse_watchlist(r#"{"coins": [{"id": "bit coin"}]}"#).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_survive_a_rewrite() {
+        let mut watchlist =
+            parse_watchlist(r#"{"coins": [{"id": "bitcoin", "note": "long"}], "version": 2}"#)
+                .unwrap();
+        watchlist.add("solana");
+        let text = serde_json::to_string(&watchlist).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["version"], 2);
+        assert_eq!(value["coins"][0]["note"], "long");
+        assert_eq!(value["coins"][1]["id"], "solana");
+    }
+
+    #[test]
+    fn load_watchlist_creates_a_missing_file() {
+        let path = temp_path("missing");
+        let (watchlist, message) = load_watchlist(&path, &default_ids());
+        assert_eq!(watchlist.ids(), default_ids());
+        assert!(message.is_none());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn load_watchlist_backs_up_a_corrupt_file() {
+        let path = temp_path("corrupt");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "{ not json").unwrap();
+        let (watchlist, message) = load_watchlist(&path, &default_ids());
+        assert_eq!(watchlist.ids(), default_ids());
+        assert!(message.unwrap().starts_with("watchlist was corrupt"));
+        let backup = std::fs::read_to_string(path.with_extension("json.bak")).unwrap();
+        assert_eq!(backup, "{ not json");
+    }
+}
@@ -0,0 +1,44 @@
+// Trade CSVs in tests/fixtures folded into portfolio holdings
+
+use tuicker::import::{fold_trades, parse_trades};
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("could not read {}: {}", path, e))
+}
+
+#[test]
+fn trades_fold_into_quantity_and_average_cost() {
+    let trades = parse_trades(&fixture("trades.csv")).unwrap();
+    assert_eq!(trades.len(), 6);
+    let holdings = fold_trades(&trades).unwrap();
+    // Bought 0.5 for 20020 and 0.5 for 25000, in date order even though the
+    // file lists the sell first, then sold half at the same average cost
+    let bitcoin = &holdings["bitcoin"];
+    assert_eq!(bitcoin.quantity, 0.75);
+    assert_eq!(bitcoin.cost_basis, Some(45020.0));
+    // Sold everything, so it is no longer held
+    assert!(!holdings.contains_key("ethereum"));
+    // No fee column at all counts as no fee
+    assert_eq!(holdings["solana"].cost_basis, Some(100.0));
+}
+
+#[test]
+fn selling_more_than_held_is_an_error() {
+    let trades = parse_trades(&fixture("trades_oversold.csv")).unwrap();
+    let errors = fold_trades(&trades).unwrap_err();
+    assert_eq!(
+        errors,
+        ["line 3: selling 0.75 bitcoin but only 0.5 held on 2024-02-10"]
+    );
+}
+
+#[test]
+fn bad_rows_are_reported_with_line_numbers() {
+    let errors = parse_trades(&fixture("trades_invalid.csv")).unwrap_err();
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(errors[0].starts_with("line 2: unknown side `hodl`"));
+    assert!(errors[1].starts_with("line 3: quantity can't be negative"));
+    assert!(errors[2].starts_with("line 4: invalid date `2024-02-30`"));
+    assert!(errors[3].starts_with("line 6: expected 6 columns"));
+}
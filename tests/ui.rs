@@ -0,0 +1,1286 @@
+// Renders `ui` into a TestBackend and checks what ended up in the buffer
+
+use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::Terminal;
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tuicker::alerts::parse_alert_rule;
+use tuicker::api::FetchDebugInfo;
+use tuicker::api::parse_global;
+use tuicker::app::{App, ChartData, ChartEntry, KeyAction, View};
+use tuicker::config::{Config, SourceKind};
+use tuicker::demo::DemoSource;
+use tuicker::error::TuickerError;
+use tuicker::failover::FailoverSource;
+use tuicker::history::Sample;
+use tuicker::keymap::{Keymap, Scope, parse_key};
+use tuicker::model::{
+    Candle, ChangeHorizon, ChartRange, Coin, NumberLocale, Quote, SortDir, SortKey, TrendingCoin,
+};
+use tuicker::portfolio::Holding;
+use tuicker::source::RefreshHooks;
+use tuicker::state::{RowLayout, Tab};
+use tuicker::theme::{ThemePreset, parse_color};
+use tuicker::ui::ui;
+
+fn coin(id: &str, symbol: &str, name: &str, price: f64, change: f64) -> Coin {
+    Coin {
+        id: id.to_string(),
+        name: name.to_string(),
+        symbol: symbol.to_string(),
+        current_price: price,
+        price_change_24h: change,
+        ..Default::default()
+    }
+}
+
+fn test_app() -> App {
+    let config = Config {
+        coins: vec![
+            "bitcoin".to_string(),
+            "ethereum".to_string(),
+            "cardano".to_string(),
+        ],
+        ..Config::default()
+    };
+    let mut app = App::new(config, None, Arc::new(DemoSource::new(0)));
+    app.apply_refresh(vec![
+        coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74),
+        coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72),
+        coin("cardano", "ADA", "Cardano", 0.35, 0.0),
+    ]);
+    app
+}
+
+fn render(app: &App, width: u16, height: u16) -> Buffer {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal.draw(|frame| ui(frame, app)).unwrap();
+    terminal.backend().buffer().clone()
+}
+
+fn lines(buffer: &Buffer) -> Vec<String> {
+    let area = buffer.area;
+    (0..area.height)
+        .map(|y| {
+            (0..area.width)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect::<String>()
+        })
+        .collect()
+}
+
+// Column of the first character of `text` on the line, counted in cells
+fn column(line: &str, text: &str) -> usize {
+    let index = line
+        .find(text)
+        .unwrap_or_else(|| panic!("`{}` not in `{}`", text, line));
+    line[..index].chars().count()
+}
+
+fn row<'a>(lines: &'a [String], text: &str) -> (usize, &'a str) {
+    lines
+        .iter()
+        .enumerate()
+        .find(|(_, line)| line.contains(text))
+        .map(|(y, line)| (y, line.as_str()))
+        .unwrap_or_else(|| panic!("no line contains `{}`:\n{}", text, lines.join("\n")))
+}
+
+#[test]
+fn header_table_and_footer() {
+    let lines = lines(&render(&test_app(), 100, 12));
+    assert!(lines[0].starts_with("Crypto Tracker"), "{}", lines[0]);
+    assert!(
+        lines[11].starts_with("q: quit  r/F5: refresh"),
+        "{}",
+        lines[11]
+    );
+
+    let (header_y, header) = row(&lines, "SYMBOL");
+    let (bitcoin_y, bitcoin) = row(&lines, "Bitcoin");
+    let (_, ethereum) = row(&lines, "Ethereum");
+    // Rows follow the watchlist order right under the header
+    assert_eq!(bitcoin_y, header_y + 1);
+
+    // Symbol and name line up with their headers
+    assert_eq!(column(bitcoin, "BTC"), column(header, "SYMBOL"));
+    assert_eq!(column(ethereum, "Ethereum"), column(header, "NAME"));
+    // Prices and changes are right aligned under their headers
+    let price_end = column(header, "PRICE") + "PRICE".len();
+    assert_eq!(
+        column(bitcoin, "$67,187.34") + "$67,187.34".len(),
+        price_end
+    );
+    assert_eq!(column(ethereum, "$2,464.52") + "$2,464.52".len(), price_end);
+    let change_end = column(header, "24H") + "24H".len();
+    assert_eq!(column(bitcoin, "-0.74%") + "-0.74%".len(), change_end);
+    assert_eq!(column(ethereum, "+1.72%") + "+1.72%".len(), change_end);
+}
+
+#[test]
+fn market_caps_are_shortened() {
+    let mut app = test_app();
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    bitcoin.market_cap = Some(1_324_000_000_000.0);
+    let mut ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    // Only the market cap missing
+    ethereum.market_cap_rank = Some(2);
+    app.apply_refresh(vec![bitcoin, ethereum]);
+    let lines = lines(&render(&app, 100, 12));
+    let (_, header) = row(&lines, "SYMBOL");
+    let mcap_end = column(header, "MCAP") + "MCAP".len();
+    let (_, bitcoin) = row(&lines, "Bitcoin");
+    assert_eq!(column(bitcoin, "$1.3T") + "$1.3T".len(), mcap_end);
+    // Coins without one get a dash
+    let (_, ethereum) = row(&lines, "Ethereum");
+    assert_eq!(column(ethereum, "—") + 1, mcap_end);
+}
+
+#[test]
+fn market_cap_and_volume_columns_can_be_hidden() {
+    let mut app = test_app();
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    bitcoin.total_volume = Some(28_751_026_092.0);
+    bitcoin.market_cap_rank = Some(1);
+    app.apply_refresh(vec![bitcoin]);
+    let lines = lines(&render(&app, 100, 12));
+    let (_, header) = row(&lines, "SYMBOL");
+    let (_, bitcoin_row) = row(&lines, "Bitcoin");
+    let volume_end = column(header, "VOL 24H") + "VOL 24H".len();
+    assert_eq!(column(bitcoin_row, "$28.8B") + "$28.8B".len(), volume_end);
+
+    app.config.show_market_cap = false;
+    app.config.show_volume = false;
+    let narrow = self::lines(&render(&app, 100, 12));
+    let (_, header) = row(&narrow, "SYMBOL");
+    assert!(!header.contains("MCAP"), "{}", header);
+    assert!(!header.contains("VOL 24H"), "{}", header);
+    assert!(!row(&narrow, "Bitcoin").1.contains('—'));
+}
+
+#[test]
+fn number_locale_changes_the_separators() {
+    let mut app = test_app();
+    app.config.number_locale = Some(NumberLocale::De);
+    let lines = lines(&render(&app, 100, 12));
+    let (_, bitcoin) = row(&lines, "Bitcoin");
+    assert!(bitcoin.contains("$67.187,34"), "{}", bitcoin);
+}
+
+#[test]
+fn negative_change_keeps_its_sign_and_is_red() {
+    let buffer = render(&test_app(), 100, 12);
+    let lines = lines(&buffer);
+    let (y, bitcoin) = row(&lines, "-0.74%");
+    let x = column(bitcoin, "-0.74%");
+    assert_eq!(buffer[(x as u16, y as u16)].symbol(), "-");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::Red);
+    let (y, ethereum) = row(&lines, "+1.72%");
+    let x = column(ethereum, "+1.72%");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::Green);
+}
+
+#[test]
+fn theme_colors_replace_the_defaults() {
+    let mut app = test_app();
+    app.config.theme.overrides = vec![
+        ("loss".to_string(), parse_color("#ff8800").unwrap()),
+        ("border".to_string(), parse_color("dark_gray").unwrap()),
+        ("selection_bg".to_string(), parse_color("color236").unwrap()),
+    ];
+    let buffer = render(&app, 100, 12);
+    let lines = lines(&buffer);
+    let (y, bitcoin) = row(&lines, "-0.74%");
+    let x = column(bitcoin, "-0.74%");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::Rgb(255, 136, 0));
+    let (y, _) = row(&lines, "SYMBOL");
+    assert_eq!(buffer[(0, y as u16)].fg, Color::DarkGray);
+    // The selected row gets the background instead of being reversed
+    let (y, selected) = row(&lines, "> ");
+    let x = column(selected, "> ");
+    assert_eq!(buffer[(x as u16, y as u16)].bg, Color::Indexed(236));
+    assert!(
+        !buffer[(x as u16, y as u16)]
+            .modifier
+            .contains(Modifier::REVERSED)
+    );
+}
+
+#[test]
+fn theme_toggle_switches_presets_under_the_overrides() {
+    let mut app = test_app();
+    app.config.theme.overrides = vec![("loss".to_string(), Color::Magenta)];
+    let gain_and_loss = |app: &App| {
+        let buffer = render(app, 100, 12);
+        let lines = self::lines(&buffer);
+        let (y, bitcoin) = row(&lines, "-0.74%");
+        let loss = buffer[(column(bitcoin, "-0.74%") as u16, y as u16)].fg;
+        let (y, ethereum) = row(&lines, "+1.72%");
+        let gain = buffer[(column(ethereum, "+1.72%") as u16, y as u16)].fg;
+        (gain, loss)
+    };
+    assert_eq!(gain_and_loss(&app), (Color::Green, Color::Magenta));
+    app.toggle_theme();
+    assert_eq!(
+        gain_and_loss(&app),
+        (ThemePreset::Light.theme().gain, Color::Magenta)
+    );
+    app.toggle_theme();
+    assert_eq!(gain_and_loss(&app).0, Color::Green);
+}
+
+// Nothing but the terminal's own colors and plain characters
+fn assert_plain(buffer: &Buffer) {
+    for cell in &buffer.content {
+        assert_eq!(
+            (cell.fg, cell.bg, cell.modifier),
+            (Color::Reset, Color::Reset, Modifier::empty()),
+            "{:?}",
+            cell
+        );
+        assert!(cell.symbol().is_ascii(), "{:?}", cell.symbol());
+    }
+}
+
+#[test]
+fn no_color_leaves_no_styling_and_only_ascii() {
+    let mut app = test_app();
+    app.config.no_color = true;
+    app.config.alerts = vec![parse_alert_rule("bitcoin above 60000").unwrap()];
+    // A second refresh for the sparklines, which also fires the alert
+    app.apply_refresh(vec![
+        coin("bitcoin", "BTC", "Bitcoin", 68000.0, -0.5),
+        coin("ethereum", "ETH", "Ethereum", 2500.0, 1.72),
+        coin("cardano", "ADA", "Cardano", 0.3, 0.0),
+    ]);
+    app.sort_key = tuicker::model::SortKey::Price;
+    let buffer = render(&app, 100, 12);
+    assert_plain(&buffer);
+    let lines = lines(&buffer);
+    let (_, bitcoin) = row(&lines, "Bitcoin");
+    assert!(bitcoin.contains("!BTC"), "{}", bitcoin);
+    assert!(bitcoin.contains("-0.50%"), "{}", bitcoin);
+    assert!(row(&lines, "Ethereum").1.contains("+1.72%"));
+    assert!(bitcoin.starts_with("|> "), "{}", bitcoin);
+    assert!(lines.iter().any(|line| line.contains("[1 Market]")));
+
+    app.view = View::Detail("bitcoin".to_string());
+    app.charts.insert(
+        ("bitcoin".to_string(), ChartRange::Week),
+        ChartEntry {
+            data: ChartData::Loaded(vec![
+                (1_700_000_000_000.0, 65000.0),
+                (1_700_086_400_000.0, 66000.0),
+                (1_700_172_800_000.0, 64000.0),
+            ]),
+            fetched_at: std::time::Instant::now(),
+        },
+    );
+    assert_plain(&render(&app, 40, 24));
+    app.view = View::Market;
+    app.show_help = true;
+    assert_plain(&render(&app, 100, 50));
+}
+
+fn candle(hours: f64, open: f64, high: f64, low: f64, close: f64) -> Candle {
+    Candle {
+        time: 1_700_000_000_000.0 + hours * 3_600_000.0,
+        open,
+        high,
+        low,
+        close,
+    }
+}
+
+#[test]
+fn l_switches_the_chart_to_a_log_axis() {
+    let mut app = test_app();
+    app.handle_key(KeyCode::Enter.into());
+    app.finish_chart(
+        "bitcoin".to_string(),
+        ChartRange::Week,
+        Ok(vec![
+            (1_709_993_100_000.0, 5.0),
+            (1_710_079_500_000.0, 0.0),
+            (1_710_165_900_000.0, 65000.0),
+        ]),
+    );
+    let lines = self::lines(&render(&app, 60, 20));
+    assert!(!lines.iter().any(|line| line.contains("log")));
+    assert!(lines.iter().any(|line| line.contains("65.0k")));
+
+    assert_eq!(
+        app.handle_key(KeyCode::Char('l').into()),
+        KeyAction::Nothing
+    );
+    let lines = self::lines(&render(&app, 60, 20));
+    assert!(lines.iter().any(|line| line.contains("log")));
+    // Every other decade, the price of zero left out
+    for label in ["1M  │log", "10k │", "100 │", "1   │"] {
+        row(&lines, label);
+    }
+    // The scale stays for the next coin
+    app.handle_key(KeyCode::Esc.into());
+    app.select_next();
+    app.handle_key(KeyCode::Enter.into());
+    assert!(app.log_scale);
+}
+
+#[test]
+fn saved_prices_are_charted_until_the_chart_is_fetched() {
+    let mut app = test_app();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let hour = 60 * 60 * 1000;
+    let saved = (0..24)
+        .map(|hours| Sample {
+            time: now - (24 - hours) * hour,
+            price: 60000.0 + hours as f64 * 100.0,
+        })
+        .collect();
+    app.finish_history_load(Ok(HashMap::from([("bitcoin".to_string(), saved)])));
+    app.handle_key(KeyCode::Enter.into());
+    let lines = lines(&render(&app, 80, 20));
+    let (_, title) = row(&lines, "7 days, UTC · saved prices, loading…");
+    assert!(!title.contains("Loading chart"));
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains('⠉') || line.contains('⣀'))
+    );
+
+    app.finish_chart(
+        "bitcoin".to_string(),
+        ChartRange::Week,
+        Err(TuickerError::RateLimited(Duration::from_secs(30))),
+    );
+    let lines = self::lines(&render(&app, 80, 20));
+    row(&lines, "saved prices, could not load the chart");
+    // Ethereum has nothing saved
+    app.handle_key(KeyCode::Esc.into());
+    app.select_next();
+    app.handle_key(KeyCode::Enter.into());
+    let lines = self::lines(&render(&app, 80, 20));
+    row(&lines, "Loading chart…");
+}
+
+#[test]
+fn candles_replace_the_line_chart_with_c() {
+    let mut app = test_app();
+    app.handle_key(KeyCode::Enter.into());
+    assert_eq!(
+        app.handle_key(KeyCode::Char('c').into()),
+        KeyAction::FetchOhlc("bitcoin".to_string())
+    );
+    let lines = lines(&render(&app, 60, 20));
+    assert!(lines.iter().any(|line| line.contains("Loading chart…")));
+    app.finish_ohlc(
+        "bitcoin".to_string(),
+        ChartRange::Week,
+        Ok(vec![
+            candle(0.0, 100.0, 120.0, 90.0, 110.0),
+            candle(4.0, 110.0, 115.0, 80.0, 85.0),
+        ]),
+    );
+    let buffer = render(&app, 60, 20);
+    let colors: Vec<Color> = buffer
+        .content
+        .iter()
+        .filter(|cell| cell.symbol() == "█")
+        .map(|cell| cell.fg)
+        .collect();
+    assert!(colors.contains(&Color::Green) && colors.contains(&Color::Red));
+    let lines = self::lines(&buffer);
+    // The axis is labeled the way the line chart is
+    assert!(row(&lines, "120.00").1.contains('│'));
+    assert!(lines.iter().any(|line| line.contains("80.00")));
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("Nov 14") && line.contains("Nov 15"))
+    );
+    // Back to the line chart, which was not fetched yet
+    assert_eq!(
+        app.handle_key(KeyCode::Char('c').into()),
+        KeyAction::Nothing
+    );
+}
+
+#[test]
+fn range_keys_fetch_each_range_once_and_mark_the_one_shown() {
+    let mut app = test_app();
+    app.handle_key(KeyCode::Enter.into());
+    app.finish_chart(
+        "bitcoin".to_string(),
+        ChartRange::Week,
+        Ok(vec![
+            (1_709_993_100_000.0, 65000.0),
+            (1_710_079_500_000.0, 66000.0),
+        ]),
+    );
+    assert_eq!(
+        app.handle_key(KeyCode::Char('1').into()),
+        KeyAction::FetchChart("bitcoin".to_string())
+    );
+    assert_eq!(app.chart_range, ChartRange::Day);
+    // Still in the detail view rather than back on the market
+    assert_eq!(app.view, View::Detail("bitcoin".to_string()));
+    let buffer = render(&app, 60, 20);
+    let lines = lines(&buffer);
+    assert!(lines.iter().any(|line| line.contains("Loading chart…")));
+    let (y, selector) = row(&lines, "30D");
+    let x = column(selector, "1D") as u16;
+    assert!(buffer[(x, y as u16)].modifier.contains(Modifier::REVERSED));
+    app.finish_chart(
+        "bitcoin".to_string(),
+        ChartRange::Day,
+        Ok(vec![
+            (1_709_993_100_000.0, 65000.0),
+            (1_710_000_300_000.0, 66000.0),
+        ]),
+    );
+    let lines = self::lines(&render(&app, 60, 20));
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("14:05") && line.contains("16:05"))
+    );
+
+    // Both are cached now
+    assert_eq!(app.handle_key(KeyCode::Right.into()), KeyAction::Nothing);
+    assert_eq!(app.chart_range, ChartRange::Week);
+    let lines = self::lines(&render(&app, 60, 20));
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("Mar 9") && line.contains("Mar 10"))
+    );
+    app.handle_key(KeyCode::Left.into());
+    assert_eq!(
+        app.handle_key(KeyCode::Left.into()),
+        KeyAction::FetchChart("bitcoin".to_string())
+    );
+    assert_eq!(app.chart_range, ChartRange::Year);
+}
+
+#[test]
+fn long_names_are_truncated() {
+    let mut app = test_app();
+    // What a narrow terminal would be set up with
+    app.config.show_volume = false;
+    app.config.coins.push("wrapped".to_string());
+    app.apply_refresh(vec![coin(
+        "wrapped",
+        "WBTC",
+        "Wrapped Bitcoin Bridged From Somewhere Far Away",
+        67000.0,
+        0.1,
+    )]);
+    let lines = lines(&render(&app, 60, 10));
+    let (_, wrapped) = row(&lines, "WBTC");
+    assert!(wrapped.contains('…'), "{}", wrapped);
+    assert!(wrapped.contains("$67,000.00"), "{}", wrapped);
+}
+
+#[test]
+fn tiny_terminals_get_a_message_instead_of_the_screen() {
+    let app = test_app();
+    let text = lines(&render(&app, 10, 3)).concat();
+    assert!(
+        text.contains("Terminal") && text.contains("too small"),
+        "{}",
+        text
+    );
+    assert!(!text.contains("BTC"), "{}", text);
+    let text = lines(&render(&app, 39, 20)).join(" ");
+    assert!(text.contains("need at least 40×8"), "{}", text);
+    // Back to normal once it is big enough
+    let lines = self::lines(&render(&app, 40, 8));
+    assert!(row(&lines, "BTC").1.contains("-0.74%"));
+}
+
+#[test]
+fn session_range_columns_and_arrows_mark_new_extremes() {
+    let mut app = test_app();
+    app.config.show_session_range = true;
+    app.apply_refresh(vec![
+        coin("bitcoin", "BTC", "Bitcoin", 68000.0, -0.74),
+        coin("ethereum", "ETH", "Ethereum", 2400.0, 1.72),
+        coin("cardano", "ADA", "Cardano", 0.35, 0.0),
+    ]);
+    let lines = lines(&render(&app, 140, 12));
+    let (_, header) = row(&lines, "SYMBOL");
+    assert!(column(header, "PRICE") < column(header, "HI"));
+    assert!(column(header, "HI") < column(header, "LO"));
+    let words = |line: &str| line.split_whitespace().collect::<Vec<_>>().join(" ");
+    let bitcoin = words(row(&lines, "Bitcoin").1);
+    assert!(
+        bitcoin.contains("↑ $68,000.00 $68,000.00 $67,187.34"),
+        "{}",
+        bitcoin
+    );
+    let ethereum = words(row(&lines, "Ethereum").1);
+    assert!(
+        ethereum.contains("↓ $2,400.00 $2,464.52 $2,400.00"),
+        "{}",
+        ethereum
+    );
+    let (_, cardano) = row(&lines, "Cardano");
+    assert!(!cardano.contains('↑') && !cardano.contains('↓'));
+    assert!(
+        lines
+            .last()
+            .unwrap()
+            .contains("BTC new session high · ETH new session low")
+    );
+
+    // The arrows are gone once a refresh sets no new extreme
+    app.apply_refresh(vec![coin("bitcoin", "BTC", "Bitcoin", 67500.0, -0.74)]);
+    let lines = self::lines(&render(&app, 140, 12));
+    assert!(!row(&lines, "Bitcoin").1.contains('↑'));
+}
+
+#[test]
+fn narrow_terminals_drop_the_less_needed_columns() {
+    let app = test_app();
+    let lines = lines(&render(&app, 45, 10));
+    let (_, header) = row(&lines, "SYMBOL");
+    assert!(
+        header.contains("PRICE") && header.contains("24H"),
+        "{}",
+        header
+    );
+    assert!(
+        !header.contains("NAME") && !header.contains("MCAP"),
+        "{}",
+        header
+    );
+    let (_, bitcoin) = row(&lines, "BTC");
+    assert!(bitcoin.contains("-0.74%"), "{}", bitcoin);
+    // The name comes back once there is room
+    let lines = self::lines(&render(&app, 70, 10));
+    let (_, header) = row(&lines, "SYMBOL");
+    assert!(
+        header.contains("NAME") && !header.contains("MCAP"),
+        "{}",
+        header
+    );
+}
+
+#[test]
+fn change_columns_follow_the_config() {
+    let mut app = test_app();
+    app.config.change_columns = vec![ChangeHorizon::Hour, ChangeHorizon::Week];
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    bitcoin.change_1h = Some(0.18);
+    bitcoin.change_7d = Some(-4.11);
+    let ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    app.apply_refresh(vec![bitcoin, ethereum]);
+    let buffer = render(&app, 100, 12);
+    let lines = lines(&buffer);
+    let (_, header) = row(&lines, "SYMBOL");
+    // Only the one of the volume column is left
+    assert_eq!(header.matches("24H").count(), 1, "{}", header);
+    let week_end = column(header, "7D") + "7D".len();
+    let (y, bitcoin) = row(&lines, "Bitcoin");
+    assert_eq!(column(bitcoin, "-4.11%") + "-4.11%".len(), week_end);
+    let x = column(bitcoin, "+0.18%");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::Green);
+    // No rank, 1h, 7d, market cap or volume, only the price is known
+    let (_, ethereum) = row(&lines, "Ethereum");
+    assert_eq!(ethereum.matches('—').count(), 5, "{}", ethereum);
+}
+
+#[test]
+fn trending_tab_lists_the_coins_and_its_own_errors() {
+    let mut app = test_app();
+    app.view = View::Trending;
+    let lines = lines(&render(&app, 100, 12));
+    row(&lines, "Loading trending coins…");
+
+    app.finish_trending(Ok(vec![
+        TrendingCoin {
+            id: "pepe".to_string(),
+            symbol: "PEPE".to_string(),
+            name: "Pepe".to_string(),
+            market_cap_rank: Some(24478),
+            score: 0,
+        },
+        TrendingCoin {
+            id: "bitcoin".to_string(),
+            symbol: "BTC".to_string(),
+            name: "Bitcoin".to_string(),
+            market_cap_rank: Some(1),
+            score: 1,
+        },
+    ]));
+    app.finish_trending(Err(TuickerError::RateLimited(Duration::from_secs(30))));
+    let lines = self::lines(&render(&app, 100, 12));
+    row(&lines, "Trending · could not refresh: rate limited");
+    let (pepe_y, pepe) = row(&lines, "PEPE");
+    assert!(pepe.contains("#24478"), "{}", pepe);
+    let (bitcoin_y, bitcoin) = row(&lines, "Bitcoin");
+    assert_eq!(bitcoin_y, pepe_y + 1);
+    assert!(bitcoin.contains("tracked"), "{}", bitcoin);
+    assert!(!pepe.contains("tracked"), "{}", pepe);
+}
+
+#[test]
+fn header_shows_the_global_figures_until_a_fetch_fails() {
+    let mut app = test_app();
+    let global = |dominance: f64| {
+        let body = format!(
+            r#"{{"data": {{"total_market_cap": {{"usd": 2.3119e12}},
+                "market_cap_percentage": {{"btc": {}}},
+                "market_cap_change_percentage_24h_usd": -1.41}}}}"#,
+            dominance
+        );
+        parse_global(&body).unwrap()
+    };
+    app.finish_global(Ok(global(52.1)));
+    app.finish_global(Ok(global(52.31)));
+    let buffer = render(&app, 100, 12);
+    let lines = lines(&buffer);
+    assert!(
+        lines[0].starts_with("Crypto Tracker · Total: $2.31T (-1.4%) · BTC.D 52.3%"),
+        "{}",
+        lines[0]
+    );
+    let x = column(&lines[0], "(-1.4%)");
+    assert_eq!(buffer[(x as u16, 0)].fg, Color::Red);
+    let x = column(&lines[0], "BTC.D");
+    assert_eq!(buffer[(x as u16, 0)].fg, Color::Green);
+
+    app.finish_global(Err(TuickerError::RateLimited(Duration::from_secs(30))));
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(lines[0].trim_end(), "Crypto Tracker");
+}
+
+#[test]
+fn fear_greed_index_is_hidden_unless_enabled_and_fetched() {
+    let mut app = test_app();
+    app.finish_fear_greed(Ok(82));
+    let buffer = render(&app, 100, 12);
+    assert!(!lines(&buffer)[0].contains("F&G"));
+
+    app.config.show_fear_greed = true;
+    let buffer = render(&app, 100, 12);
+    let lines = self::lines(&buffer);
+    let x = column(&lines[0], "F&G 82 Extreme Greed");
+    assert_eq!(buffer[(x as u16, 0)].fg, Color::Green);
+
+    app.finish_fear_greed(Err(TuickerError::RateLimited(Duration::from_secs(30))));
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(lines[0].trim_end(), "Crypto Tracker");
+}
+
+#[test]
+fn tab_bar_marks_the_open_tab() {
+    let mut app = test_app();
+    app.show_tab(Tab::Alerts);
+    let buffer = render(&app, 100, 12);
+    let lines = lines(&buffer);
+    assert!(lines[1].contains("1 Market"), "{}", lines[1]);
+    assert!(lines[1].contains("4 Trending"), "{}", lines[1]);
+    let x = column(&lines[1], "3 Alerts");
+    assert!(buffer[(x as u16, 1)].modifier.contains(Modifier::REVERSED));
+    let x = column(&lines[1], "1 Market");
+    assert!(!buffer[(x as u16, 1)].modifier.contains(Modifier::REVERSED));
+}
+
+#[test]
+fn help_overlay_lists_the_keymap_by_category() {
+    let mut app = test_app();
+    app.show_help = true;
+    let lines = lines(&render(&app, 100, 50));
+    let (navigation_y, _) = row(&lines, "Navigation");
+    let (actions_y, _) = row(&lines, "Actions");
+    let (views_y, _) = row(&lines, "Views");
+    assert!(navigation_y < actions_y && actions_y < views_y);
+    let keymap = Keymap::default();
+    for (binding, _) in keymap.bindings_in(Scope::Market) {
+        row(&lines, binding.description);
+    }
+    assert!(!lines.iter().any(|line| line.contains("chart the last day")));
+    let (_, refresh) = row(&lines, "refresh");
+    assert!(refresh.contains("r/F5  refresh"), "{}", refresh);
+
+    // Remapped keys show up as they are set
+    let bindings = &mut app.config.keys.bindings;
+    let quit = bindings.iter_mut().find(|binding| binding.name == "quit");
+    quit.unwrap().keys = vec![parse_key("ctrl-c").unwrap()];
+    let lines = self::lines(&render(&app, 100, 50));
+    let (_, quit) = row(&lines, "quit");
+    assert!(quit.contains("ctrl-c  quit"), "{}", quit);
+
+    // The detail view lists its own keys, the range keys over the tab keys
+    app.view = View::Detail("bitcoin".to_string());
+    let lines = self::lines(&render(&app, 100, 50));
+    let (_, day) = row(&lines, "chart the last day");
+    assert!(day.contains("1  chart the last day"), "{}", day);
+    assert!(!lines.iter().any(|line| line.contains("  market  ")));
+    assert!(!lines.iter().any(|line| line.contains("add a coin")));
+}
+
+fn mouse(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+    MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::NONE,
+    }
+}
+
+#[test]
+fn clicking_a_row_selects_it_and_opens_it_the_second_time() {
+    let mut app = test_app();
+    let lines = lines(&render(&app, 100, 12));
+    let (y, _) = row(&lines, "Ethereum");
+    let click = mouse(MouseEventKind::Down(MouseButton::Left), 10, y as u16);
+    assert_eq!(app.handle_mouse(click), KeyAction::Nothing);
+    assert_eq!(app.selected(), Some(1));
+    assert_eq!(
+        app.handle_mouse(click),
+        KeyAction::FetchChart("ethereum".to_string())
+    );
+    assert_eq!(app.view, View::Detail("ethereum".to_string()));
+
+    // The header row and anything below the last coin select nothing
+    app.show_market();
+    let lines = self::lines(&render(&app, 100, 12));
+    let (header_y, _) = row(&lines, "SYMBOL");
+    let click = mouse(MouseEventKind::Down(MouseButton::Left), 10, header_y as u16);
+    app.handle_mouse(click);
+    let click = mouse(MouseEventKind::Down(MouseButton::Left), 10, 9);
+    app.handle_mouse(click);
+    assert_eq!(app.selected(), Some(1));
+
+    app.handle_mouse(mouse(MouseEventKind::ScrollDown, 10, 5));
+    assert_eq!(app.selected(), Some(2));
+    app.handle_mouse(mouse(MouseEventKind::ScrollUp, 10, 5));
+    assert_eq!(app.selected(), Some(1));
+}
+
+// Coins ranked 1 to `count`, named C01, C02 and so on
+fn long_watchlist(count: usize) -> App {
+    let ids: Vec<String> = (1..=count).map(|rank| format!("coin{:02}", rank)).collect();
+    let config = Config {
+        coins: ids.clone(),
+        ..Config::default()
+    };
+    let mut app = App::new(config, None, Arc::new(DemoSource::new(0)));
+    let coins = ids.iter().enumerate().map(|(index, id)| {
+        let mut coin = coin(id, &format!("C{:02}", index + 1), id, 1.0, 0.0);
+        coin.market_cap_rank = Some(index as u32 + 1);
+        coin
+    });
+    app.apply_refresh(coins.collect());
+    app
+}
+
+// The symbols of the coins on screen, top to bottom
+fn shown_symbols(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .filter_map(|line| line.split_whitespace().find(|word| word.starts_with('C')))
+        .filter(|word| word.len() == 3)
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn long_watchlists_scroll_with_the_selection() {
+    let mut app = long_watchlist(30);
+    // 12 lines leave 6 rows for coins
+    for _ in 0..10 {
+        app.select_next();
+    }
+    let lines = lines(&render(&app, 100, 12));
+    assert_eq!(
+        shown_symbols(&lines),
+        ["C06", "C07", "C08", "C09", "C10", "C11"]
+    );
+    // Moving back up within the window leaves it where it is
+    app.select_previous();
+    app.select_previous();
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(shown_symbols(&lines)[0], "C06");
+
+    app.page_down();
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(app.selected(), Some(14));
+    assert_eq!(shown_symbols(&lines).last().unwrap(), "C15");
+    app.page_up();
+    assert_eq!(app.selected(), Some(8));
+
+    // At the bottom, then on a taller terminal no empty rows are left below
+    app.select_last();
+    render(&app, 100, 12);
+    let lines = self::lines(&render(&app, 100, 20));
+    let shown = shown_symbols(&lines);
+    assert_eq!(shown.len(), 14);
+    assert_eq!(shown[0], "C17");
+    assert_eq!(shown.last().unwrap(), "C30");
+}
+
+#[test]
+fn detailed_rows_take_two_lines_for_selection_scrolling_and_clicks() {
+    let mut app = test_app();
+    app.ui_state.row_layout = RowLayout::Detailed;
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    bitcoin.change_7d = Some(-4.11);
+    let ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    app.apply_refresh(vec![bitcoin, ethereum]);
+    let lines = lines(&render(&app, 100, 12));
+    let (y, symbol) = row(&lines, "BTC");
+    assert!(symbol.contains("$67,187.34") && symbol.contains("-0.74%"));
+    assert!(lines[y + 1].contains("Bitcoin"), "{}", lines[y + 1]);
+    assert!(lines[y + 1].contains("-4.11%"), "{}", lines[y + 1]);
+    // Either line of a coin selects it
+    let click = mouse(MouseEventKind::Down(MouseButton::Left), 10, y as u16 + 3);
+    app.handle_mouse(click);
+    assert_eq!(app.selected(), Some(1));
+
+    // 12 lines leave room for 3 coins
+    let mut app = long_watchlist(30);
+    app.ui_state.row_layout = RowLayout::Detailed;
+    for _ in 0..4 {
+        app.select_next();
+    }
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(shown_symbols(&lines), ["C03", "C04", "C05"]);
+    app.page_down();
+    assert_eq!(app.selected(), Some(7));
+    app.select_last();
+    let lines = self::lines(&render(&app, 100, 12));
+    assert_eq!(shown_symbols(&lines), ["C28", "C29", "C30"]);
+}
+
+#[test]
+fn heatmap_colors_the_coins_and_moves_the_selection_across_cells() {
+    let mut app = test_app();
+    let mut coins = vec![
+        coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74),
+        coin("ethereum", "ETH", "Ethereum", 2464.52, 12.5),
+        coin("cardano", "ADA", "Cardano", 0.35, 0.0),
+    ];
+    for (coin, market_cap) in coins.iter_mut().zip([1.3e12, 3.0e11, 1.2e10]) {
+        coin.market_cap = Some(market_cap);
+    }
+    app.apply_refresh(coins);
+    app.handle_key(KeyCode::Char('m').into());
+    let buffer = render(&app, 40, 24);
+    let lines = lines(&buffer);
+    assert!(!lines.iter().any(|line| line.contains("SYMBOL")));
+    let (y, bitcoin) = row(&lines, "BTC");
+    assert!(lines[y + 1].contains("-0.74%"), "{}", bitcoin);
+    // Bitcoin weighs the most and gets the first row to itself
+    let (eth_y, ethereum) = row(&lines, "ETH");
+    assert!(eth_y > y && ethereum.contains("ADA"), "{}", ethereum);
+    let x = column(ethereum, "ETH") as u16;
+    assert_eq!(buffer[(x, eth_y as u16)].bg, Color::Rgb(20, 150, 40));
+
+    app.handle_key(KeyCode::Down.into());
+    assert_eq!(app.selected(), Some(1));
+    app.handle_key(KeyCode::Right.into());
+    assert_eq!(app.selected(), Some(2));
+    let lines = self::lines(&render(&app, 40, 24));
+    assert!(row(&lines, "ETH").1.contains("> ADA"));
+    app.handle_key(KeyCode::Up.into());
+    assert_eq!(app.selected(), Some(0));
+    assert_eq!(
+        app.handle_key(KeyCode::Enter.into()),
+        KeyAction::FetchChart("bitcoin".to_string())
+    );
+    // Back to the heatmap, where a click picks a cell
+    app.handle_key(KeyCode::Char('q').into());
+    let click = mouse(MouseEventKind::Down(MouseButton::Left), x, eth_y as u16);
+    app.handle_mouse(click);
+    assert_eq!(app.selected(), Some(1));
+}
+
+#[test]
+fn scrollbar_shows_only_when_the_coins_overflow() {
+    let buffer = render(&long_watchlist(30), 100, 12);
+    let thumb = (3..9).filter(|&y| buffer[(99, y)].symbol() == "█").count();
+    assert!(thumb > 0, "{:?}", self::lines(&buffer));
+    let buffer = render(&long_watchlist(3), 100, 12);
+    assert!((3..9).all(|y| buffer[(99, y)].symbol() == "│"));
+}
+
+#[test]
+fn coins_are_listed_by_rank() {
+    let mut app = test_app();
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    bitcoin.market_cap_rank = Some(1);
+    let mut ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    ethereum.market_cap_rank = Some(2);
+    app.apply_refresh(vec![ethereum, bitcoin]);
+    let lines = lines(&render(&app, 100, 12));
+    let (header_y, header) = row(&lines, "SYMBOL");
+    let rank_end = column(header, "# ▲") + "# ▲".chars().count();
+    let (bitcoin_y, bitcoin) = row(&lines, "Bitcoin");
+    assert_eq!(bitcoin_y, header_y + 1);
+    assert_eq!(column(bitcoin, "1 ") + 1, rank_end);
+
+    app.view = View::Detail("bitcoin".to_string());
+    let lines = self::lines(&render(&app, 100, 12));
+    row(&lines, "Bitcoin (BTC) · #1");
+}
+
+#[test]
+fn small_terminals_do_not_panic() {
+    let mut app = test_app();
+    for (width, height) in [(40, 10), (20, 5), (10, 3), (1, 1)] {
+        render(&app, width, height);
+    }
+    let lines = lines(&render(&app, 40, 10));
+    assert!(lines[0].starts_with("Crypto Tracker"));
+
+    app.view = View::Detail("bitcoin".to_string());
+    app.charts.insert(
+        ("bitcoin".to_string(), ChartRange::Week),
+        ChartEntry {
+            data: ChartData::Loaded(vec![
+                (1_700_000_000_000.0, 65000.0),
+                (1_700_086_400_000.0, 66000.0),
+                (1_700_172_800_000.0, 64000.0),
+            ]),
+            fetched_at: std::time::Instant::now(),
+        },
+    );
+    for (width, height) in [(40, 10), (20, 5), (1, 1)] {
+        render(&app, width, height);
+    }
+}
+
+#[test]
+fn empty_watchlist_hint() {
+    let config = Config {
+        coins: Vec::new(),
+        ..Config::default()
+    };
+    let app = App::new(config, None, Arc::new(DemoSource::new(0)));
+    let lines = lines(&render(&app, 60, 8));
+    row(&lines, "No coins tracked");
+}
+
+#[test]
+fn first_fetch_failure_shows_the_error() {
+    let mut app = App::new(Config::default(), None, Arc::new(DemoSource::new(0)));
+    app.refresh_failed(&TuickerError::Api {
+        status: StatusCode::BAD_GATEWAY,
+        message: String::new(),
+    });
+    let lines = lines(&render(&app, 100, 8));
+    row(
+        &lines,
+        "Unable to reach CoinGecko: HTTP error 502 Bad Gateway",
+    );
+    row(&lines, "Press r to retry, q to quit");
+}
+
+#[test]
+fn portfolio_marks_missing_prices_and_cost_basis() {
+    let mut app = test_app();
+    for (coin_id, quantity, cost_basis) in [("bitcoin", 0.5, Some(60000.0)), ("solana", 10.0, None)]
+    {
+        let holding = Holding {
+            quantity,
+            cost_basis,
+        };
+        app.portfolio.holdings.insert(coin_id.to_string(), holding);
+    }
+    app.view = View::Portfolio;
+    let lines = lines(&render(&app, 100, 14));
+    let (_, bitcoin) = row(&lines, "BTC");
+    assert!(bitcoin.contains("$33,593.67"), "{}", bitcoin);
+    assert!(bitcoin.contains("+$3,593.67"), "{}", bitcoin);
+    assert!(bitcoin.contains("+11.98%"), "{}", bitcoin);
+    let (_, solana) = row(&lines, "solana");
+    assert!(solana.contains("—"), "{}", solana);
+    assert!(solana.contains("n/a"), "{}", solana);
+    let (_, total) = row(&lines, "TOTAL (partial)");
+    assert!(total.contains("$33,593.67"), "{}", total);
+    // Solana has no price, so bitcoin is the whole allocation
+    let (_, allocation) = row(&lines, "100.00%");
+    assert!(allocation.contains("BTC"), "{}", allocation);
+}
+
+#[test]
+fn alerts_tab_lists_rules_with_their_state() {
+    let mut app = test_app();
+    app.config.alerts = vec![
+        parse_alert_rule("bitcoin above 60000").unwrap(),
+        parse_alert_rule("solana below 100").unwrap(),
+    ];
+    // Bitcoin is above 60000, so its alert fires
+    app.apply_refresh(vec![coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74)]);
+    app.dismiss_alerts();
+    app.view = View::Alerts;
+    let lines = lines(&render(&app, 100, 8));
+    let (_, bitcoin) = row(&lines, "BTC");
+    assert!(bitcoin.contains("above"), "{}", bitcoin);
+    assert!(bitcoin.contains("$60,000.00"), "{}", bitcoin);
+    assert!(bitcoin.contains("triggered"), "{}", bitcoin);
+    // Not in the watchlist, so there is no price to show
+    let (_, solana) = row(&lines, "solana");
+    assert!(solana.contains("—"), "{}", solana);
+    assert!(solana.contains("armed"), "{}", solana);
+}
+
+#[test]
+fn currency_toggle_shows_the_other_prices() {
+    let mut app = test_app();
+    app.config.extra_currencies = vec!["jpy".to_string()];
+    let mut bitcoin = coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74);
+    let yen = Quote {
+        price: 10_512_345.6,
+        change_24h: -0.52,
+    };
+    bitcoin.quotes.insert("jpy".to_string(), yen);
+    app.apply_refresh(vec![
+        bitcoin,
+        coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72),
+    ]);
+    app.cycle_currency();
+    let lines = lines(&render(&app, 100, 8));
+    assert!(lines[0].starts_with("Crypto Tracker · prices in JPY"));
+    let (_, bitcoin) = row(&lines, "BTC");
+    assert!(bitcoin.contains("¥10,512,346"), "{}", bitcoin);
+    assert!(bitcoin.contains("-0.52%"), "{}", bitcoin);
+    // Ethereum has no yen price yet
+    let (_, ethereum) = row(&lines, "ETH");
+    assert!(ethereum.contains("—"), "{}", ethereum);
+}
+
+#[test]
+fn btc_denomination_marks_the_column_and_pins_bitcoin() {
+    let mut app = test_app();
+    let mut ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    let in_btc = Quote {
+        price: 0.036682,
+        change_24h: 2.47,
+    };
+    ethereum.quotes.insert("btc".to_string(), in_btc);
+    app.apply_refresh(vec![
+        coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74),
+        ethereum,
+    ]);
+    app.toggle_btc();
+    let lines = lines(&render(&app, 100, 8));
+    row(&lines, "PRICE (BTC)");
+    let (_, bitcoin) = row(&lines, "Bitcoin");
+    assert!(bitcoin.contains("1.00000000 BTC"), "{}", bitcoin);
+    let (_, ethereum) = row(&lines, "Ethereum");
+    assert!(ethereum.contains("0.03668200 BTC"), "{}", ethereum);
+    assert!(ethereum.contains("+2.47%"), "{}", ethereum);
+}
+
+#[test]
+fn prefer_sats_shows_every_btc_price_in_sats() {
+    let mut app = test_app();
+    app.config.prefer_sats = true;
+    let mut ethereum = coin("ethereum", "ETH", "Ethereum", 2464.52, 1.72);
+    let in_btc = Quote {
+        price: 0.036682,
+        change_24h: 2.47,
+    };
+    ethereum.quotes.insert("btc".to_string(), in_btc);
+    app.apply_refresh(vec![ethereum]);
+    app.toggle_btc();
+    let lines = lines(&render(&app, 100, 8));
+    let (_, ethereum) = row(&lines, "Ethereum");
+    assert!(ethereum.contains("3,668,200 sats"), "{}", ethereum);
+}
+
+#[test]
+fn space_pauses_refreshing_and_the_footer_says_so() {
+    let mut app = test_app();
+    app.handle_key(KeyCode::Char(' ').into());
+    assert!(app.paused);
+    let lines = lines(&render(&app, 100, 8));
+    let (_, footer) = row(&lines, "PAUSED");
+    assert!(footer.contains("updated 0s ago"), "{}", footer);
+    assert!(!footer.contains("next in"), "{}", footer);
+
+    assert_eq!(
+        app.handle_key(KeyCode::Char('r').into()),
+        KeyAction::Refresh
+    );
+    app.handle_key(KeyCode::Char('p').into());
+    assert!(!app.paused);
+    let lines = self::lines(&render(&app, 100, 8));
+    let (_, footer) = row(&lines, "next in");
+    assert!(!footer.contains("PAUSED"), "{}", footer);
+}
+
+#[test]
+fn i_shows_why_the_feed_is_offline() {
+    let mut app = test_app();
+    let error = TuickerError::Api {
+        status: StatusCode::BAD_GATEWAY,
+        message: String::new(),
+    };
+    for _ in 0..3 {
+        app.refresh_failed(&error);
+    }
+    let lines = lines(&render(&app, 100, 12));
+    let (_, footer) = row(&lines, "● offline: HTTP error 502 Bad Gateway");
+    assert!(footer.contains("(stale)"), "{}", footer);
+
+    app.handle_key(KeyCode::Char('i').into());
+    let lines = self::lines(&render(&app, 100, 12));
+    row(&lines, "Connection");
+    let (_, status) = row(&lines, "Status");
+    assert!(status.contains("offline"), "{}", status);
+    let (_, failures) = row(&lines, "Failures");
+    assert!(failures.contains("3 in a row"), "{}", failures);
+    row(&lines, "Last error    HTTP error 502 Bad Gateway");
+    app.handle_key(KeyCode::Esc.into());
+    assert!(!app.show_connection);
+}
+
+#[test]
+fn old_prices_are_grayed_out_and_marked_stale() {
+    let mut app = test_app();
+    app.last_updated = Some(std::time::Instant::now() - 4 * app.refresh_interval);
+    let buffer = render(&app, 100, 12);
+    let lines = lines(&buffer);
+    let (y, bitcoin) = row(&lines, "Bitcoin");
+    let x = column(bitcoin, "67,187.34");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::DarkGray);
+    let x = column(bitcoin, "-0.74%");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::DarkGray);
+    row(&lines, "(stale) · next in");
+
+    app.apply_refresh(vec![coin("bitcoin", "BTC", "Bitcoin", 67187.34, -0.74)]);
+    let buffer = render(&app, 100, 12);
+    let lines = self::lines(&buffer);
+    let (y, bitcoin) = row(&lines, "Bitcoin");
+    let x = column(bitcoin, "-0.74%");
+    assert_eq!(buffer[(x as u16, y as u16)].fg, Color::Red);
+    assert!(!lines.iter().any(|line| line.contains("stale")));
+}
+
+#[test]
+fn a_refresh_that_takes_half_the_timeout_is_called_slow() {
+    let mut app = test_app();
+    app.fetching = true;
+    app.fetch_started = Some(std::time::Instant::now());
+    let lines = lines(&render(&app, 100, 12));
+    row(&lines, "refreshing…");
+
+    app.fetch_started = Some(std::time::Instant::now() - app.config.request_timeout / 2);
+    let lines = self::lines(&render(&app, 100, 12));
+    row(&lines, "slow connection…");
+    app.retrying(2);
+    app.fetch_started = Some(std::time::Instant::now() - app.config.request_timeout);
+    let lines = self::lines(&render(&app, 100, 12));
+    row(&lines, "retrying… (attempt 2/4) · slow connection…");
+}
+
+#[test]
+fn f12_shows_the_last_raw_response_until_closed() {
+    let mut app = test_app();
+    let info = FetchDebugInfo {
+        url: "https://api.coingecko.com/api/v3/coins/markets?vs_currency=usd".to_string(),
+        status: Some(StatusCode::OK),
+        elapsed: Duration::from_millis(312),
+        body: "[\n  {\n    \"id\": \"bitcoin\"\n  }\n]".to_string(),
+        outcome: "parsed 1 coins".to_string(),
+    };
+    // Nothing is kept before debug mode is on
+    app.finish_debug(info.clone());
+    assert!(app.debug_info.is_none());
+
+    app.handle_key(KeyCode::F(12).into());
+    let lines = lines(&render(&app, 100, 16));
+    row(&lines, "Nothing yet");
+    app.finish_debug(info);
+    let lines = self::lines(&render(&app, 100, 16));
+    row(&lines, "GET https://api.coingecko.com/api/v3/coins/markets");
+    row(&lines, "200 OK · 312 ms · parsed 1 coins");
+    row(&lines, "\"id\": \"bitcoin\"");
+
+    app.handle_key(KeyCode::Down.into());
+    assert_eq!(app.debug_scroll, 1);
+    app.handle_key(KeyCode::F(12).into());
+    assert!(!app.debug);
+    assert!(app.debug_info.is_none());
+}
+
+#[tokio::test]
+async fn the_demo_market_fills_the_table_without_a_network() {
+    let config = Config {
+        coins: vec![
+            "bitcoin".to_string(),
+            "ethereum".to_string(),
+            "my-token".to_string(),
+        ],
+        ..Config::default()
+    };
+    let mut app = App::new(config, None, Arc::new(DemoSource::new(42)));
+    let ids = app.config.coins.clone();
+    let refresh = app
+        .source
+        .refresh(&ids, "usd", &[], RefreshHooks::NONE)
+        .await
+        .unwrap();
+    app.finish_refresh(refresh);
+    let price = |app: &App| {
+        let bitcoin = app.coins.iter().find(|coin| coin.id == "bitcoin");
+        bitcoin.unwrap().current_price
+    };
+    let first = price(&app);
+
+    let lines = lines(&render(&app, 100, 12));
+    row(&lines, "Bitcoin");
+    row(&lines, "Ethereum");
+    // Ids the demo doesn't know get made up on the spot
+    row(&lines, "MYTO");
+
+    app.sort_key = SortKey::Price;
+    app.sort_dir = SortDir::Ascending;
+    app.sort();
+    assert_eq!(app.coins.last().unwrap().id, "bitcoin");
+
+    // Every refresh moves the prices
+    let refresh = app
+        .source
+        .refresh(&ids, "usd", &[], RefreshHooks::NONE)
+        .await
+        .unwrap();
+    app.finish_refresh(refresh);
+    assert_ne!(price(&app), first);
+}
+
+#[tokio::test]
+async fn the_footer_names_the_source_a_failover_got_the_prices_from() {
+    let config = Config {
+        coins: vec!["bitcoin".to_string()],
+        ..Config::default()
+    };
+    let source = FailoverSource::new(vec![
+        (SourceKind::Demo, Arc::new(DemoSource::new(1))),
+        (SourceKind::CoinGecko, Arc::new(DemoSource::new(2))),
+    ]);
+    let mut app = App::new(config, None, Arc::new(source));
+    let ids = app.config.coins.clone();
+    let refresh = app
+        .source
+        .refresh(&ids, "usd", &[], RefreshHooks::NONE)
+        .await
+        .unwrap();
+    app.finish_refresh(refresh);
+
+    let lines = lines(&render(&app, 120, 12));
+    row(&lines, "ago via demo");
+}